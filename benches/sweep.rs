@@ -0,0 +1,88 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * Benchmarks of the sweep for up to a million sites
+ * The time per site should only grow logarithmically, also for sorted inputs that used to degenerate the beach line.
+ */
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use pop::geom::BoundingBox;
+use pop::random::Random;
+use pop::voronoi::DiagramBuilder;
+
+const SIZE: f64 = 1000.0;
+
+const COUNTS: [usize; 4] = [1_000, 10_000, 100_000, 1_000_000];
+
+fn create_random_sites(count: usize) -> Vec<(f64, f64)> {
+    let mut random = Random::new(count as u64);
+    (0..count).map(|_| (random.range(0.0, SIZE), random.range(0.0, SIZE))).collect()
+}
+
+/**
+ * Sites on a jittered grid, added row by row, which the sweep handles in the order of insertion
+ */
+fn create_sorted_sites(count: usize) -> Vec<(f64, f64)> {
+    let mut random = Random::new(count as u64);
+    let columns = (count as f64).sqrt().ceil() as usize;
+    let spacing = SIZE / columns as f64;
+    (0..count).map(|i| (
+	((i % columns) as f64 + random.range(0.25, 0.75)) * spacing,
+	((i / columns) as f64 + random.range(0.25, 0.75)) * spacing
+    )).collect()
+}
+
+fn bench_sweep(c: &mut Criterion, name: &str, threads: usize, create_sites: fn(usize) -> Vec<(f64, f64)>) {
+    let mut group = c.benchmark_group(name);
+    group.sample_size(10);
+    for &count in COUNTS.iter() {
+	let sites = create_sites(count);
+	group.throughput(Throughput::Elements(count as u64));
+	group.bench_with_input(BenchmarkId::from_parameter(count), &sites, |b, sites| {
+	    b.iter(|| {
+		let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, SIZE, 0.0, SIZE));
+		#[cfg(feature = "parallel")]
+		builder.set_threads(threads);
+		#[cfg(not(feature = "parallel"))]
+		assert_eq!(1, threads, "building on several threads needs the parallel feature");
+		builder.add_sites(sites.iter().cloned());
+		builder.build().unwrap()
+	    });
+	});
+    }
+    group.finish();
+}
+
+fn bench_random(c: &mut Criterion) {
+    bench_sweep(c, "sweep_random", 1, create_random_sites);
+}
+
+fn bench_sorted(c: &mut Criterion) {
+    bench_sweep(c, "sweep_sorted", 1, create_sorted_sites);
+}
+
+#[cfg(feature = "parallel")]
+fn bench_parallel(c: &mut Criterion) {
+    bench_sweep(c, "sweep_parallel", 4, create_random_sites);
+}
+
+#[cfg(feature = "parallel")]
+criterion_group!(benches, bench_random, bench_sorted, bench_parallel);
+#[cfg(not(feature = "parallel"))]
+criterion_group!(benches, bench_random, bench_sorted);
+criterion_main!(benches);
@@ -0,0 +1,13 @@
+#version 330 core
+
+layout (location = 0) in vec3 pos;
+layout (location = 1) in vec3 color;
+
+uniform mat4 view = mat4(1.0);
+
+out vec3 vertex_color;
+
+void main() {
+     gl_Position = view * vec4(pos.x, pos.y, pos.z, 1.0);
+     vertex_color = color;
+}
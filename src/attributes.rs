@@ -0,0 +1,556 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * Named columns of typed values attached to the faces, vertices or half edges of a diagram
+ *
+ * Every column has a value for every element of its kind, indexed by id, and a default value for elements that
+ * are added later. The columns follow the diagram: they are saved and loaded with it, relaxation keeps the values
+ * of the faces, and editing keeps the values of the elements that do not change. Elements that are created or
+ * rebuilt get the default value.
+ *
+ * A loaded column keeps its values encoded until it is decoded with its type.
+ */
+
+use crate::float::Float;
+use crate::serialize::{EncodedColumn, Encoder, Payload, SerializeError};
+use crate::voronoi::{Diagram, Face, HalfEdge, Vertex};
+
+use std::any::Any;
+use std::fmt::Debug;
+
+/**
+ * The kind of diagram element a column is attached to
+ */
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Element {
+    Face,
+    Vertex,
+    HalfEdge,
+}
+
+impl Element {
+
+    pub(crate) fn name(&self) -> &'static str {
+	match self {
+	    Element::Face => "face",
+	    Element::Vertex => "vertex",
+	    Element::HalfEdge => "half_edge",
+	}
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Element> {
+	[Element::Face, Element::Vertex, Element::HalfEdge].iter().copied().find(|element| element.name() == name)
+    }
+
+}
+
+/**
+ * The types that can be stored in columns
+ */
+pub trait AttributeValue: Payload + Clone + Debug + PartialEq + Send + Sync + 'static {}
+
+impl<V> AttributeValue for V where V: Payload + Clone + Debug + PartialEq + Send + Sync + 'static {}
+
+/**
+ * The values of one attribute, indexed by element id
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Column<V> {
+    default: V,
+    values: Vec<V>,
+}
+
+impl<V> Column<V> where V: AttributeValue {
+
+    pub(crate) fn new(default: V, values: Vec<V>) -> Column<V> {
+	Column {
+	    default,
+	    values,
+	}
+    }
+
+    /**
+     * The value of elements that are added to the diagram
+     */
+    pub fn default_value(&self) -> &V {
+	&self.default
+    }
+
+    /**
+     * The value of an element, panics if the element does not exist
+     */
+    pub fn get(&self, id: usize) -> &V {
+	&self.values[id]
+    }
+
+    pub fn set(&mut self, id: usize, value: V) {
+	self.values[id] = value;
+    }
+
+    /**
+     * Sets the value of every element
+     */
+    pub fn fill(&mut self, value: V) {
+	for current in self.values.iter_mut() {
+	    *current = value.clone();
+	}
+    }
+
+    pub fn values(&self) -> &[V] {
+	&self.values
+    }
+
+    pub fn values_mut(&mut self) -> &mut [V] {
+	&mut self.values
+    }
+
+    pub fn len(&self) -> usize {
+	self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+	self.values.is_empty()
+    }
+
+    /**
+     * The ids of the elements with their values
+     */
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &V)> {
+	self.values.iter().enumerate()
+    }
+
+}
+
+/**
+ * The operations on columns that do not depend on the type of their values
+ */
+pub(crate) trait AnyColumn: Debug + Send + Sync {
+
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    fn len(&self) -> usize;
+
+    /**
+     * Adds default values or drops the last values
+     */
+    fn resize(&mut self, len: usize);
+
+    /**
+     * Sets the value of an element to the default
+     */
+    fn reset(&mut self, id: usize);
+
+    /**
+     * Removes the value of an element, moving the value of the last element into its place
+     */
+    fn swap_remove(&mut self, id: usize);
+
+    fn encode_default(&self, encoder: &mut Encoder);
+
+    fn encode(&self, id: usize, encoder: &mut Encoder);
+
+    fn equals(&self, other: &dyn AnyColumn) -> bool;
+
+}
+
+impl<V> AnyColumn for Column<V> where V: AttributeValue {
+
+    fn as_any(&self) -> &dyn Any {
+	self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+	self
+    }
+
+    fn len(&self) -> usize {
+	self.values.len()
+    }
+
+    fn resize(&mut self, len: usize) {
+	self.values.resize(len, self.default.clone());
+    }
+
+    fn reset(&mut self, id: usize) {
+	self.values[id] = self.default.clone();
+    }
+
+    fn swap_remove(&mut self, id: usize) {
+	self.values.swap_remove(id);
+    }
+
+    fn encode_default(&self, encoder: &mut Encoder) {
+	self.default.encode(encoder);
+    }
+
+    fn encode(&self, id: usize, encoder: &mut Encoder) {
+	self.values[id].encode(encoder);
+    }
+
+    fn equals(&self, other: &dyn AnyColumn) -> bool {
+	match other.as_any().downcast_ref::<Column<V>>() {
+	    Some(other) => self == other,
+	    // a loaded column equals the column that was saved until it is decoded
+	    None => other.as_any().is::<EncodedColumn>() && other.equals(self),
+	}
+    }
+
+}
+
+#[derive(Debug)]
+struct Entry {
+    name: String,
+    element: Element,
+    column: Box<dyn AnyColumn>,
+}
+
+/**
+ * The columns of a diagram, in the order in which they were added
+ */
+#[derive(Debug, Default)]
+pub struct Attributes {
+    entries: Vec<Entry>,
+}
+
+impl Attributes {
+
+    pub fn new() -> Attributes {
+	Attributes {
+	    entries: Vec::new(),
+	}
+    }
+
+    pub fn len(&self) -> usize {
+	self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+	self.entries.is_empty()
+    }
+
+    /**
+     * The names of the columns with the kind of element they are attached to
+     */
+    pub fn names(&self) -> impl Iterator<Item = (&str, Element)> {
+	self.entries.iter().map(|entry| (entry.name.as_str(), entry.element))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+	self.entries.iter().any(|entry| entry.name == name)
+    }
+
+    pub fn element(&self, name: &str) -> Option<Element> {
+	self.entry(name).map(|entry| entry.element)
+    }
+
+    /**
+     * The column with a name, or None if there is no such column, it has another type or it has not been decoded
+     */
+    pub fn get<V>(&self, name: &str) -> Option<&Column<V>> where V: AttributeValue {
+	self.entry(name)?.column.as_any().downcast_ref()
+    }
+
+    pub fn get_mut<V>(&mut self, name: &str) -> Option<&mut Column<V>> where V: AttributeValue {
+	self.entries.iter_mut().find(|entry| entry.name == name)?.column.as_any_mut().downcast_mut()
+    }
+
+    /**
+     * Decodes a loaded column with its type, which makes it available to get
+     * Fails if the column does not exist, has another type or its values can not be read as the type
+     */
+    pub fn decode<V>(&mut self, name: &str) -> Result<&mut Column<V>, SerializeError> where V: AttributeValue {
+	let entry = self.entries.iter_mut().find(|entry| entry.name == name)
+	    .ok_or_else(|| SerializeError::Invalid(format!("there is no attribute \"{}\"", name)))?;
+	if let Some(encoded) = entry.column.as_any().downcast_ref::<EncodedColumn>() {
+	    entry.column = Box::new(encoded.decode::<V>()?);
+	}
+	entry.column.as_any_mut().downcast_mut()
+	    .ok_or_else(|| SerializeError::Invalid(format!("attribute \"{}\" has another type", name)))
+    }
+
+    /**
+     * Removes a column, returns whether it existed
+     */
+    pub fn remove(&mut self, name: &str) -> bool {
+	let count = self.entries.len();
+	self.entries.retain(|entry| entry.name != name);
+	self.entries.len() < count
+    }
+
+    fn entry(&self, name: &str) -> Option<&Entry> {
+	self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /**
+     * Adds a column, replacing any column with the same name
+     */
+    pub(crate) fn insert(&mut self, name: &str, element: Element, column: Box<dyn AnyColumn>) {
+	let entry = Entry {
+	    name: String::from(name),
+	    element,
+	    column,
+	};
+	match self.entries.iter_mut().find(|entry| entry.name == name) {
+	    Some(existing) => *existing = entry,
+	    None => self.entries.push(entry),
+	}
+    }
+
+    pub(crate) fn columns(&self) -> impl Iterator<Item = (&str, Element, &dyn AnyColumn)> {
+	self.entries.iter().map(|entry| (entry.name.as_str(), entry.element, entry.column.as_ref()))
+    }
+
+    fn columns_mut(&mut self, element: Element) -> impl Iterator<Item = &mut Box<dyn AnyColumn>> {
+	self.entries.iter_mut().filter(move |entry| entry.element == element).map(|entry| &mut entry.column)
+    }
+
+    pub(crate) fn resize(&mut self, element: Element, len: usize) {
+	for column in self.columns_mut(element) {
+	    column.resize(len);
+	}
+    }
+
+    pub(crate) fn reset(&mut self, element: Element, id: usize) {
+	for column in self.columns_mut(element) {
+	    column.reset(id);
+	}
+    }
+
+    pub(crate) fn swap_remove(&mut self, element: Element, id: usize) {
+	for column in self.columns_mut(element) {
+	    column.swap_remove(id);
+	}
+    }
+
+    /**
+     * Keeps the values of the faces of a diagram whose vertices and half edges were all rebuilt
+     */
+    pub(crate) fn rebuilt(mut self, face_count: usize, vertex_count: usize, half_edge_count: usize) -> Attributes {
+	self.resize(Element::Face, face_count);
+	for (element, count) in [(Element::Vertex, vertex_count), (Element::HalfEdge, half_edge_count)].iter() {
+	    self.resize(*element, 0);
+	    self.resize(*element, *count);
+	}
+	self
+    }
+
+}
+
+impl PartialEq for Attributes {
+
+    fn eq(&self, other: &Attributes) -> bool {
+	self.entries.len() == other.entries.len() && self.entries.iter().zip(other.entries.iter()).all(|(first, second)| {
+	    first.name == second.name && first.element == second.element && first.column.equals(second.column.as_ref())
+	})
+    }
+
+}
+
+impl<T, F> Diagram<T, F> where F: Float {
+
+    pub fn attributes(&self) -> &Attributes {
+	&self.attributes
+    }
+
+    pub fn attributes_mut(&mut self) -> &mut Attributes {
+	&mut self.attributes
+    }
+
+    /**
+     * The number of faces, vertices or half edges
+     */
+    pub fn element_count(&self, element: Element) -> usize {
+	match element {
+	    Element::Face => self.faces.len(),
+	    Element::Vertex => self.vertices.len(),
+	    Element::HalfEdge => self.half_edges.len(),
+	}
+    }
+
+    /**
+     * Adds a column with the default value for every element, replacing any column with the same name
+     */
+    pub fn add_attribute<V>(&mut self, name: &str, element: Element, default: V) -> &mut Column<V> where V: AttributeValue {
+	let values = vec![default.clone(); self.element_count(element)];
+	self.attributes.insert(name, element, Box::new(Column::new(default, values)));
+	self.attributes.get_mut(name).expect("the column was just added")
+    }
+
+    /**
+     * The faces with their values in a face column, or None if there is no such face column of this type
+     */
+    pub fn faces_with<V>(&self, name: &str) -> Option<impl Iterator<Item = (&Face<T, F>, &V)>> where V: AttributeValue {
+	let column = self.typed_column::<V>(name, Element::Face)?;
+	Some(self.faces.iter().zip(column.values.iter()))
+    }
+
+    /**
+     * The vertices with their values in a vertex column, or None if there is no such vertex column of this type
+     */
+    pub fn vertices_with<V>(&self, name: &str) -> Option<impl Iterator<Item = (&Vertex<F>, &V)>> where V: AttributeValue {
+	let column = self.typed_column::<V>(name, Element::Vertex)?;
+	Some(self.vertices.iter().zip(column.values.iter()))
+    }
+
+    /**
+     * The half edges with their values in a half edge column, or None if there is no such half edge column of this type
+     */
+    pub fn half_edges_with<V>(&self, name: &str) -> Option<impl Iterator<Item = (&HalfEdge, &V)>> where V: AttributeValue {
+	let column = self.typed_column::<V>(name, Element::HalfEdge)?;
+	Some(self.half_edges.iter().zip(column.values.iter()))
+    }
+
+    fn typed_column<V>(&self, name: &str, element: Element) -> Option<&Column<V>> where V: AttributeValue {
+	if self.attributes.element(name)? == element {
+	    self.attributes.get(name)
+	} else {
+	    None
+	}
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::geom::BoundingBox;
+    use crate::relax::relax;
+    use crate::serialize::Format;
+    use crate::voronoi::DiagramBuilder;
+
+    fn create_diagram() -> Diagram {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 100.0, 0.0, 100.0));
+	builder.add_sites(vec![(20.0, 30.0), (70.0, 20.0), (50.0, 60.0), (15.0, 85.0), (85.0, 80.0)]);
+	builder.build().unwrap()
+    }
+
+    #[test]
+    fn test_add_attributes() {
+	let mut diagram = create_diagram();
+	let owners = diagram.add_attribute("owner", Element::Face, String::from("nobody"));
+	owners.set(2, String::from("red"));
+	diagram.add_attribute("height", Element::Vertex, 0.5);
+	assert_eq!(2, diagram.attributes().len());
+	assert_eq!(Some(Element::Vertex), diagram.attributes().element("height"));
+	let owners = diagram.attributes().get::<String>("owner").unwrap();
+	assert_eq!(5, owners.len());
+	assert_eq!("red", owners.get(2));
+	assert_eq!("nobody", owners.get(0));
+	assert_eq!(diagram.vertices().len(), diagram.attributes().get::<f64>("height").unwrap().len());
+	// a column is only found with its own type
+	assert!(diagram.attributes().get::<u64>("owner").is_none());
+	assert!(diagram.attributes_mut().remove("height"));
+	assert!(!diagram.attributes().contains("height"));
+    }
+
+    #[test]
+    fn test_zip_with_geometry() {
+	let mut diagram = create_diagram();
+	diagram.add_attribute("population", Element::Face, 0u64).values_mut()[4] = 1200;
+	let populated: Vec<(f64, u64)> = diagram.faces_with::<u64>("population").unwrap()
+	    .filter(|(_, population)| **population > 0)
+	    .map(|(face, population)| (face.x(), *population))
+	    .collect();
+	assert_eq!(vec![(85.0, 1200)], populated);
+	assert!(diagram.vertices_with::<u64>("population").is_none());
+	diagram.add_attribute("crossing", Element::HalfEdge, false);
+	assert_eq!(diagram.half_edges().len(), diagram.half_edges_with::<bool>("crossing").unwrap().count());
+    }
+
+    #[test]
+    fn test_round_trip() {
+	let mut diagram = create_diagram();
+	diagram.add_attribute("owner", Element::Face, None).set(1, Some(String::from("blue")));
+	diagram.add_attribute("height", Element::Vertex, 0.25).set(0, -1e-300);
+	diagram.add_attribute("river", Element::HalfEdge, (0u64, false)).set(3, (7, true));
+	for format in [Format::Json, Format::Binary].iter() {
+	    let mut bytes = Vec::new();
+	    diagram.write(&mut bytes, *format).unwrap();
+	    let mut loaded = Diagram::<()>::read(&mut &bytes[..], *format).unwrap();
+	    assert_eq!(diagram, loaded);
+	    assert!(loaded.attributes().get::<Option<String>>("owner").is_none());
+	    assert_eq!(Some(&String::from("blue")), loaded.attributes_mut().decode::<Option<String>>("owner").unwrap().get(1).as_ref());
+	    assert_eq!(-1e-300, *loaded.attributes_mut().decode::<f64>("height").unwrap().get(0));
+	    assert!(loaded.attributes_mut().decode::<String>("river").is_err());
+	    assert_eq!((7, true), *loaded.attributes_mut().decode::<(u64, bool)>("river").unwrap().get(3));
+	    assert_eq!(diagram, loaded);
+	}
+    }
+
+    #[test]
+    fn test_save_undecoded() {
+	// columns that were never decoded are saved again unchanged, in either format
+	let mut diagram = create_diagram();
+	diagram.add_attribute("value", Element::Face, (-3i64, 2.5)).set(0, (4, f64::INFINITY));
+	let loaded = Diagram::<()>::from_json(&diagram.to_json()).unwrap();
+	let mut reloaded = Diagram::<()>::from_binary(&loaded.to_binary()).unwrap();
+	assert_eq!((4, f64::INFINITY), *reloaded.attributes_mut().decode::<(i64, f64)>("value").unwrap().get(0));
+	assert_eq!((-3, 2.5), *reloaded.attributes_mut().decode::<(i64, f64)>("value").unwrap().default_value());
+    }
+
+    #[test]
+    fn test_relax() {
+	let mut diagram = create_diagram();
+	diagram.add_attribute("owner", Element::Face, 0u64).set(3, 9);
+	diagram.add_attribute("height", Element::Vertex, 1.0).fill(2.0);
+	let diagram = relax(diagram, 3, None).unwrap();
+	assert_eq!(&[0, 0, 0, 9, 0], diagram.attributes().get::<u64>("owner").unwrap().values());
+	let heights = diagram.attributes().get::<f64>("height").unwrap();
+	assert_eq!(diagram.vertices().len(), heights.len());
+	assert!(heights.values().iter().all(|height| *height == 1.0));
+    }
+
+    #[test]
+    fn test_edit() {
+	let mut diagram = create_diagram();
+	diagram.add_attribute("owner", Element::Face, 0u64).values_mut().copy_from_slice(&[10, 11, 12, 13, 14]);
+	diagram.add_attribute("height", Element::Vertex, 0.0);
+	diagram.add_attribute("wall", Element::HalfEdge, false);
+	let face_id = diagram.insert_site(40.0, 40.0, ()).unwrap();
+	assert_eq!(&[10, 11, 12, 13, 14, 0], diagram.attributes().get::<u64>("owner").unwrap().values());
+	assert_eq!(diagram.vertices().len(), diagram.attributes().get::<f64>("height").unwrap().len());
+	assert_eq!(diagram.half_edges().len(), diagram.attributes().get::<bool>("wall").unwrap().len());
+	diagram.attributes_mut().get_mut::<u64>("owner").unwrap().set(face_id, 15);
+	diagram.remove_site(1).unwrap();
+	// the last face takes over the id of the removed face
+	assert_eq!(&[10, 15, 12, 13, 14], diagram.attributes().get::<u64>("owner").unwrap().values());
+	assert_eq!(diagram.vertices().len(), diagram.attributes().get::<f64>("height").unwrap().len());
+	assert_eq!(diagram.half_edges().len(), diagram.attributes().get::<bool>("wall").unwrap().len());
+    }
+
+    #[test]
+    fn test_edit_keeps_unchanged_elements() {
+	let mut diagram = create_diagram();
+	let far_id = diagram.locate(85.0, 80.0).unwrap();
+	diagram.add_attribute("wall", Element::HalfEdge, false);
+	let far_half_edges: Vec<usize> = diagram.face_half_edges(far_id).map(|half_edge| half_edge.id()).collect();
+	for half_edge_id in far_half_edges.iter() {
+	    diagram.attributes_mut().get_mut::<bool>("wall").unwrap().set(*half_edge_id, true);
+	}
+	diagram.insert_site(10.0, 10.0, ()).unwrap();
+	let walls: Vec<usize> = diagram.half_edges_with::<bool>("wall").unwrap()
+	    .filter(|(_, wall)| **wall)
+	    .map(|(half_edge, _)| half_edge.face_id())
+	    .collect();
+	assert!(!walls.is_empty());
+	assert!(walls.iter().all(|face_id| *face_id == far_id));
+    }
+
+}
@@ -0,0 +1,747 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * Clips voronoi diagrams to arbitrary simple polygons, which may be concave and may have holes
+ *
+ * The boundary of a clipped cell consists of the parts of its edges inside the polygon and the parts of the
+ * polygon edges inside the cell. These parts are chained into loops, which become the pieces of the cell and
+ * the holes in those pieces. Every point is identified by how it was constructed, so neighbouring cells
+ * share vertices exactly and their half edges can be paired as twins.
+ */
+
+use crate::geom::{polygon_signed_area, BoundingBox};
+use crate::periodic::Wrap;
+use crate::vector::{Vector2, Vector2F64};
+use crate::voronoi::{Diagram, FaceParts, VoronoiError};
+
+use std::collections::HashMap;
+
+/**
+ * A simple polygon with holes; the outer ring and the holes may be concave but must not intersect themselves or each other
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClipPolygon {
+    outer: Vec<(f64, f64)>,
+    holes: Vec<Vec<(f64, f64)>>,
+}
+
+/**
+ * The signed area of a ring, positive if the ring is counter clockwise like the faces of a diagram
+ */
+fn ring_area(ring: &[(f64, f64)]) -> f64 {
+    let points: Vec<Vector2F64> = ring.iter().map(|(x, y)| Vector2F64::from_values(*x, *y)).collect();
+    polygon_signed_area(&points)
+}
+
+/**
+ * Removes repeated corners and orients the ring, so its area has the specified sign
+ */
+fn create_ring<I>(points: I, counter_clockwise: bool) -> Result<Vec<(f64, f64)>, VoronoiError> where I: IntoIterator<Item = (f64, f64)> {
+    let mut ring: Vec<(f64, f64)> = Vec::new();
+    for point in points {
+	if !point.0.is_finite() || !point.1.is_finite() {
+	    return Err(VoronoiError::DegenerateInput(String::from("polygon corners must be finite")));
+	}
+	if ring.last() != Some(&point) {
+	    ring.push(point);
+	}
+    }
+    if ring.len() > 1 && ring.first() == ring.last() {
+	ring.pop();
+    }
+    let area = ring_area(&ring);
+    if ring.len() < 3 || area == 0.0 {
+	return Err(VoronoiError::DegenerateInput(String::from("polygon rings must have at least three corners and a non zero area")));
+    }
+    if (area > 0.0) != counter_clockwise {
+	ring.reverse();
+    }
+    Ok(ring)
+}
+
+impl ClipPolygon {
+
+    /**
+     * Creates a polygon from its outer ring, in either orientation
+     */
+    pub fn new<I>(outer: I) -> Result<ClipPolygon, VoronoiError> where I: IntoIterator<Item = (f64, f64)> {
+	Ok(ClipPolygon {
+	    outer: create_ring(outer, true)?,
+	    holes: Vec::new(),
+	})
+    }
+
+    /**
+     * Cuts a hole into the polygon; the hole must lie inside the outer ring and must not touch other holes
+     */
+    pub fn add_hole<I>(&mut self, hole: I) -> Result<(), VoronoiError> where I: IntoIterator<Item = (f64, f64)> {
+	self.holes.push(create_ring(hole, false)?);
+	Ok(())
+    }
+
+    /**
+     * The corners of the outer ring, in counter clockwise order
+     */
+    pub fn outer(&self) -> &[(f64, f64)] {
+	&self.outer
+    }
+
+    /**
+     * The corners of every hole, in clockwise order
+     */
+    pub fn holes(&self) -> &[Vec<(f64, f64)>] {
+	&self.holes
+    }
+
+    /**
+     * The smallest box containing the polygon, which is a suitable bounds for a diagram that will be clipped to it
+     */
+    pub fn bounds(&self) -> BoundingBox {
+	let (left, right, top, bottom) = self.outer.iter().fold(
+	    (f64::INFINITY, f64::NEG_INFINITY, f64::INFINITY, f64::NEG_INFINITY),
+	    |(left, right, top, bottom), (x, y)| (left.min(*x), right.max(*x), top.min(*y), bottom.max(*y)));
+	BoundingBox::new(left, right, top, bottom)
+    }
+
+    /**
+     * The area inside the outer ring and outside the holes
+     */
+    pub fn area(&self) -> f64 {
+	ring_area(&self.outer) + self.holes.iter().map(|hole| ring_area(hole)).sum::<f64>()
+    }
+
+    /**
+     * Tests whether a point lies inside the outer ring and outside the holes
+     */
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+	self.edges().filter(|(start, end)| crosses_ray(*start, *end, (x, y))).count() % 2 == 1
+    }
+
+    /**
+     * The edges of all rings, with the polygon on their left side
+     */
+    fn edges(&self) -> impl Iterator<Item = ((f64, f64), (f64, f64))> + '_ {
+	std::iter::once(&self.outer).chain(self.holes.iter())
+	    .flat_map(|ring| (0..ring.len()).map(move |i| (ring[i], ring[(i + 1) % ring.len()])))
+    }
+
+}
+
+/**
+ * Tests whether an edge crosses the ray from a point towards increasing x, counting the lower end of the edge only
+ */
+fn crosses_ray(start: (f64, f64), end: (f64, f64), point: (f64, f64)) -> bool {
+    if (start.1 > point.1) == (end.1 > point.1) {
+	return false;
+    }
+    let x = start.0 + (point.1 - start.1) / (end.1 - start.1) * (end.0 - start.0);
+    x > point.0
+}
+
+fn cross(first: (f64, f64), second: (f64, f64)) -> f64 {
+    first.0 * second.1 - first.1 * second.0
+}
+
+fn sub(first: (f64, f64), second: (f64, f64)) -> (f64, f64) {
+    (first.0 - second.0, first.1 - second.1)
+}
+
+fn distance_to_segment(point: (f64, f64), start: (f64, f64), end: (f64, f64)) -> f64 {
+    let dir = sub(end, start);
+    let length_squared = dir.0 * dir.0 + dir.1 * dir.1;
+    let t = if length_squared > 0.0 {
+	((point.0 - start.0) * dir.0 + (point.1 - start.1) * dir.1) / length_squared
+    } else {
+	0.0
+    };
+    let t = t.clamp(0.0, 1.0);
+    (start.0 + t * dir.0 - point.0).hypot(start.1 + t * dir.1 - point.1)
+}
+
+/**
+ * Identifies a point of a clipped diagram by how it was constructed
+ */
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum PointKey {
+
+    /**
+     * A vertex of the diagram that is clipped
+     */
+    Vertex(usize),
+
+    /**
+     * A corner of the polygon, numbered through all rings
+     */
+    Corner(usize),
+
+    /**
+     * The crossing of the edge between two vertices, lowest id first, with a polygon edge
+     */
+    Crossing(usize, usize, usize),
+}
+
+/**
+ * A part of the boundary of a clipped cell
+ */
+struct Fragment {
+    start: (PointKey, (f64, f64)),
+    end: (PointKey, (f64, f64)),
+    clip_boundary: bool,
+}
+
+/**
+ * An edge of the clip polygon, with the numbers of its corners
+ */
+struct ClipEdge {
+    start: (f64, f64),
+    end: (f64, f64),
+    start_corner: usize,
+    end_corner: usize,
+}
+
+/**
+ * How a point lies relative to a region
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Side {
+    Inside,
+    Outside,
+    Boundary,
+}
+
+struct Clipper<'a> {
+    polygon: &'a ClipPolygon,
+    edges: Vec<ClipEdge>,
+    tolerance: f64,
+}
+
+impl<'a> Clipper<'a> {
+
+    fn new(polygon: &'a ClipPolygon, tolerance: f64) -> Clipper<'a> {
+	let mut edges = Vec::new();
+	let mut first_corner = 0;
+	for ring in std::iter::once(&polygon.outer).chain(polygon.holes.iter()) {
+	    for i in 0..ring.len() {
+		let j = (i + 1) % ring.len();
+		edges.push(ClipEdge {
+		    start: ring[i],
+		    end: ring[j],
+		    start_corner: first_corner + i,
+		    end_corner: first_corner + j,
+		});
+	    }
+	    first_corner += ring.len();
+	}
+	Clipper {
+	    polygon,
+	    edges,
+	    tolerance,
+	}
+    }
+
+    /**
+     * Finds how a point lies relative to the polygon, where only the specified edges can be close to the point
+     */
+    fn side_of_polygon(&self, point: (f64, f64), edge_ids: &[usize]) -> Side {
+	if edge_ids.iter().any(|edge_id| {
+	    let edge = &self.edges[*edge_id];
+	    distance_to_segment(point, edge.start, edge.end) <= self.tolerance
+	}) {
+	    Side::Boundary
+	} else if self.polygon.contains(point.0, point.1) {
+	    Side::Inside
+	} else {
+	    Side::Outside
+	}
+    }
+
+    /**
+     * Intersects the edge between two diagram vertices with a polygon edge
+     * Returns the key and position of the crossing and its parameters along both edges. The crossing is computed
+     * from the vertex with the lowest id, so both cells along the edge find exactly the same point.
+     */
+    fn crossing(&self, first: (usize, (f64, f64)), second: (usize, (f64, f64)), edge_id: usize) -> Option<(PointKey, (f64, f64), f64, f64)> {
+	let swapped = first.0 > second.0;
+	let ((start_id, start), (end_id, end)) = if swapped { (second, first) } else { (first, second) };
+	let edge = &self.edges[edge_id];
+	let dir = sub(end, start);
+	let edge_dir = sub(edge.end, edge.start);
+	let length = dir.0.hypot(dir.1);
+	let edge_length = edge_dir.0.hypot(edge_dir.1);
+	// collinear edges have no single crossing, the crossings of the edges next to them bound the overlap
+	let start_distance = cross(edge_dir, sub(start, edge.start)) / edge_length;
+	let end_distance = cross(edge_dir, sub(end, edge.start)) / edge_length;
+	if start_distance.abs() <= self.tolerance && end_distance.abs() <= self.tolerance {
+	    return None;
+	}
+	let denominator = cross(dir, edge_dir);
+	if denominator == 0.0 {
+	    return None;
+	}
+	let offset = sub(edge.start, start);
+	let t = cross(offset, edge_dir) / denominator;
+	let s = cross(offset, dir) / denominator;
+	let (t_tolerance, s_tolerance) = (self.tolerance / length, self.tolerance / edge_length);
+	if t < -t_tolerance || t > 1.0 + t_tolerance || s < -s_tolerance || s > 1.0 + s_tolerance {
+	    return None;
+	}
+	let (key, point) = if t <= t_tolerance {
+	    (PointKey::Vertex(start_id), start)
+	} else if t >= 1.0 - t_tolerance {
+	    (PointKey::Vertex(end_id), end)
+	} else if s <= s_tolerance {
+	    (PointKey::Corner(edge.start_corner), edge.start)
+	} else if s >= 1.0 - s_tolerance {
+	    (PointKey::Corner(edge.end_corner), edge.end)
+	} else {
+	    (PointKey::Crossing(start_id, end_id, edge_id), (start.0 + t * dir.0, start.1 + t * dir.1))
+	};
+	let t = t.clamp(0.0, 1.0);
+	Some((key, point, if swapped { 1.0 - t } else { t }, s.clamp(0.0, 1.0)))
+    }
+
+    /**
+     * Finds the parts of the boundary of the clipped cell, the cell is a counter clockwise convex polygon
+     */
+    fn clip_cell(&self, cell: &[(usize, (f64, f64))]) -> Vec<Fragment> {
+	let (left, right, top, bottom) = cell.iter().fold(
+	    (f64::INFINITY, f64::NEG_INFINITY, f64::INFINITY, f64::NEG_INFINITY),
+	    |(left, right, top, bottom), (_, (x, y))| (left.min(*x), right.max(*x), top.min(*y), bottom.max(*y)));
+	let edge_ids: Vec<usize> = self.edges.iter().enumerate()
+	    .filter(|(_, edge)| {
+		edge.start.0.max(edge.end.0) >= left - self.tolerance && edge.start.0.min(edge.end.0) <= right + self.tolerance
+		    && edge.start.1.max(edge.end.1) >= top - self.tolerance && edge.start.1.min(edge.end.1) <= bottom + self.tolerance
+	    })
+	    .map(|(edge_id, _)| edge_id)
+	    .collect();
+	let mut fragments = Vec::new();
+
+	// a cell away from the polygon edges lies completely inside or outside the polygon
+	if edge_ids.is_empty() {
+	    let (start, end) = (cell[0].1, cell[1 % cell.len()].1);
+	    if self.polygon.contains((start.0 + end.0) / 2.0, (start.1 + end.1) / 2.0) {
+		for i in 0..cell.len() {
+		    let (start_id, start) = cell[i];
+		    let (end_id, end) = cell[(i + 1) % cell.len()];
+		    fragments.push(Fragment { start: (PointKey::Vertex(start_id), start), end: (PointKey::Vertex(end_id), end), clip_boundary: false });
+		}
+	    }
+	    return fragments;
+	}
+
+	// the parts of the cell edges inside the polygon
+	for i in 0..cell.len() {
+	    let (start_id, start) = cell[i];
+	    let (end_id, end) = cell[(i + 1) % cell.len()];
+	    let mut points = vec![(0.0, PointKey::Vertex(start_id), start), (1.0, PointKey::Vertex(end_id), end)];
+	    for edge_id in edge_ids.iter() {
+		if let Some((key, point, t, _)) = self.crossing(cell[i], cell[(i + 1) % cell.len()], *edge_id) {
+		    points.push((t, key, point));
+		}
+	    }
+	    for pair in sort_points(points).windows(2) {
+		let (start, end) = (pair[0], pair[1]);
+		let middle = ((start.1.0 + end.1.0) / 2.0, (start.1.1 + end.1.1) / 2.0);
+		// parts on the boundary of the polygon are added as polygon edges, if at all
+		if self.side_of_polygon(middle, &edge_ids) == Side::Inside {
+		    fragments.push(Fragment { start, end, clip_boundary: false });
+		}
+	    }
+	}
+
+	// the parts of the polygon edges inside the cell
+	for edge_id in edge_ids.iter() {
+	    let edge = &self.edges[*edge_id];
+	    let corner_key = |corner: usize, point: (f64, f64)| {
+		match cell.iter().find(|(_, vertex)| (vertex.0 - point.0).hypot(vertex.1 - point.1) <= self.tolerance) {
+		    Some((vertex_id, vertex)) => (PointKey::Vertex(*vertex_id), *vertex),
+		    None => (PointKey::Corner(corner), point),
+		}
+	    };
+	    let (start_key, start) = corner_key(edge.start_corner, edge.start);
+	    let (end_key, end) = corner_key(edge.end_corner, edge.end);
+	    let mut points = vec![(0.0, start_key, start), (1.0, end_key, end)];
+	    for i in 0..cell.len() {
+		if let Some((key, point, _, s)) = self.crossing(cell[i], cell[(i + 1) % cell.len()], *edge_id) {
+		    points.push((s, key, point));
+		}
+	    }
+	    let edge_dir = sub(edge.end, edge.start);
+	    for pair in sort_points(points).windows(2) {
+		let (start, end) = (pair[0], pair[1]);
+		let middle = ((start.1.0 + end.1.0) / 2.0, (start.1.1 + end.1.1) / 2.0);
+		let keep = match side_of_cell(cell, middle, self.tolerance) {
+		    (Side::Inside, _) => true,
+		    // a polygon edge along a cell edge only bounds the clipped cell if the polygon lies on the same side
+		    (Side::Boundary, Some(dir)) => dir.0 * edge_dir.0 + dir.1 * edge_dir.1 > 0.0,
+		    _ => false,
+		};
+		if keep {
+		    fragments.push(Fragment { start, end, clip_boundary: true });
+		}
+	    }
+	}
+	fragments
+    }
+
+}
+
+/**
+ * Sorts points along an edge by their parameter and removes repeated points
+ */
+fn sort_points(mut points: Vec<(f64, PointKey, (f64, f64))>) -> Vec<(PointKey, (f64, f64))> {
+    points.sort_by(|first, second| first.0.partial_cmp(&second.0).unwrap_or(std::cmp::Ordering::Equal));
+    let mut result: Vec<(PointKey, (f64, f64))> = Vec::with_capacity(points.len());
+    for (_, key, point) in points {
+	if result.iter().all(|(other_key, _)| *other_key != key) {
+	    result.push((key, point));
+	}
+    }
+    result
+}
+
+/**
+ * Finds how a point lies relative to a counter clockwise convex polygon, and the direction of the edge it lies on
+ */
+fn side_of_cell(cell: &[(usize, (f64, f64))], point: (f64, f64), tolerance: f64) -> (Side, Option<(f64, f64)>) {
+    let mut side = Side::Inside;
+    let mut boundary_dir = None;
+    for i in 0..cell.len() {
+	let start = cell[i].1;
+	let dir = sub(cell[(i + 1) % cell.len()].1, start);
+	let length = dir.0.hypot(dir.1);
+	if length == 0.0 {
+	    continue;
+	}
+	let distance = cross(dir, sub(point, start)) / length;
+	if distance < -tolerance {
+	    return (Side::Outside, None);
+	} else if distance <= tolerance {
+	    side = Side::Boundary;
+	    boundary_dir = Some(dir);
+	}
+    }
+    (side, boundary_dir)
+}
+
+/**
+ * Chains the fragments into closed loops; where several fragments continue a loop, the one turning furthest
+ * towards the inside is taken, which keeps pieces that touch at a single point apart
+ */
+fn chain_fragments(fragments: &[Fragment]) -> Result<Vec<Vec<usize>>, VoronoiError> {
+    let mut starts: HashMap<PointKey, Vec<usize>> = HashMap::new();
+    for (id, fragment) in fragments.iter().enumerate() {
+	starts.entry(fragment.start.0).or_default().push(id);
+    }
+    let direction = |id: usize| sub(fragments[id].end.1, fragments[id].start.1);
+    let mut used = vec![false; fragments.len()];
+    let mut loops = Vec::new();
+    for first_id in 0..fragments.len() {
+	if used[first_id] {
+	    continue;
+	}
+	used[first_id] = true;
+	let mut fragment_ids = vec![first_id];
+	loop {
+	    let current_id = *fragment_ids.last().unwrap_or(&first_id);
+	    let end_key = fragments[current_id].end.0;
+	    let incoming = direction(current_id);
+	    let next_id = starts.get(&end_key).into_iter().flatten()
+		.filter(|id| !used[**id] || **id == first_id)
+		.map(|id| {
+		    let outgoing = direction(*id);
+		    (*id, cross(incoming, outgoing).atan2(incoming.0 * outgoing.0 + incoming.1 * outgoing.1))
+		})
+		.fold(None, |best: Option<(usize, f64)>, candidate| match best {
+		    Some(best) if best.1 >= candidate.1 => Some(best),
+		    _ => Some(candidate),
+		})
+		.map(|(id, _)| id)
+		.ok_or_else(|| VoronoiError::topology("the boundary of a clipped cell does not close"))?;
+	    if next_id == first_id {
+		break;
+	    }
+	    used[next_id] = true;
+	    fragment_ids.push(next_id);
+	}
+	loops.push(fragment_ids);
+    }
+    Ok(loops)
+}
+
+/**
+ * Tests whether a point lies inside a loop of fragments
+ */
+fn loop_contains(fragments: &[Fragment], fragment_ids: &[usize], point: (f64, f64)) -> bool {
+    fragment_ids.iter().filter(|id| crosses_ray(fragments[**id].start.1, fragments[**id].end.1, point)).count() % 2 == 1
+}
+
+impl<T> Diagram<T> where T: Clone {
+
+    /**
+     * Clips the diagram to a polygon
+     * Cells outside the polygon are removed, and every piece of a cell inside the polygon becomes a face with the
+     * site and data of the cell. Faces can be concave and can have holes, and the half edges along the polygon
+     * have no twin and are marked as clip boundary. The polygon is also clipped to the bounds of the diagram.
+     * Periodic diagrams cannot be clipped.
+     */
+    pub fn clip(&self, polygon: &ClipPolygon) -> Result<Diagram<T>, VoronoiError> {
+	if self.wrap() != Wrap::None {
+	    return Err(VoronoiError::Unsupported(String::from("periodic diagrams cannot be clipped")));
+	}
+	let bounds = self.bounds();
+	let clipper = Clipper::new(polygon, 1e-9 * (bounds.width() + bounds.height()));
+	let mut vertex_ids: HashMap<PointKey, usize> = HashMap::new();
+	let mut vertices = Vec::new();
+	let mut half_edges = Vec::new();
+	let mut half_edge_keys = Vec::new();
+	let mut clip_boundaries = Vec::new();
+	let mut faces = Vec::new();
+
+	for face in self.faces().iter() {
+	    let cell: Vec<(usize, (f64, f64))> = self.face_vertices(face.id())
+		.map(|vertex| (vertex.id(), (vertex.x(), vertex.y())))
+		.collect();
+	    let fragments = clipper.clip_cell(&cell);
+	    let mut pieces = Vec::new();
+	    let mut holes = Vec::new();
+	    for fragment_ids in chain_fragments(&fragments)? {
+		let (area, perimeter) = fragment_ids.iter().fold((0.0, 0.0), |(area, perimeter), id| {
+		    let (start, end) = (fragments[*id].start.1, fragments[*id].end.1);
+		    (area + cross(start, end) / 2.0, perimeter + (end.0 - start.0).hypot(end.1 - start.1))
+		});
+		// loops thinner than the tolerance are rounding errors along the polygon
+		if area.abs() <= clipper.tolerance * perimeter {
+		    continue;
+		}
+		if area > 0.0 {
+		    pieces.push((fragment_ids, Vec::new()));
+		} else {
+		    holes.push(fragment_ids);
+		}
+	    }
+	    for hole in holes {
+		let start = fragments[hole[0]].start.1;
+		let end = fragments[hole[0]].end.1;
+		let middle = ((start.0 + end.0) / 2.0, (start.1 + end.1) / 2.0);
+		let piece = pieces.iter_mut()
+		    .find(|(fragment_ids, _)| loop_contains(&fragments, fragment_ids, middle))
+		    .ok_or_else(|| VoronoiError::topology("a hole of a clipped cell lies outside its pieces"))?;
+		piece.1.push(hole);
+	    }
+
+	    for (outer, holes) in pieces {
+		let face_id = faces.len();
+		let mut starts = Vec::with_capacity(holes.len() + 1);
+		for fragment_ids in std::iter::once(&outer).chain(holes.iter()) {
+		    let first_id = half_edges.len();
+		    starts.push(first_id);
+		    for (i, fragment_id) in fragment_ids.iter().enumerate() {
+			let fragment = &fragments[*fragment_id];
+			let start_id = *vertex_ids.entry(fragment.start.0).or_insert_with(|| {
+			    vertices.push(fragment.start.1);
+			    vertices.len() - 1
+			});
+			let prev_id = first_id + (i + fragment_ids.len() - 1) % fragment_ids.len();
+			let next_id = first_id + (i + 1) % fragment_ids.len();
+			half_edges.push((face_id, start_id, None, prev_id, next_id, fragment.clip_boundary));
+			half_edge_keys.push((fragment.start.0, fragment.end.0));
+			clip_boundaries.push(fragment.clip_boundary);
+		    }
+		}
+		faces.push(FaceParts {
+		    x: face.x(),
+		    y: face.y(),
+		    start_id: starts[0],
+		    site_id: face.site_id(),
+		    hole_start_ids: starts[1..].to_vec(),
+		    weight: face.weight(),
+		    data: face.data().clone(),
+		});
+	    }
+	}
+
+	// half edges along cell edges are paired with the half edge running the other way
+	let ids_by_key: HashMap<(PointKey, PointKey), usize> = half_edge_keys.iter().enumerate()
+	    .filter(|(id, _)| !clip_boundaries[*id])
+	    .map(|(id, key)| (*key, id))
+	    .collect();
+	for (id, (start, end)) in half_edge_keys.iter().enumerate() {
+	    if !clip_boundaries[id] {
+		let (face_id, start_id, _, prev_id, next_id, clip_boundary) = half_edges[id];
+		half_edges[id] = (face_id, start_id, ids_by_key.get(&(*end, *start)).copied(), prev_id, next_id, clip_boundary);
+	    }
+	}
+	Ok(Diagram::from_face_parts(bounds.clone(), vertices, half_edges, faces))
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::sites;
+    use crate::voronoi::DiagramBuilder;
+
+    fn create_diagram(bounds: BoundingBox, seed: u64) -> Diagram<usize> {
+	let mut builder = DiagramBuilder::with_data(bounds.clone());
+	builder.add_sites_with_data(sites::uniform(&bounds, 200, seed).into_iter().enumerate().map(|(id, (x, y))| (x, y, id)));
+	builder.build().unwrap()
+    }
+
+    fn face_area<T>(diagram: &Diagram<T>, face_id: usize) -> f64 {
+	polygon_signed_area(&diagram.face_polygon(face_id))
+	    + diagram.hole_polygons(face_id).iter().map(|hole| polygon_signed_area(hole)).sum::<f64>()
+    }
+
+    fn total_area<T>(diagram: &Diagram<T>) -> f64 {
+	(0..diagram.faces().len()).map(|face_id| face_area(diagram, face_id)).sum()
+    }
+
+    fn clip_boundary_length<T>(diagram: &Diagram<T>) -> f64 {
+	diagram.half_edges().iter()
+	    .filter(|half_edge| half_edge.is_clip_boundary())
+	    .map(|half_edge| {
+		let start = &diagram.vertices()[half_edge.start_id()];
+		let end = &diagram.vertices()[diagram.half_edges()[half_edge.next_id()].start_id()];
+		(end.x() - start.x()).hypot(end.y() - start.y())
+	    })
+	    .sum()
+    }
+
+    /**
+     * Checks that every face lies inside the polygon and inside the cell of its site
+     */
+    fn assert_clipped(original: &Diagram<usize>, clipped: &Diagram<usize>, polygon: &ClipPolygon) {
+	assert_eq!(Ok(()), clipped.validate());
+	assert!((total_area(clipped) - polygon.area()).abs() < 1e-6 * polygon.area());
+	for face in clipped.faces().iter() {
+	    assert_eq!(face.site_id(), *face.data());
+	    assert!(face_area(clipped, face.id()) > 0.0);
+	    let cell = original.face_polygon(face.site_id());
+	    for half_edge in clipped.face_half_edges(face.id()) {
+		let start = &clipped.vertices()[half_edge.start_id()];
+		let end = &clipped.vertices()[clipped.half_edges()[half_edge.next_id()].start_id()];
+		let middle = ((start.x() + end.x()) / 2.0, (start.y() + end.y()) / 2.0);
+		assert!(!half_edge.is_clip_boundary() || half_edge.twin_id().is_none());
+		assert!(half_edge.is_clip_boundary() || polygon.contains(middle.0, middle.1));
+		for i in 0..cell.len() {
+		    let (a, b) = (&cell[i], &cell[(i + 1) % cell.len()]);
+		    let side = (b.get_x() - a.get_x()) * (start.y() - a.get_y()) - (b.get_y() - a.get_y()) * (start.x() - a.get_x());
+		    assert!(side >= -1e-6);
+		}
+	    }
+	}
+    }
+
+    #[test]
+    fn test_clip_polygon() {
+	assert!(ClipPolygon::new(vec![(0.0, 0.0), (1.0, 1.0), (2.0, 2.0)]).is_err());
+	assert!(ClipPolygon::new(vec![(0.0, 0.0), (1.0, 0.0)]).is_err());
+	// the orientation is normalized, and a repeated first corner is removed
+	let mut polygon = ClipPolygon::new(vec![(0.0, 0.0), (0.0, 10.0), (10.0, 10.0), (10.0, 0.0), (0.0, 0.0)]).unwrap();
+	assert_eq!(&[(10.0, 0.0), (10.0, 10.0), (0.0, 10.0), (0.0, 0.0)], polygon.outer());
+	polygon.add_hole(vec![(2.0, 2.0), (4.0, 2.0), (4.0, 4.0), (2.0, 4.0)]).unwrap();
+	assert_eq!(96.0, polygon.area());
+	assert!(polygon.contains(1.0, 1.0));
+	assert!(!polygon.contains(3.0, 3.0));
+	assert!(!polygon.contains(11.0, 3.0));
+	assert_eq!(BoundingBox::new(0.0, 10.0, 0.0, 10.0), polygon.bounds());
+    }
+
+    #[test]
+    fn test_clip_to_bounds() {
+	let bounds = BoundingBox::new(0.0, 100.0, 0.0, 100.0);
+	let diagram = create_diagram(bounds.clone(), 1);
+	let polygon = ClipPolygon::new(vec![(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)]).unwrap();
+	let clipped = diagram.clip(&polygon).unwrap();
+	assert_clipped(&diagram, &clipped, &polygon);
+	assert_eq!(diagram.faces().len(), clipped.faces().len());
+	assert_eq!(diagram.vertices().len(), clipped.vertices().len());
+	assert!((clip_boundary_length(&clipped) - 400.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_clip_concave() {
+	let bounds = BoundingBox::new(0.0, 100.0, 0.0, 100.0);
+	let diagram = create_diagram(bounds, 2);
+	let polygon = ClipPolygon::new(vec![(5.0, 5.0), (95.0, 10.0), (90.0, 95.0), (50.0, 30.0), (10.0, 90.0)]).unwrap();
+	let clipped = diagram.clip(&polygon).unwrap();
+	assert_clipped(&diagram, &clipped, &polygon);
+	assert!(clipped.faces().len() < diagram.faces().len());
+	let perimeter: f64 = (0..5).map(|i| {
+	    let (a, b) = (polygon.outer()[i], polygon.outer()[(i + 1) % 5]);
+	    (b.0 - a.0).hypot(b.1 - a.1)
+	}).sum();
+	assert!((clip_boundary_length(&clipped) - perimeter).abs() < 1e-6);
+	for half_edge in clipped.half_edges().iter().filter(|half_edge| !half_edge.is_clip_boundary()) {
+	    assert!(half_edge.twin_id().is_some());
+	}
+    }
+
+    #[test]
+    fn test_clip_with_holes() {
+	let bounds = BoundingBox::new(0.0, 100.0, 0.0, 100.0);
+	let diagram = create_diagram(bounds, 3);
+	let mut polygon = ClipPolygon::new(vec![(2.0, 2.0), (98.0, 2.0), (98.0, 98.0), (2.0, 98.0)]).unwrap();
+	polygon.add_hole(vec![(20.0, 20.0), (60.0, 25.0), (40.0, 70.0)]).unwrap();
+	let clipped = diagram.clip(&polygon).unwrap();
+	assert_clipped(&diagram, &clipped, &polygon);
+	let hole_perimeter = 40.0f64.hypot(5.0) + 20.0f64.hypot(45.0) + 20.0f64.hypot(50.0);
+	assert!((clip_boundary_length(&clipped) - 384.0 - hole_perimeter).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_hole_inside_cell() {
+	let mut builder = DiagramBuilder::with_data(BoundingBox::new(0.0, 100.0, 0.0, 100.0));
+	builder.add_sites_with_data(vec![(25.0, 50.0, 0), (75.0, 50.0, 1)]);
+	let diagram = builder.build().unwrap();
+	let mut polygon = ClipPolygon::new(vec![(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)]).unwrap();
+	polygon.add_hole(vec![(10.0, 40.0), (20.0, 40.0), (20.0, 60.0), (10.0, 60.0)]).unwrap();
+	let clipped = diagram.clip(&polygon).unwrap();
+	assert_clipped(&diagram, &clipped, &polygon);
+	assert_eq!(2, clipped.faces().len());
+	assert_eq!(1, clipped.faces()[0].hole_start_ids().len());
+	assert!(clipped.faces()[1].hole_start_ids().is_empty());
+	assert_eq!(4, clipped.hole_polygons(0)[0].len());
+	assert!((face_area(&clipped, 0) - 4800.0).abs() < 1e-9);
+	assert!(clipped.is_clipped());
+	assert!(!diagram.is_clipped());
+    }
+
+    #[test]
+    fn test_split_cell() {
+	// a U shaped polygon cuts the cell of the left site into two pieces
+	let mut builder = DiagramBuilder::with_data(BoundingBox::new(0.0, 100.0, 0.0, 100.0));
+	builder.add_sites_with_data(vec![(50.0, 60.0, 0), (50.0, 70.0, 1)]);
+	let diagram = builder.build().unwrap();
+	let polygon = ClipPolygon::new(vec![(10.0, 10.0), (30.0, 10.0), (30.0, 70.0), (70.0, 70.0), (70.0, 10.0), (90.0, 10.0),
+					    (90.0, 90.0), (10.0, 90.0)]).unwrap();
+	let clipped = diagram.clip(&polygon).unwrap();
+	assert_clipped(&diagram, &clipped, &polygon);
+	// the cell of the first site lies above y = 65, where it only contains the two arms of the U
+	let site_ids: Vec<usize> = clipped.faces().iter().map(|face| face.site_id()).collect();
+	assert_eq!(vec![0, 0, 1], site_ids);
+	assert_eq!(clipped.faces()[0].data(), clipped.faces()[1].data());
+	assert!((face_area(&clipped, 0) - 1100.0).abs() < 1e-9);
+	assert!((face_area(&clipped, 1) - 1100.0).abs() < 1e-9);
+	assert!((face_area(&clipped, 2) - 1600.0 - 200.0).abs() < 1e-9);
+    }
+
+}
@@ -0,0 +1,500 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * Derives the delaunay triangulation of the sites from a voronoi diagram
+ */
+
+use crate::periodic::Wrap;
+use crate::predicates::{incircle, orient2d};
+use crate::vector::{Vector2, Vector2F64};
+use crate::voronoi::{Diagram, VoronoiError};
+
+use std::collections::{HashMap, HashSet};
+
+/**
+ * A triangle between three sites of a diagram
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Triangle {
+
+    /**
+     * The ids of the faces whose sites are the corners of the triangle, ordered like the half edges of a face
+     */
+    face_ids: [usize; 3],
+
+    /**
+     * The triangles sharing an edge with this triangle, where neighbour i lies across the edge from corner i to corner i + 1
+     */
+    neighbour_ids: [Option<usize>; 3],
+}
+
+impl Triangle {
+
+    pub fn face_ids(&self) -> &[usize; 3] {
+	&self.face_ids
+    }
+
+    pub fn neighbour_ids(&self) -> &[Option<usize>; 3] {
+	&self.neighbour_ids
+    }
+
+}
+
+/**
+ * The delaunay triangulation of the sites of a diagram
+ *
+ * Every vertex of the diagram inside the bounds becomes a triangle, or a fan of triangles if more than three
+ * sites lie on the same circle. Triangles whose circumcenter lies outside the bounds have no vertex in the diagram,
+ * so they are recovered by wrapping around the edges of the mesh that are not yet shared by two triangles, until
+ * the triangles cover the convex hull of the sites. For power diagrams this is the weighted delaunay triangulation.
+ */
+#[derive(Debug, PartialEq)]
+pub struct Triangulation {
+
+    /**
+     * The triangles of the mesh
+     */
+    triangles: Vec<Triangle>,
+
+    /**
+     * The neighbouring faces of each face, in the order of its half edges, followed by the neighbours
+     * whose common edge lies outside the bounds
+     */
+    neighbours: Vec<Vec<usize>>,
+}
+
+impl Triangulation {
+
+    /**
+     * Creates the triangulation from the twin half edges of the diagram
+     * Clipped and periodic diagrams are not supported, because their clip boundaries and seams hide which
+     * sites are neighbours.
+     */
+    pub fn new<T>(diagram: &Diagram<T>) -> Result<Triangulation, VoronoiError> {
+	if diagram.is_clipped() || diagram.wrap() != Wrap::None {
+	    return Err(VoronoiError::Unsupported(String::from("only unclipped and not periodic diagrams can be triangulated")));
+	}
+	let mut neighbours: Vec<Vec<usize>> = (0..diagram.faces().len()).map(|face_id| {
+	    let mut face_ids = Vec::new();
+	    for neighbour_id in diagram.neighbours(face_id) {
+		if !face_ids.contains(&neighbour_id) {
+		    face_ids.push(neighbour_id);
+		}
+	    }
+	    face_ids
+	}).collect();
+	let mut triangles = Triangulation::create_triangles(diagram);
+	let first_filled = triangles.len();
+	Triangulation::fill_hull(diagram, &mut triangles, &neighbours);
+	for triangle in triangles[first_filled..].iter() {
+	    for i in 0..3 {
+		let (first_id, second_id) = (triangle.face_ids[i], triangle.face_ids[(i + 1) % 3]);
+		if !neighbours[first_id].contains(&second_id) {
+		    neighbours[first_id].push(second_id);
+		    neighbours[second_id].push(first_id);
+		}
+	    }
+	}
+	Triangulation::link_triangles(&mut triangles);
+	Ok(Triangulation {
+	    triangles,
+	    neighbours,
+	})
+    }
+
+    /**
+     * Creates the triangles around the vertices of the diagram
+     */
+    fn create_triangles<T>(diagram: &Diagram<T>) -> Vec<Triangle> {
+	let mut outgoing_ids = vec![None; diagram.vertices().len()];
+	for half_edge in diagram.half_edges().iter() {
+	    if outgoing_ids[half_edge.start_id()].is_none() {
+		outgoing_ids[half_edge.start_id()] = Some(half_edge.id());
+	    }
+	}
+
+	let mut triangles = Vec::new();
+	for start_id in outgoing_ids.into_iter().flatten() {
+	    if let Some(face_ids) = Triangulation::faces_around_vertex(diagram, start_id) {
+		// sites on the same circle form a convex polygon, which is split into a fan
+		for i in 1..face_ids.len() - 1 {
+		    triangles.push(Triangle {
+			face_ids: [face_ids[0], face_ids[i], face_ids[i + 1]],
+			neighbour_ids: [None; 3],
+		    });
+		}
+	    }
+	}
+	triangles
+    }
+
+    /**
+     * Adds the triangles whose circumcenter lies outside the bounds
+     * Every edge of a triangle without a triangle on its other side is a delaunay edge, so the triangle on that side
+     * is the one through the site with the emptiest circle, if there is a site on that side at all. The faces of
+     * a diagram without any vertex inside the bounds still share edges, which start the wrapping.
+     */
+    fn fill_hull<T>(diagram: &Diagram<T>, triangles: &mut Vec<Triangle>, neighbours: &[Vec<usize>]) {
+	let sites: Vec<Vector2F64> = (0..diagram.faces().len()).map(|face_id| diagram.site(face_id)).collect();
+	let weights: Vec<f64> = diagram.faces().iter().map(|face| face.weight()).collect();
+	let weighted = diagram.is_weighted();
+	// positive if the fourth site lies inside the circle through the first three, where weights shrink the circles
+	let in_circle = |a: usize, b: usize, c: usize, d: usize| {
+	    if weighted {
+		power_incircle(&sites, &weights, [a, b, c, d])
+	    } else {
+		incircle(&sites[a], &sites[b], &sites[c], &sites[d])
+	    }
+	};
+
+	let mut edges = HashSet::new();
+	let mut open = Vec::new();
+	for triangle in triangles.iter() {
+	    for i in 0..3 {
+		edges.insert((triangle.face_ids[i], triangle.face_ids[(i + 1) % 3]));
+	    }
+	}
+	for triangle in triangles.iter() {
+	    for i in 0..3 {
+		let edge = (triangle.face_ids[(i + 1) % 3], triangle.face_ids[i]);
+		if !edges.contains(&edge) {
+		    open.push(edge);
+		}
+	    }
+	}
+	if triangles.is_empty() {
+	    if let Some(neighbour_id) = neighbours.first().and_then(|face_ids| face_ids.first()) {
+		open.push((0, *neighbour_id));
+		open.push((*neighbour_id, 0));
+	    }
+	}
+
+	while let Some((a, b)) = open.pop() {
+	    if edges.contains(&(a, b)) {
+		continue;
+	    }
+	    let mut best = None;
+	    for c in 0..sites.len() {
+		if orient2d(&sites[a], &sites[b], &sites[c]) > 0.0 && best.is_none_or(|best| in_circle(a, b, best, c) > 0.0) {
+		    best = Some(c);
+		}
+	    }
+	    let best = match best {
+		Some(best) => best,
+		// nothing lies on the left, so this is an edge of the convex hull
+		None => continue,
+	    };
+	    // sites on the same circle allow several triangles, so pick one that does not overlap the existing ones
+	    let is_free = |c: usize| !edges.contains(&(b, c)) && !edges.contains(&(c, a));
+	    let chosen = if is_free(best) {
+		Some(best)
+	    } else {
+		(0..sites.len()).find(|c| orient2d(&sites[a], &sites[b], &sites[*c]) > 0.0 && in_circle(a, b, best, *c) == 0.0 && is_free(*c))
+	    };
+	    if let Some(c) = chosen {
+		edges.extend([(a, b), (b, c), (c, a)].iter().copied());
+		open.push((c, b));
+		open.push((a, c));
+		triangles.push(Triangle {
+		    face_ids: [a, b, c],
+		    neighbour_ids: [None; 3],
+		});
+	    }
+	}
+    }
+
+    fn link_triangles(triangles: &mut [Triangle]) {
+	let mut edges = HashMap::new();
+	for (id, triangle) in triangles.iter().enumerate() {
+	    for i in 0..3 {
+		edges.insert((triangle.face_ids[i], triangle.face_ids[(i + 1) % 3]), id);
+	    }
+	}
+	for triangle in triangles.iter_mut() {
+	    for i in 0..3 {
+		triangle.neighbour_ids[i] = edges.get(&(triangle.face_ids[(i + 1) % 3], triangle.face_ids[i])).copied();
+	    }
+	}
+    }
+
+    /**
+     * Lists the faces around the start vertex of a half edge in counter clockwise order,
+     * or nothing if the vertex lies on the bounds of the diagram
+     */
+    fn faces_around_vertex<T>(diagram: &Diagram<T>, start_id: usize) -> Option<Vec<usize>> {
+	let half_edges = diagram.half_edges();
+	let mut face_ids = Vec::new();
+	let mut half_edge_id = start_id;
+	loop {
+	    let half_edge = &half_edges[half_edge_id];
+	    face_ids.push(half_edge.face_id());
+	    // the previous half edge ends at the vertex, so its twin starts there in the next face
+	    half_edge_id = half_edges[half_edge.prev_id()].twin_id()?;
+	    if half_edge_id == start_id {
+		break;
+	    } else if face_ids.len() > half_edges.len() {
+		return None;
+	    }
+	}
+	if face_ids.len() < 3 {
+	    None
+	} else {
+	    Some(face_ids)
+	}
+    }
+
+    pub fn triangles(&self) -> &[Triangle] {
+	&self.triangles
+    }
+
+    /**
+     * The faces that share an edge with the face with the specified id, including edges outside the bounds
+     */
+    pub fn neighbours(&self, face_id: usize) -> &[usize] {
+	&self.neighbours[face_id]
+    }
+
+    /**
+     * Returns true if the faces with the specified ids share an edge, including edges outside the bounds
+     */
+    pub fn are_neighbours(&self, first_id: usize, second_id: usize) -> bool {
+	self.neighbours[first_id].contains(&second_id)
+    }
+
+}
+
+/**
+ * The in circle test of the weighted sites with the specified ids, which lifts every site by its squared distance
+ * to the last site minus its weight
+ */
+fn power_incircle(sites: &[Vector2F64], weights: &[f64], ids: [usize; 4]) -> f64 {
+    let d = &sites[ids[3]];
+    let rows: Vec<(f64, f64, f64)> = ids[..3].iter().map(|id| {
+	let dx = sites[*id].get_x() - d.get_x();
+	let dy = sites[*id].get_y() - d.get_y();
+	(dx, dy, dx * dx + dy * dy - weights[*id] + weights[ids[3]])
+    }).collect();
+    let (a, b, c) = (rows[0], rows[1], rows[2]);
+    a.2 * (b.0 * c.1 - c.0 * b.1) + b.2 * (c.0 * a.1 - a.0 * c.1) + c.2 * (a.0 * b.1 - b.0 * a.1)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::clip::ClipPolygon;
+    use crate::geom::{circle_through_points, BoundingBox};
+    use crate::power::PowerDiagramBuilder;
+    use crate::random::{random_sites, Random};
+    use crate::voronoi::DiagramBuilder;
+
+    /**
+     * Checks that the triangles are counter clockwise and linked, and that their area is the area of the convex hull,
+     * whose edges are the pairs of sites with all other sites on their left
+     */
+    fn assert_covers_hull<T>(diagram: &Diagram<T>, triangulation: &Triangulation) {
+	let sites: Vec<Vector2F64> = (0..diagram.faces().len()).map(|face_id| diagram.site(face_id)).collect();
+	let cross = |a: &Vector2F64, b: &Vector2F64| a.get_x() * b.get_y() - a.get_y() * b.get_x();
+	let mut hull_area = 0.0;
+	for first in 0..sites.len() {
+	    for second in 0..sites.len() {
+		if first != second && (0..sites.len()).all(|id| id == first || id == second || orient2d(&sites[first], &sites[second], &sites[id]) > 0.0) {
+		    hull_area += cross(&sites[first], &sites[second]) / 2.0;
+		}
+	    }
+	}
+	let mut area = 0.0;
+	for (id, triangle) in triangulation.triangles().iter().enumerate() {
+	    let ids = triangle.face_ids();
+	    assert!(orient2d(&sites[ids[0]], &sites[ids[1]], &sites[ids[2]]) > 0.0);
+	    for i in 0..3 {
+		area += cross(&sites[ids[i]], &sites[ids[(i + 1) % 3]]) / 2.0;
+		assert!(triangulation.are_neighbours(ids[i], ids[(i + 1) % 3]));
+		if let Some(neighbour_id) = triangle.neighbour_ids()[i] {
+		    assert!(triangulation.triangles()[neighbour_id].neighbour_ids().contains(&Some(id)));
+		}
+	    }
+	}
+	assert!((hull_area - area).abs() <= 1e-9 * hull_area.abs().max(1.0));
+    }
+
+    #[test]
+    fn test_delaunay_binary() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 1000.0, 0.0, 1000.0));
+	builder.add_site(100.0, 100.0);
+	builder.add_site(900.0, 900.0);
+	let triangulation = builder.build().unwrap().delaunay().unwrap();
+	assert!(triangulation.triangles().is_empty());
+	assert_eq!(&[1], triangulation.neighbours(0));
+	assert_eq!(&[0], triangulation.neighbours(1));
+    }
+
+    #[test]
+    fn test_delaunay_single_triangle() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 1000.0, 0.0, 1000.0));
+	builder.add_site(300.0, 300.0);
+	builder.add_site(700.0, 350.0);
+	builder.add_site(500.0, 700.0);
+	let triangulation = builder.build().unwrap().delaunay().unwrap();
+	assert_eq!(1, triangulation.triangles().len());
+	let triangle = &triangulation.triangles()[0];
+	let mut face_ids = triangle.face_ids().to_vec();
+	face_ids.sort_unstable();
+	assert_eq!(vec![0, 1, 2], face_ids);
+	assert_eq!(&[None, None, None], triangle.neighbour_ids());
+	for face_id in 0..3 {
+	    assert_eq!(2, triangulation.neighbours(face_id).len());
+	}
+    }
+
+    #[test]
+    fn test_delaunay_square() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 1000.0, 0.0, 1000.0));
+	builder.add_sites(vec![(250.0, 250.0), (750.0, 250.0), (750.0, 750.0), (250.0, 750.0)]);
+	let diagram = builder.build().unwrap();
+	let triangulation = diagram.delaunay().unwrap();
+	assert_eq!(2, triangulation.triangles().len());
+	let first = &triangulation.triangles()[0];
+	let second = &triangulation.triangles()[1];
+	assert_eq!(1, first.neighbour_ids().iter().filter(|id| **id == Some(1)).count());
+	assert_eq!(1, second.neighbour_ids().iter().filter(|id| **id == Some(0)).count());
+	// the diagonal sites only meet in a single vertex
+	assert!(!triangulation.are_neighbours(0, 2));
+	assert!(triangulation.are_neighbours(0, 1));
+    }
+
+    #[test]
+    fn test_delaunay_random() {
+	let bounds = BoundingBox::new(0.0, 1.0, 0.0, 1.0);
+	for seed in 0..10 {
+	    let mut builder = DiagramBuilder::new(bounds.clone());
+	    builder.add_sites(random_sites(40, seed, &bounds));
+	    let diagram = builder.build().unwrap();
+	    let triangulation = diagram.delaunay().unwrap();
+	    let count = diagram.faces().len();
+
+	    let mut expected = Vec::new();
+	    for first in 0..count {
+		for second in (first + 1)..count {
+		    for third in (second + 1)..count {
+			let (center, radius) = circle_through_points(&diagram.site(first), &diagram.site(second), &diagram.site(third)).unwrap();
+			let inside = (0..count).any(|id| {
+			    let site = diagram.site(id);
+			    (site.get_x() - center.get_x()).hypot(site.get_y() - center.get_y()) < radius - 1e-9
+			});
+			if !inside {
+			    expected.push(vec![first, second, third]);
+			}
+		    }
+		}
+	    }
+
+	    let mut actual: Vec<Vec<usize>> = triangulation.triangles().iter().map(|triangle| {
+		let mut face_ids = triangle.face_ids().to_vec();
+		face_ids.sort_unstable();
+		face_ids
+	    }).collect();
+	    actual.sort();
+	    expected.sort();
+	    assert_eq!(expected, actual);
+	    assert_covers_hull(&diagram, &triangulation);
+	    for face_id in 0..count {
+		for neighbour_id in triangulation.neighbours(face_id) {
+		    assert!(triangulation.are_neighbours(*neighbour_id, face_id));
+		}
+	    }
+	}
+    }
+
+    #[test]
+    fn test_delaunay_outside_bounds() {
+	// the circumcenter of the flat triangle lies far below the bounds, and the outer sites do not share an edge
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 1000.0, 0.0, 1000.0));
+	builder.add_sites(vec![(100.0, 500.0), (900.0, 500.0), (500.0, 520.0)]);
+	let diagram = builder.build().unwrap();
+	assert_eq!(None, diagram.neighbours(0).find(|face_id| *face_id == 1));
+	let triangulation = diagram.delaunay().unwrap();
+	assert_eq!(1, triangulation.triangles().len());
+	assert!(triangulation.are_neighbours(0, 1));
+	assert!(triangulation.are_neighbours(1, 0));
+	assert_covers_hull(&diagram, &triangulation);
+    }
+
+    #[test]
+    fn test_delaunay_small_bounds() {
+	let mut random = Random::new(4242);
+	// sites along the edges of the bounds create many thin triangles around the hull
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 1.0, 0.0, 1.0));
+	for i in 0..200 {
+	    let t = random.range(0.0, 1.0);
+	    let offset = random.range(0.0, 0.01);
+	    let (x, y) = match i % 4 {
+		0 => (t, offset),
+		1 => (1.0 - offset, t),
+		2 => (t, 1.0 - offset),
+		_ => (offset, t),
+	    };
+	    builder.add_site(x, y);
+	}
+	let diagram = builder.build().unwrap();
+	assert_covers_hull(&diagram, &diagram.delaunay().unwrap());
+    }
+
+    #[test]
+    fn test_delaunay_power() {
+	let mut builder = PowerDiagramBuilder::new(BoundingBox::new(0.0, 100.0, 0.0, 100.0));
+	builder.add_sites(vec![(10.0, 50.0, 0.0), (90.0, 50.0, 0.0), (50.0, 52.0, 1.0), (50.0, 95.0, 30.0), (70.0, 80.0, 0.0)]);
+	let diagram = builder.build().unwrap();
+	let triangulation = diagram.delaunay().unwrap();
+	assert_covers_hull(&diagram, &triangulation);
+
+	let sites: Vec<Vector2F64> = (0..diagram.faces().len()).map(|face_id| diagram.site(face_id)).collect();
+	let weights: Vec<f64> = diagram.faces().iter().map(|face| face.weight()).collect();
+	let mut expected = Vec::new();
+	for first in 0..sites.len() {
+	    for second in 0..sites.len() {
+		for third in 0..sites.len() {
+		    if first < second && first < third && orient2d(&sites[first], &sites[second], &sites[third]) > 0.0
+			&& (0..sites.len()).all(|id| power_incircle(&sites, &weights, [first, second, third, id]) <= 1e-9) {
+			expected.push(vec![first, second, third]);
+		    }
+		}
+	    }
+	}
+	let mut actual: Vec<Vec<usize>> = triangulation.triangles().iter().map(|triangle| {
+	    let ids = triangle.face_ids();
+	    let start = (0..3).min_by_key(|i| ids[*i]).unwrap();
+	    (0..3).map(|i| ids[(start + i) % 3]).collect()
+	}).collect();
+	actual.sort();
+	expected.sort();
+	assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_delaunay_unsupported() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 10.0, 0.0, 10.0));
+	builder.add_sites(vec![(2.0, 2.0), (8.0, 3.0), (4.0, 7.0), (7.0, 8.0)]);
+	let diagram = builder.build().unwrap();
+	let clipped = diagram.clip(&ClipPolygon::new(vec![(0.0, 0.0), (10.0, 0.0), (0.0, 10.0)]).unwrap()).unwrap();
+	assert!(matches!(clipped.delaunay(), Err(VoronoiError::Unsupported(_))));
+	builder.set_wrap(Wrap::Both);
+	assert!(matches!(builder.build().unwrap().delaunay(), Err(VoronoiError::Unsupported(_))));
+    }
+
+}
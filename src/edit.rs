@@ -0,0 +1,603 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * Inserts and removes single sites of a voronoi diagram without rebuilding it
+ *
+ * An inserted site takes area from the cells that have a corner closer to it than to their own site, and the
+ * area of a removed site goes to the cells around it. Only these cells are recomputed, by cutting the bounds with
+ * the bisectors towards their neighbours, and only their half edges are replaced. Their corners on the border of
+ * the edited region are snapped to the existing vertices, so the twins of the unchanged cells can be restored.
+ */
+
+use crate::attributes::Element;
+use crate::periodic::Wrap;
+use crate::power::{cut_cell, tolerance};
+use crate::vector::{Vector2, Vector2F64};
+use crate::voronoi::{Diagram, Face, HalfEdge, Vertex, VoronoiError};
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/**
+ * The twin of a new half edge
+ */
+#[derive(Clone, Copy)]
+enum Twin {
+
+    /**
+     * Another new half edge, by its index in the new loops
+     */
+    New(usize),
+
+    /**
+     * A half edge of an unchanged face
+     */
+    Existing(usize),
+}
+
+/**
+ * The new loops of the edited faces, checked before they replace the old half edges
+ */
+struct Rebuild {
+
+    /**
+     * The vertex ids of the new loop of every edited face, where ids after the existing vertices are new vertices
+     */
+    loops: Vec<(usize, Vec<usize>)>,
+
+    /**
+     * The positions of the new vertices
+     */
+    new_vertices: Vec<(f64, f64)>,
+
+    /**
+     * The twins of the new half edges, in the order of the loops
+     */
+    twins: Vec<Option<Twin>>,
+
+    /**
+     * The half edges of the old loops, which are replaced
+     */
+    old_half_edge_ids: Vec<usize>,
+
+    /**
+     * The vertices of the old loops that are not used anymore
+     */
+    unused_vertex_ids: Vec<usize>,
+}
+
+impl<T> Diagram<T> {
+
+    /**
+     * Adds a site and returns the id of its face, which comes after all existing faces
+     * Only the cells around the site change. Clipped, power and periodic diagrams can not be edited.
+     * Attributes keep their values, except on the new face and the rebuilt vertices and half edges of the changed cells.
+     */
+    pub fn insert_site(&mut self, x: f64, y: f64, data: T) -> Result<usize, VoronoiError> {
+	self.check_editable()?;
+	let face_id = self.faces.len();
+	if !self.bounds.contains(&Vector2F64::from_values(x, y)) {
+	    return Err(VoronoiError::SiteOutOfBounds { id: face_id, x, y });
+	}
+	let affected_ids = match self.locate(x, y) {
+	    None => Vec::new(),
+	    Some(start_id) if self.faces[start_id].x == x && self.faces[start_id].y == y => {
+		return Err(VoronoiError::DuplicateSite { first_id: start_id, second_id: face_id });
+	    },
+	    Some(start_id) => self.find_closer_faces(start_id, x, y),
+	};
+	let mut polygons: Vec<(usize, Vec<(f64, f64)>)> = affected_ids.iter()
+	    .map(|affected_id| {
+		let others: Vec<usize> = self.neighbours(*affected_id).collect();
+		let polygon = self.cell_polygon(self.site_position(*affected_id), others.into_iter().map(|other_id| self.site_position(other_id)));
+		(*affected_id, cut_cell(polygon, (self.faces[*affected_id].x, self.faces[*affected_id].y, 0.0), (x, y, 0.0), tolerance(&self.bounds)))
+	    })
+	    .collect();
+	// the new cell borders the affected cells, but its bisectors with their neighbours are cheap insurance
+	let mut other_ids = affected_ids.clone();
+	other_ids.extend(affected_ids.iter().flat_map(|affected_id| self.neighbours(*affected_id)));
+	other_ids.sort_unstable();
+	other_ids.dedup();
+	polygons.push((face_id, self.cell_polygon((x, y), other_ids.into_iter().map(|other_id| self.site_position(other_id)))));
+	let rebuild = self.plan_rebuild(polygons, None)?;
+	self.faces.push(Face {
+	    id: face_id,
+	    x,
+	    y,
+	    start_id: 0,
+	    site_id: face_id,
+	    hole_start_ids: Vec::new(),
+	    weight: 0.0,
+	    data,
+	});
+	self.attributes.resize(Element::Face, self.faces.len());
+	self.apply_rebuild(rebuild);
+	Ok(face_id)
+    }
+
+    /**
+     * Removes the site of a face and returns its data
+     * The last face takes over the id of the removed face, and only the cells around the site change.
+     * Clipped, power and periodic diagrams can not be edited, and the face must exist.
+     */
+    pub fn remove_site(&mut self, face_id: usize) -> Result<T, VoronoiError> {
+	self.check_editable()?;
+	if face_id >= self.faces.len() {
+	    return Err(VoronoiError::DegenerateInput(format!("face {} does not exist", face_id)));
+	}
+	let mut around_ids: Vec<usize> = self.face_half_edges(face_id)
+	    .flat_map(|half_edge| self.faces_around_vertex(half_edge.id))
+	    .filter(|around_id| *around_id != face_id)
+	    .collect();
+	around_ids.sort_unstable();
+	around_ids.dedup();
+	if around_ids.is_empty() {
+	    // the only face covers the bounds
+	    self.vertices.clear();
+	    self.half_edges.clear();
+	    for element in [Element::Face, Element::Vertex, Element::HalfEdge].iter() {
+		self.attributes.resize(*element, 0);
+	    }
+	    return Ok(self.faces.pop().expect("the face should exist").data);
+	}
+	let polygons = around_ids.iter()
+	    .map(|around_id| {
+		let others: Vec<usize> = self.neighbours(*around_id)
+		    .chain(around_ids.iter().copied())
+		    .filter(|other_id| *other_id != face_id && other_id != around_id)
+		    .collect();
+		(*around_id, self.cell_polygon(self.site_position(*around_id), others.into_iter().map(|other_id| self.site_position(other_id))))
+	    })
+	    .collect();
+	let rebuild = self.plan_rebuild(polygons, Some(face_id))?;
+	self.apply_rebuild(rebuild);
+	let face = self.faces.swap_remove(face_id);
+	self.attributes.swap_remove(Element::Face, face_id);
+	if face_id < self.faces.len() {
+	    let moved = &mut self.faces[face_id];
+	    moved.id = face_id;
+	    moved.site_id = face_id;
+	    let half_edge_ids: Vec<usize> = self.face_half_edges(face_id).map(|half_edge| half_edge.id).collect();
+	    for half_edge_id in half_edge_ids {
+		self.half_edges[half_edge_id].face_id = face_id;
+	    }
+	}
+	Ok(face.data)
+    }
+
+    fn check_editable(&self) -> Result<(), VoronoiError> {
+	if self.is_clipped() || self.is_weighted() || self.wrap != Wrap::None {
+	    Err(VoronoiError::Unsupported(String::from("only the sites of unclipped, unweighted and not periodic diagrams can be edited")))
+	} else {
+	    Ok(())
+	}
+    }
+
+    fn site_position(&self, face_id: usize) -> (f64, f64) {
+	(self.faces[face_id].x, self.faces[face_id].y)
+    }
+
+    /**
+     * Finds the faces that have a corner closer to a new site than to their own site, which are connected
+     * to the face containing the new site
+     */
+    fn find_closer_faces(&self, start_id: usize, x: f64, y: f64) -> Vec<usize> {
+	let tolerance = tolerance(&self.bounds);
+	let is_closer = |face_id: usize| {
+	    let face = &self.faces[face_id];
+	    self.face_vertices(face_id).any(|vertex| (vertex.x - x).hypot(vertex.y - y) < (vertex.x - face.x).hypot(vertex.y - face.y) - tolerance)
+	};
+	let mut face_ids = vec![start_id];
+	let mut visited: HashSet<usize> = face_ids.iter().copied().collect();
+	let mut queue: VecDeque<usize> = face_ids.iter().copied().collect();
+	while let Some(face_id) = queue.pop_front() {
+	    for neighbour_id in self.neighbours(face_id) {
+		if visited.insert(neighbour_id) && is_closer(neighbour_id) {
+		    face_ids.push(neighbour_id);
+		    queue.push_back(neighbour_id);
+		}
+	    }
+	}
+	face_ids.sort_unstable();
+	face_ids
+    }
+
+    /**
+     * Cuts the bounds with the bisectors between a site and the other sites
+     */
+    fn cell_polygon<I>(&self, site: (f64, f64), others: I) -> Vec<(f64, f64)> where I: Iterator<Item = (f64, f64)> {
+	let tolerance = tolerance(&self.bounds);
+	let mut polygon: Vec<(f64, f64)> = vec![
+	    (self.bounds.left(), self.bounds.top()),
+	    (self.bounds.right(), self.bounds.top()),
+	    (self.bounds.right(), self.bounds.bottom()),
+	    (self.bounds.left(), self.bounds.bottom()),
+	];
+	for other in others {
+	    polygon = cut_cell(polygon, (site.0, site.1, 0.0), (other.0, other.1, 0.0), tolerance);
+	}
+	polygon
+    }
+
+    /**
+     * The faces around the start vertex of a half edge, turning in both directions until the bounds are reached
+     */
+    fn faces_around_vertex(&self, half_edge_id: usize) -> Vec<usize> {
+	let mut face_ids = vec![self.half_edges[half_edge_id].face_id];
+	let mut current_id = half_edge_id;
+	loop {
+	    // the twin of the incoming half edge leaves the vertex in the next face
+	    match self.half_edges[self.half_edges[current_id].prev_id].twin_id {
+		Some(twin_id) if twin_id == half_edge_id => return face_ids,
+		Some(twin_id) => {
+		    face_ids.push(self.half_edges[twin_id].face_id);
+		    current_id = twin_id;
+		},
+		None => break,
+	    }
+	}
+	current_id = half_edge_id;
+	while let Some(twin_id) = self.half_edges[current_id].twin_id {
+	    current_id = self.half_edges[twin_id].next_id;
+	    face_ids.push(self.half_edges[current_id].face_id);
+	}
+	face_ids
+    }
+
+    /**
+     * Snaps the corners of the new cells to vertices and finds the twins of their half edges,
+     * failing without changing the diagram if the new cells do not fit into the unchanged ones
+     */
+    fn plan_rebuild(&self, polygons: Vec<(usize, Vec<(f64, f64)>)>, removed_id: Option<usize>) -> Result<Rebuild, VoronoiError> {
+	let tolerance = tolerance(&self.bounds);
+	let mut edited_ids: Vec<usize> = polygons.iter()
+	    .map(|(face_id, _)| *face_id)
+	    .chain(removed_id)
+	    .filter(|face_id| *face_id < self.faces.len())
+	    .collect();
+	edited_ids.sort_unstable();
+	let is_edited = |face_id: usize| edited_ids.binary_search(&face_id).is_ok();
+	let old_half_edge_ids: Vec<usize> = edited_ids.iter()
+	    .flat_map(|face_id| self.face_half_edges(*face_id).map(|half_edge| half_edge.id))
+	    .collect();
+	let mut old_vertex_ids: Vec<usize> = old_half_edge_ids.iter().map(|half_edge_id| self.half_edges[*half_edge_id].start_id).collect();
+	old_vertex_ids.sort_unstable();
+	old_vertex_ids.dedup();
+
+	// the half edges of the unchanged faces around the edited ones that need new twins, by start and end vertex
+	let mut surrounding_ids: Vec<usize> = old_half_edge_ids.iter()
+	    .flat_map(|half_edge_id| self.faces_around_vertex(*half_edge_id))
+	    .filter(|face_id| !is_edited(*face_id))
+	    .collect();
+	surrounding_ids.sort_unstable();
+	surrounding_ids.dedup();
+	let mut border: HashMap<(usize, usize), usize> = HashMap::new();
+	let mut used_vertex_ids = HashSet::new();
+	for surrounding_id in surrounding_ids {
+	    for half_edge in self.face_half_edges(surrounding_id) {
+		used_vertex_ids.insert(half_edge.start_id);
+		if half_edge.twin_id.map(|twin_id| is_edited(self.half_edges[twin_id].face_id)).unwrap_or(false) {
+		    border.insert((half_edge.start_id, self.half_edges[half_edge.next_id].start_id), half_edge.id);
+		}
+	    }
+	}
+
+	let mut new_vertices: Vec<(f64, f64)> = Vec::new();
+	let mut loops = Vec::with_capacity(polygons.len());
+	for (face_id, polygon) in polygons {
+	    let mut vertex_ids: Vec<usize> = polygon.into_iter()
+		.map(|(x, y)| {
+		    let is_close = |(other_x, other_y): (f64, f64)| (other_x - x).hypot(other_y - y) <= tolerance;
+		    if let Some(vertex_id) = old_vertex_ids.iter().find(|vertex_id| is_close(self.vertex_position(**vertex_id))) {
+			*vertex_id
+		    } else if let Some(index) = new_vertices.iter().position(|position| is_close(*position)) {
+			self.vertices.len() + index
+		    } else {
+			new_vertices.push((x, y));
+			self.vertices.len() + new_vertices.len() - 1
+		    }
+		})
+		.collect();
+	    vertex_ids.dedup();
+	    while vertex_ids.len() > 1 && vertex_ids.first() == vertex_ids.last() {
+		vertex_ids.pop();
+	    }
+	    if vertex_ids.len() < 3 {
+		return Err(VoronoiError::topology("an edited cell collapsed"));
+	    }
+	    loops.push((face_id, vertex_ids));
+	}
+
+	let position = |vertex_id: usize| {
+	    if vertex_id < self.vertices.len() {
+		self.vertex_position(vertex_id)
+	    } else {
+		new_vertices[vertex_id - self.vertices.len()]
+	    }
+	};
+	let on_bounds = |start: (f64, f64), end: (f64, f64)| {
+	    let bounds = &self.bounds;
+	    let near = |first: f64, second: f64, side: f64| (first - side).abs() <= tolerance && (second - side).abs() <= tolerance;
+	    near(start.0, end.0, bounds.left()) || near(start.0, end.0, bounds.right())
+		|| near(start.1, end.1, bounds.top()) || near(start.1, end.1, bounds.bottom())
+	};
+	let edges: Vec<(usize, usize)> = loops.iter()
+	    .flat_map(|(_, vertex_ids)| (0..vertex_ids.len()).map(move |i| (vertex_ids[i], vertex_ids[(i + 1) % vertex_ids.len()])))
+	    .collect();
+	let edge_indices: HashMap<(usize, usize), usize> = edges.iter().enumerate().map(|(index, edge)| (*edge, index)).collect();
+	let mut twins = Vec::with_capacity(edges.len());
+	for (start_id, end_id) in edges.iter() {
+	    if let Some(index) = edge_indices.get(&(*end_id, *start_id)) {
+		twins.push(Some(Twin::New(*index)));
+	    } else if let Some(half_edge_id) = border.remove(&(*end_id, *start_id)) {
+		twins.push(Some(Twin::Existing(half_edge_id)));
+	    } else if on_bounds(position(*start_id), position(*end_id)) {
+		twins.push(None);
+	    } else {
+		return Err(VoronoiError::topology("an edited cell does not fit its neighbours"));
+	    }
+	}
+	if !border.is_empty() {
+	    return Err(VoronoiError::topology("an unchanged cell lost its neighbour"));
+	}
+
+	let new_vertex_ids: HashSet<usize> = loops.iter().flat_map(|(_, vertex_ids)| vertex_ids.iter().copied()).collect();
+	let unused_vertex_ids = old_vertex_ids.into_iter()
+	    .filter(|vertex_id| !new_vertex_ids.contains(vertex_id) && !used_vertex_ids.contains(vertex_id))
+	    .collect();
+	Ok(Rebuild {
+	    loops,
+	    new_vertices,
+	    twins,
+	    old_half_edge_ids,
+	    unused_vertex_ids,
+	})
+    }
+
+    fn vertex_position(&self, vertex_id: usize) -> (f64, f64) {
+	(self.vertices[vertex_id].x, self.vertices[vertex_id].y)
+    }
+
+    /**
+     * Replaces the loops of the edited faces, reusing the ids of old half edges and unused vertices
+     */
+    fn apply_rebuild(&mut self, rebuild: Rebuild) {
+	let vertex_count = self.vertices.len();
+	let mut free_vertex_ids = rebuild.unused_vertex_ids.into_iter();
+	let new_vertex_ids: Vec<usize> = rebuild.new_vertices.into_iter()
+	    .map(|(x, y)| match free_vertex_ids.next() {
+		Some(id) => {
+		    self.vertices[id] = Vertex { id, x, y };
+		    self.attributes.reset(Element::Vertex, id);
+		    id
+		},
+		None => {
+		    let id = self.vertices.len();
+		    self.vertices.push(Vertex { id, x, y });
+		    id
+		},
+	    })
+	    .collect();
+	self.attributes.resize(Element::Vertex, self.vertices.len());
+	let vertex_id = |vertex_id: usize| if vertex_id < vertex_count { vertex_id } else { new_vertex_ids[vertex_id - vertex_count] };
+
+	let mut free_half_edge_ids = rebuild.old_half_edge_ids.into_iter();
+	let half_edge_ids: Vec<usize> = (0..rebuild.twins.len())
+	    .map(|_| free_half_edge_ids.next().unwrap_or_else(|| {
+		self.half_edges.push(HalfEdge { id: 0, face_id: 0, start_id: 0, twin_id: None, prev_id: 0, next_id: 0, clip_boundary: false, wrap: (0, 0) });
+		self.half_edges.len() - 1
+	    }))
+	    .collect();
+	self.attributes.resize(Element::HalfEdge, self.half_edges.len());
+	for half_edge_id in half_edge_ids.iter() {
+	    self.attributes.reset(Element::HalfEdge, *half_edge_id);
+	}
+	let mut index = 0;
+	for (face_id, vertex_ids) in rebuild.loops {
+	    let count = vertex_ids.len();
+	    for (i, start_id) in vertex_ids.into_iter().enumerate() {
+		let id = half_edge_ids[index + i];
+		let twin_id = match rebuild.twins[index + i] {
+		    None => None,
+		    Some(Twin::New(twin_index)) => Some(half_edge_ids[twin_index]),
+		    Some(Twin::Existing(twin_id)) => {
+			self.half_edges[twin_id].twin_id = Some(id);
+			Some(twin_id)
+		    },
+		};
+		self.half_edges[id] = HalfEdge {
+		    id,
+		    face_id,
+		    start_id: vertex_id(start_id),
+		    twin_id,
+		    prev_id: half_edge_ids[index + (i + count - 1) % count],
+		    next_id: half_edge_ids[index + (i + 1) % count],
+		    clip_boundary: false,
+		    wrap: (0, 0),
+		};
+	    }
+	    self.faces[face_id].start_id = half_edge_ids[index];
+	    index += count;
+	}
+	self.remove_half_edges(free_half_edge_ids.collect());
+	self.remove_vertices(free_vertex_ids.collect());
+    }
+
+    /**
+     * Removes half edges that are not referenced anymore, moving the last half edges into their places
+     */
+    fn remove_half_edges(&mut self, mut half_edge_ids: Vec<usize>) {
+	// removing from the back means the last half edge is never one that should be removed
+	half_edge_ids.sort_unstable_by(|first, second| second.cmp(first));
+	for half_edge_id in half_edge_ids {
+	    let last_id = self.half_edges.len() - 1;
+	    self.half_edges.swap_remove(half_edge_id);
+	    self.attributes.swap_remove(Element::HalfEdge, half_edge_id);
+	    if half_edge_id == last_id {
+		continue;
+	    }
+	    let moved = &mut self.half_edges[half_edge_id];
+	    moved.id = half_edge_id;
+	    let (prev_id, next_id, twin_id, face_id) = (moved.prev_id, moved.next_id, moved.twin_id, moved.face_id);
+	    self.half_edges[prev_id].next_id = half_edge_id;
+	    self.half_edges[next_id].prev_id = half_edge_id;
+	    if let Some(twin_id) = twin_id {
+		self.half_edges[twin_id].twin_id = Some(half_edge_id);
+	    }
+	    if self.faces[face_id].start_id == last_id {
+		self.faces[face_id].start_id = half_edge_id;
+	    }
+	}
+    }
+
+    /**
+     * Removes vertices that are not used anymore, moving the last vertices into their places
+     * There is no index from vertices to half edges, so this takes a pass over the half edges for every moved vertex.
+     */
+    fn remove_vertices(&mut self, mut vertex_ids: Vec<usize>) {
+	vertex_ids.sort_unstable_by(|first, second| second.cmp(first));
+	for vertex_id in vertex_ids {
+	    let last_id = self.vertices.len() - 1;
+	    self.vertices.swap_remove(vertex_id);
+	    self.attributes.swap_remove(Element::Vertex, vertex_id);
+	    if vertex_id == last_id {
+		continue;
+	    }
+	    self.vertices[vertex_id].id = vertex_id;
+	    for half_edge in self.half_edges.iter_mut().filter(|half_edge| half_edge.start_id == last_id) {
+		half_edge.start_id = vertex_id;
+	    }
+	}
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::geom::{polygon_signed_area, BoundingBox};
+    use crate::random::Random;
+    use crate::voronoi::DiagramBuilder;
+
+    fn rebuild(diagram: &Diagram<usize>) -> Diagram<usize> {
+	let mut builder = DiagramBuilder::with_data(diagram.bounds().clone());
+	builder.add_sites_with_data(diagram.faces().iter().map(|face| (face.x(), face.y(), *face.data())));
+	builder.build().unwrap()
+    }
+
+    /**
+     * Checks that an edited diagram is valid and has the same cells as a diagram built from its sites
+     */
+    fn assert_same_as_rebuild(diagram: &Diagram<usize>) {
+	assert_eq!(Ok(()), diagram.validate());
+	let expected = rebuild(diagram);
+	assert_eq!(expected.faces().len(), diagram.faces().len());
+	assert_eq!(expected.vertices().len(), diagram.vertices().len());
+	assert_eq!(expected.half_edges().len(), diagram.half_edges().len());
+	for face in diagram.faces().iter() {
+	    let area = polygon_signed_area(&diagram.face_polygon(face.id()));
+	    assert!((polygon_signed_area(&expected.face_polygon(face.id())) - area).abs() < 1e-6);
+	    let mut neighbours: Vec<usize> = diagram.neighbours(face.id()).collect();
+	    let mut expected_neighbours: Vec<usize> = expected.neighbours(face.id()).collect();
+	    neighbours.sort_unstable();
+	    expected_neighbours.sort_unstable();
+	    assert_eq!(expected_neighbours, neighbours);
+	}
+    }
+
+    #[test]
+    fn test_insert_sites() {
+	let bounds = BoundingBox::new(0.0, 100.0, 0.0, 80.0);
+	let mut random = Random::new(1);
+	let mut builder = DiagramBuilder::with_data(bounds);
+	builder.add_sites_with_data((0..100).map(|id| (random.range(0.0, 100.0), random.range(0.0, 80.0), id)));
+	let mut diagram = builder.build().unwrap();
+	for id in 100..150 {
+	    let face_id = diagram.insert_site(random.range(0.0, 100.0), random.range(0.0, 80.0), id).unwrap();
+	    assert_eq!(id, face_id);
+	    assert_same_as_rebuild(&diagram);
+	}
+	// sites on the bounds and in the corners
+	diagram.insert_site(0.0, 40.0, 150).unwrap();
+	diagram.insert_site(100.0, 80.0, 151).unwrap();
+	assert_same_as_rebuild(&diagram);
+    }
+
+    #[test]
+    fn test_remove_sites() {
+	let bounds = BoundingBox::new(0.0, 100.0, 0.0, 80.0);
+	let mut random = Random::new(2);
+	let mut builder = DiagramBuilder::with_data(bounds);
+	builder.add_sites_with_data((0..100).map(|id| (random.range(0.0, 100.0), random.range(0.0, 80.0), id)));
+	let mut diagram = builder.build().unwrap();
+	for _ in 0..50 {
+	    let face_id = random.index(diagram.faces().len());
+	    let id = *diagram.faces()[face_id].data();
+	    let last_id = *diagram.faces().last().unwrap().data();
+	    assert_eq!(Ok(id), diagram.remove_site(face_id));
+	    if face_id < diagram.faces().len() {
+		assert_eq!(last_id, *diagram.faces()[face_id].data());
+	    }
+	    assert_same_as_rebuild(&diagram);
+	}
+    }
+
+    #[test]
+    fn test_edit_grid() {
+	// co-circular sites, where four cells meet at every vertex
+	let bounds = BoundingBox::new(0.0, 50.0, 0.0, 50.0);
+	let mut builder = DiagramBuilder::with_data(bounds.clone());
+	builder.add_sites_with_data((0..25).map(|id| (5.0 + 10.0 * (id % 5) as f64, 5.0 + 10.0 * (id / 5) as f64, id)));
+	let mut diagram = builder.build().unwrap();
+	diagram.remove_site(12).unwrap();
+	assert_same_as_rebuild(&diagram);
+	diagram.insert_site(25.0, 25.0, 12).unwrap();
+	assert_same_as_rebuild(&diagram);
+	diagram.insert_site(10.0, 10.0, 25).unwrap();
+	assert_same_as_rebuild(&diagram);
+    }
+
+    #[test]
+    fn test_edit_single_site() {
+	let mut diagram: Diagram<usize> = DiagramBuilder::with_data(BoundingBox::new(0.0, 10.0, 0.0, 10.0)).build().unwrap();
+	assert_eq!(Ok(0), diagram.insert_site(3.0, 4.0, 7));
+	assert_same_as_rebuild(&diagram);
+	assert_eq!(Ok(1), diagram.insert_site(6.0, 4.0, 8));
+	assert_same_as_rebuild(&diagram);
+	assert_eq!(Ok(7), diagram.remove_site(0));
+	assert_same_as_rebuild(&diagram);
+	assert_eq!(Ok(8), diagram.remove_site(0));
+	assert!(diagram.faces().is_empty() && diagram.half_edges().is_empty() && diagram.vertices().is_empty());
+    }
+
+    #[test]
+    fn test_edit_errors() {
+	let bounds = BoundingBox::new(0.0, 10.0, 0.0, 10.0);
+	let mut builder = DiagramBuilder::new(bounds.clone());
+	builder.add_sites(vec![(2.0, 2.0), (7.0, 3.0), (4.0, 8.0)]);
+	let mut diagram = builder.build().unwrap();
+	assert_eq!(Err(VoronoiError::DuplicateSite { first_id: 1, second_id: 3 }), diagram.insert_site(7.0, 3.0, ()));
+	assert_eq!(Err(VoronoiError::SiteOutOfBounds { id: 3, x: -1.0, y: 3.0 }), diagram.insert_site(-1.0, 3.0, ()));
+	assert_eq!(Err(VoronoiError::DegenerateInput(String::from("face 3 does not exist"))), diagram.remove_site(3));
+	let mut clipped = diagram.clip(&crate::clip::ClipPolygon::new(vec![(0.0, 0.0), (10.0, 0.0), (0.0, 10.0)]).unwrap()).unwrap();
+	assert!(matches!(clipped.insert_site(1.0, 1.0, ()), Err(VoronoiError::Unsupported(_))));
+	assert!(matches!(clipped.remove_site(0), Err(VoronoiError::Unsupported(_))));
+	assert_eq!(3, diagram.faces().len());
+    }
+
+}
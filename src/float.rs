@@ -0,0 +1,198 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * The scalar type the sweep and the geometry helpers are generic over
+ */
+
+use std::cmp::Ordering;
+use std::fmt::{Debug, Display};
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use crate::matrix::{FixedMatrix, Matrix, Matrix2x3F32, Matrix2x3F64};
+use crate::vector::{Vector2, Vector2F32, Vector2F64};
+
+/**
+ * A floating point like scalar
+ * Besides f32 and f64 the trait can be implemented for extended precision or exact number types,
+ * all constants and coordinates enter the computations through from_f64
+ */
+pub trait Float: Copy + Debug + Default + Display + PartialEq + PartialOrd + Send + Sync
+    + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self> + Neg<Output = Self> {
+
+    /**
+     * The two dimensional vector with this scalar
+     */
+    type Vector2: Vector2<Value = Self> + Copy + Debug + PartialEq + Send + Sync
+	+ Add<Output = Self::Vector2> + Sub<Output = Self::Vector2> + Neg<Output = Self::Vector2>
+	+ Mul<Self, Output = Self::Vector2> + Div<Self, Output = Self::Vector2>;
+
+    /**
+     * The 2x3 matrix with this scalar used to intersect lines
+     */
+    type Matrix2x3: FixedMatrix + Matrix<Value = Self> + Debug;
+
+    /**
+     * Positive infinity
+     */
+    const INFINITY: Self;
+
+    /**
+     * The difference between 1 and the next larger representable value
+     */
+    const EPSILON: Self;
+
+    /**
+     * Converts a f64 to this type, rounding if necessary
+     */
+    fn from_f64(value: f64) -> Self;
+
+    /**
+     * Converts this value to f64, rounding if necessary
+     */
+    fn to_f64(self) -> f64;
+
+    /**
+     * The square root
+     */
+    fn sqrt(self) -> Self;
+
+    /**
+     * The absolute value
+     */
+    fn abs(self) -> Self;
+
+    /**
+     * The four quadrant arctangent of self (y) and x in radians
+     */
+    fn atan2(self, x: Self) -> Self;
+
+    /**
+     * Checks whether the value is neither infinite nor NaN
+     */
+    fn is_finite(self) -> bool;
+
+    /**
+     * The larger of two values
+     */
+    fn max(self, other: Self) -> Self {
+	if other > self {
+	    other
+	} else {
+	    self
+	}
+    }
+
+    /**
+     * The smaller of two values
+     */
+    fn min(self, other: Self) -> Self {
+	if other < self {
+	    other
+	} else {
+	    self
+	}
+    }
+
+    /**
+     * Compares two values in a total order for sorting, which puts NaN after all numbers
+     */
+    fn compare(self, other: Self) -> Ordering {
+	match self.partial_cmp(&other) {
+	    Some(ordering) => ordering,
+	    // only NaN is unordered to itself
+	    None => self.partial_cmp(&self).is_none().cmp(&other.partial_cmp(&other).is_none()),
+	}
+    }
+}
+
+/**
+ * Widens a tolerance relative to the size of the bounds to what the scalar type can resolve
+ */
+pub(crate) fn relative_tolerance<F>(tolerance: f64) -> f64 where F: Float {
+    tolerance.max(64.0 * F::EPSILON.to_f64())
+}
+
+/**
+ * Converts a vector of any scalar type to f64
+ */
+pub(crate) fn to_vector2_f64<V>(point: &V) -> Vector2F64 where V: Vector2, V::Value: Float {
+    Vector2F64::from_values(point.get_x().to_f64(), point.get_y().to_f64())
+}
+
+macro_rules! define_float {
+
+    ($value_type_name:ident, $vector_type_name:ident, $matrix_type_name:ident) => {
+
+	impl Float for $value_type_name {
+
+	    type Vector2 = $vector_type_name;
+
+	    type Matrix2x3 = $matrix_type_name;
+
+	    const INFINITY: Self = $value_type_name::INFINITY;
+
+	    const EPSILON: Self = $value_type_name::EPSILON;
+
+	    fn from_f64(value: f64) -> Self {
+		value as $value_type_name
+	    }
+
+	    fn to_f64(self) -> f64 {
+		self as f64
+	    }
+
+	    fn sqrt(self) -> Self {
+		$value_type_name::sqrt(self)
+	    }
+
+	    fn abs(self) -> Self {
+		$value_type_name::abs(self)
+	    }
+
+	    fn atan2(self, x: Self) -> Self {
+		$value_type_name::atan2(self, x)
+	    }
+
+	    fn is_finite(self) -> bool {
+		$value_type_name::is_finite(self)
+	    }
+
+	}
+    }
+}
+
+define_float!(f32, Vector2F32, Matrix2x3F32);
+define_float!(f64, Vector2F64, Matrix2x3F64);
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_conversion() {
+	assert_eq!(<f32 as Float>::from_f64(0.5), 0.5f32);
+	assert_eq!(Float::to_f64(0.25f32), 0.25);
+	assert_eq!(<f64 as Float>::from_f64(1e300), 1e300);
+	assert_eq!(Float::max(1.0f32, 2.0), 2.0);
+	assert_eq!(Float::min(1.0f64, 2.0), 1.0);
+	assert_eq!(relative_tolerance::<f64>(1e-9), 1e-9);
+	assert!(relative_tolerance::<f32>(1e-9) > 1e-6);
+    }
+
+}
@@ -19,26 +19,29 @@
  * Utility functions for 2D geometry
  */
 
+use crate::float::{to_vector2_f64, Float};
 use crate::linear::solve_from_matrix;
-use crate::matrix::{FixedMatrix, Matrix, Matrix2x3F64};
+use crate::matrix::{FixedMatrix, Matrix};
+use crate::predicates::orient2d;
 use crate::quadratic::{Solution as QuadraticSolution, solve as solve_quadratic};
 use crate::vector::{Vector2, Vector2F64};
 
 /**
  * A bounding box
  */
-pub struct BoundingBox {
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoundingBox<F: Float = f64> {
 
     /**
      * The points of the box
      */
-    points: [Vector2F64; 4],
+    points: [F::Vector2; 4],
 
 }
 
-impl BoundingBox {
+impl<F> BoundingBox<F> where F: Float {
 
-    pub fn new(x1: f64, x2: f64, y1: f64, y2: f64) -> BoundingBox {
+    pub fn new(x1: F, x2: F, y1: F, y2: F) -> BoundingBox<F> {
 	let (left, right) = if x1 > x2 {
 	    (x2, x1)
 	} else {
@@ -51,13 +54,119 @@ impl BoundingBox {
 	};
 	BoundingBox{
 	    points: [
-		Vector2F64::from_values(left, top),
-		Vector2F64::from_values(right, top),
-		Vector2F64::from_values(right, bottom),
-		Vector2F64::from_values(left, bottom),
+		F::Vector2::from_values(left, top),
+		F::Vector2::from_values(right, top),
+		F::Vector2::from_values(right, bottom),
+		F::Vector2::from_values(left, bottom),
 	    ]
 	}
     }
+
+    /**
+     * The smallest x coordinate of the box
+     */
+    pub fn left(&self) -> F {
+	self.points[0].get_x()
+    }
+
+    /**
+     * The smallest y coordinate of the box
+     */
+    pub fn top(&self) -> F {
+	self.points[0].get_y()
+    }
+
+    /**
+     * The largest x coordinate of the box
+     */
+    pub fn right(&self) -> F {
+	self.points[2].get_x()
+    }
+
+    /**
+     * The largest y coordinate of the box
+     */
+    pub fn bottom(&self) -> F {
+	self.points[2].get_y()
+    }
+
+    /**
+     * The width of the box
+     */
+    pub fn width(&self) -> F {
+	self.right() - self.left()
+    }
+
+    /**
+     * The height of the box
+     */
+    pub fn height(&self) -> F {
+	self.bottom() - self.top()
+    }
+
+    /**
+     * Returns the corners of the box, starting at the top left corner and moving towards the top right corner
+     */
+    pub fn corners(&self) -> &[F::Vector2; 4] {
+	&self.points
+    }
+
+    /**
+     * Checks whether the point lies inside the box or on its border
+     */
+    pub fn contains(&self, point: &F::Vector2) -> bool {
+	point.get_x() >= self.left() && point.get_x() <= self.right()
+	    && point.get_y() >= self.top() && point.get_y() <= self.bottom()
+    }
+
+    /**
+     * Converts the box to another scalar type
+     */
+    pub fn cast<G>(&self) -> BoundingBox<G> where G: Float {
+	BoundingBox::new(
+	    G::from_f64(self.left().to_f64()), G::from_f64(self.right().to_f64()),
+	    G::from_f64(self.top().to_f64()), G::from_f64(self.bottom().to_f64())
+	)
+    }
+
+    /**
+     * Clips the parametric line point + t * dir with t in [t_min, t_max] to the box
+     * Infinite parameter bounds are allowed to clip rays and lines
+     * Returns the clipped parameter range or None if the line does not pass through the box
+     */
+    pub fn clip_line(&self, point: &F::Vector2, dir: &F::Vector2, t_min: F, t_max: F) -> Option<(F, F)> {
+	// Liang-Barsky: every side of the box yields a constraint p * t <= q
+	let constraints = [
+	    (- dir.get_x(), point.get_x() - self.left()),
+	    (dir.get_x(), self.right() - point.get_x()),
+	    (- dir.get_y(), point.get_y() - self.top()),
+	    (dir.get_y(), self.bottom() - point.get_y()),
+	];
+	let zero = F::from_f64(0.0);
+	let mut start = t_min;
+	let mut end = t_max;
+	for &(p, q) in constraints.iter() {
+	    if p == zero {
+		if q < zero {
+		    return None;
+		}
+	    } else {
+		let t = q / p;
+		if p < zero {
+		    if t > start {
+			start = t;
+		    }
+		} else if t < end {
+		    end = t;
+		}
+	    }
+	}
+	if start <= end {
+	    Some((start, end))
+	} else {
+	    None
+	}
+    }
 }
 
 /**
@@ -67,66 +176,69 @@ impl BoundingBox {
  */
 
 #[derive(Debug)]
-pub struct IntersectionCalculator {
+pub struct IntersectionCalculator<F: Float = f64> {
 
     /**
      * A 2x3 matrix
      */
-    matrix: Matrix2x3F64,
+    matrix: F::Matrix2x3,
     
 }
 
-impl IntersectionCalculator {
+impl<F> IntersectionCalculator<F> where F: Float {
 
     /**
      * Creates a new intersection struct
      */
-    pub fn new() -> IntersectionCalculator {
+    pub fn new() -> IntersectionCalculator<F> {
 	IntersectionCalculator{
-	    matrix: Matrix2x3F64::new(),
+	    matrix: F::Matrix2x3::new(),
 	}
     }
     
     /**
      * Intersects two lines
      */
-    pub fn line_with_line(&mut self, first_point: &Vector2F64, first_dir: &Vector2F64,
-			  second_point: &Vector2F64, second_dir: &Vector2F64) -> Option<Vector2F64> {
+    pub fn line_with_line(&mut self, first_point: &F::Vector2, first_dir: &F::Vector2,
+			  second_point: &F::Vector2, second_dir: &F::Vector2) -> Option<F::Vector2> {
 	self.set_parameter_equations(first_point, first_dir, second_point, second_dir);
 	self.solve_for_parameters()
-	    .map(|(k, _)| IntersectionCalculator::substitute_parameter(first_point, first_dir, k))
+	    .map(|(k, _)| IntersectionCalculator::<F>::substitute_parameter(first_point, first_dir, k))
     }
 
     /**
      * Intersects two rays
      */
-    pub fn ray_with_ray(&mut self,first_point: &Vector2F64, first_dir: &Vector2F64,
-			  second_point: &Vector2F64, second_dir: &Vector2F64) -> Option<Vector2F64> {
+    pub fn ray_with_ray(&mut self,first_point: &F::Vector2, first_dir: &F::Vector2,
+			  second_point: &F::Vector2, second_dir: &F::Vector2) -> Option<F::Vector2> {
+	let zero = F::from_f64(0.0);
 	self.set_parameter_equations(first_point, first_dir, second_point, second_dir);
 	self.solve_for_parameters()
-	    .filter(|(k, j)| k >= &0.0 && j >= &0.0)
-	    .map(|(k, _)| IntersectionCalculator::substitute_parameter(first_point, first_dir, k))
+	    .filter(|&(k, j)| k >= zero && j >= zero)
+	    .map(|(k, _)| IntersectionCalculator::<F>::substitute_parameter(first_point, first_dir, k))
     }
 
     /**
      * Intersects a ray with a segment
      */
-    pub fn ray_with_segment(&mut self, first_point: &Vector2F64, first_dir: &Vector2F64,
-			    second_start: &Vector2F64, second_end: &Vector2F64) -> Option<Vector2F64> {
-	let second_dir = Vector2F64::from_values(
+    pub fn ray_with_segment(&mut self, first_point: &F::Vector2, first_dir: &F::Vector2,
+			    second_start: &F::Vector2, second_end: &F::Vector2) -> Option<F::Vector2> {
+	let zero = F::from_f64(0.0);
+	let one = F::from_f64(1.0);
+	let second_dir = F::Vector2::from_values(
 	    second_end.get_x() - second_start.get_x(),
 	    second_end.get_y() - second_start.get_y()
 	);
 	self.set_parameter_equations(first_point, first_dir, second_start, &second_dir);
 	self.solve_for_parameters()
-	    .filter(|(k, j)| k >= &0.0 && j >= &0.0 && j <= &1.0)
-	    .map(|(k, _)| IntersectionCalculator::substitute_parameter(first_point, first_dir, k))
+	    .filter(|&(k, j)| k >= zero && j >= zero && j <= one)
+	    .map(|(k, _)| IntersectionCalculator::<F>::substitute_parameter(first_point, first_dir, k))
     }
 
     /**
      * Intersects a ray with a bounding box
      */
-    pub fn ray_with_bounding_box(&mut self, first_point: &Vector2F64, first_dir: &Vector2F64, bounding_box: &BoundingBox) -> Option<Vector2F64> {
+    pub fn ray_with_bounding_box(&mut self, first_point: &F::Vector2, first_dir: &F::Vector2, bounding_box: &BoundingBox<F>) -> Option<F::Vector2> {
 
 	self.ray_with_segment(first_point, first_dir,
 			      &bounding_box.points[0], &bounding_box.points[1])
@@ -141,40 +253,38 @@ impl IntersectionCalculator {
 
     /**
      * Returns the circle through the three specified points or None if they are colinear
+     * Colinearity is decided exactly, and the center is calculated in f64 relative to the first point
+     * with the adaptive orientation determinant, so nearly colinear points yield a distant but consistent center.
      */
-    pub fn circle_through_points(&mut self, first_point: &Vector2F64, second_point: &Vector2F64, third_point: &Vector2F64) -> Option<(Vector2F64, f64)> {
-	let d1 = Vector2F64::from_values(
-	    second_point.get_y() - first_point.get_y(),
-	    first_point.get_x() - second_point.get_x()
-	);
-	let d2 = Vector2F64::from_values(
-	    second_point.get_y() - third_point.get_y(),
-	    third_point.get_x() - second_point.get_x()
-	);
-
-	if vectors_are_dependent(&d1, &d2) {
-	    None
-	} else {
-	    let p1 = Vector2F64::from_values(
-		(first_point.get_x() + second_point.get_x()) / 2.0,
-		(first_point.get_y() + second_point.get_y()) / 2.0
-	    );
-	    let p2 = Vector2F64::from_values(
-		(third_point.get_x() + second_point.get_x()) / 2.0,
-		(third_point.get_y() + second_point.get_y()) / 2.0
-	    );
-	    let focus = self.line_with_line(&p1, &d1, &p2, &d2).expect("expected intersection");
-	    let radius = distance_between_points(&focus, first_point);
-	    Some((focus, radius))
+    pub fn circle_through_points(&mut self, first_point: &F::Vector2, second_point: &F::Vector2, third_point: &F::Vector2) -> Option<(F::Vector2, F)> {
+	let first = to_vector2_f64(first_point);
+	let second = to_vector2_f64(second_point);
+	let third = to_vector2_f64(third_point);
+	let area = orient2d(&first, &second, &third);
+	if area == 0.0 {
+	    return None;
 	}
+	let (bx, by) = (second.get_x() - first.get_x(), second.get_y() - first.get_y());
+	let (cx, cy) = (third.get_x() - first.get_x(), third.get_y() - first.get_y());
+	let b_length = bx * bx + by * by;
+	let c_length = cx * cx + cy * cy;
+	let dx = (cy * b_length - by * c_length) / (2.0 * area);
+	let dy = (bx * c_length - cx * b_length) / (2.0 * area);
+	if !(dx.is_finite() && dy.is_finite()) {
+	    return None;
+	}
+	Some((
+	    F::Vector2::from_values(F::from_f64(first.get_x() + dx), F::from_f64(first.get_y() + dy)),
+	    F::from_f64(dx.hypot(dy))
+	))
     }
     
     /**
      * Sets the matrix to the specified parameter equation of two lines
      */
     
-    fn set_parameter_equations(&mut self, first_point: &Vector2F64, first_dir: &Vector2F64,
-				second_point: &Vector2F64, second_dir: &Vector2F64) {
+    fn set_parameter_equations(&mut self, first_point: &F::Vector2, first_dir: &F::Vector2,
+				second_point: &F::Vector2, second_dir: &F::Vector2) {
 	self.matrix.set(0, 0, first_dir.get_x());
 	self.matrix.set(0, 1, - second_dir.get_x());
 	self.matrix.set(0, 2, first_point.get_x() - second_point.get_x());
@@ -186,7 +296,7 @@ impl IntersectionCalculator {
     /**
      * Solves the line intersection system and returns the parameters
      */
-    fn solve_for_parameters(&mut self) -> Option<(f64, f64)>{
+    fn solve_for_parameters(&mut self) -> Option<(F, F)>{
 	solve_from_matrix(&mut self.matrix)
 	    .ok()
 	    .map(|solution| (solution[0], solution[1]))
@@ -195,8 +305,8 @@ impl IntersectionCalculator {
     /**
      * Substitutes the parameter in the line equation point + k * dir = the resultant point on the line
      */
-    fn substitute_parameter(point: &Vector2F64, dir: &Vector2F64, param: f64) -> Vector2F64 {
-	Vector2F64::from_values(
+    fn substitute_parameter(point: &F::Vector2, dir: &F::Vector2, param: F) -> F::Vector2 {
+	F::Vector2::from_values(
 	    point.get_x() + param * dir.get_x(),
 	    point.get_y() + param * dir.get_y()
 	)
@@ -209,8 +319,10 @@ impl IntersectionCalculator {
  * Intersects two lines specified by two start points and two directions
  * Returns None if both lines are either identical or parallel
  */
-pub fn intersect_line_with_line(first_point: &Vector2F64, first_dir: &Vector2F64, second_point: &Vector2F64, second_dir: &Vector2F64) -> Option<Vector2F64> {
-    let mut calculator = IntersectionCalculator::new();
+pub fn intersect_line_with_line<V>(first_point: &V, first_dir: &V, second_point: &V, second_dir: &V) -> Option<V> where
+    V: Vector2,
+    V::Value: Float<Vector2 = V> {
+    let mut calculator = IntersectionCalculator::<V::Value>::new();
     calculator.line_with_line(first_point, first_dir, second_point, second_dir)
 }
 
@@ -219,8 +331,10 @@ pub fn intersect_line_with_line(first_point: &Vector2F64, first_dir: &Vector2F64
  * Intersects two rays specified by two start points and two directions
  * Returns None if both rays are identical, parallel or do not intersect
  */
-pub fn intersect_ray_with_ray(first_point: &Vector2F64, first_dir: &Vector2F64, second_point: &Vector2F64, second_dir: &Vector2F64) -> Option<Vector2F64> {
-    let mut calculator = IntersectionCalculator::new();
+pub fn intersect_ray_with_ray<V>(first_point: &V, first_dir: &V, second_point: &V, second_dir: &V) -> Option<V> where
+    V: Vector2,
+    V::Value: Float<Vector2 = V> {
+    let mut calculator = IntersectionCalculator::<V::Value>::new();
     calculator.ray_with_ray(first_point, first_dir, second_point, second_dir)
 }
 
@@ -228,23 +342,27 @@ pub fn intersect_ray_with_ray(first_point: &Vector2F64, first_dir: &Vector2F64,
  * Intersects two rays specified by two start points and two directions
  * Returns None if both rays are identical, parallel or do not intersect
  */
-pub fn intersect_ray_with_segment(first_point: &Vector2F64, first_dir: &Vector2F64, second_start: &Vector2F64, second_end: &Vector2F64) -> Option<Vector2F64> {
-    let mut calculator = IntersectionCalculator::new();
+pub fn intersect_ray_with_segment<V>(first_point: &V, first_dir: &V, second_start: &V, second_end: &V) -> Option<V> where
+    V: Vector2,
+    V::Value: Float<Vector2 = V> {
+    let mut calculator = IntersectionCalculator::<V::Value>::new();
     calculator.ray_with_segment(first_point, first_dir, second_start, second_end)
 }
 
 /**
  * Intersects a ray with a bounding box
  */
-pub fn intersect_ray_with_bounding_box(first_point: &Vector2F64, first_dir: &Vector2F64, bounding_box: &BoundingBox) -> Option<Vector2F64>{
-    let mut calculator = IntersectionCalculator::new();
+pub fn intersect_ray_with_bounding_box<V>(first_point: &V, first_dir: &V, bounding_box: &BoundingBox<V::Value>) -> Option<V> where
+    V: Vector2,
+    V::Value: Float<Vector2 = V> {
+    let mut calculator = IntersectionCalculator::<V::Value>::new();
     calculator.ray_with_bounding_box(first_point, first_dir, bounding_box)
 }
 
 /**
  * Intersection between two parabolas
  */
-pub enum ParabolaIntersection {
+pub enum ParabolaIntersection<V = Vector2F64> {
 
     /**
      * No intersections
@@ -254,12 +372,12 @@ pub enum ParabolaIntersection {
     /**
      * One intersection
      */
-    One(Vector2F64),
+    One(V),
 
     /**
      * Two intersections
      */
-    Two(Vector2F64, Vector2F64),
+    Two(V, V),
 
     /**
      * An infinite amount of intersections (i.e. intersecting parabolas are identical)
@@ -271,7 +389,9 @@ pub enum ParabolaIntersection {
  * Calculates a parabola from a focus and a directrix.
  * E.g. P is point on parabola <=> dist(P, focus) = dist(P, directrix)
  */
-fn calc_parabola_from_focus(focus: &Vector2F64, dir_y: f64) -> (f64, f64, f64) {
+fn calc_parabola_from_focus<V>(focus: &V, dir_y: V::Value) -> (V::Value, V::Value, V::Value) where
+    V: Vector2,
+    V::Value: Float {
     //
     // dist²(P, focus) = dist²(P, dir)
     // (x - focus.x)² + (y - focus.y)² = (y - dir_y)²
@@ -280,8 +400,9 @@ fn calc_parabola_from_focus(focus: &Vector2F64, dir_y: f64) -> (f64, f64, f64) {
     // a = 1 / (2 * (focus.y - dir_y))
     // y = a x² - 2 * focus.x * a * x + (focus.x² + focus.y²  - dir_y²) * a
 
-    let a = 1.0 / (2.0 * (focus.get_y() - dir_y));
-    let b = - 2.0 * focus.get_x() * a;
+    let two = V::Value::from_f64(2.0);
+    let a = V::Value::from_f64(1.0) / (two * (focus.get_y() - dir_y));
+    let b = - two * focus.get_x() * a;
     let c = (focus.get_x() * focus.get_x() + focus.get_y() * focus.get_y() - dir_y * dir_y) * a;
     (a, b, c)
 }
@@ -289,64 +410,306 @@ fn calc_parabola_from_focus(focus: &Vector2F64, dir_y: f64) -> (f64, f64, f64) {
 /**
  * Calculates the intersection between two parabolas specified by their focii and a horizontal directrix
  */
-pub fn intersect_parabolas_from_foci(first_focus: &Vector2F64, second_focus: &Vector2F64, dir_y: f64) -> ParabolaIntersection{
+pub fn intersect_parabolas_from_foci<V>(first_focus: &V, second_focus: &V, dir_y: V::Value) -> ParabolaIntersection<V> where
+    V: Vector2,
+    V::Value: Float {
     let (a1, b1, c1) = calc_parabola_from_focus(first_focus, dir_y);
     let (a2, b2, c2) = calc_parabola_from_focus(second_focus, dir_y);
     let a = a2 - a1;
     let b = b2 - b1;
     let c = c2 - c1;
-    if a == 0.0 && b == 0.0 && c == 0.0 {
+    let zero = V::Value::from_f64(0.0);
+    if a == zero && b == zero && c == zero {
 	// degenerate case
 	ParabolaIntersection::Infinite
     } else {
 	match solve_quadratic(a, b, c) {
 	    QuadraticSolution::None => ParabolaIntersection::None,
 	    QuadraticSolution::One(x) => ParabolaIntersection::One(
-		Vector2F64::from_values(x, a1 * x * x + b1 * x + c1)
+		V::from_values(x, a1 * x * x + b1 * x + c1)
 	    ),
 	    QuadraticSolution::Two(x1, x2) => ParabolaIntersection::Two(
-		Vector2F64::from_values(x1, a1 * x1 * x1 + b1 * x1 + c1),
-		Vector2F64::from_values(x2, a1 * x2 * x2 + b1 * x2 + c1)
+		V::from_values(x1, a1 * x1 * x1 + b1 * x1 + c1),
+		V::from_values(x2, a1 * x2 * x2 + b1 * x2 + c1)
 	    ),
 	}
     }
 }
 
 /**
- * Calculates the distance between two points
+ * Returns the focus and radius of a circle through the three specified points or None if they are colinear
  */
-fn distance_between_points(first: &Vector2F64, second: &Vector2F64) -> f64 {
-    let dx = first.get_x() - second.get_x();
-    let dy = first.get_y() - second.get_y();
-    (dx * dx + dy * dy).sqrt()
+pub fn circle_through_points<V>(first: &V, second: &V, third: &V) -> Option<(V, V::Value)> where
+    V: Vector2,
+    V::Value: Float<Vector2 = V> {
+    let mut calculator = IntersectionCalculator::<V::Value>::new();
+    calculator.circle_through_points(first, second, third)
 }
 
 /**
- * Tests whether vectors are dependent
+ * Checks whether two vectors represent a clockwise turn
+ * The sign of the cross product is exact for vectors with f32 or f64 coordinates
  */
-fn vectors_are_dependent(first: &Vector2F64, second: &Vector2F64) -> bool {
-    first.get_x() * second.get_y() - second.get_x() * first.get_y() == 0.0
+pub fn is_clockwise<V>(first: &V, second: &V) -> bool where
+    V: Vector2,
+    V::Value: Float {
+    orient2d(&Vector2F64::from_values(0.0, 0.0), &to_vector2_f64(first), &to_vector2_f64(second)) < 0.0
 }
 
 /**
- * Returns the focus and radius of a circle through the three specified points or None if they are colinear
+ * Calculates the signed area of a polygon using the shoelace formula
+ * The area is positive if the points are ordered counter clockwise in a coordinate system where y points up
  */
-pub fn circle_through_points(first: &Vector2F64, second: &Vector2F64, third: &Vector2F64) -> Option<(Vector2F64, f64)>{
-    let mut calculator = IntersectionCalculator::new();
-    calculator.circle_through_points(first, second, third)
+pub fn polygon_signed_area(points: &[Vector2F64]) -> f64 {
+    let mut area = 0.0;
+    for (id, point) in points.iter().enumerate() {
+	let next = &points[(id + 1) % points.len()];
+	area += point.get_x() * next.get_y() - next.get_x() * point.get_y();
+    }
+    area / 2.0
 }
 
 /**
- * Checks whether two vectors represent a clockwise turn
+ * Calculates the centroid of the area enclosed by a polygon
+ * Returns None if the polygon has no area
  */
-pub fn is_clockwise(first: &Vector2F64, second: &Vector2F64) -> bool {
-    if first.get_x() * second.get_y() - first.get_y() * second.get_x() < 0.0 {
-	true
-    } else {
-	false
+pub fn polygon_centroid(points: &[Vector2F64]) -> Option<Vector2F64> {
+    let area = polygon_signed_area(points);
+    if area == 0.0 {
+	return None;
+    }
+    // translate to the first point to lose less precision on polygons far from the origin
+    let origin = &points[0];
+    let mut x = 0.0;
+    let mut y = 0.0;
+    for (id, point) in points.iter().enumerate() {
+	let next = &points[(id + 1) % points.len()];
+	let (x1, y1) = (point.get_x() - origin.get_x(), point.get_y() - origin.get_y());
+	let (x2, y2) = (next.get_x() - origin.get_x(), next.get_y() - origin.get_y());
+	let cross = x1 * y2 - x2 * y1;
+	x += (x1 + x2) * cross;
+	y += (y1 + y2) * cross;
     }
+    Some(Vector2F64::from_values(
+	origin.get_x() + x / (6.0 * area),
+	origin.get_y() + y / (6.0 * area)
+    ))
 }
 
+/**
+ * A closed polygon, given by its corners in order
+ * The corners may be ordered either way, and signed values follow polygon_signed_area.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polygon {
+    points: Vec<Vector2F64>,
+}
+
+impl Polygon {
+
+    pub fn new(points: Vec<Vector2F64>) -> Polygon {
+	Polygon {
+	    points,
+	}
+    }
+
+    pub fn points(&self) -> &[Vector2F64] {
+	&self.points
+    }
+
+    pub fn into_points(self) -> Vec<Vector2F64> {
+	self.points
+    }
+
+    pub fn len(&self) -> usize {
+	self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+	self.points.is_empty()
+    }
+
+    /**
+     * The area, which is positive if the points are ordered counter clockwise in a coordinate system where y points up
+     */
+    pub fn signed_area(&self) -> f64 {
+	polygon_signed_area(&self.points)
+    }
+
+    pub fn area(&self) -> f64 {
+	self.signed_area().abs()
+    }
+
+    /**
+     * The centroid of the enclosed area, or None if the polygon has no area
+     */
+    pub fn centroid(&self) -> Option<Vector2F64> {
+	polygon_centroid(&self.points)
+    }
+
+    /**
+     * The length of the boundary, including the edge from the last point back to the first
+     */
+    pub fn perimeter(&self) -> f64 {
+	self.edges().map(|(start, end)| distance(start, end)).sum()
+    }
+
+    /**
+     * Checks whether a point lies inside the polygon using the even odd rule
+     * Points on the boundary may be counted as inside or outside.
+     */
+    pub fn contains(&self, point: &Vector2F64) -> bool {
+	let (x, y) = (point.get_x(), point.get_y());
+	let mut inside = false;
+	for (start, end) in self.edges() {
+	    if (start.get_y() > y) != (end.get_y() > y) {
+		let t = (y - start.get_y()) / (end.get_y() - start.get_y());
+		if x < start.get_x() + t * (end.get_x() - start.get_x()) {
+		    inside = !inside;
+		}
+	    }
+	}
+	inside
+    }
+
+    /**
+     * Checks whether the polygon is convex, which means that it turns the same way at every corner and goes around
+     * only once
+     * Straight corners are allowed, but polygons without area are not convex.
+     */
+    pub fn is_convex(&self) -> bool {
+	let count = self.points.len();
+	let mut direction = 0.0;
+	let mut turn = 0.0;
+	for id in 0..count {
+	    let previous = &self.points[(id + count - 1) % count];
+	    let point = &self.points[id];
+	    let next = &self.points[(id + 1) % count];
+	    let orientation = orient2d(previous, point, next);
+	    if orientation * direction < 0.0 {
+		return false;
+	    }
+	    if orientation != 0.0 {
+		direction = orientation;
+	    }
+	    let (dx1, dy1) = (point.get_x() - previous.get_x(), point.get_y() - previous.get_y());
+	    let (dx2, dy2) = (next.get_x() - point.get_x(), next.get_y() - point.get_y());
+	    turn += (dx1 * dy2 - dy1 * dx2).atan2(dx1 * dx2 + dy1 * dy2);
+	}
+	// a polygon that winds around more than once turns by a multiple of four pi
+	direction != 0.0 && turn.abs() < 3.0 * std::f64::consts::PI
+    }
+
+    /**
+     * Moves every edge outwards by a distance, or inwards if it is negative, and joins the moved edges at their
+     * intersections
+     * Corners are not rounded, so sharp corners move further than the distance. Moving inwards by more than the
+     * polygon is wide gives a polygon that overlaps itself.
+     */
+    pub fn offset(&self, distance: f64) -> Polygon {
+	let mut points: Vec<Vector2F64> = vec![];
+	for point in self.points.iter() {
+	    if points.last() != Some(point) {
+		points.push(*point);
+	    }
+	}
+	while points.len() > 1 && points.first() == points.last() {
+	    points.pop();
+	}
+	if points.len() < 3 {
+	    return Polygon::new(points);
+	}
+	let side = polygon_signed_area(&points).signum();
+	let count = points.len();
+	let edges: Vec<(Vector2F64, Vector2F64)> = (0..count)
+	    .map(|id| {
+		let (start, end) = (&points[id], &points[(id + 1) % count]);
+		let (dx, dy) = (end.get_x() - start.get_x(), end.get_y() - start.get_y());
+		let length = dx.hypot(dy);
+		let normal = Vector2F64::from_values(side * dy / length, - side * dx / length);
+		let moved = Vector2F64::from_values(start.get_x() + normal.get_x() * distance, start.get_y() + normal.get_y() * distance);
+		(moved, Vector2F64::from_values(dx, dy))
+	    })
+	    .collect();
+	let offset = (0..count)
+	    .map(|id| {
+		let (previous_point, previous_dir) = &edges[(id + count - 1) % count];
+		let (point, dir) = &edges[id];
+		// edges that continue in the same direction are moved by the same amount
+		intersect_line_with_line(previous_point, previous_dir, point, dir).unwrap_or(*point)
+	    })
+	    .collect();
+	Polygon::new(offset)
+    }
+
+    /**
+     * Removes corners with the Douglas Peucker algorithm, so that no removed corner is further than the tolerance from
+     * the simplified boundary
+     * The first point and the point furthest from it are always kept, so polygons that are smaller than the tolerance
+     * become two points.
+     */
+    pub fn simplify(&self, tolerance: f64) -> Polygon {
+	let count = self.points.len();
+	if count < 4 {
+	    return self.clone();
+	}
+	let first = &self.points[0];
+	let furthest_id = (1..count)
+	    .max_by(|a, b| distance(first, &self.points[*a]).total_cmp(&distance(first, &self.points[*b])))
+	    .unwrap_or(0);
+	let mut keep = vec![false; count];
+	keep[0] = true;
+	keep[furthest_id] = true;
+	self.simplify_chain(0, furthest_id, tolerance, &mut keep);
+	self.simplify_chain(furthest_id, count, tolerance, &mut keep);
+	Polygon::new(self.points.iter().zip(keep).filter(|(_, keep)| *keep).map(|(point, _)| *point).collect())
+    }
+
+    /**
+     * Marks the corners to keep between two kept corners, where the end id wraps around to the first point
+     */
+    fn simplify_chain(&self, start_id: usize, end_id: usize, tolerance: f64, keep: &mut [bool]) {
+	if end_id <= start_id + 1 {
+	    return;
+	}
+	let start = &self.points[start_id];
+	let end = &self.points[end_id % self.points.len()];
+	let (furthest_id, furthest) = (start_id + 1..end_id)
+	    .map(|id| (id, segment_distance(&self.points[id], start, end)))
+	    .fold((start_id, 0.0), |furthest, candidate| if candidate.1 > furthest.1 { candidate } else { furthest });
+	if furthest > tolerance {
+	    keep[furthest_id] = true;
+	    self.simplify_chain(start_id, furthest_id, tolerance, keep);
+	    self.simplify_chain(furthest_id, end_id, tolerance, keep);
+	}
+    }
+
+    /**
+     * The edges as pairs of start and end points
+     */
+    fn edges(&self) -> impl Iterator<Item = (&Vector2F64, &Vector2F64)> {
+	let count = self.points.len();
+	self.points.iter().enumerate().map(move |(id, point)| (point, &self.points[(id + 1) % count]))
+    }
+
+}
+
+fn distance(first: &Vector2F64, second: &Vector2F64) -> f64 {
+    (second.get_x() - first.get_x()).hypot(second.get_y() - first.get_y())
+}
+
+/**
+ * The distance from a point to the closest point of a segment
+ */
+fn segment_distance(point: &Vector2F64, start: &Vector2F64, end: &Vector2F64) -> f64 {
+    let (dx, dy) = (end.get_x() - start.get_x(), end.get_y() - start.get_y());
+    let squared_length = dx * dx + dy * dy;
+    if squared_length == 0.0 {
+	return distance(point, start);
+    }
+    let t = (((point.get_x() - start.get_x()) * dx + (point.get_y() - start.get_y()) * dy) / squared_length).clamp(0.0, 1.0);
+    distance(point, &Vector2F64::from_values(start.get_x() + t * dx, start.get_y() + t * dy))
+}
 
 #[cfg(test)]
 mod test {
@@ -354,6 +717,14 @@ mod test {
     use float_eq::assert_float_eq;
     
     use super::*;
+    use crate::vector::Vector2F32;
+
+    /**
+     * Calculates the distance between two points
+     */
+    fn distance_between_points(first: &Vector2F64, second: &Vector2F64) -> f64 {
+	(first.get_x() - second.get_x()).hypot(first.get_y() - second.get_y())
+    }
 
     #[test]
     fn test_intersect_parallel_lines() {
@@ -517,6 +888,53 @@ mod test {
 	assert_eq!(intersect_ray_with_segment(&p1, &d1, &start, &end), None);
     }
 
+    #[test]
+    fn test_clip_segment_to_bounding_box() {
+	let bounds = BoundingBox::new(10.0, 20.0, -5.0, 5.0);
+	let p = Vector2F64::from_values(0.0, 0.0);
+	let d = Vector2F64::from_values(30.0, 0.0);
+
+	assert_eq!(bounds.clip_line(&p, &d, 0.0, 1.0), Some((1.0 / 3.0, 2.0 / 3.0)));
+	assert_eq!(bounds.clip_line(&p, &d, 0.0, 0.25), None);
+    }
+
+    #[test]
+    fn test_clip_line_to_bounding_box() {
+	let bounds = BoundingBox::new(0.0, 10.0, 0.0, 10.0);
+	let p = Vector2F64::from_values(5.0, 5.0);
+	let d = Vector2F64::from_values(0.0, 1.0);
+
+	assert_eq!(bounds.clip_line(&p, &d, f64::NEG_INFINITY, f64::INFINITY), Some((-5.0, 5.0)));
+	assert_eq!(bounds.clip_line(&p, &d, 0.0, f64::INFINITY), Some((0.0, 5.0)));
+    }
+
+    #[test]
+    fn test_clip_line_outside_bounding_box() {
+	let bounds = BoundingBox::new(0.0, 10.0, 0.0, 10.0);
+	let p = Vector2F64::from_values(-1.0, 5.0);
+	let d = Vector2F64::from_values(0.0, 1.0);
+
+	assert_eq!(bounds.clip_line(&p, &d, f64::NEG_INFINITY, f64::INFINITY), None);
+    }
+
+    #[test]
+    fn test_f32_geometry() {
+	let p1 = Vector2F32::from_values(1.0, 1.0);
+	let d1 = Vector2F32::from_values(1.0, 1.0);
+	let p2 = Vector2F32::from_values(-3.0, 4.0);
+	let d2 = Vector2F32::from_values(1.0, 0.0);
+	assert_eq!(intersect_line_with_line(&p1, &d1, &p2, &d2), Some(Vector2F32::from_values(4.0, 4.0)));
+
+	let bounds = BoundingBox::new(0.0f32, 10.0, 0.0, 10.0);
+	assert_eq!(bounds.clip_line(&p1, &d1, 0.0, f32::INFINITY), Some((0.0, 9.0)));
+
+	let (focus, radius) = circle_through_points(&Vector2F32::from_values(0.0, 0.0), &Vector2F32::from_values(4.0, 0.0),
+						    &Vector2F32::from_values(0.0, 4.0)).unwrap();
+	assert_float_eq!(2.0, focus.get_x(), abs <= 1e-5);
+	assert_float_eq!(2.0, focus.get_y(), abs <= 1e-5);
+	assert_float_eq!(8.0f32.sqrt(), radius, abs <= 1e-5);
+    }
+
     #[test]
     fn test_clockwise_vector() {
 	let p1 = Vector2F64::from_values(- 1.0, 1.0);
@@ -526,4 +944,122 @@ mod test {
 	assert_eq!(is_clockwise(&p2, &p1), false);
     }
     
+    #[test]
+    fn test_polygon_area() {
+	let square = vec![
+	    Vector2F64::from_values(1.0, 1.0),
+	    Vector2F64::from_values(3.0, 1.0),
+	    Vector2F64::from_values(3.0, 3.0),
+	    Vector2F64::from_values(1.0, 3.0),
+	];
+	assert_float_eq!(4.0, polygon_signed_area(&square), abs <= 1e-12);
+	let reversed: Vec<Vector2F64> = square.iter().rev().cloned().collect();
+	assert_float_eq!(-4.0, polygon_signed_area(&reversed), abs <= 1e-12);
+	assert_float_eq!(0.0, polygon_signed_area(&square[0..2]), abs <= 1e-12);
+    }
+
+    #[test]
+    fn test_polygon_centroid() {
+	let triangle = vec![
+	    Vector2F64::from_values(1000.0, 1000.0),
+	    Vector2F64::from_values(1003.0, 1000.0),
+	    Vector2F64::from_values(1000.0, 1003.0),
+	];
+	let centroid = polygon_centroid(&triangle).unwrap();
+	assert_float_eq!(1001.0, centroid.get_x(), abs <= 1e-9);
+	assert_float_eq!(1001.0, centroid.get_y(), abs <= 1e-9);
+	let l_shape = vec![
+	    Vector2F64::from_values(0.0, 0.0),
+	    Vector2F64::from_values(2.0, 0.0),
+	    Vector2F64::from_values(2.0, 1.0),
+	    Vector2F64::from_values(1.0, 1.0),
+	    Vector2F64::from_values(1.0, 2.0),
+	    Vector2F64::from_values(0.0, 2.0),
+	];
+	let centroid = polygon_centroid(&l_shape).unwrap();
+	assert_float_eq!(5.0 / 6.0, centroid.get_x(), abs <= 1e-12);
+	assert_float_eq!(5.0 / 6.0, centroid.get_y(), abs <= 1e-12);
+	assert!(polygon_centroid(&l_shape[0..2]).is_none());
+    }
+
+    fn create_polygon(points: &[(f64, f64)]) -> Polygon {
+	Polygon::new(points.iter().map(|(x, y)| Vector2F64::from_values(*x, *y)).collect())
+    }
+
+    #[test]
+    fn test_polygon_measures() {
+	let square = create_polygon(&[(1.0, 1.0), (3.0, 1.0), (3.0, 3.0), (1.0, 3.0)]);
+	assert_float_eq!(4.0, square.signed_area(), abs <= 1e-12);
+	assert_float_eq!(4.0, square.area(), abs <= 1e-12);
+	assert_float_eq!(8.0, square.perimeter(), abs <= 1e-12);
+	let centroid = square.centroid().unwrap();
+	assert_float_eq!(2.0, centroid.get_x(), abs <= 1e-12);
+	assert_float_eq!(2.0, centroid.get_y(), abs <= 1e-12);
+	let reversed = Polygon::new(square.points().iter().rev().cloned().collect());
+	assert_float_eq!(-4.0, reversed.signed_area(), abs <= 1e-12);
+	assert_float_eq!(4.0, reversed.area(), abs <= 1e-12);
+    }
+
+    #[test]
+    fn test_polygon_contains() {
+	let l_shape = create_polygon(&[(0.0, 0.0), (2.0, 0.0), (2.0, 1.0), (1.0, 1.0), (1.0, 2.0), (0.0, 2.0)]);
+	assert!(l_shape.contains(&Vector2F64::from_values(0.5, 0.5)));
+	assert!(l_shape.contains(&Vector2F64::from_values(1.5, 0.5)));
+	assert!(l_shape.contains(&Vector2F64::from_values(0.5, 1.5)));
+	assert!(!l_shape.contains(&Vector2F64::from_values(1.5, 1.5)));
+	assert!(!l_shape.contains(&Vector2F64::from_values(-0.5, 0.5)));
+	assert!(!l_shape.contains(&Vector2F64::from_values(0.5, 2.5)));
+	assert!(!Polygon::new(vec![]).contains(&Vector2F64::from_values(0.0, 0.0)));
+    }
+
+    #[test]
+    fn test_polygon_convex() {
+	let square = create_polygon(&[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]);
+	assert!(square.is_convex());
+	assert!(Polygon::new(square.points().iter().rev().cloned().collect()).is_convex());
+	assert!(create_polygon(&[(0.0, 0.0), (0.5, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]).is_convex());
+	assert!(!create_polygon(&[(0.0, 0.0), (2.0, 0.0), (2.0, 1.0), (1.0, 1.0), (1.0, 2.0), (0.0, 2.0)]).is_convex());
+	// a pentagram turns the same way at every corner but goes around twice
+	let star: Vec<(f64, f64)> = (0..5)
+	    .map(|id| (id as f64 * 4.0 * std::f64::consts::PI / 5.0).sin_cos())
+	    .collect();
+	assert!(!create_polygon(&star).is_convex());
+	assert!(!create_polygon(&[(0.0, 0.0), (1.0, 0.0), (2.0, 0.0)]).is_convex());
+    }
+
+    #[test]
+    fn test_polygon_offset() {
+	let square = create_polygon(&[(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0)]);
+	let outer = square.offset(1.0);
+	assert_eq!(4, outer.len());
+	assert_float_eq!(16.0, outer.area(), abs <= 1e-12);
+	assert!(outer.contains(&Vector2F64::from_values(-0.5, 1.0)));
+	let inner = square.offset(-0.5);
+	assert_float_eq!(1.0, inner.area(), abs <= 1e-12);
+	assert!(!inner.contains(&Vector2F64::from_values(0.25, 1.0)));
+	// the direction does not depend on the order of the points, and straight corners and repeated points are kept
+	let reversed = create_polygon(&[(0.0, 2.0), (2.0, 2.0), (2.0, 2.0), (2.0, 0.0), (1.0, 0.0), (0.0, 0.0)]);
+	let outer = reversed.offset(1.0);
+	assert_eq!(5, outer.len());
+	assert_float_eq!(-16.0, outer.signed_area(), abs <= 1e-12);
+	assert!(outer.points().contains(&Vector2F64::from_values(1.0, -1.0)));
+    }
+
+    #[test]
+    fn test_polygon_simplify() {
+	// a square with a small bump and points along its edges
+	let polygon = create_polygon(&[
+	    (0.0, 0.0), (5.0, 0.0), (10.0, 0.0), (10.0, 5.0), (10.1, 6.0), (10.0, 7.0),
+	    (10.0, 10.0), (5.0, 10.05), (0.0, 10.0), (0.0, 5.0),
+	]);
+	let simplified = polygon.simplify(0.2);
+	assert_eq!(create_polygon(&[(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)]), simplified);
+	let detailed = polygon.simplify(0.07);
+	assert!(detailed.points().contains(&Vector2F64::from_values(10.1, 6.0)));
+	assert!(!detailed.points().contains(&Vector2F64::from_values(5.0, 10.05)));
+	// only the points along straight edges are removed without a tolerance
+	assert_eq!(8, polygon.simplify(0.0).len());
+	assert_eq!(2, polygon.simplify(100.0).len());
+    }
+
 }
@@ -0,0 +1,472 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * Searches over the graph of faces, where two faces are connected if they share an edge
+ *
+ * Weighted searches take the cost of crossing every half edge from its face into the face of its twin, or None if it
+ * can not be crossed, so the same search works for distances, terrain penalties or borders that can not be passed.
+ */
+
+use crate::queue::IndexedQueue;
+use crate::vector::Vector2;
+use crate::voronoi::{Diagram, HalfEdge};
+
+use std::collections::VecDeque;
+
+/**
+ * A path between two faces
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct FacePath {
+    face_ids: Vec<usize>,
+    cost: f64,
+}
+
+impl FacePath {
+
+    /**
+     * The faces along the path, from the start face to the goal face
+     */
+    pub fn face_ids(&self) -> &[usize] {
+	&self.face_ids
+    }
+
+    /**
+     * The sum of the costs of the half edges that are crossed
+     */
+    pub fn cost(&self) -> f64 {
+	self.cost
+    }
+
+}
+
+/**
+ * The connected components of the faces, numbered in the order of their smallest face id
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Components {
+    face_components: Vec<usize>,
+    count: usize,
+}
+
+impl Components {
+
+    pub fn count(&self) -> usize {
+	self.count
+    }
+
+    /**
+     * The component of a face
+     */
+    pub fn component(&self, face_id: usize) -> usize {
+	self.face_components[face_id]
+    }
+
+    /**
+     * The components of all faces, indexed by face id
+     */
+    pub fn face_components(&self) -> &[usize] {
+	&self.face_components
+    }
+
+    /**
+     * The faces of a component, in ascending order
+     */
+    pub fn face_ids(&self, component: usize) -> Vec<usize> {
+	(0..self.face_components.len()).filter(|face_id| self.face_components[*face_id] == component).collect()
+    }
+
+}
+
+impl<T> Diagram<T> {
+
+    /**
+     * The faces that can be reached from a start face through faces that pass a test, in breadth first order
+     * The start face is always included.
+     */
+    pub fn flood_fill<P>(&self, start_id: usize, passable: P) -> Vec<usize> where P: Fn(usize) -> bool {
+	let mut visited = vec![false; self.faces.len()];
+	visited[start_id] = true;
+	let mut face_ids = vec![start_id];
+	let mut next = 0;
+	while next < face_ids.len() {
+	    let face_id = face_ids[next];
+	    next += 1;
+	    for neighbour_id in self.neighbours(face_id) {
+		if !visited[neighbour_id] && passable(neighbour_id) {
+		    visited[neighbour_id] = true;
+		    face_ids.push(neighbour_id);
+		}
+	    }
+	}
+	face_ids
+    }
+
+    /**
+     * The smallest number of edges between every face and the nearest source face, or None if it can not be reached
+     */
+    pub fn face_steps(&self, source_ids: &[usize]) -> Vec<Option<usize>> {
+	let mut steps = vec![None; self.faces.len()];
+	let mut queue = VecDeque::new();
+	for source_id in source_ids.iter() {
+	    if steps[*source_id].is_none() {
+		steps[*source_id] = Some(0);
+		queue.push_back(*source_id);
+	    }
+	}
+	while let Some(face_id) = queue.pop_front() {
+	    let step = steps[face_id].map(|step| step + 1);
+	    for neighbour_id in self.neighbours(face_id) {
+		if steps[neighbour_id].is_none() {
+		    steps[neighbour_id] = step;
+		    queue.push_back(neighbour_id);
+		}
+	    }
+	}
+	steps
+    }
+
+    /**
+     * Splits the faces into components, where neighbouring faces are in the same component if they are connected
+     */
+    pub fn connected_components<P>(&self, connected: P) -> Components where P: Fn(usize, usize) -> bool {
+	let mut face_components = vec![usize::MAX; self.faces.len()];
+	let mut count = 0;
+	let mut stack = vec![];
+	for start_id in 0..self.faces.len() {
+	    if face_components[start_id] != usize::MAX {
+		continue;
+	    }
+	    face_components[start_id] = count;
+	    stack.push(start_id);
+	    while let Some(face_id) = stack.pop() {
+		for neighbour_id in self.neighbours(face_id) {
+		    if face_components[neighbour_id] == usize::MAX && connected(face_id, neighbour_id) {
+			face_components[neighbour_id] = count;
+			stack.push(neighbour_id);
+		    }
+		}
+	    }
+	    count += 1;
+	}
+	Components {
+	    face_components,
+	    count,
+	}
+    }
+
+    /**
+     * The cost of the cheapest path from the nearest source face to every face, or None if it can not be reached
+     * Costs must not be negative.
+     */
+    pub fn path_costs<C>(&self, source_ids: &[usize], cost: C) -> Vec<Option<f64>> where C: Fn(&HalfEdge) -> Option<f64> {
+	let mut search = Search::new(self.faces.len());
+	for source_id in source_ids.iter() {
+	    search.push(*source_id, 0.0, 0.0, None);
+	}
+	while let Some(face_id) = search.pop() {
+	    self.relax_neighbours(&mut search, face_id, &cost, |_| 0.0);
+	}
+	search.costs
+    }
+
+    /**
+     * Finds the cheapest path between two faces with Dijkstra's algorithm, or None if there is none
+     * Costs must not be negative.
+     */
+    pub fn shortest_path<C>(&self, start_id: usize, goal_id: usize, cost: C) -> Option<FacePath> where C: Fn(&HalfEdge) -> Option<f64> {
+	self.a_star(start_id, goal_id, cost, |_| 0.0)
+    }
+
+    /**
+     * Finds the cheapest path between two faces with A*, or None if there is none
+     * The heuristic estimates the cost from a face to the goal, and the path is the cheapest one as long as the
+     * heuristic never overestimates. Costs must not be negative.
+     */
+    pub fn a_star<C, H>(&self, start_id: usize, goal_id: usize, cost: C, heuristic: H) -> Option<FacePath> where C: Fn(&HalfEdge) -> Option<f64>, H: Fn(usize) -> f64 {
+	let mut search = Search::new(self.faces.len());
+	search.push(start_id, 0.0, heuristic(start_id), None);
+	while let Some(face_id) = search.pop() {
+	    if face_id == goal_id {
+		let mut face_ids = vec![goal_id];
+		while let Some(previous_id) = search.previous_ids[face_ids[face_ids.len() - 1]] {
+		    face_ids.push(previous_id);
+		}
+		face_ids.reverse();
+		return Some(FacePath {
+		    face_ids,
+		    cost: search.costs[goal_id].unwrap_or(0.0),
+		});
+	    }
+	    self.relax_neighbours(&mut search, face_id, &cost, &heuristic);
+	}
+	None
+    }
+
+    fn relax_neighbours<C, H>(&self, search: &mut Search, face_id: usize, cost: &C, heuristic: H) where C: Fn(&HalfEdge) -> Option<f64>, H: Fn(usize) -> f64 {
+	let face_cost = search.costs[face_id].unwrap_or(0.0);
+	for half_edge in self.face_half_edges(face_id) {
+	    let neighbour_id = match half_edge.twin_id {
+		Some(twin_id) => self.half_edges[twin_id].face_id,
+		None => continue,
+	    };
+	    if search.done[neighbour_id] {
+		continue;
+	    }
+	    if let Some(edge_cost) = cost(half_edge) {
+		let neighbour_cost = face_cost + edge_cost;
+		if search.costs[neighbour_id].is_none_or(|current| neighbour_cost < current) {
+		    search.push(neighbour_id, neighbour_cost, neighbour_cost + heuristic(neighbour_id), Some(face_id));
+		}
+	    }
+	}
+    }
+
+    /**
+     * A cost that is the distance between the sites on both sides of a half edge, measured across the seams of
+     * periodic diagrams
+     */
+    pub fn site_distance(&self) -> impl Fn(&HalfEdge) -> Option<f64> + '_ {
+	move |half_edge| {
+	    let twin_id = half_edge.twin_id?;
+	    Some(self.face_distance(half_edge.face_id, self.half_edges[twin_id].face_id))
+	}
+    }
+
+    /**
+     * A cost that is the length of a half edge
+     */
+    pub fn edge_length(&self) -> impl Fn(&HalfEdge) -> Option<f64> + '_ {
+	move |half_edge| {
+	    let start = self.half_edge_start(half_edge.id);
+	    let end = self.half_edge_start(half_edge.next_id);
+	    Some((end.get_x() - start.get_x()).hypot(end.get_y() - start.get_y()))
+	}
+    }
+
+    /**
+     * A heuristic for A* that is the straight distance from the site of a face to the site of the goal face, which
+     * never overestimates the site distance cost
+     */
+    pub fn site_distance_to(&self, goal_id: usize) -> impl Fn(usize) -> f64 + '_ {
+	move |face_id| self.face_distance(face_id, goal_id)
+    }
+
+    fn face_distance(&self, first_id: usize, second_id: usize) -> f64 {
+	let (first, second) = (&self.faces[first_id], &self.faces[second_id]);
+	let (dx, dy) = self.wrap.shortest_offset(&self.bounds, second.x - first.x, second.y - first.y);
+	dx.hypot(dy)
+    }
+
+}
+
+/**
+ * The state of a weighted search
+ */
+struct Search {
+    queue: IndexedQueue<f64, usize>,
+    handles: Vec<Option<usize>>,
+    costs: Vec<Option<f64>>,
+    previous_ids: Vec<Option<usize>>,
+    done: Vec<bool>,
+}
+
+impl Search {
+
+    fn new(face_count: usize) -> Search {
+	Search {
+	    queue: IndexedQueue::new(),
+	    handles: vec![None; face_count],
+	    costs: vec![None; face_count],
+	    previous_ids: vec![None; face_count],
+	    done: vec![false; face_count],
+	}
+    }
+
+    /**
+     * Queues a face with a new lower cost, replacing its entry if it is already queued
+     */
+    fn push(&mut self, face_id: usize, cost: f64, priority: f64, previous_id: Option<usize>) {
+	if let Some(handle) = self.handles[face_id] {
+	    self.queue.remove(handle);
+	}
+	self.costs[face_id] = Some(cost);
+	self.previous_ids[face_id] = previous_id;
+	self.handles[face_id] = Some(self.queue.push(priority, face_id));
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+	let (_, _, face_id) = self.queue.pop()?;
+	self.handles[face_id] = None;
+	self.done[face_id] = true;
+	Some(face_id)
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::geom::BoundingBox;
+    use crate::periodic::Wrap;
+    use crate::sites;
+    use crate::voronoi::DiagramBuilder;
+
+    use float_eq::assert_float_eq;
+
+    fn create_diagram(wrap: Wrap) -> Diagram {
+	let bounds = BoundingBox::new(0.0, 100.0, 0.0, 100.0);
+	let mut builder = DiagramBuilder::new(bounds.clone());
+	builder.set_wrap(wrap);
+	builder.add_sites(sites::jittered_grid(&bounds, 10.0, 0.5, 6));
+	builder.build().unwrap()
+    }
+
+    /**
+     * Checks that consecutive faces of a path are neighbours and that its cost is the sum of the crossed edges
+     */
+    fn check_path<C>(diagram: &Diagram, path: &FacePath, cost: C) where C: Fn(&HalfEdge) -> Option<f64> {
+	let mut total = 0.0;
+	for pair in path.face_ids().windows(2) {
+	    let half_edge = diagram.face_half_edges(pair[0])
+		.find(|half_edge| half_edge.twin_id().map(|twin_id| diagram.half_edges()[twin_id].face_id()) == Some(pair[1]))
+		.expect("faces along a path should be neighbours");
+	    total += cost(half_edge).unwrap();
+	}
+	assert_float_eq!(total, path.cost(), abs <= 1e-9);
+    }
+
+    #[test]
+    fn test_flood_fill() {
+	let diagram = create_diagram(Wrap::None);
+	let start_id = diagram.locate(5.0, 5.0).unwrap();
+	let left = diagram.flood_fill(start_id, |face_id| diagram.faces()[face_id].x() < 50.0);
+	assert_eq!(start_id, left[0]);
+	assert_eq!(diagram.faces().iter().filter(|face| face.x() < 50.0).count(), left.len());
+	let all = diagram.flood_fill(start_id, |_| true);
+	assert_eq!(diagram.faces().len(), all.len());
+	assert_eq!(vec![start_id], diagram.flood_fill(start_id, |_| false));
+    }
+
+    #[test]
+    fn test_face_steps() {
+	let diagram = create_diagram(Wrap::None);
+	let steps = diagram.face_steps(&[0]);
+	assert_eq!(Some(0), steps[0]);
+	for face in diagram.faces() {
+	    let step = steps[face.id()].unwrap();
+	    for neighbour_id in diagram.neighbours(face.id()) {
+		let neighbour_step = steps[neighbour_id].unwrap();
+		assert!(neighbour_step + 1 >= step && step + 1 >= neighbour_step);
+	    }
+	    if step > 0 {
+		assert!(diagram.neighbours(face.id()).any(|neighbour_id| steps[neighbour_id] == Some(step - 1)));
+	    }
+	}
+	assert!(diagram.face_steps(&[]).iter().all(|step| step.is_none()));
+    }
+
+    #[test]
+    fn test_connected_components() {
+	let diagram = create_diagram(Wrap::None);
+	let side = |face_id: usize| diagram.faces()[face_id].x() < 50.0;
+	let components = diagram.connected_components(|first_id, second_id| side(first_id) == side(second_id));
+	assert_eq!(2, components.count());
+	assert_eq!(0, components.component(0));
+	for face in diagram.faces() {
+	    assert_eq!(side(face.id()) == side(0), components.component(face.id()) == 0);
+	}
+	let total: usize = (0..components.count()).map(|component| components.face_ids(component).len()).sum();
+	assert_eq!(diagram.faces().len(), total);
+	assert_eq!(diagram.faces().len(), diagram.connected_components(|_, _| false).count());
+    }
+
+    #[test]
+    fn test_shortest_path() {
+	let diagram = create_diagram(Wrap::None);
+	let start_id = diagram.locate(5.0, 5.0).unwrap();
+	let goal_id = diagram.locate(95.0, 95.0).unwrap();
+	let path = diagram.shortest_path(start_id, goal_id, diagram.site_distance()).unwrap();
+	assert_eq!(Some(&start_id), path.face_ids().first());
+	assert_eq!(Some(&goal_id), path.face_ids().last());
+	check_path(&diagram, &path, diagram.site_distance());
+	// the path can not be shorter than the straight line, and is not much longer on an even grid
+	let straight = diagram.site_distance_to(goal_id)(start_id);
+	assert!(path.cost() >= straight && path.cost() < straight * 1.3);
+	assert_float_eq!(path.cost(), diagram.path_costs(&[start_id], diagram.site_distance())[goal_id].unwrap(), abs <= 1e-9);
+	let path = diagram.shortest_path(start_id, goal_id, diagram.edge_length()).unwrap();
+	check_path(&diagram, &path, diagram.edge_length());
+	assert_eq!(vec![start_id], diagram.shortest_path(start_id, start_id, diagram.site_distance()).unwrap().face_ids());
+    }
+
+    #[test]
+    fn test_a_star() {
+	let diagram = create_diagram(Wrap::None);
+	for (start_id, goal_id) in [(0, 99), (17, 62), (45, 3)].iter() {
+	    let dijkstra = diagram.shortest_path(*start_id, *goal_id, diagram.site_distance()).unwrap();
+	    let a_star = diagram.a_star(*start_id, *goal_id, diagram.site_distance(), diagram.site_distance_to(*goal_id)).unwrap();
+	    check_path(&diagram, &a_star, diagram.site_distance());
+	    assert_float_eq!(dijkstra.cost(), a_star.cost(), abs <= 1e-9);
+	}
+    }
+
+    #[test]
+    fn test_impassable() {
+	let diagram = create_diagram(Wrap::None);
+	// a wall of the faces across x = 50, with a gap at the bottom
+	let crosses = |face_id: usize| {
+	    let polygon = diagram.face_polygon(face_id);
+	    polygon.iter().any(|point| point.get_x() < 50.0) && polygon.iter().any(|point| point.get_x() > 50.0)
+	};
+	let in_wall = |face_id: usize| crosses(face_id) && diagram.faces()[face_id].y() < 80.0;
+	let site_distance = diagram.site_distance();
+	let cost = |half_edge: &HalfEdge| {
+	    let twin_id = half_edge.twin_id()?;
+	    if in_wall(diagram.half_edges()[twin_id].face_id()) {
+		None
+	    } else {
+		site_distance(half_edge)
+	    }
+	};
+	let start_id = diagram.locate(20.0, 20.0).unwrap();
+	let goal_id = diagram.locate(80.0, 20.0).unwrap();
+	let path = diagram.shortest_path(start_id, goal_id, cost).unwrap();
+	assert!(path.face_ids().iter().all(|face_id| !in_wall(*face_id)));
+	assert!(path.face_ids().iter().any(|face_id| diagram.faces()[*face_id].y() > 75.0));
+	// closing the gap leaves no path
+	let closed = |half_edge: &HalfEdge| {
+	    let twin_id = half_edge.twin_id()?;
+	    if crosses(diagram.half_edges()[twin_id].face_id()) { None } else { site_distance(half_edge) }
+	};
+	assert!(diagram.shortest_path(start_id, goal_id, closed).is_none());
+	assert!(diagram.path_costs(&[start_id], closed)[goal_id].is_none());
+    }
+
+    #[test]
+    fn test_periodic() {
+	let diagram = create_diagram(Wrap::Horizontal);
+	let start_id = diagram.locate(5.0, 50.0).unwrap();
+	let goal_id = diagram.locate(95.0, 50.0).unwrap();
+	// the faces are close across the seam
+	let path = diagram.shortest_path(start_id, goal_id, diagram.site_distance()).unwrap();
+	assert!(path.face_ids().len() <= 3, "{:?}", path.face_ids());
+	assert!(path.cost() < 20.0);
+	assert_eq!(Some(1), diagram.face_steps(&[start_id])[goal_id]);
+    }
+
+}
@@ -22,6 +22,7 @@
 /**
  * A color struct
  */
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Color {
     pub red: f32,
     pub green: f32,
@@ -37,6 +38,18 @@ impl Color {
     pub fn black() -> Color {
 	Color{red: 0.0, green: 0.0, blue: 0.0, alpha: 1.0}
     }
+
+    /**
+     * Creates an opaque color from its hexadecimal RGB code, such as 0x336699
+     */
+    pub fn from_rgb(rgb: u32) -> Color {
+	Color {
+	    red: ((rgb >> 16) & 0xff) as f32 / 255.0,
+	    green: ((rgb >> 8) & 0xff) as f32 / 255.0,
+	    blue: (rgb & 0xff) as f32 / 255.0,
+	    alpha: 1.0,
+	}
+    }
     
 }
 
@@ -109,6 +122,20 @@ pub trait Graphics {
     
     fn create_triangles(&mut self, vertices: Vec<f32>, indices: Vec<u32>) -> Result<VertexArrayRef, GraphicsError>;
 
+    ///
+    /// Creates a vertex buffer filled with colored triangles.
+    /// Vertices should be specified as x, y, z, r, g, b
+    ///
+
+    fn create_colored_triangles(&mut self, vertices: Vec<f32>, indices: Vec<u32>) -> Result<VertexArrayRef, GraphicsError>;
+
+    ///
+    /// Creates a vertex buffer filled with lines, where every pair of indices is a line.
+    /// Coordinates should be specified as x, y, z
+    ///
+
+    fn create_lines(&mut self, vertices: Vec<f32>, indices: Vec<u32>) -> Result<VertexArrayRef, GraphicsError>;
+
     ///
     /// Creates and uses a graphics pipeline program
     ///
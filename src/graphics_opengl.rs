@@ -145,15 +145,21 @@ struct VertexArray {
     /// The number of vertices to draw
     ///
     vertex_count: i32,
+
+    ///
+    /// The kind of primitives the vertices form, such as triangles or lines
+    ///
+    mode: u32,
 }
 
 impl VertexArray {
 
     ///
     /// Creates a new VAO and associated VBO's
+    /// The attributes are the number of floats of each vertex attribute, in the order in which they are interleaved
     ///
     
-    fn new(vertices: Vec<f32>, indices: Vec<u32>) -> VertexArray{
+    fn new(vertices: Vec<f32>, indices: Vec<u32>, attributes: &[usize], mode: u32) -> VertexArray{
 	let (vertex_array_id, vertex_buffer_id, element_buffer_id) = unsafe{
 
 	    let mut vertex_buffer_id = 0;
@@ -173,16 +179,21 @@ impl VertexArray {
 		vertices.as_ptr().cast(),
 		gl::STATIC_DRAW
 	    );
-	    
-	    gl::VertexAttribPointer(
-		0,
-		3,
-		gl::FLOAT,
-		gl::FALSE,
-		(std::mem::size_of::<f32>() * 3).try_into().unwrap(),
-		0 as *const _,
-	    );
-	    gl::EnableVertexAttribArray(0);
+
+	    let stride = std::mem::size_of::<f32>() * attributes.iter().sum::<usize>();
+	    let mut offset = 0;
+	    for (location, size) in attributes.iter().enumerate() {
+		gl::VertexAttribPointer(
+		    location as u32,
+		    *size as i32,
+		    gl::FLOAT,
+		    gl::FALSE,
+		    stride.try_into().unwrap(),
+		    offset as *const _,
+		);
+		gl::EnableVertexAttribArray(location as u32);
+		offset += std::mem::size_of::<f32>() * size;
+	    }
 
 	    gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, element_buffer_id);
 	    gl::BufferData(
@@ -199,13 +210,14 @@ impl VertexArray {
 	    element_buffer_id,
 	    vertex_array_id,
 	    vertex_count: indices.len().try_into().unwrap(),
+	    mode,
 	}
     }
 
     fn draw(&self) {
 	unsafe {
 	    gl::BindVertexArray(self.vertex_array_id);
-	    gl::DrawElements(gl::TRIANGLES, self.vertex_count, gl::UNSIGNED_INT, 0 as * const _);
+	    gl::DrawElements(self.mode, self.vertex_count, gl::UNSIGNED_INT, 0 as * const _);
 	}
     }
 }
@@ -318,7 +330,7 @@ impl OpenGLGraphics {
 	let context = sdl_window.gl_create_context().expect("could not load OpenGL context");
 	gl::load_with(|s| sdl_video.gl_get_proc_address(s) as * const std::os::raw::c_void);
 	unsafe {
-	    gl::PolygonMode(gl::FRONT_AND_BACK, gl::LINE);
+	    gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
 	}
 	OpenGLGraphics {
 	    context,
@@ -369,7 +381,25 @@ impl Graphics for OpenGLGraphics {
     ///
     fn create_triangles(&mut self, vertices: Vec<f32>, indices: Vec<u32>) -> Result<VertexArrayRef, GraphicsError> {
 	let id = self.vertex_arrays.len();
-	self.vertex_arrays.push(VertexArray::new(vertices, indices));
+	self.vertex_arrays.push(VertexArray::new(vertices, indices, &[3], gl::TRIANGLES));
+	Ok(id)
+    }
+
+    ///
+    /// Creates a vertex buffer filled with colored triangle data and returns a reference to it
+    ///
+    fn create_colored_triangles(&mut self, vertices: Vec<f32>, indices: Vec<u32>) -> Result<VertexArrayRef, GraphicsError> {
+	let id = self.vertex_arrays.len();
+	self.vertex_arrays.push(VertexArray::new(vertices, indices, &[3, 3], gl::TRIANGLES));
+	Ok(id)
+    }
+
+    ///
+    /// Creates a vertex buffer filled with line data and returns a reference to it
+    ///
+    fn create_lines(&mut self, vertices: Vec<f32>, indices: Vec<u32>) -> Result<VertexArrayRef, GraphicsError> {
+	let id = self.vertex_arrays.len();
+	self.vertex_arrays.push(VertexArray::new(vertices, indices, &[3], gl::LINES));
 	Ok(id)
     }
 
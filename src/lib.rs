@@ -0,0 +1,57 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * Library root, exposing the diagram generation and graphics modules to the application and other tools
+ */
+
+pub mod attributes;
+pub mod clip;
+pub mod delaunay;
+pub mod edit;
+pub mod float;
+pub mod geom;
+pub mod graph;
+pub mod graphics;
+pub mod graphics_opengl;
+pub mod linear;
+pub mod locate;
+pub mod map;
+pub mod quadratic;
+pub mod matrix;
+pub mod minor_matrix_view;
+#[cfg(feature = "parallel")]
+pub mod parallel;
+pub mod periodic;
+pub mod power;
+pub mod predicates;
+pub mod queue;
+pub mod random;
+pub mod region;
+pub mod relax;
+pub mod row_matrix_view;
+pub mod serialize;
+pub mod settings;
+pub mod sites;
+pub mod sphere;
+pub mod sub_matrix_view;
+pub mod svg;
+pub mod transposed_matrix_view;
+pub mod transform;
+pub mod validate;
+pub mod vector;
+pub mod voronoi;
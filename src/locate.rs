@@ -0,0 +1,186 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * Point location in voronoi diagrams
+ */
+
+use crate::vector::{Vector2, Vector2F64};
+use crate::voronoi::Diagram;
+
+/**
+ * Spatial index that finds the face containing a point
+ *
+ * The bounds are divided into a grid with about one site per cell. Every cell remembers the face containing
+ * its center, from which a query walks to the face containing the point in a few steps. Queries on clipped
+ * diagrams return None outside the clip polygon, like Diagram::locate_from.
+ */
+pub struct PointLocator<'a, T> {
+
+    /**
+     * The indexed diagram
+     */
+    diagram: &'a Diagram<T>,
+
+    /**
+     * The number of grid cells along the x axis
+     */
+    columns: usize,
+
+    /**
+     * The number of grid cells along the y axis
+     */
+    rows: usize,
+
+    /**
+     * The face whose site is closest to the center of each grid cell, row by row
+     */
+    face_ids: Vec<usize>,
+}
+
+impl<'a, T> PointLocator<'a, T> {
+
+    /**
+     * Creates the index for a diagram
+     */
+    pub fn new(diagram: &'a Diagram<T>) -> PointLocator<'a, T> {
+	let bounds = diagram.bounds();
+	let count = diagram.faces().len().max(1) as f64;
+	let aspect = bounds.width() / bounds.height();
+	let columns = ((count * aspect).sqrt().ceil() as usize).max(1);
+	let rows = ((count / aspect).sqrt().ceil() as usize).max(1);
+	let mut face_ids = Vec::with_capacity(columns * rows);
+	if !diagram.faces().is_empty() {
+	    let mut face_id = 0;
+	    for row in 0..rows {
+		for column in 0..columns {
+		    let x = bounds.left() + (column as f64 + 0.5) * bounds.width() / columns as f64;
+		    let y = bounds.top() + (row as f64 + 0.5) * bounds.height() / rows as f64;
+		    // the previous cell is close by, so the walk is short
+		    face_id = diagram.walk_to_closest(face_id, x, y);
+		    face_ids.push(face_id);
+		}
+	    }
+	}
+	PointLocator {
+	    diagram,
+	    columns,
+	    rows,
+	    face_ids,
+	}
+    }
+
+    /**
+     * Finds the face containing a point, or None if the point lies outside the bounds or the clip polygon
+     */
+    pub fn locate(&self, x: f64, y: f64) -> Option<usize> {
+	let bounds = self.diagram.bounds();
+	if self.face_ids.is_empty() || !bounds.contains(&Vector2F64::from_values(x, y)) {
+	    return None;
+	}
+	let column = (((x - bounds.left()) / bounds.width() * self.columns as f64) as usize).min(self.columns - 1);
+	let row = (((y - bounds.top()) / bounds.height() * self.rows as f64) as usize).min(self.rows - 1);
+	self.diagram.locate_from(self.face_ids[row * self.columns + column], x, y)
+    }
+
+    /**
+     * Finds the face containing a point, or None if the point lies outside the bounds or the clip polygon
+     */
+    pub fn locate_point(&self, point: &Vector2F64) -> Option<usize> {
+	self.locate(point.get_x(), point.get_y())
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::clip::ClipPolygon;
+    use crate::geom::BoundingBox;
+    use crate::random::random_sites;
+    use crate::voronoi::DiagramBuilder;
+
+    /**
+     * Finds the face with the closest site by comparing all sites
+     */
+    fn find_closest_face<T>(diagram: &Diagram<T>, x: f64, y: f64) -> usize {
+	let mut closest_id = 0;
+	for face in diagram.faces().iter() {
+	    let closest = &diagram.faces()[closest_id];
+	    if (face.x() - x).hypot(face.y() - y) < (closest.x() - x).hypot(closest.y() - y) {
+		closest_id = face.id();
+	    }
+	}
+	closest_id
+    }
+
+    #[test]
+    fn test_locate_empty() {
+	let diagram = DiagramBuilder::new(BoundingBox::new(0.0, 10.0, 0.0, 10.0)).build().unwrap();
+	assert_eq!(None, diagram.locator().locate(5.0, 5.0));
+	assert_eq!(None, diagram.locate(5.0, 5.0));
+    }
+
+    #[test]
+    fn test_locate_outside_bounds() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 10.0, 0.0, 10.0));
+	builder.add_sites(vec![(2.0, 2.0), (8.0, 8.0)]);
+	let diagram = builder.build().unwrap();
+	let locator = diagram.locator();
+	assert_eq!(None, locator.locate(-1.0, 5.0));
+	assert_eq!(None, locator.locate(5.0, 10.5));
+	assert_eq!(Some(0), locator.locate(0.0, 0.0));
+	assert_eq!(Some(1), locator.locate(10.0, 10.0));
+    }
+
+    #[test]
+    fn test_locate_random() {
+	let bounds = BoundingBox::new(-50.0, 150.0, 20.0, 70.0);
+	let mut builder = DiagramBuilder::new(bounds.clone());
+	builder.add_sites(random_sites(500, 99, &bounds));
+	let diagram = builder.build().unwrap();
+	let locator = diagram.locator();
+	for (x, y) in random_sites(2000, 100, &bounds) {
+	    let expected = find_closest_face(&diagram, x, y);
+	    assert_eq!(Some(expected), locator.locate(x, y));
+	    assert_eq!(Some(expected), diagram.locate(x, y));
+	}
+    }
+
+    #[test]
+    fn test_locate_clipped() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 10.0, 0.0, 10.0));
+	builder.add_sites(vec![(2.0, 2.0), (8.0, 2.0), (8.0, 8.0), (2.0, 8.0)]);
+	let mut polygon = ClipPolygon::new(vec![(0.0, 0.0), (10.0, 0.0), (0.0, 10.0)]).unwrap();
+	polygon.add_hole(vec![(1.0, 1.0), (3.0, 1.0), (1.0, 3.0)]).unwrap();
+	let diagram = builder.build().unwrap().clip(&polygon).unwrap();
+	let locator = diagram.locator();
+	for (x, y) in [(9.0, 9.0), (6.0, 6.0), (1.5, 1.5)].iter() {
+	    assert_eq!(None, diagram.locate(*x, *y));
+	    assert_eq!(None, locator.locate(*x, *y));
+	}
+	// the cell of the site at (8, 8) lies outside the triangle, so the closest remaining site is the closest site
+	assert_eq!(3, diagram.faces().len());
+	for (x, y) in [(0.5, 0.5), (4.0, 1.0), (8.0, 1.0), (1.0, 8.0), (4.5, 4.5), (2.5, 2.5)].iter() {
+	    let expected = find_closest_face(&diagram, *x, *y);
+	    assert_eq!(Some(expected), diagram.locate(*x, *y));
+	    assert_eq!(Some(expected), locator.locate(*x, *y));
+	}
+    }
+
+}
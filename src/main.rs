@@ -19,25 +19,14 @@
  * Main application subroutines
  */
 
-mod geom;
-mod graphics;
-mod graphics_opengl;
-mod linear;
-mod quadratic;
-mod matrix;
-mod minor_matrix_view;
-mod row_matrix_view;
-mod settings;
-mod sub_matrix_view;
-mod transposed_matrix_view;
-mod transform;
-mod vector;
-mod voronoi;
-
-
-use crate::graphics::{Color, Graphics};
-use crate::graphics_opengl::OpenGLGraphics;
-use crate::settings::Settings;
+use pop::geom::BoundingBox;
+use pop::graphics::{Color, Graphics};
+use pop::graphics_opengl::OpenGLGraphics;
+use pop::map::{Map, MapSettings};
+use pop::relax::relax;
+use pop::settings::Settings;
+use pop::sites;
+use pop::voronoi::DiagramBuilder;
 
 use log::info;
 use sdl2::event::Event;
@@ -55,7 +44,14 @@ fn main() {
     settings.log();
 
     info!("generating voronoi diagram");
-    let diagram = voronoi::generate();
+    let bounds = BoundingBox::new(0.0, 1000.0, 0.0, 1000.0);
+    let mut builder = DiagramBuilder::new(bounds.clone());
+    builder.add_sites(sites::uniform(&bounds, 4000, 0));
+    let diagram = builder.build().expect("could not generate voronoi diagram");
+    let diagram = relax(diagram, 2, None).expect("could not relax voronoi diagram");
+
+    info!("generating map");
+    let map = Map::generate(&diagram, &MapSettings::default());
     
     let sdl_context = sdl2::init().expect("could not initialize SDL context");
 
@@ -69,13 +65,12 @@ fn main() {
 
     let mut graphics = OpenGLGraphics::new(&settings, &sdl_video, &sdl_window);
 
-    let vertex_shader = graphics.create_vertex_shader("vertex.shader").expect("unable to create vertex shader");
-    let fragment_shader = graphics.create_fragment_shader("fragment.shader").expect("unable to create fragment shader");
+    let vertex_shader = graphics.create_vertex_shader("colored_vertex.shader").expect("unable to create vertex shader");
+    let fragment_shader = graphics.create_fragment_shader("colored_fragment.shader").expect("unable to create fragment shader");
     let program = graphics.create_program(vertex_shader, fragment_shader).expect("unable to create graphics pipeline");
     
-    let (vertices, indices) = diagram.create_triangles();
-    println!("{:?}", vertices);
-    let vertex_buffer = graphics.create_triangles(vertices, indices).expect("unable to create vertex array");
+    let (vertices, indices) = map.create_triangles(&diagram);
+    let vertex_buffer = graphics.create_colored_triangles(vertices, indices).expect("unable to create vertex array");
 
     let mut sdl_event_pump = sdl_context.event_pump()
 	.expect("could not initialize SDL events");
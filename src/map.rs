@@ -0,0 +1,836 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * Island maps on top of voronoi diagrams: land and water, elevation, rivers, moisture and biomes
+ *
+ * The faces of the diagram are split into land and water by seeded noise that falls off towards the edges of the
+ * bounds. Water that reaches the edges is ocean, the rest is lakes. The vertices get their elevation from their
+ * distance to the coast, and rivers run down the vertices from random springs to the ocean. Moisture spreads from
+ * rivers and lakes, and the biome of every face follows from its elevation and moisture.
+ */
+
+use crate::graphics::Color;
+use crate::periodic::SeamMode;
+use crate::queue::IndexedQueue;
+use crate::random::Random;
+use crate::vector::Vector2;
+use crate::voronoi::Diagram;
+
+use std::collections::VecDeque;
+
+/**
+ * The octaves of noise summed for the land shape
+ */
+const NOISE_OCTAVES: u32 = 4;
+
+/**
+ * How much of the moisture of a vertex is passed on to its neighbours
+ */
+const MOISTURE_DECAY: f64 = 0.9;
+
+/**
+ * The elevations between which rivers may spring
+ */
+const SPRING_ELEVATIONS: (f64, f64) = (0.3, 0.9);
+
+/**
+ * The kind of landscape of a face
+ */
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Biome {
+    Ocean,
+    Lake,
+    Marsh,
+    Ice,
+    Beach,
+    Snow,
+    Tundra,
+    Bare,
+    Scorched,
+    Taiga,
+    Shrubland,
+    TemperateDesert,
+    TemperateRainForest,
+    TemperateDeciduousForest,
+    Grassland,
+    TropicalRainForest,
+    TropicalSeasonalForest,
+    SubtropicalDesert,
+}
+
+impl Biome {
+
+    /**
+     * The biome of a land face that is not on the coast, from its elevation and moisture in [0, 1]
+     */
+    pub fn classify(elevation: f64, moisture: f64) -> Biome {
+	if elevation > 0.8 {
+	    if moisture > 0.5 {
+		Biome::Snow
+	    } else if moisture > 0.33 {
+		Biome::Tundra
+	    } else if moisture > 0.16 {
+		Biome::Bare
+	    } else {
+		Biome::Scorched
+	    }
+	} else if elevation > 0.6 {
+	    if moisture > 0.66 {
+		Biome::Taiga
+	    } else if moisture > 0.33 {
+		Biome::Shrubland
+	    } else {
+		Biome::TemperateDesert
+	    }
+	} else if elevation > 0.3 {
+	    if moisture > 0.83 {
+		Biome::TemperateRainForest
+	    } else if moisture > 0.5 {
+		Biome::TemperateDeciduousForest
+	    } else if moisture > 0.16 {
+		Biome::Grassland
+	    } else {
+		Biome::TemperateDesert
+	    }
+	} else if moisture > 0.66 {
+	    Biome::TropicalRainForest
+	} else if moisture > 0.33 {
+	    Biome::TropicalSeasonalForest
+	} else if moisture > 0.16 {
+	    Biome::Grassland
+	} else {
+	    Biome::SubtropicalDesert
+	}
+    }
+
+    /**
+     * The color with which the biome is drawn
+     */
+    pub fn color(&self) -> Color {
+	Color::from_rgb(match self {
+	    Biome::Ocean => 0x44447a,
+	    Biome::Lake => 0x336699,
+	    Biome::Marsh => 0x2f6666,
+	    Biome::Ice => 0x99ffff,
+	    Biome::Beach => 0xa09077,
+	    Biome::Snow => 0xffffff,
+	    Biome::Tundra => 0xbbbbaa,
+	    Biome::Bare => 0x888888,
+	    Biome::Scorched => 0x555555,
+	    Biome::Taiga => 0x99aa77,
+	    Biome::Shrubland => 0x889977,
+	    Biome::TemperateDesert => 0xc9d29b,
+	    Biome::TemperateRainForest => 0x448855,
+	    Biome::TemperateDeciduousForest => 0x679459,
+	    Biome::Grassland => 0x88aa55,
+	    Biome::TropicalRainForest => 0x337755,
+	    Biome::TropicalSeasonalForest => 0x559944,
+	    Biome::SubtropicalDesert => 0xd2b98b,
+	})
+    }
+
+}
+
+/**
+ * The parameters of map generation
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapSettings {
+
+    /**
+     * The seed of the noise and the springs of the rivers, the same seed on the same diagram gives the same map
+     */
+    pub seed: u64,
+
+    /**
+     * The number of noise features across the width of the bounds
+     */
+    pub frequency: f64,
+
+    /**
+     * The noise level above which the middle of the bounds is land
+     */
+    pub sea_level: f64,
+
+    /**
+     * How much the sea level rises towards the edges of the bounds, which makes the land an island
+     */
+    pub falloff: f64,
+
+    /**
+     * The number of rivers, each starting at a random spring
+     */
+    pub rivers: usize,
+}
+
+impl Default for MapSettings {
+
+    fn default() -> Self {
+	MapSettings {
+	    seed: 0,
+	    frequency: 4.0,
+	    sea_level: 0.3,
+	    falloff: 0.3,
+	    rivers: 30,
+	}
+    }
+
+}
+
+/**
+ * The attributes of the faces and vertices of a diagram, indexed by their ids
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Map {
+    water: Vec<bool>,
+    ocean: Vec<bool>,
+    coast: Vec<bool>,
+    elevations: Vec<f64>,
+    moistures: Vec<f64>,
+    biomes: Vec<Biome>,
+    vertex_elevations: Vec<f64>,
+    vertex_moistures: Vec<f64>,
+    downslopes: Vec<Option<usize>>,
+    flows: Vec<u32>,
+}
+
+impl Map {
+
+    /**
+     * Generates the map of a diagram
+     */
+    pub fn generate<T>(diagram: &Diagram<T>, settings: &MapSettings) -> Map {
+	let (water, ocean) = assign_water(diagram, settings);
+	let coast: Vec<bool> = (0..diagram.faces.len())
+	    .map(|face_id| !water[face_id] && diagram.neighbours(face_id).any(|neighbour_id| ocean[neighbour_id]))
+	    .collect();
+
+	let corners = VertexFaces::new(diagram, &water, &ocean);
+	let adjacency = vertex_adjacency(diagram);
+	let (vertex_elevations, downslopes) = assign_elevations(&adjacency, &corners);
+	let flows = assign_rivers(&vertex_elevations, &downslopes, &corners, settings);
+	let vertex_moistures = assign_moistures(&adjacency, &corners, &flows);
+
+	let elevations = face_averages(diagram, &vertex_elevations);
+	let moistures = face_averages(diagram, &vertex_moistures);
+	let biomes = (0..diagram.faces.len()).map(|face_id| {
+	    if ocean[face_id] {
+		Biome::Ocean
+	    } else if water[face_id] {
+		if elevations[face_id] < 0.1 {
+		    Biome::Marsh
+		} else if elevations[face_id] > 0.8 {
+		    Biome::Ice
+		} else {
+		    Biome::Lake
+		}
+	    } else if coast[face_id] {
+		Biome::Beach
+	    } else {
+		Biome::classify(elevations[face_id], moistures[face_id])
+	    }
+	}).collect();
+
+	Map {
+	    water,
+	    ocean,
+	    coast,
+	    elevations,
+	    moistures,
+	    biomes,
+	    vertex_elevations,
+	    vertex_moistures,
+	    downslopes,
+	    flows,
+	}
+    }
+
+    /**
+     * Whether a face is ocean or lake
+     */
+    pub fn is_water(&self, face_id: usize) -> bool {
+	self.water[face_id]
+    }
+
+    /**
+     * Whether a face is water connected to the edges of the bounds
+     */
+    pub fn is_ocean(&self, face_id: usize) -> bool {
+	self.ocean[face_id]
+    }
+
+    /**
+     * Whether a face is water enclosed by land
+     */
+    pub fn is_lake(&self, face_id: usize) -> bool {
+	self.water[face_id] && !self.ocean[face_id]
+    }
+
+    /**
+     * Whether a face is land next to the ocean
+     */
+    pub fn is_coast(&self, face_id: usize) -> bool {
+	self.coast[face_id]
+    }
+
+    /**
+     * The mean elevation of the vertices of a face, in [0, 1]
+     */
+    pub fn elevation(&self, face_id: usize) -> f64 {
+	self.elevations[face_id]
+    }
+
+    /**
+     * The mean moisture of the vertices of a face, in [0, 1]
+     */
+    pub fn moisture(&self, face_id: usize) -> f64 {
+	self.moistures[face_id]
+    }
+
+    pub fn biome(&self, face_id: usize) -> Biome {
+	self.biomes[face_id]
+    }
+
+    /**
+     * The biomes of all faces, indexed by face id
+     */
+    pub fn biomes(&self) -> &[Biome] {
+	&self.biomes
+    }
+
+    /**
+     * The elevation of a vertex, which is zero on the ocean and the coast and grows with the distance to the coast
+     * up to one
+     */
+    pub fn vertex_elevation(&self, vertex_id: usize) -> f64 {
+	self.vertex_elevations[vertex_id]
+    }
+
+    pub fn vertex_moisture(&self, vertex_id: usize) -> f64 {
+	self.vertex_moistures[vertex_id]
+    }
+
+    /**
+     * The neighbour into which water flows from a vertex, on the shortest path to the coast
+     * The neighbour is lower, or as high if both are in the same lake. Vertices on the coast have none.
+     */
+    pub fn downslope(&self, vertex_id: usize) -> Option<usize> {
+	self.downslopes[vertex_id]
+    }
+
+    /**
+     * The number of rivers that flow through a vertex
+     */
+    pub fn river_flow(&self, vertex_id: usize) -> u32 {
+	self.flows[vertex_id]
+    }
+
+    /**
+     * The segments of all rivers as start vertex id, end vertex id and the number of rivers that flow along them
+     */
+    pub fn rivers(&self) -> impl Iterator<Item = (usize, usize, u32)> + '_ {
+	self.flows.iter().enumerate()
+	    .filter(|(_, flow)| **flow > 0)
+	    .filter_map(move |(vertex_id, flow)| self.downslopes[vertex_id].map(|downslope_id| (vertex_id, downslope_id, *flow)))
+    }
+
+    /**
+     * The colors of the biomes of all faces, which can be used as fills of an SVG image of the diagram
+     */
+    pub fn face_colors(&self) -> Vec<Option<Color>> {
+	self.biomes.iter().map(|biome| Some(biome.color())).collect()
+    }
+
+    /**
+     * Creates triangles of the faces of the diagram of the map colored by their biome
+     * Vertices are given as x, y, z, r, g, b, with the coordinates transformed like those of the diagram's triangles
+     */
+    pub fn create_triangles<T>(&self, diagram: &Diagram<T>) -> (Vec<f32>, Vec<u32>) {
+	let scale = diagram.bounds.width() / 2.0;
+	let left = diagram.bounds.left();
+	let top = diagram.bounds.top();
+	let mut vertices = Vec::new();
+	let mut indices = Vec::new();
+	for face_id in 0..diagram.faces.len() {
+	    let color = self.biomes[face_id].color();
+	    for polygon in diagram.seam_polygons(face_id, SeamMode::Split) {
+		let first = (vertices.len() / 6) as u32;
+		for point in polygon.iter() {
+		    vertices.push(((point.get_x() - left) / scale - 1.0) as f32);
+		    vertices.push(((point.get_y() - top) / scale - 1.0) as f32);
+		    vertices.push(0.0f32);
+		    vertices.push(color.red);
+		    vertices.push(color.green);
+		    vertices.push(color.blue);
+		}
+		for i in 1..polygon.len().saturating_sub(1) as u32 {
+		    indices.push(first);
+		    indices.push(first + i);
+		    indices.push(first + i + 1);
+		}
+	    }
+	}
+	(vertices, indices)
+    }
+
+}
+
+/**
+ * What kinds of faces meet at every vertex
+ */
+struct VertexFaces {
+    touches_land: Vec<bool>,
+    touches_ocean: Vec<bool>,
+    touches_lake: Vec<bool>,
+}
+
+impl VertexFaces {
+
+    fn new<T>(diagram: &Diagram<T>, water: &[bool], ocean: &[bool]) -> VertexFaces {
+	let count = diagram.vertices.len();
+	let mut corners = VertexFaces {
+	    touches_land: vec![false; count],
+	    touches_ocean: vec![false; count],
+	    touches_lake: vec![false; count],
+	};
+	for half_edge in diagram.half_edges.iter() {
+	    let (vertex_id, face_id) = (half_edge.start_id, half_edge.face_id);
+	    if ocean[face_id] {
+		corners.touches_ocean[vertex_id] = true;
+	    } else if water[face_id] {
+		corners.touches_lake[vertex_id] = true;
+	    } else {
+		corners.touches_land[vertex_id] = true;
+	    }
+	}
+	corners
+    }
+
+    /**
+     * Whether a vertex is on the ocean or its coast, where elevation is zero
+     */
+    fn is_sea_level(&self, vertex_id: usize) -> bool {
+	self.touches_ocean[vertex_id]
+    }
+
+    /**
+     * Whether a vertex is only surrounded by lakes
+     */
+    fn is_lake(&self, vertex_id: usize) -> bool {
+	self.touches_lake[vertex_id] && !self.touches_land[vertex_id] && !self.touches_ocean[vertex_id]
+    }
+
+}
+
+/**
+ * Splits the faces into water and land, and finds the water connected to the edges of the bounds
+ * Faces on the edges of the bounds, or on the seams of a periodic diagram, are always ocean.
+ */
+fn assign_water<T>(diagram: &Diagram<T>, settings: &MapSettings) -> (Vec<bool>, Vec<bool>) {
+    let bounds = &diagram.bounds;
+    let count = diagram.faces.len();
+    let mut border = vec![false; count];
+    for half_edge in diagram.half_edges.iter() {
+	if half_edge.twin_id.is_none() || half_edge.wrap != (0, 0) {
+	    border[half_edge.face_id] = true;
+	}
+    }
+
+    let water: Vec<bool> = diagram.faces.iter().map(|face| {
+	if border[face.id] {
+	    return true;
+	}
+	let u = (face.x - bounds.left()) / bounds.width();
+	let v = (face.y - bounds.top()) / bounds.height();
+	let (x, y) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+	let noise = fractal_noise(settings.seed, u * settings.frequency, v * settings.frequency * bounds.height() / bounds.width());
+	noise <= settings.sea_level + settings.falloff * (x * x + y * y)
+    }).collect();
+
+    let mut ocean = vec![false; count];
+    let mut queue = VecDeque::new();
+    for face_id in 0..count {
+	if border[face_id] {
+	    ocean[face_id] = true;
+	    queue.push_back(face_id);
+	}
+    }
+    while let Some(face_id) = queue.pop_front() {
+	for neighbour_id in diagram.neighbours(face_id) {
+	    if water[neighbour_id] && !ocean[neighbour_id] {
+		ocean[neighbour_id] = true;
+		queue.push_back(neighbour_id);
+	    }
+	}
+    }
+    (water, ocean)
+}
+
+/**
+ * The neighbours of every vertex with the lengths of the edges to them
+ */
+fn vertex_adjacency<T>(diagram: &Diagram<T>) -> Vec<Vec<(usize, f64)>> {
+    let mut adjacency: Vec<Vec<(usize, f64)>> = vec![vec![]; diagram.vertices.len()];
+    for half_edge in diagram.half_edges.iter() {
+	let next = &diagram.half_edges[half_edge.next_id];
+	let (start_id, end_id) = (half_edge.start_id, next.start_id);
+	let start = diagram.half_edge_start(half_edge.id);
+	let end = diagram.half_edge_start(next.id);
+	let length = (end.get_x() - start.get_x()).hypot(end.get_y() - start.get_y());
+	for (from_id, to_id) in [(start_id, end_id), (end_id, start_id)].iter() {
+	    if !adjacency[*from_id].iter().any(|(neighbour_id, _)| neighbour_id == to_id) {
+		adjacency[*from_id].push((*to_id, length));
+	    }
+	}
+    }
+    adjacency
+}
+
+/**
+ * Computes the elevation of every vertex from its distance to the coast, along with the next vertex towards the coast
+ * Distances across lakes are free, so lakes are flat. The distances are redistributed so that high land is rarer
+ * than low land.
+ */
+fn assign_elevations(adjacency: &[Vec<(usize, f64)>], corners: &VertexFaces) -> (Vec<f64>, Vec<Option<usize>>) {
+    let count = adjacency.len();
+    let mut distances = vec![f64::INFINITY; count];
+    let mut downslopes = vec![None; count];
+    let mut handles: Vec<Option<usize>> = vec![None; count];
+    let mut queue = IndexedQueue::new();
+    for vertex_id in 0..count {
+	if corners.is_sea_level(vertex_id) {
+	    distances[vertex_id] = 0.0;
+	    handles[vertex_id] = Some(queue.push(0.0, vertex_id));
+	}
+    }
+    while let Some((_, distance, vertex_id)) = queue.pop() {
+	handles[vertex_id] = None;
+	for (neighbour_id, length) in adjacency[vertex_id].iter() {
+	    let cost = if corners.is_lake(vertex_id) && corners.is_lake(*neighbour_id) {
+		0.0
+	    } else {
+		*length
+	    };
+	    if distance + cost < distances[*neighbour_id] {
+		distances[*neighbour_id] = distance + cost;
+		downslopes[*neighbour_id] = Some(vertex_id);
+		if let Some(handle) = handles[*neighbour_id] {
+		    queue.remove(handle);
+		}
+		handles[*neighbour_id] = Some(queue.push(distance + cost, *neighbour_id));
+	    }
+	}
+    }
+
+    let ranks = ranks(&distances, |vertex_id| !corners.is_sea_level(vertex_id));
+    let elevations = (0..count).map(|vertex_id| match ranks[vertex_id] {
+	Some(rank) => 1.0 - (1.0 - rank).sqrt(),
+	None => 0.0,
+    }).collect();
+    (elevations, downslopes)
+}
+
+/**
+ * Runs rivers from random springs down to the coast, returns the number of rivers through every vertex
+ */
+fn assign_rivers(elevations: &[f64], downslopes: &[Option<usize>], corners: &VertexFaces, settings: &MapSettings) -> Vec<u32> {
+    let mut flows = vec![0; elevations.len()];
+    let springs: Vec<usize> = (0..elevations.len())
+	.filter(|vertex_id| !corners.touches_ocean[*vertex_id] && !corners.touches_lake[*vertex_id])
+	.filter(|vertex_id| elevations[*vertex_id] >= SPRING_ELEVATIONS.0 && elevations[*vertex_id] <= SPRING_ELEVATIONS.1)
+	.collect();
+    if springs.is_empty() {
+	return flows;
+    }
+    // the springs do not share the random sequence of the noise, whose lattice is hashed from the seed
+    let mut random = Random::new(settings.seed);
+    for _ in 0..settings.rivers {
+	let mut vertex_id = springs[random.index(springs.len())];
+	flows[vertex_id] += 1;
+	while let Some(downslope_id) = downslopes[vertex_id] {
+	    vertex_id = downslope_id;
+	    flows[vertex_id] += 1;
+	}
+    }
+    flows
+}
+
+/**
+ * Spreads moisture from rivers and lakes over the land, redistributed so that all moistures in [0, 1] are as common
+ * The ocean and its coast are wet.
+ */
+fn assign_moistures(adjacency: &[Vec<(usize, f64)>], corners: &VertexFaces, flows: &[u32]) -> Vec<f64> {
+    let count = adjacency.len();
+    let mut steps: Vec<Option<i32>> = vec![None; count];
+    let mut queue = VecDeque::new();
+    for vertex_id in 0..count {
+	if flows[vertex_id] > 0 || corners.touches_lake[vertex_id] {
+	    steps[vertex_id] = Some(0);
+	    queue.push_back(vertex_id);
+	}
+    }
+    while let Some(vertex_id) = queue.pop_front() {
+	let step = steps[vertex_id].expect("queued vertices should have a step") + 1;
+	for (neighbour_id, _) in adjacency[vertex_id].iter() {
+	    if steps[*neighbour_id].is_none() {
+		steps[*neighbour_id] = Some(step);
+		queue.push_back(*neighbour_id);
+	    }
+	}
+    }
+
+    let moistures: Vec<f64> = steps.iter().map(|step| step.map_or(0.0, |step| MOISTURE_DECAY.powi(step))).collect();
+    let ranks = ranks(&moistures, |vertex_id| !corners.is_sea_level(vertex_id));
+    ranks.iter().map(|rank| rank.unwrap_or(1.0)).collect()
+}
+
+/**
+ * The ranks in (0, 1] of the selected values, the share of selected values that are lower plus one
+ * Equal values have equal ranks.
+ */
+fn ranks<P>(values: &[f64], selected: P) -> Vec<Option<f64>> where P: Fn(usize) -> bool {
+    let mut ids: Vec<usize> = (0..values.len()).filter(|id| selected(*id)).collect();
+    ids.sort_by(|first, second| values[*first].partial_cmp(&values[*second]).unwrap_or(std::cmp::Ordering::Equal));
+    let mut ranks = vec![None; values.len()];
+    let mut lower = 0;
+    for (i, id) in ids.iter().enumerate() {
+	if i > 0 && values[ids[i - 1]] < values[*id] {
+	    lower = i;
+	}
+	ranks[*id] = Some((lower + 1) as f64 / ids.len() as f64);
+    }
+    ranks
+}
+
+/**
+ * The mean of the values of the vertices of every face
+ */
+fn face_averages<T>(diagram: &Diagram<T>, values: &[f64]) -> Vec<f64> {
+    let mut sums = vec![0.0; diagram.faces.len()];
+    let mut counts = vec![0; diagram.faces.len()];
+    for half_edge in diagram.half_edges.iter() {
+	sums[half_edge.face_id] += values[half_edge.start_id];
+	counts[half_edge.face_id] += 1;
+    }
+    sums.iter().zip(counts.iter()).map(|(sum, count)| if *count > 0 { sum / *count as f64 } else { 0.0 }).collect()
+}
+
+/**
+ * Smooth noise in [0, 1] summed over several octaves
+ */
+fn fractal_noise(seed: u64, x: f64, y: f64) -> f64 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut total = 0.0;
+    let mut frequency = 1.0;
+    for octave in 0..NOISE_OCTAVES {
+	sum += amplitude * value_noise(seed.wrapping_add(octave as u64), x * frequency, y * frequency);
+	total += amplitude;
+	amplitude *= 0.5;
+	frequency *= 2.0;
+    }
+    sum / total
+}
+
+/**
+ * Interpolates random values on the integer lattice
+ */
+fn value_noise(seed: u64, x: f64, y: f64) -> f64 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (ix, iy) = (x0 as i64, y0 as i64);
+    let smooth = |t: f64| t * t * (3.0 - 2.0 * t);
+    let (sx, sy) = (smooth(x - x0), smooth(y - y0));
+    let top = lattice(seed, ix, iy) * (1.0 - sx) + lattice(seed, ix + 1, iy) * sx;
+    let bottom = lattice(seed, ix, iy + 1) * (1.0 - sx) + lattice(seed, ix + 1, iy + 1) * sx;
+    top * (1.0 - sy) + bottom * sy
+}
+
+/**
+ * A random value in [0, 1) for a lattice point, hashed with the splitmix64 finalizer
+ */
+fn lattice(seed: u64, x: i64, y: i64) -> f64 {
+    let mut z = seed
+	.wrapping_add((x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+	.wrapping_add((y as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::geom::BoundingBox;
+    use crate::periodic::Wrap;
+    use crate::sites;
+    use crate::voronoi::DiagramBuilder;
+
+    fn create_diagram() -> Diagram {
+	let bounds = BoundingBox::new(0.0, 1000.0, 0.0, 1000.0);
+	let mut builder = DiagramBuilder::new(bounds.clone());
+	builder.add_sites(sites::poisson_disc(&bounds, 25.0, 3));
+	builder.build().unwrap()
+    }
+
+    fn create_settings(seed: u64) -> MapSettings {
+	MapSettings {
+	    seed,
+	    ..MapSettings::default()
+	}
+    }
+
+    #[test]
+    fn test_deterministic() {
+	let diagram = create_diagram();
+	let map = Map::generate(&diagram, &create_settings(5));
+	assert_eq!(map, Map::generate(&diagram, &create_settings(5)));
+	assert_ne!(map.biomes(), Map::generate(&diagram, &create_settings(6)).biomes());
+    }
+
+    #[test]
+    fn test_water() {
+	let diagram = create_diagram();
+	let map = Map::generate(&diagram, &create_settings(1));
+	let land = (0..diagram.faces().len()).filter(|face_id| !map.is_water(*face_id)).count();
+	assert!(land > diagram.faces().len() / 5, "{} land faces", land);
+	for face in diagram.faces() {
+	    let on_border = diagram.face_half_edges(face.id()).any(|half_edge| half_edge.twin_id().is_none());
+	    if on_border {
+		assert!(map.is_ocean(face.id()));
+	    }
+	    if map.is_ocean(face.id()) {
+		assert!(map.is_water(face.id()));
+	    }
+	    // lakes never touch the ocean, which would have flooded them
+	    if map.is_lake(face.id()) {
+		assert!(diagram.neighbours(face.id()).all(|neighbour_id| !map.is_ocean(neighbour_id)));
+	    }
+	    let next_to_ocean = diagram.neighbours(face.id()).any(|neighbour_id| map.is_ocean(neighbour_id));
+	    assert_eq!(!map.is_water(face.id()) && next_to_ocean, map.is_coast(face.id()));
+	}
+    }
+
+    #[test]
+    fn test_elevation() {
+	let diagram = create_diagram();
+	let map = Map::generate(&diagram, &create_settings(2));
+	let mut highest: f64 = 0.0;
+	for vertex in diagram.vertices() {
+	    let elevation = map.vertex_elevation(vertex.id());
+	    assert!((0.0..=1.0).contains(&elevation));
+	    highest = highest.max(elevation);
+	    match map.downslope(vertex.id()) {
+		Some(downslope_id) => assert!(map.vertex_elevation(downslope_id) <= elevation),
+		None => assert_eq!(0.0, elevation),
+	    }
+	}
+	assert_eq!(1.0, highest);
+	for face in diagram.faces() {
+	    if map.is_ocean(face.id()) {
+		assert_eq!(0.0, map.elevation(face.id()));
+	    } else if !map.is_coast(face.id()) {
+		assert!(map.elevation(face.id()) > 0.0);
+	    }
+	}
+    }
+
+    #[test]
+    fn test_rivers() {
+	let diagram = create_diagram();
+	let settings = MapSettings {
+	    rivers: 10,
+	    ..create_settings(4)
+	};
+	let map = Map::generate(&diagram, &settings);
+	let segments: Vec<(usize, usize, u32)> = map.rivers().collect();
+	assert!(!segments.is_empty());
+	for (start_id, end_id, flow) in segments.iter() {
+	    assert!(map.vertex_elevation(*end_id) <= map.vertex_elevation(*start_id));
+	    assert!(map.river_flow(*end_id) >= *flow);
+	}
+	// every river ends on the coast, and all rivers together reach it 10 times
+	let mouths: u32 = diagram.vertices().iter()
+	    .filter(|vertex| map.river_flow(vertex.id()) > 0 && map.downslope(vertex.id()).is_none())
+	    .map(|vertex| map.river_flow(vertex.id()))
+	    .sum();
+	assert_eq!(10, mouths);
+    }
+
+    #[test]
+    fn test_moisture_and_biomes() {
+	let diagram = create_diagram();
+	let map = Map::generate(&diagram, &create_settings(3));
+	assert_eq!(diagram.faces().len(), map.biomes().len());
+	for face in diagram.faces() {
+	    assert!((0.0..=1.0).contains(&map.moisture(face.id())));
+	    let biome = map.biome(face.id());
+	    assert_eq!(map.is_ocean(face.id()), biome == Biome::Ocean);
+	    assert_eq!(map.is_coast(face.id()), biome == Biome::Beach);
+	}
+	assert!(map.biomes().iter().any(|biome| !matches!(biome, Biome::Ocean | Biome::Beach | Biome::Lake | Biome::Marsh | Biome::Ice)));
+    }
+
+    #[test]
+    fn test_classify() {
+	assert_eq!(Biome::Snow, Biome::classify(0.9, 0.9));
+	assert_eq!(Biome::Scorched, Biome::classify(0.9, 0.1));
+	assert_eq!(Biome::Taiga, Biome::classify(0.7, 0.7));
+	assert_eq!(Biome::TemperateRainForest, Biome::classify(0.5, 0.9));
+	assert_eq!(Biome::Grassland, Biome::classify(0.5, 0.3));
+	assert_eq!(Biome::SubtropicalDesert, Biome::classify(0.1, 0.1));
+	assert_eq!(Biome::TropicalRainForest, Biome::classify(0.1, 0.9));
+    }
+
+    #[test]
+    fn test_periodic() {
+	let bounds = BoundingBox::new(0.0, 1000.0, 0.0, 500.0);
+	let mut builder = DiagramBuilder::new(bounds.clone());
+	builder.set_wrap(Wrap::Horizontal);
+	builder.add_sites(sites::poisson_disc(&bounds, 25.0, 8));
+	let diagram = builder.build().unwrap();
+	let map = Map::generate(&diagram, &create_settings(8));
+	for half_edge in diagram.half_edges() {
+	    if half_edge.wrap() != (0, 0) {
+		assert!(map.is_ocean(half_edge.face_id()));
+	    }
+	}
+	for vertex in diagram.vertices() {
+	    assert!((0.0..=1.0).contains(&map.vertex_elevation(vertex.id())));
+	}
+    }
+
+    #[test]
+    fn test_create_triangles() {
+	let diagram = create_diagram();
+	let map = Map::generate(&diagram, &create_settings(1));
+	let (vertices, indices) = map.create_triangles(&diagram);
+	let corners: usize = (0..diagram.faces().len()).map(|face_id| diagram.face_polygon(face_id).len()).sum();
+	assert_eq!(corners * 6, vertices.len());
+	assert_eq!((corners - 2 * diagram.faces().len()) * 3, indices.len());
+	for chunk in vertices.chunks(6) {
+	    assert!(chunk[0] >= -1.0 && chunk[0] <= 1.0);
+	    assert!(chunk[1] >= -1.0 && chunk[1] <= 1.0);
+	}
+	// the first face is drawn in the color of its biome
+	let color = map.biome(0).color();
+	assert_eq!(&[color.red, color.green, color.blue], &vertices[3..6]);
+	assert_eq!(map.face_colors()[0], Some(color));
+    }
+
+}
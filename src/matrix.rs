@@ -459,6 +459,7 @@ define_variable_matrix_type!(MatrixVarF64, f64);
 define_fixed_matrix_type_2x3!(Matrix2x3F64, f64);
 define_fixed_matrix_type_3x3!(Matrix3x3F64, f64);
 
+define_fixed_matrix_type_2x3!(Matrix2x3F32, f32);
 define_fixed_matrix_type_3x3!(Matrix3x3F32, f32);
 
 #[cfg(test)]
@@ -0,0 +1,945 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * Builds voronoi diagrams of many sites on several threads
+ *
+ * The sites are sorted by their x coordinate and cut into vertical strips with the same number of sites. Each thread
+ * builds the diagram of one strip together with the sites in a margin on either side of it, and keeps the cells of the
+ * strip whose vertices are closer to their site than to any site beyond the margin, since the other sites can not
+ * change these cells. A strip with cells that may reach past its margin is built again with a wider margin. The cells
+ * are then joined along the seams between the strips.
+ *
+ * A single sweep numbers vertices and half edges in the order in which its events create them, so each strip traces
+ * the event behind every vertex and half edge of its cells. Events are identified by their sites and ordered like the
+ * queue of a single sweep orders them, which yields the same numbering and the same vertices as a single thread.
+ */
+
+use crate::attributes::Attributes;
+use crate::float::{relative_tolerance, Float};
+use crate::geom::BoundingBox;
+use crate::periodic::Wrap;
+use crate::vector::Vector2;
+use crate::voronoi::{Diagram, DiagramBuilder, Face, HalfEdge, SweepMetrics, Vertex, VoronoiError};
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::thread;
+
+/**
+ * Strips with fewer sites than this are not worth a thread of their own
+ */
+const MIN_STRIP_SITES: usize = 64;
+
+/**
+ * The width of the first margin around a strip, in units of the average distance between sites
+ */
+const INITIAL_MARGIN: f64 = 3.0;
+
+/**
+ * The height of the first band along the top of the bounds, in units of the average distance between sites
+ */
+const INITIAL_BAND: f64 = 3.0;
+
+/**
+ * The distance by which the circle around a vertex has to stay clear of the sites beyond the margin, relative to the
+ * size of the bounds; this is far above the tolerance for merging vertices, so near misses are built again
+ */
+const SEAM_TOLERANCE: f64 = 1e-4;
+
+/**
+ * Refers to an event of the sweep of a single builder, a site by the id of its face or a circle by the id of its vertex
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum EventRef {
+    Site(usize),
+    Circle(usize),
+}
+
+/**
+ * A handled circle event of a traced sweep
+ */
+pub(crate) struct CircleEvent<F: Float> {
+
+    /**
+     * The sites of the left, removed and right arc
+     */
+    sites: [usize; 3],
+
+    center: F::Vector2,
+    priority: F,
+
+    /**
+     * The event during which this event was queued, and how many events that event queued before it
+     */
+    parent: Option<(EventRef, usize)>,
+}
+
+/**
+ * Records which event of the sweep created each vertex and half edge of a diagram
+ * Vertices and half edges are numbered in the order in which they are created, so a trace lets the cells of several
+ * strips be numbered like those of a single sweep. During the sweep it is indexed by the ids of the builder, the
+ * diagram renumbers it to its own ids.
+ */
+pub(crate) struct SweepTrace<F: Float> {
+
+    /**
+     * The circle events in the order in which they were handled, which is the order of the vertices they created
+     */
+    circles: Vec<CircleEvent<F>>,
+
+    /**
+     * For each half edge of the sweep, the event that created its pair and whether it was created first or second
+     */
+    half_edges: Vec<Option<(EventRef, usize)>>,
+
+    /**
+     * For each half edge whose start vertex was placed on the bounds when clipping, the order in which the ends of its
+     * edge were placed
+     */
+    clip_orders: Vec<Option<usize>>,
+
+    /**
+     * For each vertex, the circle event that created it
+     */
+    vertices: Vec<Option<usize>>,
+
+    current: Option<EventRef>,
+    queued: usize,
+
+    /**
+     * The parent of each queued event, by the handle of the queue
+     */
+    pending: Vec<Option<(EventRef, usize)>>,
+}
+
+impl<F> SweepTrace<F> where F: Float {
+
+    pub(crate) fn new() -> SweepTrace<F> {
+	SweepTrace {
+	    circles: vec![],
+	    half_edges: vec![],
+	    clip_orders: vec![],
+	    vertices: vec![],
+	    current: None,
+	    queued: 0,
+	    pending: vec![],
+	}
+    }
+
+    pub(crate) fn push_event(&mut self, handle: usize) {
+	if self.pending.len() <= handle {
+	    self.pending.resize(handle + 1, None);
+	}
+	self.pending[handle] = self.current.map(|event| (event, self.queued));
+	self.queued += 1;
+    }
+
+    pub(crate) fn handle_site(&mut self, face_id: usize) {
+	self.current = Some(EventRef::Site(face_id));
+	self.queued = 0;
+    }
+
+    /**
+     * Starts a circle event, the sites are filled in when the diagram is renumbered
+     */
+    pub(crate) fn handle_circle(&mut self, handle: usize, priority: F, center: &F::Vector2) {
+	self.current = Some(EventRef::Circle(self.circles.len()));
+	self.queued = 0;
+	self.circles.push(CircleEvent {
+	    sites: [0; 3],
+	    center: *center,
+	    priority,
+	    parent: self.pending.get(handle).copied().flatten(),
+	});
+    }
+
+    pub(crate) fn create_pair(&mut self) {
+	self.half_edges.push(self.current.map(|event| (event, 0)));
+	self.half_edges.push(self.current.map(|event| (event, 1)));
+    }
+
+    pub(crate) fn clip(&mut self, half_edge_id: usize, order: usize) {
+	if self.clip_orders.len() <= half_edge_id {
+	    self.clip_orders.resize(half_edge_id + 1, None);
+	}
+	self.clip_orders[half_edge_id] = Some(order);
+    }
+
+    pub(crate) fn swap_clip(&mut self, half_edge_id: usize, twin_id: usize) {
+	let len = half_edge_id.max(twin_id) + 1;
+	if self.clip_orders.len() < len {
+	    self.clip_orders.resize(len, None);
+	}
+	self.clip_orders.swap(half_edge_id, twin_id);
+    }
+
+    /**
+     * Moves the trace from the ids of the builder to those of the diagram
+     */
+    pub(crate) fn renumber(&mut self, vertex_sites: &[Option<[usize; 3]>], vertex_ids: &[Option<usize>], half_edge_ids: &[Option<usize>]) {
+	for (circle, sites) in self.circles.iter_mut().zip(vertex_sites) {
+	    if let Some(sites) = sites {
+		circle.sites = *sites;
+	    }
+	}
+	let half_edge_count = half_edge_ids.iter().flatten().count();
+	self.half_edges = renumber(&self.half_edges, half_edge_ids, half_edge_count);
+	self.clip_orders = renumber(&self.clip_orders, half_edge_ids, half_edge_count);
+	let circle_ids: Vec<Option<usize>> = (0..self.circles.len()).map(Some).collect();
+	self.vertices = renumber(&circle_ids, vertex_ids, vertex_ids.iter().flatten().count());
+    }
+
+}
+
+/**
+ * Moves values from old to new ids, values without a new id are dropped and missing values become none
+ */
+fn renumber<V>(values: &[Option<V>], ids: &[Option<usize>], count: usize) -> Vec<Option<V>> where V: Copy {
+    let mut result = vec![None; count];
+    for (old_id, new_id) in ids.iter().enumerate() {
+	if let Some(new_id) = new_id {
+	    result[*new_id] = values.get(old_id).copied().flatten();
+	}
+    }
+    result
+}
+
+/**
+ * Identifies an event of the sweep by the ids of its sites, so the same event can be found in the sweeps of
+ * different strips
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum EventId {
+    Site(usize),
+    Circle([usize; 3]),
+}
+
+/**
+ * The priority of a circle event and the event during which it was queued, with the number of events queued before it
+ */
+type CircleInfo<F> = (F, Option<(EventId, usize)>);
+
+/**
+ * A corner of a cell and the edge that starts at it
+ */
+struct Corner<F> {
+    x: F,
+    y: F,
+
+    /**
+     * The face on the other side of the edge, none on the bounds
+     */
+    twin_face_id: Option<usize>,
+
+    clip_boundary: bool,
+
+    /**
+     * The event that created the pair of half edges of the edge and whether this one was created first or second,
+     * none for edges along the bounds
+     */
+    event: Option<(EventId, usize)>,
+
+    /**
+     * The circle event that created the corner
+     */
+    start_event: Option<EventId>,
+
+    /**
+     * The order in which the corner was placed on the bounds when its edge was clipped
+     */
+    clip_order: Option<usize>,
+}
+
+/**
+ * The id of a site and the corners of its cell
+ */
+type Cell<F> = (usize, Vec<Corner<F>>);
+
+/**
+ * The cells of the sites of a strip, the circle events of their sweep that involve these sites and the counts of
+ * the events of all sweeps of the strip
+ */
+struct Strip<F> {
+    cells: Vec<Cell<F>>,
+    circles: Vec<(EventId, CircleInfo<F>)>,
+    metrics: SweepMetrics,
+}
+
+/**
+ * The order in which vertices are created by a sweep: those of circle events, those placed on the bounds when
+ * clipping edges and the corners of the bounds when closing the faces
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum VertexKey {
+    Circle(usize),
+    Clip(usize, usize),
+    Bound(usize, usize),
+}
+
+/**
+ * The order in which half edges are created by a sweep: the pairs of the events and the edges along the bounds
+ * when closing the faces
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum HalfEdgeKey {
+    Sweep(usize, usize),
+    Bound(usize, usize),
+}
+
+/**
+ * Builds the diagram of sites that were checked to lie inside the bounds and to have different positions
+ * Returns the diagram with the counts of the events of the sweeps of all strips.
+ */
+pub(crate) fn build_parallel<T, F>(bounds: &BoundingBox<F>, threads: usize, sites: Vec<(F, F, T)>) -> Result<(Diagram<T, F>, SweepMetrics), VoronoiError>
+where F: Float {
+    let strip_count = threads.min(sites.len() / MIN_STRIP_SITES);
+    if strip_count < 2 {
+	let mut builder = DiagramBuilder::with_data(bounds.clone());
+	builder.add_sites_with_data(sites);
+	let diagram = builder.build()?;
+	return Ok((diagram, *builder.metrics()));
+    }
+    let positions: Vec<(F, F)> = sites.iter().map(|(x, y, _)| (*x, *y)).collect();
+    let mut order: Vec<usize> = (0..positions.len()).collect();
+    order.sort_by(|first, second| positions[*first].partial_cmp(&positions[*second]).unwrap_or(Ordering::Equal));
+    let mut rows: Vec<usize> = (0..positions.len()).collect();
+    rows.sort_by(|first, second| {
+	let (first, second) = (positions[*first], positions[*second]);
+	(first.1, first.0).partial_cmp(&(second.1, second.0)).unwrap_or(Ordering::Equal)
+    });
+    let strips: Vec<Range<usize>> = (0..strip_count)
+	.map(|strip| strip * order.len() / strip_count..(strip + 1) * order.len() / strip_count)
+	.collect();
+
+    let strips = thread::scope(|scope| {
+	let handles: Vec<_> = strips.into_iter()
+	    .map(|strip| {
+		let (positions, order, rows) = (&positions, &order, &rows);
+		scope.spawn(move || build_strip(bounds, positions, order, rows, strip))
+	    })
+	    .collect();
+	handles.into_iter()
+	    .map(|handle| handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+	    .collect::<Result<Vec<Strip<F>>, VoronoiError>>()
+    })?;
+
+    let mut metrics = SweepMetrics::default();
+    let mut cells: Vec<Option<Vec<Corner<F>>>> = (0..sites.len()).map(|_| None).collect();
+    let mut circles = HashMap::new();
+    for strip in strips {
+	add_metrics(&mut metrics, &strip.metrics);
+	for (site_id, corners) in strip.cells {
+	    cells[site_id] = Some(corners);
+	}
+	circles.extend(strip.circles);
+    }
+    let diagram = join_cells(bounds, sites, cells, &circles)?;
+    Ok((diagram, metrics))
+}
+
+/**
+ * Adds the counts of the events of a sweep to a total, the largest queue is the largest of either
+ */
+fn add_metrics(total: &mut SweepMetrics, metrics: &SweepMetrics) {
+    total.site_events += metrics.site_events;
+    total.circle_events += metrics.circle_events;
+    total.cancelled_events += metrics.cancelled_events;
+    total.max_queued_events = total.max_queued_events.max(metrics.max_queued_events);
+}
+
+/**
+ * Builds the cells of the sites in a range of the sorted sites, widening the margin until all of them are final
+ * The edges of the cells also have to be created by the same events as in a sweep of all sites, and the arcs of the
+ * first sites are wide enough to reach far past the margin. The sites above the lowest point of the circle of every
+ * such event are added to the sweep as a band across the bounds, so these events are final as well.
+ * Returns the id of every site of the strip with the corners of its cell, and the circle events that involve them.
+ */
+fn build_strip<F>(bounds: &BoundingBox<F>, positions: &[(F, F)], order: &[usize], rows: &[usize], strip: Range<usize>) -> Result<Strip<F>, VoronoiError>
+where F: Float {
+    let size = (bounds.width() + bounds.height()).to_f64();
+    let spacing = (bounds.width() * bounds.height()).to_f64().sqrt() / (positions.len() as f64).sqrt();
+    let tolerance = relative_tolerance::<F>(SEAM_TOLERANCE) * size;
+    let x = |index: usize| positions[order[index]].0.to_f64();
+    let mut strip_ids = order[strip.clone()].to_vec();
+    strip_ids.sort_unstable();
+    let mut metrics = SweepMetrics::default();
+    let mut margin = INITIAL_MARGIN * spacing;
+    let band_y = bounds.top().to_f64() + INITIAL_BAND * spacing;
+    let mut band = rows.partition_point(|site_id| positions[*site_id].1.to_f64() < band_y);
+    loop {
+	// the margin contains the sites with an x coordinate in range, sites beyond it lie on or past the limits
+	let (min_x, max_x) = (x(strip.start) - margin, x(strip.end - 1) + margin);
+	let first = order.partition_point(|site_id| positions[*site_id].0.to_f64() < min_x);
+	let last = order.partition_point(|site_id| positions[*site_id].0.to_f64() <= max_x);
+	let left_limit = if first > 0 { x(first - 1) } else { f64::NEG_INFINITY };
+	let right_limit = if last < order.len() { x(last) } else { f64::INFINITY };
+	// the band contains the sites above its limit, the other sites lie on or below it
+	let band_limit = if band < rows.len() { positions[rows[band]].1.to_f64() } else { f64::INFINITY };
+	let is_clear = |center_x: f64, radius: f64, bottom: f64| {
+	    (center_x - radius > left_limit + tolerance && center_x + radius < right_limit - tolerance) || bottom < band_limit - tolerance
+	};
+
+	let mut site_ids = order[first..last].to_vec();
+	site_ids.extend_from_slice(&rows[..band]);
+	site_ids.sort_unstable();
+	site_ids.dedup();
+	let mut builder = DiagramBuilder::new(bounds.clone());
+	builder.add_sites(site_ids.iter().map(|site_id| positions[*site_id]));
+	let result = builder.build_traced();
+	add_metrics(&mut metrics, builder.metrics());
+	let (diagram, trace) = match result {
+	    Ok(result) => result,
+	    // a subset of the sites can be degenerate where all of them are not, so the margin is widened up to all sites
+	    Err(_) if first > 0 || last < order.len() => {
+		margin *= 2.0;
+		continue;
+	    },
+	    Err(error) => return Err(error),
+	};
+	let event_id = |event: EventRef| match event {
+	    EventRef::Site(face_id) => EventId::Site(site_ids[face_id]),
+	    EventRef::Circle(circle_id) => EventId::Circle(trace.circles[circle_id].sites.map(|face_id| site_ids[face_id])),
+	};
+
+	let mut cells = Vec::with_capacity(strip.len());
+	let mut band_bottom = None;
+	for site_id in order[strip.clone()].iter() {
+	    let face_id = site_ids.binary_search(site_id)
+		.map_err(|_| VoronoiError::topology("site of a strip should be part of its diagram"))?;
+	    let (site_x, site_y) = (positions[*site_id].0.to_f64(), positions[*site_id].1.to_f64());
+	    let is_final = diagram.face_vertices(face_id).all(|vertex| {
+		let (vertex_x, vertex_y) = (vertex.x().to_f64(), vertex.y().to_f64());
+		let radius = (vertex_x - site_x).hypot(vertex_y - site_y);
+		is_clear(vertex_x, radius, vertex_y + radius)
+	    });
+	    if !is_final {
+		break;
+	    }
+	    for half_edge in diagram.face_half_edges(face_id) {
+		if let Some((center_x, radius, bottom)) = get_event_circle(&diagram, &trace, half_edge) {
+		    if !is_clear(center_x, radius, bottom) {
+			band_bottom = Some(band_bottom.map_or(bottom, |other: f64| other.max(bottom)));
+		    }
+		}
+	    }
+	    let corners = diagram.face_half_edges(face_id)
+		.map(|half_edge| {
+		    let vertex = &diagram.vertices()[half_edge.start_id()];
+		    Corner {
+			x: vertex.x(),
+			y: vertex.y(),
+			twin_face_id: half_edge.twin_id().map(|twin_id| site_ids[diagram.half_edges()[twin_id].face_id()]),
+			clip_boundary: half_edge.is_clip_boundary(),
+			event: trace.half_edges[half_edge.id()].map(|(event, index)| (event_id(event), index)),
+			start_event: trace.vertices[half_edge.start_id()].map(|circle_id| event_id(EventRef::Circle(circle_id))),
+			clip_order: trace.clip_orders[half_edge.id()],
+		    }
+		})
+		.collect();
+	    cells.push((*site_id, corners));
+	}
+	if cells.len() == strip.len() && band_bottom.is_none() {
+	    let circles = trace.circles.iter().enumerate()
+		.filter(|(_, circle)| circle.sites.iter().any(|face_id| strip_ids.binary_search(&site_ids[*face_id]).is_ok()))
+		.map(|(circle_id, circle)| (
+		    event_id(EventRef::Circle(circle_id)),
+		    (circle.priority, circle.parent.map(|(event, queued)| (event_id(event), queued)))
+		))
+		.collect();
+	    return Ok(Strip {
+		cells,
+		circles,
+		metrics,
+	    });
+	}
+	if cells.len() < strip.len() {
+	    margin *= 2.0;
+	}
+	if let Some(bottom) = band_bottom {
+	    band = rows.partition_point(|site_id| positions[*site_id].1.to_f64() <= bottom + tolerance).max(band + 1);
+	}
+    }
+}
+
+/**
+ * Returns the center x, radius and lowest y of the circle of the event that created the edge of a half edge, which
+ * does not contain any sites if the event is the same in a sweep of all sites
+ * The circle of a site event touches the directrix at the new site and passes through the site of the split arc.
+ */
+fn get_event_circle<F>(diagram: &Diagram<(), F>, trace: &SweepTrace<F>, half_edge: &HalfEdge) -> Option<(f64, f64, f64)>
+where F: Float {
+    match trace.half_edges[half_edge.id()]?.0 {
+	EventRef::Circle(circle_id) => {
+	    let circle = &trace.circles[circle_id];
+	    let center = (circle.center.get_x().to_f64(), circle.center.get_y().to_f64());
+	    let priority = circle.priority.to_f64();
+	    Some((center.0, priority - center.1, priority))
+	},
+	EventRef::Site(face_id) => {
+	    let other_id = if half_edge.face_id() == face_id {
+		diagram.half_edges()[half_edge.twin_id()?].face_id()
+	    } else {
+		half_edge.face_id()
+	    };
+	    let (site, other) = (diagram.site(face_id), diagram.site(other_id));
+	    let (site_x, site_y) = (site.get_x().to_f64(), site.get_y().to_f64());
+	    let (other_x, other_y) = (other.get_x().to_f64(), other.get_y().to_f64());
+	    if other_y == site_y {
+		// the edges of the first sites start infinitely high
+		return Some((site_x, f64::INFINITY, site_y));
+	    }
+	    let center_y = ((site_x - other_x).powi(2) + other_y * other_y - site_y * site_y) / (2.0 * (other_y - site_y));
+	    Some((site_x, site_y - center_y, site_y))
+	},
+    }
+}
+
+/**
+ * Compares events in the order in which a single sweep handles them
+ * Events are handled by priority, sites before circles with the same priority. Sites with the same priority are
+ * handled from left to right and circles in the order in which they were queued, i.e. in the order of the events
+ * that queued them. Rounding can give a circle event a priority below that of the event that queued it, e.g. for
+ * sites on a circle through a later site, and such an event is handled right after the event that queued it.
+ */
+struct EventOrder<'a, F: Float> {
+    positions: &'a [(F, F)],
+    circles: &'a HashMap<EventId, CircleInfo<F>>,
+
+    /**
+     * The priority of every known event at which it can be handled, which is never before the event that queued it
+     */
+    times: HashMap<EventId, F>,
+
+    /**
+     * The rank of every early circle event among those handled right after the same event, which itself has rank zero
+     */
+    early_ranks: HashMap<EventId, (EventId, usize)>,
+}
+
+impl<'a, F> EventOrder<'a, F> where F: Float {
+
+    fn new(positions: &'a [(F, F)], circles: &'a HashMap<EventId, CircleInfo<F>>) -> EventOrder<'a, F> {
+	let mut order = EventOrder {
+	    positions,
+	    circles,
+	    times: HashMap::new(),
+	    early_ranks: HashMap::new(),
+	};
+	let events: Vec<EventId> = circles.keys().copied().collect();
+	for event in events.iter() {
+	    order.compute_time(*event);
+	}
+	// the early events after an event are handled by priority and in the order in which they were queued
+	let mut children: HashMap<EventId, Vec<(usize, EventId)>> = HashMap::new();
+	for event in events.iter() {
+	    if let Some((parent, queued)) = order.early_parent(event) {
+		children.entry(parent).or_default().push((queued, *event));
+	    }
+	}
+	let anchors: Vec<EventId> = children.keys().filter(|event| order.early_parent(event).is_none()).copied().collect();
+	for anchor in anchors {
+	    let mut waiting: Vec<(F, usize, usize, EventId)> = Vec::new();
+	    let mut handled = 0;
+	    let mut next = Some(anchor);
+	    while let Some(event) = next {
+		for (queued_before, child) in children.get(&event).into_iter().flatten() {
+		    waiting.push((order.circles[child].0, handled, *queued_before, *child));
+		}
+		next = waiting.iter().enumerate()
+		    .min_by(|(_, first), (_, second)| first.0.compare(second.0).then((first.1, first.2).cmp(&(second.1, second.2))))
+		    .map(|(index, _)| index)
+		    .map(|index| waiting.swap_remove(index).3);
+		handled += 1;
+		if let Some(event) = next {
+		    order.early_ranks.insert(event, (anchor, handled));
+		}
+	    }
+	}
+	order
+    }
+
+    fn priority(&self, event: &EventId) -> Option<F> {
+	match event {
+	    EventId::Site(site_id) => Some(self.positions[*site_id].1),
+	    EventId::Circle(_) => self.circles.get(event).map(|(priority, _)| *priority),
+	}
+    }
+
+    /**
+     * Computes the times of an event and the events that queued it, following the chain until a known time
+     */
+    fn compute_time(&mut self, event: EventId) -> Option<F> {
+	let mut chain = vec![];
+	let mut next = Some(event);
+	let mut time = None;
+	while let Some(event) = next {
+	    if let Some(known) = self.times.get(&event) {
+		time = Some(*known);
+		break;
+	    }
+	    chain.push(event);
+	    next = match event {
+		EventId::Circle(_) => self.circles.get(&event).and_then(|(_, parent)| parent.map(|(parent, _)| parent)),
+		EventId::Site(_) => None,
+	    };
+	}
+	for event in chain.into_iter().rev() {
+	    let priority = self.priority(&event)?;
+	    let event_time = time.map_or(priority, |time: F| time.max(priority));
+	    self.times.insert(event, event_time);
+	    time = Some(event_time);
+	}
+	self.times.get(&event).copied()
+    }
+
+    /**
+     * The event that queued an event with a priority below the time of that event, with the number of events queued
+     * before it
+     */
+    fn early_parent(&self, event: &EventId) -> Option<(EventId, usize)> {
+	let (priority, parent) = self.circles.get(event)?;
+	let (parent, queued) = (*parent)?;
+	if *priority < *self.times.get(&parent)? {
+	    Some((parent, queued))
+	} else {
+	    None
+	}
+    }
+
+    /**
+     * Returns none if the order depends on a circle event that no strip knows
+     */
+    fn compare(&self, first: &EventId, second: &EventId) -> Option<Ordering> {
+	if first == second {
+	    return Some(Ordering::Equal);
+	}
+	let (first_anchor, first_rank) = self.early_ranks.get(first).copied().unwrap_or((*first, 0));
+	let (second_anchor, second_rank) = self.early_ranks.get(second).copied().unwrap_or((*second, 0));
+	if first_anchor == second_anchor {
+	    return Some(first_rank.cmp(&second_rank));
+	}
+	let (first, second) = (&first_anchor, &second_anchor);
+	let ordering = self.priority(first)?.partial_cmp(&self.priority(second)?)?;
+	if ordering != Ordering::Equal {
+	    return Some(ordering);
+	}
+	match (first, second) {
+	    (EventId::Site(first_id), EventId::Site(second_id)) => self.positions[*first_id].0.partial_cmp(&self.positions[*second_id].0),
+	    (EventId::Site(_), EventId::Circle(_)) => Some(Ordering::Less),
+	    (EventId::Circle(_), EventId::Site(_)) => Some(Ordering::Greater),
+	    (EventId::Circle(_), EventId::Circle(_)) => {
+		let (first_parent, first_queued) = self.circles.get(first)?.1?;
+		let (second_parent, second_queued) = self.circles.get(second)?.1?;
+		self.compare(&first_parent, &second_parent).map(|ordering| ordering.then(first_queued.cmp(&second_queued)))
+	    },
+	}
+    }
+
+}
+
+/**
+ * Joins the cells of all sites into a diagram, numbering the vertices and half edges in the order in which a single
+ * sweep creates them
+ */
+fn join_cells<T, F>(bounds: &BoundingBox<F>,
+		    sites: Vec<(F, F, T)>,
+		    cells: Vec<Option<Vec<Corner<F>>>>,
+		    circles: &HashMap<EventId, CircleInfo<F>>) -> Result<Diagram<T, F>, VoronoiError>
+where F: Float {
+    let positions: Vec<(F, F)> = sites.iter().map(|(x, y, _)| (*x, *y)).collect();
+    let cells = cells.into_iter()
+	.map(|corners| corners.ok_or_else(|| VoronoiError::topology("every site should have a cell")))
+	.collect::<Result<Vec<Vec<Corner<F>>>, VoronoiError>>()?;
+
+    // the rank of every event that created a vertex or half edge, in the order of a single sweep
+    let mut events: Vec<EventId> = cells.iter().flatten()
+	.flat_map(|corner| corner.event.map(|(event, _)| event).into_iter().chain(corner.start_event))
+	.collect();
+    let event_order = EventOrder::new(&positions, circles);
+    let mut is_ordered = true;
+    events.sort_by(|first, second| event_order.compare(first, second).unwrap_or_else(|| {
+	is_ordered = false;
+	Ordering::Equal
+    }));
+    if !is_ordered {
+	return Err(VoronoiError::topology("every event of the cells should be part of the sweep of a strip"));
+    }
+    events.dedup();
+    let ranks: HashMap<EventId, usize> = events.into_iter().enumerate().map(|(rank, event)| (event, rank)).collect();
+
+    // cells of different strips share the exact positions of their vertices, but a sweep places a separate vertex
+    // on the bounds when clipping an edge that ends at a circle event on the bounds
+    let clipped: HashMap<(EventId, usize), bool> = cells.iter().flatten()
+	.filter_map(|corner| corner.event.map(|event| (event, corner.start_event.is_none() && corner.clip_order.is_some())))
+	.collect();
+    let mut positions_ids: HashMap<(u64, u64, bool), usize> = HashMap::new();
+    let mut vertex_positions = Vec::new();
+    let mut vertex_keys: Vec<Option<VertexKey>> = Vec::new();
+    let mut half_edge_keys = Vec::new();
+    let mut half_edges = Vec::new();
+    let mut twin_face_ids = Vec::new();
+    let mut face_start_ids = Vec::with_capacity(cells.len());
+    for (face_id, corners) in cells.into_iter().enumerate() {
+	let start_id = half_edges.len();
+	let count = corners.len();
+	face_start_ids.push(start_id);
+	let previous_events: Vec<Option<(EventId, usize)>> = (0..count).map(|i| corners[(i + count - 1) % count].event).collect();
+	for (i, corner) in corners.into_iter().enumerate() {
+	    // an edge along the bounds starts where the twin of the previous edge starts
+	    let is_clipped = match (corner.event, previous_events[i]) {
+		(Some(event), _) => clipped[&event],
+		(None, Some((event, index))) => clipped.get(&(event, 1 - index)).copied().unwrap_or(false),
+		(None, None) => false,
+	    };
+	    let key = (corner.x.to_f64().to_bits(), corner.y.to_f64().to_bits(), is_clipped);
+	    let vertex_id = *positions_ids.entry(key).or_insert_with(|| {
+		vertex_positions.push((corner.x, corner.y));
+		vertex_keys.push(None);
+		vertex_positions.len() - 1
+	    });
+	    let (half_edge_key, vertex_key) = match corner.event {
+		Some((event, index)) => {
+		    let rank = ranks[&event];
+		    let vertex_key = match (corner.start_event, corner.clip_order) {
+			(Some(start_event), _) => VertexKey::Circle(ranks[&start_event]),
+			(None, Some(order)) => VertexKey::Clip(rank, order),
+			(None, None) => return Err(VoronoiError::topology("edge should start at a circle event or on the bounds")),
+		    };
+		    (HalfEdgeKey::Sweep(rank, index), vertex_key)
+		},
+		None => (HalfEdgeKey::Bound(face_id, i), VertexKey::Bound(face_id, i)),
+	    };
+	    vertex_keys[vertex_id] = Some(vertex_keys[vertex_id].map_or(vertex_key, |other_key| other_key.min(vertex_key)));
+	    half_edge_keys.push(half_edge_key);
+	    half_edges.push(HalfEdge {
+		id: start_id + i,
+		face_id,
+		start_id: vertex_id,
+		twin_id: None,
+		prev_id: start_id + (i + count - 1) % count,
+		next_id: start_id + (i + 1) % count,
+		clip_boundary: corner.clip_boundary,
+		wrap: (0, 0),
+	    });
+	    twin_face_ids.push(corner.twin_face_id);
+	}
+    }
+
+    // twins run between the same vertices in the opposite direction
+    let edge_ids: HashMap<(usize, usize), usize> = half_edges.iter()
+	.map(|half_edge| ((half_edge.start_id, half_edges[half_edge.next_id].start_id), half_edge.id))
+	.collect();
+    for half_edge_id in 0..half_edges.len() {
+	if let Some(twin_face_id) = twin_face_ids[half_edge_id] {
+	    let key = (half_edges[half_edges[half_edge_id].next_id].start_id, half_edges[half_edge_id].start_id);
+	    match edge_ids.get(&key) {
+		Some(twin_id) if half_edges[*twin_id].face_id == twin_face_id => half_edges[half_edge_id].twin_id = Some(*twin_id),
+		_ => return Err(VoronoiError::topology("cells of neighbouring strips should share their edges")),
+	    }
+	}
+    }
+
+    // number the vertices and half edges in the order of their keys
+    let vertex_ids = order_by_keys(&vertex_keys.into_iter().collect::<Option<Vec<VertexKey>>>()
+				   .ok_or_else(|| VoronoiError::topology("every vertex should start a half edge"))?)?;
+    let half_edge_ids = order_by_keys(&half_edge_keys)?;
+    let mut vertices: Vec<Vertex<F>> = vertex_positions.into_iter().enumerate()
+	.map(|(vertex_id, (x, y))| Vertex {
+	    id: vertex_ids[vertex_id],
+	    x,
+	    y,
+	})
+	.collect();
+    vertices.sort_by_key(|vertex| vertex.id);
+    let mut half_edges: Vec<HalfEdge> = half_edges.into_iter()
+	.map(|half_edge| HalfEdge {
+	    id: half_edge_ids[half_edge.id],
+	    face_id: half_edge.face_id,
+	    start_id: vertex_ids[half_edge.start_id],
+	    twin_id: half_edge.twin_id.map(|twin_id| half_edge_ids[twin_id]),
+	    prev_id: half_edge_ids[half_edge.prev_id],
+	    next_id: half_edge_ids[half_edge.next_id],
+	    clip_boundary: half_edge.clip_boundary,
+	    wrap: half_edge.wrap,
+	})
+	.collect();
+    half_edges.sort_by_key(|half_edge| half_edge.id);
+    let faces = sites.into_iter().zip(face_start_ids).enumerate()
+	.map(|(face_id, ((x, y, data), start_id))| Face {
+	    id: face_id,
+	    x,
+	    y,
+	    start_id: half_edge_ids[start_id],
+	    site_id: face_id,
+	    hole_start_ids: Vec::new(),
+	    weight: F::from_f64(0.0),
+	    data,
+	})
+	.collect();
+    Ok(Diagram {
+	bounds: bounds.clone(),
+	vertices,
+	half_edges,
+	faces,
+	wrap: Wrap::None,
+	attributes: Attributes::new(),
+    })
+}
+
+/**
+ * Returns the new id of every element, which is its position in the order of the keys
+ */
+fn order_by_keys<K>(keys: &[K]) -> Result<Vec<usize>, VoronoiError> where K: Ord {
+    let mut order: Vec<usize> = (0..keys.len()).collect();
+    order.sort_by(|first, second| keys[*first].cmp(&keys[*second]));
+    if order.windows(2).any(|pair| keys[pair[0]] == keys[pair[1]]) {
+	return Err(VoronoiError::topology("cells of different strips should be created by different events"));
+    }
+    let mut ids = vec![0; keys.len()];
+    for (new_id, old_id) in order.into_iter().enumerate() {
+	ids[old_id] = new_id;
+    }
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::random::Random;
+
+    fn build(sites: &[(f64, f64)], threads: usize) -> Diagram {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 1000.0, 0.0, 1000.0));
+	builder.set_threads(threads);
+	builder.add_sites(sites.iter().cloned());
+	builder.build().unwrap()
+    }
+
+    fn assert_same_as_sequential(sites: &[(f64, f64)], threads: usize) {
+	let diagram = build(sites, threads);
+	assert_eq!(Ok(()), diagram.validate());
+	assert_eq!(build(sites, 1), diagram);
+    }
+
+    #[test]
+    fn test_random() {
+	for seed in 0..5 {
+	    let mut random = Random::new(seed);
+	    let sites: Vec<(f64, f64)> = (0..2000).map(|_| (random.range(0.0, 1000.0), random.range(0.0, 1000.0))).collect();
+	    assert_same_as_sequential(&sites, 4);
+	}
+    }
+
+    #[test]
+    fn test_clustered() {
+	// the cells between the clusters reach far past the first margin
+	let mut random = Random::new(3);
+	let sites: Vec<(f64, f64)> = (0..1000)
+	    .map(|i| {
+		let center = if i % 2 == 0 { 100.0 } else { 900.0 };
+		(center + random.range(-20.0, 20.0), random.range(0.0, 1000.0))
+	    })
+	    .collect();
+	assert_same_as_sequential(&sites, 3);
+    }
+
+    #[test]
+    fn test_grid() {
+	// four sites meet at every vertex of a grid, and the strips end between sites with the same x coordinate
+	let sites: Vec<(f64, f64)> = (0..1024).map(|i| (15.625 + 31.25 * (i % 32) as f64, 15.625 + 31.25 * (i / 32) as f64)).collect();
+	assert_same_as_sequential(&sites, 5);
+    }
+
+    fn assert_same_on_lattice(seed: u64, size: usize) {
+	let mut random = Random::new(seed);
+	let count = 10 + random.index(200);
+	let threads = 2 + random.index(7);
+	let mut sites: Vec<(f64, f64)> = (0..count).map(|_| (random.index(size + 1) as f64, random.index(size + 1) as f64)).collect();
+	sites.sort_by(|first, second| first.partial_cmp(second).unwrap());
+	sites.dedup();
+	let build = |threads: usize| {
+	    let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, size as f64, 0.0, size as f64));
+	    builder.set_threads(threads);
+	    builder.add_sites(sites.iter().cloned());
+	    builder.build().unwrap()
+	};
+	assert_eq!(build(1), build(threads), "seed {}", seed);
+    }
+
+    #[test]
+    fn test_integer_lattice() {
+	// many sites lie on a circle, and rounding can give a circle event a priority below that of the site which
+	// queued it
+	for seed in 0..150 {
+	    assert_same_on_lattice(seed, 30);
+	}
+	// the center of the circle through three sites lies on the bounds, where the sweep places a second vertex
+	assert_same_on_lattice(1459, 100);
+    }
+
+    #[test]
+    fn test_few_sites() {
+	let sites = vec![(100.0, 100.0), (900.0, 900.0), (500.0, 200.0)];
+	assert_same_as_sequential(&sites, 8);
+    }
+
+    #[test]
+    fn test_metrics() {
+	// every strip sweeps its own sites and those of its margin, so the strips handle more events than a single sweep
+	let mut random = Random::new(7);
+	let sites: Vec<(f64, f64)> = (0..1000).map(|_| (random.range(0.0, 1000.0), random.range(0.0, 1000.0))).collect();
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 1000.0, 0.0, 1000.0));
+	builder.add_sites(sites.iter().cloned());
+	builder.build().unwrap();
+	let sequential = *builder.metrics();
+	builder.set_threads(4);
+	builder.add_sites(sites.iter().cloned());
+	builder.build().unwrap();
+	let metrics = builder.metrics();
+	assert!(metrics.site_events > sequential.site_events);
+	assert!(metrics.circle_events > sequential.circle_events);
+	assert!(metrics.max_queued_events > 0 && metrics.max_queued_events < sequential.max_queued_events);
+    }
+
+    #[test]
+    fn test_with_data() {
+	let mut random = Random::new(11);
+	let sites: Vec<(f64, f64, usize)> = (0..500).map(|i| (random.range(0.0, 1000.0), random.range(0.0, 1000.0), i)).collect();
+	let mut builder = DiagramBuilder::with_data(BoundingBox::new(0.0, 1000.0, 0.0, 1000.0));
+	builder.set_threads(4);
+	builder.add_sites_with_data(sites.clone());
+	let diagram = builder.build().unwrap();
+	assert!(diagram.faces().iter().enumerate().all(|(id, face)| *face.data() == id));
+	builder.set_threads(1);
+	builder.add_sites_with_data(sites);
+	assert_eq!(builder.build().unwrap(), diagram);
+    }
+
+    #[test]
+    fn test_f32() {
+	let mut random = Random::new(5);
+	let sites: Vec<(f32, f32)> = (0..1000).map(|_| (random.range(0.0, 1000.0) as f32, random.range(0.0, 1000.0) as f32)).collect();
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0f32, 1000.0, 0.0, 1000.0));
+	builder.add_sites(sites.iter().cloned());
+	let sequential = builder.build().unwrap();
+	builder.set_threads(4);
+	builder.add_sites(sites.iter().cloned());
+	assert_eq!(sequential, builder.build().unwrap());
+    }
+
+}
@@ -0,0 +1,614 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * Periodic voronoi diagrams, which wrap around their bounds horizontally, vertically or both
+ *
+ * A periodic diagram is the diagram of the sites and all their copies moved by whole periods, where the periods are
+ * the width and height of the bounds. Every cell is computed once around its site, so cells on a seam reach past the
+ * bounds and stay continuous. Vertices are stored inside the bounds, and every half edge knows by how many periods
+ * its start vertex is moved in its face, which lets twins link across the seams.
+ */
+
+use crate::geom::{BoundingBox, polygon_signed_area};
+use crate::power::{cut_cell, tolerance};
+use crate::vector::{Vector2, Vector2F64};
+use crate::voronoi::{Diagram, FaceParts, VoronoiError};
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/**
+ * How a diagram wraps around its bounds
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Wrap {
+
+    /**
+     * The diagram is clipped to its bounds
+     */
+    #[default]
+    None,
+
+    /**
+     * The left and right side of the bounds are joined, as on a cylinder
+     */
+    Horizontal,
+
+    /**
+     * The top and bottom side of the bounds are joined, as on a cylinder
+     */
+    Vertical,
+
+    /**
+     * Both pairs of sides are joined, as on a torus
+     */
+    Both,
+}
+
+impl Wrap {
+
+    pub fn from_axes(horizontal: bool, vertical: bool) -> Wrap {
+	match (horizontal, vertical) {
+	    (false, false) => Wrap::None,
+	    (true, false) => Wrap::Horizontal,
+	    (false, true) => Wrap::Vertical,
+	    (true, true) => Wrap::Both,
+	}
+    }
+
+    pub fn is_horizontal(&self) -> bool {
+	matches!(self, Wrap::Horizontal | Wrap::Both)
+    }
+
+    pub fn is_vertical(&self) -> bool {
+	matches!(self, Wrap::Vertical | Wrap::Both)
+    }
+
+    /**
+     * Moves an offset between two points by whole periods along the wrapped axes, so it becomes as short as possible
+     */
+    pub fn shortest_offset(&self, bounds: &BoundingBox, dx: f64, dy: f64) -> (f64, f64) {
+	let dx = if self.is_horizontal() { dx - bounds.width() * (dx / bounds.width()).round() } else { dx };
+	let dy = if self.is_vertical() { dy - bounds.height() * (dy / bounds.height()).round() } else { dy };
+	(dx, dy)
+    }
+
+    /**
+     * Moves a point by whole periods along the wrapped axes, so it lies inside the bounds
+     * Points on the right or bottom side are moved to the left or top side, which is the same place.
+     */
+    pub fn wrap_point(&self, bounds: &BoundingBox, x: f64, y: f64) -> (f64, f64) {
+	let x = if self.is_horizontal() { bounds.left() + (x - bounds.left()).rem_euclid(bounds.width()) } else { x };
+	let y = if self.is_vertical() { bounds.top() + (y - bounds.top()).rem_euclid(bounds.height()) } else { y };
+	(x, y)
+    }
+
+    /**
+     * The numbers of periods by which copies can be moved along the x and y axis
+     */
+    fn copies(&self) -> impl Iterator<Item = (i32, i32)> {
+	let horizontal = if self.is_horizontal() { -1..=1 } else { 0..=0 };
+	let vertical = if self.is_vertical() { -1..=1 } else { 0..=0 };
+	vertical.flat_map(move |copy_y| horizontal.clone().map(move |copy_x| (copy_x, copy_y)))
+    }
+
+}
+
+/**
+ * How the cells on the seams of a periodic diagram are drawn
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SeamMode {
+
+    /**
+     * Every cell is drawn whole, once for every copy that overlaps the bounds, so seam cells reach past the bounds
+     */
+    Duplicate,
+
+    /**
+     * Every cell is cut at the seams, and the pieces are moved into the bounds
+     */
+    #[default]
+    Split,
+}
+
+impl<T> Diagram<T> {
+
+    /**
+     * The polygons that show a face inside the bounds, depending on how seam cells are drawn
+     * Faces of diagrams that do not wrap are returned as they are.
+     */
+    pub fn seam_polygons(&self, face_id: usize, seams: SeamMode) -> Vec<Vec<Vector2F64>> {
+	let polygon = self.face_polygon(face_id);
+	if self.wrap == Wrap::None {
+	    return vec![polygon];
+	}
+	let bounds = &self.bounds;
+	let mut polygons = Vec::new();
+	for (copy_x, copy_y) in self.wrap.copies() {
+	    let (dx, dy) = (copy_x as f64 * bounds.width(), copy_y as f64 * bounds.height());
+	    let copy: Vec<Vector2F64> = polygon.iter().map(|point| Vector2F64::from_values(point.get_x() + dx, point.get_y() + dy)).collect();
+	    let inside = clip_to_bounds(&copy, bounds);
+	    // copies that only touch the bounds are left out
+	    if polygon_signed_area(&inside) > tolerance(bounds) * (bounds.width() + bounds.height()) {
+		polygons.push(match seams {
+		    SeamMode::Duplicate => copy,
+		    SeamMode::Split => inside,
+		});
+	    }
+	}
+	polygons
+    }
+
+    /**
+     * Creates triangles for the polygons of all faces in the same format as create_triangles,
+     * with a fan of triangles around the first corner of every polygon
+     */
+    pub fn create_seam_triangles(&self, seams: SeamMode) -> (Vec<f32>, Vec<u32>) {
+	let scale = self.bounds.width() / 2.0;
+	let left = self.bounds.left();
+	let top = self.bounds.top();
+	let mut vertices = Vec::new();
+	let mut indices = Vec::new();
+	for face_id in 0..self.faces.len() {
+	    for polygon in self.seam_polygons(face_id, seams) {
+		let first = (vertices.len() / 3) as u32;
+		for point in polygon.iter() {
+		    vertices.push(((point.get_x() - left) / scale - 1.0) as f32);
+		    vertices.push(((point.get_y() - top) / scale - 1.0) as f32);
+		    vertices.push(0.0f32);
+		}
+		for i in 1..polygon.len().saturating_sub(1) as u32 {
+		    indices.push(first);
+		    indices.push(first + i);
+		    indices.push(first + i + 1);
+		}
+	    }
+	}
+	(vertices, indices)
+    }
+
+}
+
+/**
+ * Cuts a convex polygon to the bounds (Sutherland-Hodgman)
+ */
+fn clip_to_bounds(polygon: &[Vector2F64], bounds: &BoundingBox) -> Vec<Vector2F64> {
+    let sides: [(f64, f64, f64); 4] = [
+	(-1.0, 0.0, -bounds.left()),
+	(1.0, 0.0, bounds.right()),
+	(0.0, -1.0, -bounds.top()),
+	(0.0, 1.0, bounds.bottom()),
+    ];
+    let mut result = polygon.to_vec();
+    for (normal_x, normal_y, offset) in sides.iter() {
+	// the polygon is kept where normal * point <= offset
+	let side = |point: &Vector2F64| normal_x * point.get_x() + normal_y * point.get_y() - offset;
+	let points = result;
+	result = Vec::with_capacity(points.len() + 1);
+	for (i, start) in points.iter().enumerate() {
+	    let end = &points[(i + 1) % points.len()];
+	    let (start_side, end_side) = (side(start), side(end));
+	    if (start_side <= 0.0) != (end_side <= 0.0) {
+		let t = start_side / (start_side - end_side);
+		result.push(Vector2F64::from_values(start.get_x() + t * (end.get_x() - start.get_x()), start.get_y() + t * (end.get_y() - start.get_y())));
+	    }
+	    if end_side <= 0.0 {
+		result.push(*end);
+	    }
+	}
+    }
+    result
+}
+
+/**
+ * Builds a periodic diagram from sites that were checked to lie inside the bounds
+ */
+pub(crate) fn build_periodic<T>(bounds: &BoundingBox, wrap: Wrap, sites: Vec<(f64, f64, T)>) -> Result<Diagram<T>, VoronoiError> {
+    let sites: Vec<(f64, f64, T)> = sites.into_iter()
+	.map(|(x, y, data)| {
+	    let (x, y) = wrap.wrap_point(bounds, x, y);
+	    (x, y, data)
+	})
+	.collect();
+    let mut site_ids: Vec<usize> = (0..sites.len()).collect();
+    let compare = |first: usize, second: usize| (sites[first].0, sites[first].1).partial_cmp(&(sites[second].0, sites[second].1)).unwrap_or(Ordering::Equal);
+    site_ids.sort_by(|first, second| compare(*first, *second));
+    for pair in site_ids.windows(2) {
+	// sites on opposite sides of a seam are only found here, after they were moved to the same side
+	if compare(pair[0], pair[1]) == Ordering::Equal {
+	    return Err(VoronoiError::DuplicateSite {
+		first_id: pair[0].min(pair[1]),
+		second_id: pair[0].max(pair[1]),
+	    });
+	}
+    }
+    let positions: Vec<(f64, f64)> = sites.iter().map(|(x, y, _)| (*x, *y)).collect();
+    let cells = PeriodicCells::new(bounds, wrap, &positions);
+    let mut vertices = Vertices::new(bounds, wrap);
+    let mut half_edges = Vec::new();
+    let mut wraps = Vec::new();
+    let mut faces = Vec::with_capacity(sites.len());
+    for (site_id, (x, y, data)) in sites.into_iter().enumerate() {
+	let mut corners: Vec<(usize, (i32, i32))> = cells.compute_cell(site_id).into_iter()
+	    .map(|(corner_x, corner_y)| vertices.snap(corner_x, corner_y))
+	    .collect();
+	corners.dedup();
+	while corners.len() > 1 && corners.first() == corners.last() {
+	    corners.pop();
+	}
+	if corners.len() < 3 {
+	    return Err(VoronoiError::topology("a periodic cell collapsed"));
+	}
+	let face_id = faces.len();
+	let start_id = half_edges.len();
+	let count = corners.len();
+	for (i, (vertex_id, corner_wrap)) in corners.into_iter().enumerate() {
+	    half_edges.push((face_id, vertex_id, None, start_id + (i + count - 1) % count, start_id + (i + 1) % count, false));
+	    wraps.push(corner_wrap);
+	}
+	faces.push(FaceParts {
+	    x,
+	    y,
+	    start_id,
+	    site_id,
+	    hole_start_ids: Vec::new(),
+	    weight: 0.0,
+	    data,
+	});
+    }
+
+    // twins run between the same vertices in the opposite direction, and cross the seams in the opposite direction
+    let keys: Vec<(usize, usize, i32, i32)> = half_edges.iter().enumerate()
+	.map(|(half_edge_id, half_edge)| {
+	    let next_id = half_edge.4;
+	    let (start_wrap, end_wrap) = (wraps[half_edge_id], wraps[next_id]);
+	    (half_edge.1, half_edges[next_id].1, end_wrap.0 - start_wrap.0, end_wrap.1 - start_wrap.1)
+	})
+	.collect();
+    let edge_ids: HashMap<(usize, usize, i32, i32), usize> = keys.iter().enumerate().map(|(half_edge_id, key)| (*key, half_edge_id)).collect();
+    for (half_edge, (start_id, end_id, dx, dy)) in half_edges.iter_mut().zip(keys) {
+	half_edge.2 = edge_ids.get(&(end_id, start_id, -dx, -dy)).copied();
+    }
+    let mut diagram = Diagram::from_face_parts(bounds.clone(), vertices.positions, half_edges, faces);
+    diagram.wrap = wrap;
+    for (half_edge, wrap) in diagram.half_edges.iter_mut().zip(wraps) {
+	half_edge.wrap = wrap;
+    }
+    Ok(diagram)
+}
+
+/**
+ * Computes the cell of every site around the site, cutting a region of one period around it with the bisectors
+ * to the nearby copies of all sites, which are found with a grid
+ */
+struct PeriodicCells<'a> {
+    bounds: &'a BoundingBox,
+    wrap: Wrap,
+    sites: &'a [(f64, f64)],
+    columns: i64,
+    rows: i64,
+    cell_width: f64,
+    cell_height: f64,
+
+    /**
+     * The ids of the sites in each grid cell, row by row
+     */
+    site_ids: Vec<Vec<usize>>,
+}
+
+impl<'a> PeriodicCells<'a> {
+
+    fn new(bounds: &'a BoundingBox, wrap: Wrap, sites: &'a [(f64, f64)]) -> PeriodicCells<'a> {
+	let count = sites.len().max(1) as f64;
+	let aspect = bounds.width() / bounds.height();
+	let columns = ((count * aspect).sqrt().ceil() as i64).max(1);
+	let rows = ((count / aspect).sqrt().ceil() as i64).max(1);
+	let mut cells = PeriodicCells {
+	    bounds,
+	    wrap,
+	    sites,
+	    columns,
+	    rows,
+	    cell_width: bounds.width() / columns as f64,
+	    cell_height: bounds.height() / rows as f64,
+	    site_ids: vec![Vec::new(); (columns * rows) as usize],
+	};
+	for (site_id, (x, y)) in sites.iter().enumerate() {
+	    let (column, row) = cells.grid_cell(*x, *y);
+	    cells.site_ids[(row * columns + column) as usize].push(site_id);
+	}
+	cells
+    }
+
+    fn grid_cell(&self, x: f64, y: f64) -> (i64, i64) {
+	let column = (((x - self.bounds.left()) / self.cell_width) as i64).clamp(0, self.columns - 1);
+	let row = (((y - self.bounds.top()) / self.cell_height) as i64).clamp(0, self.rows - 1);
+	(column, row)
+    }
+
+    /**
+     * Cuts the region around the site with the bisectors to the sites in growing rings of grid cells, where rings
+     * continue past the seams into the copies, until the sites outside the rings are too far away to cut the cell
+     */
+    fn compute_cell(&self, site_id: usize) -> Vec<(f64, f64)> {
+	let tolerance = tolerance(self.bounds);
+	let (x, y) = self.sites[site_id];
+	let (width, height) = (self.bounds.width(), self.bounds.height());
+	let (left, right) = if self.wrap.is_horizontal() { (x - width, x + width) } else { (self.bounds.left(), self.bounds.right()) };
+	let (top, bottom) = if self.wrap.is_vertical() { (y - height, y + height) } else { (self.bounds.top(), self.bounds.bottom()) };
+	let mut polygon = vec![(left, top), (right, top), (right, bottom), (left, bottom)];
+	let (column, row) = self.grid_cell(x, y);
+	let mut ring = 0;
+	loop {
+	    for (ring_column, ring_row) in self.ring_cells(column, row, ring) {
+		let copy_x = ring_column.div_euclid(self.columns);
+		let copy_y = ring_row.div_euclid(self.rows);
+		let cell_id = ring_row.rem_euclid(self.rows) * self.columns + ring_column.rem_euclid(self.columns);
+		for other_id in self.site_ids[cell_id as usize].iter() {
+		    if *other_id != site_id || copy_x != 0 || copy_y != 0 {
+			let other = self.sites[*other_id];
+			let other = (other.0 + copy_x as f64 * width, other.1 + copy_y as f64 * height, 0.0);
+			polygon = cut_cell(polygon, (x, y, 0.0), other, tolerance);
+		    }
+		}
+	    }
+	    // the sites outside the rings are at least this far from the site
+	    let mut distance = f64::INFINITY;
+	    if self.wrap.is_horizontal() || column > ring {
+		distance = distance.min(x - (self.bounds.left() + (column - ring) as f64 * self.cell_width));
+	    }
+	    if self.wrap.is_horizontal() || column + ring + 1 < self.columns {
+		distance = distance.min(self.bounds.left() + (column + ring + 1) as f64 * self.cell_width - x);
+	    }
+	    if self.wrap.is_vertical() || row > ring {
+		distance = distance.min(y - (self.bounds.top() + (row - ring) as f64 * self.cell_height));
+	    }
+	    if self.wrap.is_vertical() || row + ring + 1 < self.rows {
+		distance = distance.min(self.bounds.top() + (row + ring + 1) as f64 * self.cell_height - y);
+	    }
+	    let radius = polygon.iter()
+		.map(|(corner_x, corner_y)| (corner_x - x).hypot(corner_y - y))
+		.fold(0.0, f64::max);
+	    // a site can only cut the cell if it is closer to a corner than the site itself
+	    if distance >= 2.0 * radius {
+		return polygon;
+	    }
+	    ring += 1;
+	}
+    }
+
+    /**
+     * The grid cells at the specified chebyshev distance from a grid cell, which lie outside the grid
+     * along the wrapped axes
+     */
+    fn ring_cells(&self, column: i64, row: i64, ring: i64) -> Vec<(i64, i64)> {
+	let columns = if self.wrap.is_horizontal() { column - ring..=column + ring } else { (column - ring).max(0)..=(column + ring).min(self.columns - 1) };
+	let rows = if self.wrap.is_vertical() { row - ring..=row + ring } else { (row - ring).max(0)..=(row + ring).min(self.rows - 1) };
+	rows.flat_map(|ring_row| columns.clone().map(move |ring_column| (ring_column, ring_row)))
+	    .filter(|(ring_column, ring_row)| (ring_column - column).abs().max((ring_row - row).abs()) == ring)
+	    .collect()
+    }
+
+}
+
+/**
+ * The vertices of a periodic diagram, which lie inside the bounds
+ */
+struct Vertices {
+    bounds: BoundingBox,
+    wrap: Wrap,
+    tolerance: f64,
+    positions: Vec<(f64, f64)>,
+    grid: HashMap<(i64, i64), Vec<usize>>,
+}
+
+impl Vertices {
+
+    fn new(bounds: &BoundingBox, wrap: Wrap) -> Vertices {
+	Vertices {
+	    bounds: bounds.clone(),
+	    wrap,
+	    tolerance: tolerance(bounds),
+	    positions: Vec::new(),
+	    grid: HashMap::new(),
+	}
+    }
+
+    fn key(&self, x: f64, y: f64) -> (i64, i64) {
+	((x / self.tolerance).floor() as i64, (y / self.tolerance).floor() as i64)
+    }
+
+    /**
+     * Finds or adds the vertex at a corner of a cell, returning its id and the number of periods
+     * by which the corner is moved from the vertex
+     */
+    fn snap(&mut self, x: f64, y: f64) -> (usize, (i32, i32)) {
+	let (width, height) = (self.bounds.width(), self.bounds.height());
+	let (inside_x, inside_y) = self.wrap.wrap_point(&self.bounds, x, y);
+	let wrap = (((x - inside_x) / width).round() as i32, ((y - inside_y) / height).round() as i32);
+	// a corner on a seam can match a vertex on the opposite side
+	for (copy_x, copy_y) in self.wrap.copies() {
+	    let (copy_x_position, copy_y_position) = (inside_x + copy_x as f64 * width, inside_y + copy_y as f64 * height);
+	    let key = self.key(copy_x_position, copy_y_position);
+	    for neighbour_key in (key.0 - 1..=key.0 + 1).flat_map(|column| (key.1 - 1..=key.1 + 1).map(move |row| (column, row))) {
+		let found = self.grid.get(&neighbour_key).and_then(|vertex_ids| vertex_ids.iter().find(|vertex_id| {
+		    let (vertex_x, vertex_y) = self.positions[**vertex_id];
+		    (vertex_x - copy_x_position).hypot(vertex_y - copy_y_position) <= self.tolerance
+		}));
+		if let Some(vertex_id) = found {
+		    return (*vertex_id, (wrap.0 - copy_x, wrap.1 - copy_y));
+		}
+	    }
+	}
+	let key = self.key(inside_x, inside_y);
+	self.positions.push((inside_x, inside_y));
+	self.grid.entry(key).or_default().push(self.positions.len() - 1);
+	(self.positions.len() - 1, wrap)
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::random::Random;
+    use crate::voronoi::DiagramBuilder;
+
+    fn create_periodic_diagram(wrap: Wrap, count: usize, seed: u64) -> Diagram<usize> {
+	let mut random = Random::new(seed);
+	let mut builder = DiagramBuilder::with_data(BoundingBox::new(0.0, 100.0, 0.0, 80.0));
+	builder.add_sites_with_data((0..count).map(|id| (random.range(0.0, 100.0), random.range(0.0, 80.0), id)));
+	builder.set_wrap(wrap);
+	builder.set_validate(true);
+	builder.build().unwrap()
+    }
+
+    /**
+     * Builds an ordinary diagram of the sites and their copies, where the copies are added after the sites
+     */
+    fn create_tiled_diagram(diagram: &Diagram<usize>) -> Diagram {
+	let bounds = diagram.bounds();
+	let wrap = diagram.wrap();
+	let (columns, rows) = (if wrap.is_horizontal() { 3.0 } else { 1.0 }, if wrap.is_vertical() { 3.0 } else { 1.0 });
+	let left = if wrap.is_horizontal() { bounds.left() - bounds.width() } else { bounds.left() };
+	let top = if wrap.is_vertical() { bounds.top() - bounds.height() } else { bounds.top() };
+	let mut builder = DiagramBuilder::new(BoundingBox::new(left, left + columns * bounds.width(), top, top + rows * bounds.height()));
+	let mut copies: Vec<(i32, i32)> = wrap.copies().collect();
+	copies.sort_by_key(|copy| *copy != (0, 0));
+	for (copy_x, copy_y) in copies {
+	    builder.add_sites(diagram.faces().iter().map(|face| (face.x() + copy_x as f64 * bounds.width(), face.y() + copy_y as f64 * bounds.height())));
+	}
+	builder.build().unwrap()
+    }
+
+    fn assert_same_as_tiled(diagram: &Diagram<usize>) {
+	let tiled = create_tiled_diagram(diagram);
+	for face in diagram.faces().iter() {
+	    let area = polygon_signed_area(&diagram.face_polygon(face.id()));
+	    assert!((polygon_signed_area(&tiled.face_polygon(face.id())) - area).abs() < 1e-6);
+	}
+	let total: f64 = (0..diagram.faces().len()).map(|face_id| polygon_signed_area(&diagram.face_polygon(face_id))).sum();
+	assert!((total - 8000.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_torus() {
+	let diagram = create_periodic_diagram(Wrap::Both, 200, 1);
+	assert_eq!(Wrap::Both, diagram.wrap());
+	assert!(diagram.half_edges().iter().all(|half_edge| half_edge.twin_id().is_some()));
+	assert!(diagram.half_edges().iter().any(|half_edge| half_edge.wrap() != (0, 0)));
+	assert!(diagram.vertices().iter().all(|vertex| diagram.bounds().contains(&Vector2F64::from_values(vertex.x(), vertex.y()))));
+	assert_same_as_tiled(&diagram);
+	// twins match up once the seam crossing is undone
+	for half_edge in diagram.half_edges().iter() {
+	    let twin = &diagram.half_edges()[half_edge.twin_id().unwrap()];
+	    let start = diagram.half_edge_start(half_edge.id());
+	    let twin_end = diagram.half_edge_start(twin.next_id());
+	    let (dx, dy) = diagram.wrap().shortest_offset(diagram.bounds(), start.get_x() - twin_end.get_x(), start.get_y() - twin_end.get_y());
+	    assert!(dx.hypot(dy) < 1e-9);
+	}
+    }
+
+    #[test]
+    fn test_cylinders() {
+	let horizontal = create_periodic_diagram(Wrap::Horizontal, 150, 2);
+	assert_same_as_tiled(&horizontal);
+	let vertical = create_periodic_diagram(Wrap::Vertical, 150, 3);
+	assert_same_as_tiled(&vertical);
+	// only the sides that do not wrap have half edges without twins
+	for half_edge in horizontal.half_edges().iter().filter(|half_edge| half_edge.twin_id().is_none()) {
+	    let start = horizontal.half_edge_start(half_edge.id());
+	    assert!(start.get_y() == 0.0 || start.get_y() == 80.0);
+	}
+    }
+
+    #[test]
+    fn test_locate_periodic() {
+	let diagram = create_periodic_diagram(Wrap::Both, 100, 4);
+	let locator = diagram.locator();
+	let mut random = Random::new(5);
+	for _ in 0..1000 {
+	    let (x, y) = (random.range(0.0, 100.0), random.range(0.0, 80.0));
+	    let distance = |face_id: usize| {
+		let face = &diagram.faces()[face_id];
+		let (dx, dy) = diagram.wrap().shortest_offset(diagram.bounds(), face.x() - x, face.y() - y);
+		dx.hypot(dy)
+	    };
+	    let closest_id = (0..diagram.faces().len()).min_by(|first, second| distance(*first).partial_cmp(&distance(*second)).unwrap()).unwrap();
+	    let face_id = locator.locate(x, y).unwrap();
+	    assert!(face_id == closest_id || (distance(face_id) - distance(closest_id)).abs() < 1e-9);
+	}
+    }
+
+    #[test]
+    fn test_few_sites() {
+	let bounds = BoundingBox::new(0.0, 10.0, 0.0, 10.0);
+	let mut builder = DiagramBuilder::new(bounds.clone());
+	builder.set_wrap(Wrap::Both);
+	builder.set_validate(true);
+	builder.add_site(3.0, 4.0);
+	let single = builder.build().unwrap();
+	// the only cell borders its own copies
+	assert_eq!(1, single.faces().len());
+	assert!((polygon_signed_area(&single.face_polygon(0)) - 100.0).abs() < 1e-9);
+	assert!(single.neighbours(0).all(|neighbour_id| neighbour_id == 0));
+	builder.add_sites(vec![(0.0, 5.0), (10.0, 5.0)]);
+	assert_eq!(Err(VoronoiError::DuplicateSite { first_id: 0, second_id: 1 }), builder.build());
+	builder.add_sites(vec![(10.0, 5.0), (5.0, 5.0)]);
+	let pair = builder.build().unwrap();
+	assert_eq!(0.0, pair.faces()[0].x());
+	assert!((polygon_signed_area(&pair.face_polygon(0)) - 50.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_seam_polygons() {
+	let diagram = create_periodic_diagram(Wrap::Both, 50, 6);
+	let bounds = diagram.bounds();
+	let mut split_area = 0.0;
+	let mut duplicates = 0;
+	for face_id in 0..diagram.faces().len() {
+	    let area = polygon_signed_area(&diagram.face_polygon(face_id));
+	    let pieces = diagram.seam_polygons(face_id, SeamMode::Split);
+	    for piece in pieces.iter() {
+		assert!(piece.iter().all(|point| point.get_x() >= 0.0 && point.get_x() <= 100.0 && point.get_y() >= 0.0 && point.get_y() <= 80.0));
+		split_area += polygon_signed_area(piece);
+	    }
+	    let copies = diagram.seam_polygons(face_id, SeamMode::Duplicate);
+	    assert_eq!(pieces.len(), copies.len());
+	    for copy in copies.iter() {
+		assert!((polygon_signed_area(copy) - area).abs() < 1e-9);
+	    }
+	    duplicates += copies.len() - 1;
+	}
+	assert!((split_area - bounds.width() * bounds.height()).abs() < 1e-6);
+	assert!(duplicates > 0);
+	let (vertices, indices) = diagram.create_triangles();
+	assert!(vertices.len() / 3 > diagram.vertices().len());
+	assert_eq!(0, indices.len() % 3);
+    }
+
+    #[test]
+    fn test_periodic_round_trip() {
+	let diagram = create_periodic_diagram(Wrap::Horizontal, 40, 7);
+	assert_eq!(diagram, Diagram::from_json(&diagram.to_json()).unwrap());
+	assert_eq!(diagram, Diagram::from_binary(&diagram.to_binary()).unwrap());
+    }
+
+}
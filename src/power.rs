@@ -0,0 +1,562 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * Builds power diagrams, voronoi diagrams where every site has a weight
+ *
+ * The power distance of a point to a site is the squared distance minus the weight of the site, and every point
+ * belongs to the cell of the site with the smallest power distance. Heavier sites push the bisectors away from
+ * themselves, but the bisectors stay straight, so the cells are convex polygons in the same half edge structure
+ * as ordinary diagrams. A cell does not always contain its site, and a site that is dominated by its neighbours
+ * has no cell at all. With equal weights the diagram is the ordinary voronoi diagram.
+ *
+ * Every cell is computed by cutting the bounds with the half planes towards the nearby sites, which are found
+ * with a grid. Neighbouring cells are computed separately, so their corners are snapped together afterwards.
+ */
+
+use crate::geom::{polygon_signed_area, BoundingBox};
+use crate::vector::{Vector2, Vector2F64};
+use crate::voronoi::{Diagram, FaceParts, VoronoiError};
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/**
+ * A weighted site and its user data
+ */
+struct PowerSite<T> {
+    x: f64,
+    y: f64,
+    weight: f64,
+    data: T,
+}
+
+/**
+ * Builds a power diagram from a list of weighted sites
+ * The user can attach data to each site, which ends up in the face of that site.
+ * Faces are numbered in the order in which their sites were added, skipping sites without a cell;
+ * the site id of every face is the index of its site.
+ */
+pub struct PowerDiagramBuilder<T = ()> {
+    bounds: BoundingBox,
+    validate: bool,
+    sites: Vec<PowerSite<T>>,
+}
+
+impl PowerDiagramBuilder {
+
+    /**
+     * Creates a new builder for a diagram clipped to the specified bounds, without user data for the sites
+     */
+    pub fn new(bounds: BoundingBox) -> PowerDiagramBuilder {
+	PowerDiagramBuilder::with_data(bounds)
+    }
+
+}
+
+impl<T> PowerDiagramBuilder<T> {
+
+    /**
+     * Creates a new builder for a diagram clipped to the specified bounds, with user data for each site
+     */
+    pub fn with_data(bounds: BoundingBox) -> PowerDiagramBuilder<T> {
+	PowerDiagramBuilder {
+	    bounds,
+	    validate: false,
+	    sites: vec![],
+	}
+    }
+
+    /**
+     * Sets whether every built diagram is validated, which is off by default
+     */
+    pub fn set_validate(&mut self, validate: bool) {
+	self.validate = validate;
+    }
+
+    /**
+     * Adds a site with the specified weight and user data
+     */
+    pub fn add_site_with_data(&mut self, x: f64, y: f64, weight: f64, data: T) {
+	self.sites.push(PowerSite {
+	    x,
+	    y,
+	    weight,
+	    data,
+	});
+    }
+
+    /**
+     * Adds all sites with their weights and user data
+     */
+    pub fn add_sites_with_data<I>(&mut self, sites: I) where I: IntoIterator<Item = (f64, f64, f64, T)> {
+	for (x, y, weight, data) in sites {
+	    self.add_site_with_data(x, y, weight, data);
+	}
+    }
+
+    /**
+     * Builds the diagram from the sites that were added
+     * The builder is cleared afterwards, so it can be reused
+     */
+    pub fn build(&mut self) -> Result<Diagram<T>, VoronoiError> {
+	let sites: Vec<PowerSite<T>> = self.sites.drain(..).collect();
+	self.check_bounds()?;
+	check_sites(&self.bounds, &sites)?;
+	let cells = PowerCells::new(&self.bounds, &sites).compute();
+	let diagram = create_diagram(self.bounds.clone(), sites, cells);
+	if self.validate {
+	    diagram.validate().map_err(VoronoiError::Invalid)?;
+	}
+	Ok(diagram)
+    }
+
+    fn check_bounds(&self) -> Result<(), VoronoiError> {
+	let width = self.bounds.width();
+	let height = self.bounds.height();
+	if !width.is_finite() || !height.is_finite() {
+	    Err(VoronoiError::DegenerateInput(String::from("bounds should be finite")))
+	} else if width <= 0.0 || height <= 0.0 {
+	    Err(VoronoiError::DegenerateInput(String::from("bounds should have a positive width and height")))
+	} else {
+	    Ok(())
+	}
+    }
+
+}
+
+impl<T> PowerDiagramBuilder<T> where T: Default {
+
+    /**
+     * Adds a site with the specified weight without user data
+     */
+    pub fn add_site(&mut self, x: f64, y: f64, weight: f64) {
+	self.add_site_with_data(x, y, weight, T::default());
+    }
+
+    /**
+     * Adds all sites with their weights without user data
+     */
+    pub fn add_sites<I>(&mut self, sites: I) where I: IntoIterator<Item = (f64, f64, f64)> {
+	for (x, y, weight) in sites {
+	    self.add_site(x, y, weight);
+	}
+    }
+
+}
+
+/**
+ * Checks that all sites lie inside the bounds, that all weights are finite and that no two sites are the same
+ * Sites with the same position but different weights are allowed, the lighter one simply has no cell.
+ */
+fn check_sites<T>(bounds: &BoundingBox, sites: &[PowerSite<T>]) -> Result<(), VoronoiError> {
+    for (id, site) in sites.iter().enumerate() {
+	if !bounds.contains(&Vector2F64::from_values(site.x, site.y)) {
+	    return Err(VoronoiError::SiteOutOfBounds {
+		id,
+		x: site.x,
+		y: site.y,
+	    });
+	}
+	if !site.weight.is_finite() {
+	    return Err(VoronoiError::DegenerateInput(format!("site {} should have a finite weight", id)));
+	}
+    }
+    let compare = |first: usize, second: usize| {
+	let first = &sites[first];
+	let second = &sites[second];
+	(first.x, first.y, first.weight).partial_cmp(&(second.x, second.y, second.weight)).unwrap_or(Ordering::Equal)
+    };
+    let mut site_ids: Vec<usize> = (0..sites.len()).collect();
+    site_ids.sort_by(|first, second| compare(*first, *second));
+    for pair in site_ids.windows(2) {
+	if compare(pair[0], pair[1]) == Ordering::Equal {
+	    return Err(VoronoiError::DuplicateSite {
+		first_id: pair[0].min(pair[1]),
+		second_id: pair[0].max(pair[1]),
+	    });
+	}
+    }
+    Ok(())
+}
+
+/**
+ * Computes the cell of every site as a polygon, using a grid to find the sites that can cut a cell
+ */
+struct PowerCells<'a, T> {
+    bounds: &'a BoundingBox,
+    sites: &'a [PowerSite<T>],
+    max_weight: f64,
+    columns: usize,
+    rows: usize,
+    cell_width: f64,
+    cell_height: f64,
+
+    /**
+     * The ids of the sites in each grid cell, row by row
+     */
+    site_ids: Vec<Vec<usize>>,
+}
+
+impl<'a, T> PowerCells<'a, T> {
+
+    fn new(bounds: &'a BoundingBox, sites: &'a [PowerSite<T>]) -> PowerCells<'a, T> {
+	let count = sites.len().max(1) as f64;
+	let aspect = bounds.width() / bounds.height();
+	let columns = ((count * aspect).sqrt().ceil() as usize).max(1);
+	let rows = ((count / aspect).sqrt().ceil() as usize).max(1);
+	let mut cells = PowerCells {
+	    bounds,
+	    sites,
+	    max_weight: sites.iter().map(|site| site.weight).fold(f64::NEG_INFINITY, f64::max),
+	    columns,
+	    rows,
+	    cell_width: bounds.width() / columns as f64,
+	    cell_height: bounds.height() / rows as f64,
+	    site_ids: vec![Vec::new(); columns * rows],
+	};
+	for (site_id, site) in sites.iter().enumerate() {
+	    let (column, row) = cells.grid_cell(site.x, site.y);
+	    cells.site_ids[row * columns + column].push(site_id);
+	}
+	cells
+    }
+
+    fn grid_cell(&self, x: f64, y: f64) -> (usize, usize) {
+	let column = (((x - self.bounds.left()) / self.cell_width) as usize).min(self.columns - 1);
+	let row = (((y - self.bounds.top()) / self.cell_height) as usize).min(self.rows - 1);
+	(column, row)
+    }
+
+    /**
+     * Computes the cells of all sites, which are empty for sites without a cell
+     */
+    fn compute(&self) -> Vec<Vec<(f64, f64)>> {
+	(0..self.sites.len()).map(|site_id| self.compute_cell(site_id)).collect()
+    }
+
+    /**
+     * Cuts the bounds with the half planes of the sites in growing rings of grid cells around the site,
+     * until the sites outside the rings are too far away to reach any corner of the cell
+     */
+    fn compute_cell(&self, site_id: usize) -> Vec<(f64, f64)> {
+	let site = &self.sites[site_id];
+	let mut polygon: Vec<(f64, f64)> = self.bounds.corners().iter()
+	    .map(|corner| (corner.get_x(), corner.get_y()))
+	    .collect();
+	let (column, row) = self.grid_cell(site.x, site.y);
+	let mut ring = 0;
+	loop {
+	    for other_id in self.ring_site_ids(column, row, ring) {
+		if other_id != site_id {
+		    let other = &self.sites[other_id];
+		    polygon = cut_cell(polygon, (site.x, site.y, site.weight), (other.x, other.y, other.weight), tolerance(self.bounds));
+		    if polygon.is_empty() {
+			return polygon;
+		    }
+		}
+	    }
+	    // the sites outside the rings are at least this far from the site
+	    let mut distance = f64::INFINITY;
+	    if column > ring {
+		distance = distance.min(site.x - (self.bounds.left() + (column - ring) as f64 * self.cell_width));
+	    }
+	    if column + ring + 1 < self.columns {
+		distance = distance.min(self.bounds.left() + (column + ring + 1) as f64 * self.cell_width - site.x);
+	    }
+	    if row > ring {
+		distance = distance.min(site.y - (self.bounds.top() + (row - ring) as f64 * self.cell_height));
+	    }
+	    if row + ring + 1 < self.rows {
+		distance = distance.min(self.bounds.top() + (row + ring + 1) as f64 * self.cell_height - site.y);
+	    }
+	    if distance.is_infinite() {
+		return polygon;
+	    }
+	    let radius = polygon.iter()
+		.map(|(x, y)| (x - site.x).hypot(y - site.y))
+		.fold(0.0, f64::max);
+	    if distance > radius && (distance - radius).powi(2) - self.max_weight >= radius * radius - site.weight {
+		return polygon;
+	    }
+	    ring += 1;
+	}
+    }
+
+    /**
+     * The ids of the sites in the grid cells at the specified chebyshev distance from a grid cell
+     */
+    fn ring_site_ids(&self, column: usize, row: usize, ring: usize) -> impl Iterator<Item = usize> + '_ {
+	let min_column = column.saturating_sub(ring);
+	let max_column = (column + ring).min(self.columns - 1);
+	let min_row = row.saturating_sub(ring);
+	let max_row = (row + ring).min(self.rows - 1);
+	(min_row..=max_row)
+	    .flat_map(move |ring_row| (min_column..=max_column).map(move |ring_column| (ring_column, ring_row)))
+	    .filter(move |(ring_column, ring_row)| ring_column.abs_diff(column).max(ring_row.abs_diff(row)) == ring)
+	    .flat_map(move |(ring_column, ring_row)| self.site_ids[ring_row * self.columns + ring_column].iter().copied())
+    }
+
+}
+
+/**
+ * The distance below which corners of neighbouring cells are the same vertex
+ */
+pub(crate) fn tolerance(bounds: &BoundingBox) -> f64 {
+    1e-9 * (bounds.width() + bounds.height())
+}
+
+/**
+ * Keeps the part of a convex polygon where the power distance to the site is at most the power distance
+ * to the other site (Sutherland-Hodgman), where both sites are given as x, y and weight
+ * Points within the tolerance of the bisector are kept, so sites meeting at a corner do not create tiny edges.
+ */
+pub(crate) fn cut_cell(polygon: Vec<(f64, f64)>, site: (f64, f64, f64), other: (f64, f64, f64), tolerance: f64) -> Vec<(f64, f64)> {
+    // the polygon is kept where normal * point <= offset
+    let normal = (2.0 * (other.0 - site.0), 2.0 * (other.1 - site.1));
+    let offset = other.0 * other.0 + other.1 * other.1 - site.0 * site.0 - site.1 * site.1 - other.2 + site.2;
+    let tolerance = tolerance * normal.0.hypot(normal.1);
+    let side = |(x, y): (f64, f64)| normal.0 * x + normal.1 * y - offset;
+    if polygon.iter().all(|point| side(*point) <= tolerance) {
+	return polygon;
+    }
+    let mut result = Vec::with_capacity(polygon.len() + 1);
+    for (i, start) in polygon.iter().enumerate() {
+	let end = polygon[(i + 1) % polygon.len()];
+	let start_side = side(*start);
+	let end_side = side(end);
+	let start_inside = start_side <= tolerance;
+	let end_inside = end_side <= tolerance;
+	if start_inside != end_inside {
+	    let t = start_side / (start_side - end_side);
+	    result.push((start.0 + t * (end.0 - start.0), start.1 + t * (end.1 - start.1)));
+	}
+	if end_inside {
+	    result.push(end);
+	}
+    }
+    if result.len() < 3 {
+	result.clear();
+    }
+    result
+}
+
+/**
+ * Snaps the corners of the cells to shared vertices and pairs the half edges of neighbouring cells as twins
+ * Cells that collapse to a sliver are left out.
+ */
+fn create_diagram<T>(bounds: BoundingBox, sites: Vec<PowerSite<T>>, cells: Vec<Vec<(f64, f64)>>) -> Diagram<T> {
+    let tolerance = tolerance(&bounds);
+    let mut vertices: Vec<(f64, f64)> = Vec::new();
+    let mut vertex_grid: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    let mut snap = |(x, y): (f64, f64)| {
+	let key = ((x / tolerance).floor() as i64, (y / tolerance).floor() as i64);
+	for neighbour_key in (key.0 - 1..=key.0 + 1).flat_map(|column| (key.1 - 1..=key.1 + 1).map(move |row| (column, row))) {
+	    if let Some(vertex_ids) = vertex_grid.get(&neighbour_key) {
+		if let Some(vertex_id) = vertex_ids.iter().find(|vertex_id| {
+		    let (vertex_x, vertex_y) = vertices[**vertex_id];
+		    (vertex_x - x).hypot(vertex_y - y) <= tolerance
+		}) {
+		    return *vertex_id;
+		}
+	    }
+	}
+	vertices.push((x, y));
+	vertex_grid.entry(key).or_default().push(vertices.len() - 1);
+	vertices.len() - 1
+    };
+
+    let mut half_edges = Vec::new();
+    let mut faces = Vec::new();
+    for (site_id, (site, cell)) in sites.into_iter().zip(cells).enumerate() {
+	let points: Vec<Vector2F64> = cell.iter().map(|(x, y)| Vector2F64::from_values(*x, *y)).collect();
+	let mut vertex_ids: Vec<usize> = cell.into_iter().map(&mut snap).collect();
+	vertex_ids.dedup();
+	while vertex_ids.len() > 1 && vertex_ids.first() == vertex_ids.last() {
+	    vertex_ids.pop();
+	}
+	let perimeter: f64 = (0..points.len())
+	    .map(|i| {
+		let next = &points[(i + 1) % points.len()];
+		(next.get_x() - points[i].get_x()).hypot(next.get_y() - points[i].get_y())
+	    })
+	    .sum();
+	if vertex_ids.len() < 3 || polygon_signed_area(&points) <= tolerance * perimeter {
+	    continue;
+	}
+	let face_id = faces.len();
+	let start_id = half_edges.len();
+	let count = vertex_ids.len();
+	for (i, vertex_id) in vertex_ids.into_iter().enumerate() {
+	    half_edges.push((face_id, vertex_id, None, start_id + (i + count - 1) % count, start_id + (i + 1) % count, false));
+	}
+	faces.push(FaceParts {
+	    x: site.x,
+	    y: site.y,
+	    start_id,
+	    site_id,
+	    hole_start_ids: Vec::new(),
+	    weight: site.weight,
+	    data: site.data,
+	});
+    }
+
+    let mut edge_ids = HashMap::new();
+    for (half_edge_id, half_edge) in half_edges.iter().enumerate() {
+	let end_id = half_edges[half_edge.4].1;
+	edge_ids.insert((half_edge.1, end_id), half_edge_id);
+    }
+    for half_edge_id in 0..half_edges.len() {
+	let start_id = half_edges[half_edge_id].1;
+	let end_id = half_edges[half_edges[half_edge_id].4].1;
+	half_edges[half_edge_id].2 = edge_ids.get(&(end_id, start_id)).copied();
+    }
+    Diagram::from_face_parts(bounds, vertices, half_edges, faces)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::random::Random;
+    use crate::voronoi::DiagramBuilder;
+
+    fn face_area<T>(diagram: &Diagram<T>, face_id: usize) -> f64 {
+	polygon_signed_area(&diagram.face_polygon(face_id))
+    }
+
+    fn create_random_sites(count: usize, seed: u64) -> Vec<(f64, f64, f64)> {
+	let mut random = Random::new(seed);
+	(0..count)
+	    .map(|_| (random.range(0.0, 100.0), random.range(0.0, 80.0), random.range(-50.0, 50.0)))
+	    .collect()
+    }
+
+    /**
+     * Finds the site with the smallest power distance by comparing all sites
+     */
+    fn find_closest_site(sites: &[(f64, f64, f64)], x: f64, y: f64) -> (usize, f64) {
+	sites.iter().enumerate()
+	    .map(|(site_id, (site_x, site_y, weight))| (site_id, (site_x - x).powi(2) + (site_y - y).powi(2) - weight))
+	    .fold((0, f64::INFINITY), |closest, candidate| if candidate.1 < closest.1 { candidate } else { closest })
+    }
+
+    #[test]
+    fn test_equal_weights() {
+	let bounds = BoundingBox::new(0.0, 100.0, 0.0, 80.0);
+	let sites: Vec<(f64, f64, f64)> = create_random_sites(200, 1).into_iter().map(|(x, y, _)| (x, y, 3.0)).collect();
+	let mut builder = PowerDiagramBuilder::new(bounds.clone());
+	builder.add_sites(sites.iter().copied());
+	let diagram = builder.build().unwrap();
+	let mut voronoi_builder = DiagramBuilder::new(bounds);
+	voronoi_builder.add_sites(sites.iter().map(|(x, y, _)| (*x, *y)));
+	let voronoi = voronoi_builder.build().unwrap();
+	assert_eq!(voronoi.faces().len(), diagram.faces().len());
+	assert!(diagram.is_weighted());
+	assert!(diagram.validate().is_ok());
+	for face in diagram.faces().iter() {
+	    assert_eq!(face.id(), face.site_id());
+	    assert_eq!(3.0, face.weight());
+	    assert!((face_area(&voronoi, face.id()) - face_area(&diagram, face.id())).abs() < 1e-6);
+	    let mut neighbours: Vec<usize> = diagram.neighbours(face.id()).collect();
+	    let mut voronoi_neighbours: Vec<usize> = voronoi.neighbours(face.id()).collect();
+	    neighbours.sort_unstable();
+	    voronoi_neighbours.sort_unstable();
+	    assert_eq!(voronoi_neighbours, neighbours);
+	}
+    }
+
+    #[test]
+    fn test_weighted_cells() {
+	let bounds = BoundingBox::new(0.0, 100.0, 0.0, 80.0);
+	let sites = create_random_sites(300, 2);
+	let mut builder = PowerDiagramBuilder::with_data(bounds);
+	builder.add_sites_with_data(sites.iter().enumerate().map(|(id, (x, y, weight))| (*x, *y, *weight, id)));
+	builder.set_validate(true);
+	let diagram = builder.build().unwrap();
+	let total_area: f64 = (0..diagram.faces().len()).map(|face_id| face_area(&diagram, face_id)).sum();
+	assert!((total_area - 8000.0).abs() < 1e-6);
+	for face in diagram.faces().iter() {
+	    assert_eq!(face.site_id(), *face.data());
+	    assert_eq!(sites[face.site_id()].2, face.weight());
+	}
+	let locator = diagram.locator();
+	let mut random = Random::new(3);
+	for _ in 0..2000 {
+	    let (x, y) = (random.range(0.0, 100.0), random.range(0.0, 80.0));
+	    let face_id = locator.locate(x, y).unwrap();
+	    let (site_id, power) = find_closest_site(&sites, x, y);
+	    let face = &diagram.faces()[face_id];
+	    let face_power = (face.x() - x).powi(2) + (face.y() - y).powi(2) - face.weight();
+	    assert!(face.site_id() == site_id || (face_power - power).abs() < 1e-9);
+	}
+    }
+
+    #[test]
+    fn test_dominated_site() {
+	let mut builder = PowerDiagramBuilder::new(BoundingBox::new(0.0, 10.0, 0.0, 10.0));
+	builder.add_sites(vec![(2.0, 5.0, 0.0), (5.0, 5.0, -20.0), (8.0, 5.0, 0.0), (5.0, 5.0, 1.0)]);
+	builder.set_validate(true);
+	let diagram = builder.build().unwrap();
+	// the site in the middle is dominated by its neighbours and by the heavier site at the same position
+	let site_ids: Vec<usize> = diagram.faces().iter().map(|face| face.site_id()).collect();
+	assert_eq!(vec![0, 2, 3], site_ids);
+	assert_eq!(Some(2), diagram.locate(5.0, 1.0));
+	let diagram: Diagram = Diagram::from_binary(&diagram.to_binary()).unwrap();
+	assert_eq!(vec![0, 2, 3], diagram.faces().iter().map(|face| face.site_id()).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_site_outside_cell() {
+	let mut builder = PowerDiagramBuilder::new(BoundingBox::new(0.0, 10.0, 0.0, 10.0));
+	builder.add_sites(vec![(4.0, 5.0, 0.0), (6.0, 5.0, 10.0)]);
+	builder.set_validate(true);
+	let diagram = builder.build().unwrap();
+	// the bisector lies where 2 * 2 * x = 36 - 16 - 10, at x = 2.5
+	assert_eq!(2, diagram.faces().len());
+	assert!((face_area(&diagram, 0) - 25.0).abs() < 1e-9);
+	assert_eq!(Some(1), diagram.locate(4.0, 5.0));
+    }
+
+    #[test]
+    fn test_power_errors() {
+	let bounds = BoundingBox::new(0.0, 10.0, 0.0, 10.0);
+	let mut builder = PowerDiagramBuilder::new(bounds.clone());
+	builder.add_sites(vec![(1.0, 1.0, 2.0), (3.0, 1.0, 0.0), (1.0, 1.0, 2.0)]);
+	assert_eq!(Err(VoronoiError::DuplicateSite { first_id: 0, second_id: 2 }), builder.build());
+	builder.add_site(11.0, 1.0, 0.0);
+	assert_eq!(Err(VoronoiError::SiteOutOfBounds { id: 0, x: 11.0, y: 1.0 }), builder.build());
+	builder.add_site(1.0, 1.0, f64::NAN);
+	assert!(matches!(builder.build(), Err(VoronoiError::DegenerateInput(_))));
+	let mut builder = PowerDiagramBuilder::new(BoundingBox::new(0.0, 0.0, 0.0, 10.0));
+	assert!(matches!(builder.build(), Err(VoronoiError::DegenerateInput(_))));
+	let mut builder = PowerDiagramBuilder::new(bounds);
+	assert!(builder.build().unwrap().faces().is_empty());
+    }
+
+    #[test]
+    fn test_weighted_round_trip() {
+	let mut builder = PowerDiagramBuilder::new(BoundingBox::new(0.0, 100.0, 0.0, 80.0));
+	builder.add_sites(create_random_sites(50, 4));
+	let diagram = builder.build().unwrap();
+	let text = diagram.to_json();
+	assert!(text.contains("\"weights\": ["));
+	assert_eq!(diagram, Diagram::from_json(&text).unwrap());
+	assert_eq!(diagram, Diagram::from_binary(&diagram.to_binary()).unwrap());
+    }
+
+}
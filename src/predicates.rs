@@ -0,0 +1,381 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * Adaptive precision geometric predicates after Shewchuk
+ * The determinants are first evaluated in plain floating point together with a bound of their rounding error.
+ * Only if the result is smaller than the bound, i.e. its sign is uncertain, the determinant is evaluated
+ * exactly using expansions: sums of non overlapping floating point numbers ordered by increasing magnitude.
+ */
+
+use crate::vector::{Vector2, Vector2F64};
+
+/**
+ * Half the distance between 1 and the next larger f64, the relative error of a single rounding
+ */
+const EPSILON: f64 = 1.1102230246251565e-16;
+
+/**
+ * 2^27 + 1, splits a f64 into two halves of 26 significant bits
+ */
+const SPLITTER: f64 = 134_217_729.0;
+
+/**
+ * Relative error bound of the floating point evaluation of the orientation determinant
+ */
+const ORIENT_BOUND: f64 = (3.0 + 16.0 * EPSILON) * EPSILON;
+
+/**
+ * Relative error bound of the floating point evaluation of the in circle determinant
+ */
+const INCIRCLE_BOUND: f64 = (10.0 + 96.0 * EPSILON) * EPSILON;
+
+/**
+ * Returns a positive value if a, b and c make a counter clockwise turn in a coordinate system where y points up,
+ * a negative value if they make a clockwise turn and zero if they are colinear
+ * The value approximates twice the signed area of the triangle; its sign is always exact.
+ */
+pub fn orient2d(a: &Vector2F64, b: &Vector2F64, c: &Vector2F64) -> f64 {
+    let left = (a.get_x() - c.get_x()) * (b.get_y() - c.get_y());
+    let right = (a.get_y() - c.get_y()) * (b.get_x() - c.get_x());
+    let det = left - right;
+    if det.abs() >= ORIENT_BOUND * (left.abs() + right.abs()) {
+	det
+    } else {
+	orient2d_exact(a, b, c)
+    }
+}
+
+/**
+ * Returns a positive value if d lies inside the circle through a, b and c, a negative value if it lies outside
+ * and zero if all four points lie on the same circle
+ * The points a, b and c have to make a counter clockwise turn in a coordinate system where y points up,
+ * otherwise the sign is reversed. The sign of the result is always exact.
+ */
+pub fn incircle(a: &Vector2F64, b: &Vector2F64, c: &Vector2F64, d: &Vector2F64) -> f64 {
+    let adx = a.get_x() - d.get_x();
+    let ady = a.get_y() - d.get_y();
+    let bdx = b.get_x() - d.get_x();
+    let bdy = b.get_y() - d.get_y();
+    let cdx = c.get_x() - d.get_x();
+    let cdy = c.get_y() - d.get_y();
+
+    let bdxcdy = bdx * cdy;
+    let cdxbdy = cdx * bdy;
+    let alift = adx * adx + ady * ady;
+    let cdxady = cdx * ady;
+    let adxcdy = adx * cdy;
+    let blift = bdx * bdx + bdy * bdy;
+    let adxbdy = adx * bdy;
+    let bdxady = bdx * ady;
+    let clift = cdx * cdx + cdy * cdy;
+
+    let det = alift * (bdxcdy - cdxbdy) + blift * (cdxady - adxcdy) + clift * (adxbdy - bdxady);
+    let permanent = (bdxcdy.abs() + cdxbdy.abs()) * alift
+	+ (cdxady.abs() + adxcdy.abs()) * blift
+	+ (adxbdy.abs() + bdxady.abs()) * clift;
+    if det.abs() > INCIRCLE_BOUND * permanent {
+	det
+    } else {
+	incircle_exact(a, b, c, d)
+    }
+}
+
+fn orient2d_exact(a: &Vector2F64, b: &Vector2F64, c: &Vector2F64) -> f64 {
+    let acx = difference(a.get_x(), c.get_x());
+    let acy = difference(a.get_y(), c.get_y());
+    let bcx = difference(b.get_x(), c.get_x());
+    let bcy = difference(b.get_y(), c.get_y());
+    estimate(&expansion_sum(&product(&acx, &bcy), &negate(&product(&acy, &bcx))))
+}
+
+fn incircle_exact(a: &Vector2F64, b: &Vector2F64, c: &Vector2F64, d: &Vector2F64) -> f64 {
+    let adx = difference(a.get_x(), d.get_x());
+    let ady = difference(a.get_y(), d.get_y());
+    let bdx = difference(b.get_x(), d.get_x());
+    let bdy = difference(b.get_y(), d.get_y());
+    let cdx = difference(c.get_x(), d.get_x());
+    let cdy = difference(c.get_y(), d.get_y());
+
+    let lift = |x: &[f64], y: &[f64]| expansion_sum(&product(x, x), &product(y, y));
+    let cross = |x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64]| {
+	expansion_sum(&product(x1, y2), &negate(&product(y1, x2)))
+    };
+    let a_term = product(&lift(&adx, &ady), &cross(&bdx, &bdy, &cdx, &cdy));
+    let b_term = product(&lift(&bdx, &bdy), &cross(&cdx, &cdy, &adx, &ady));
+    let c_term = product(&lift(&cdx, &cdy), &cross(&adx, &ady, &bdx, &bdy));
+    estimate(&expansion_sum(&expansion_sum(&a_term, &b_term), &c_term))
+}
+
+/**
+ * Calculates a + b and the rounding error of the sum
+ */
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let x = a + b;
+    let b_virtual = x - a;
+    let a_virtual = x - b_virtual;
+    (x, (a - a_virtual) + (b - b_virtual))
+}
+
+/**
+ * Calculates a - b and the rounding error of the difference
+ */
+fn two_diff(a: f64, b: f64) -> (f64, f64) {
+    let x = a - b;
+    let b_virtual = a - x;
+    let a_virtual = x + b_virtual;
+    (x, (a - a_virtual) + (b_virtual - b))
+}
+
+/**
+ * Splits a value into a high and a low half, so products of halves are exact
+ */
+fn split(a: f64) -> (f64, f64) {
+    let c = SPLITTER * a;
+    let high = c - (c - a);
+    (high, a - high)
+}
+
+/**
+ * Calculates a * b and the rounding error of the product
+ */
+fn two_product(a: f64, b: f64) -> (f64, f64) {
+    let x = a * b;
+    let (a_high, a_low) = split(a);
+    let (b_high, b_low) = split(b);
+    let error = ((x - a_high * b_high) - a_low * b_high) - a_high * b_low;
+    (x, a_low * b_low - error)
+}
+
+/**
+ * The exact difference of two values as an expansion
+ */
+fn difference(a: f64, b: f64) -> Vec<f64> {
+    let (x, y) = two_diff(a, b);
+    [y, x].iter().cloned().filter(|value| *value != 0.0).collect()
+}
+
+/**
+ * Adds a value to an expansion
+ */
+fn grow_expansion(e: &[f64], b: f64) -> Vec<f64> {
+    let mut result = Vec::with_capacity(e.len() + 1);
+    let mut q = b;
+    for value in e.iter() {
+	let (sum, error) = two_sum(q, *value);
+	if error != 0.0 {
+	    result.push(error);
+	}
+	q = sum;
+    }
+    if q != 0.0 {
+	result.push(q);
+    }
+    result
+}
+
+/**
+ * Adds two expansions
+ */
+fn expansion_sum(e: &[f64], f: &[f64]) -> Vec<f64> {
+    f.iter().fold(e.to_vec(), |sum, value| grow_expansion(&sum, *value))
+}
+
+/**
+ * Multiplies an expansion by a value
+ */
+fn scale_expansion(e: &[f64], b: f64) -> Vec<f64> {
+    let mut result = Vec::with_capacity(2 * e.len());
+    let mut q = 0.0;
+    for (i, value) in e.iter().enumerate() {
+	let (high, low) = two_product(*value, b);
+	if i == 0 {
+	    if low != 0.0 {
+		result.push(low);
+	    }
+	    q = high;
+	} else {
+	    let (sum, error) = two_sum(q, low);
+	    if error != 0.0 {
+		result.push(error);
+	    }
+	    let (sum, error) = two_sum(high, sum);
+	    if error != 0.0 {
+		result.push(error);
+	    }
+	    q = sum;
+	}
+    }
+    if q != 0.0 {
+	result.push(q);
+    }
+    result
+}
+
+/**
+ * Multiplies two expansions
+ */
+fn product(e: &[f64], f: &[f64]) -> Vec<f64> {
+    f.iter().fold(Vec::new(), |sum, value| expansion_sum(&sum, &scale_expansion(e, *value)))
+}
+
+fn negate(e: &[f64]) -> Vec<f64> {
+    e.iter().map(|value| - value).collect()
+}
+
+/**
+ * Approximates the value of an expansion, the sign is exact because the largest component comes last
+ */
+fn estimate(e: &[f64]) -> f64 {
+    e.iter().sum()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    /**
+     * Converts a value to an integer in units of 2^-scale, the value must be a multiple of that unit
+     */
+    fn to_integer(value: f64, scale: i32) -> i128 {
+	let scaled = value * 2f64.powi(scale);
+	assert_eq!(scaled, scaled.trunc());
+	scaled as i128
+    }
+
+    fn exact_orient2d(points: [(f64, f64); 3], scale: i32) -> i128 {
+	let p: Vec<(i128, i128)> = points.iter().map(|(x, y)| (to_integer(*x, scale), to_integer(*y, scale))).collect();
+	(p[0].0 - p[2].0) * (p[1].1 - p[2].1) - (p[0].1 - p[2].1) * (p[1].0 - p[2].0)
+    }
+
+    fn exact_incircle(points: [(i64, i64); 4]) -> i128 {
+	let d = points[3];
+	let p: Vec<(i128, i128)> = points[0..3].iter().map(|(x, y)| ((x - d.0) as i128, (y - d.1) as i128)).collect();
+	let lift = |i: usize| p[i].0 * p[i].0 + p[i].1 * p[i].1;
+	let cross = |i: usize, j: usize| p[i].0 * p[j].1 - p[i].1 * p[j].0;
+	lift(0) * cross(1, 2) + lift(1) * cross(2, 0) + lift(2) * cross(0, 1)
+    }
+
+    fn sign(value: f64) -> i32 {
+	if value > 0.0 {
+	    1
+	} else if value < 0.0 {
+	    -1
+	} else {
+	    0
+	}
+    }
+
+    fn vector(point: (f64, f64)) -> Vector2F64 {
+	Vector2F64::from_values(point.0, point.1)
+    }
+
+    #[test]
+    fn test_orient2d() {
+	let a = Vector2F64::from_values(0.0, 0.0);
+	let b = Vector2F64::from_values(1.0, 0.0);
+	let c = Vector2F64::from_values(0.0, 1.0);
+	assert_eq!(1.0, orient2d(&a, &b, &c));
+	assert_eq!(-1.0, orient2d(&a, &c, &b));
+	assert_eq!(0.0, orient2d(&a, &b, &Vector2F64::from_values(3.0, 0.0)));
+    }
+
+    #[test]
+    fn test_orient2d_near_colinear() {
+	// points on a tiny grid around a point on the line through b and c
+	let unit = 2f64.powi(-53);
+	let b = (12.0, 12.0);
+	let c = (24.0, 24.0);
+	let mut naive_wrong = 0;
+	for i in 0..64 {
+	    for j in 0..64 {
+		let a = (0.5 + i as f64 * unit, 0.5 + j as f64 * unit);
+		let expected = exact_orient2d([a, b, c], 53).signum() as i32;
+		assert_eq!(expected, sign(orient2d(&vector(a), &vector(b), &vector(c))), "{:?}", a);
+		let naive = (a.0 - c.0) * (b.1 - c.1) - (a.1 - c.1) * (b.0 - c.0);
+		if sign(naive) != expected {
+		    naive_wrong += 1;
+		}
+	    }
+	}
+	// the input is adversarial: plain floating point gets many of the signs wrong
+	assert!(naive_wrong > 0);
+    }
+
+    #[test]
+    fn test_orient2d_consistent_along_line() {
+	// every point of the line through two points is colinear with them, no matter how close they are
+	let a = Vector2F64::from_values(0.1, 0.1);
+	let b = Vector2F64::from_values(0.3, 0.3);
+	for i in 0..100 {
+	    let t = 0.5 + i as f64 * 1e-17;
+	    let c = Vector2F64::from_values(t, t);
+	    let first = sign(orient2d(&a, &b, &c));
+	    assert_eq!(first, sign(orient2d(&b, &c, &a)));
+	    assert_eq!(first, sign(orient2d(&c, &a, &b)));
+	    assert_eq!(- first, sign(orient2d(&b, &a, &c)));
+	}
+    }
+
+    #[test]
+    fn test_incircle() {
+	let a = Vector2F64::from_values(1.0, 0.0);
+	let b = Vector2F64::from_values(0.0, 1.0);
+	let c = Vector2F64::from_values(-1.0, 0.0);
+	assert!(incircle(&a, &b, &c, &Vector2F64::from_values(0.0, 0.0)) > 0.0);
+	assert!(incircle(&a, &b, &c, &Vector2F64::from_values(2.0, 0.0)) < 0.0);
+	assert_eq!(0.0, incircle(&a, &b, &c, &Vector2F64::from_values(0.0, -1.0)));
+	assert!(incircle(&a, &c, &b, &Vector2F64::from_values(0.0, 0.0)) < 0.0);
+    }
+
+    #[test]
+    fn test_incircle_near_cocircular() {
+	// large integer points close to a circle, far from the origin, so the lifted coordinates lose precision
+	let offset = 1i64 << 26;
+	let radius = (1i64 << 25) as f64;
+	let points: Vec<(i64, i64)> = (0..24).map(|i| {
+	    let angle = i as f64 * std::f64::consts::PI / 12.0;
+	    (offset + (radius * angle.cos()).round() as i64, offset + (radius * angle.sin()).round() as i64)
+	}).collect();
+	for i in 0..points.len() {
+	    let (a, b, c) = (points[i], points[(i + 5) % 24], points[(i + 11) % 24]);
+	    for d in points.iter() {
+		for (dx, dy) in [(0, 0), (1, 0), (0, -1), (-1, 1)].iter() {
+		    let d = (d.0 + dx, d.1 + dy);
+		    let expected = exact_incircle([a, b, c, d]).signum() as i32;
+		    let to_vector = |point: (i64, i64)| vector((point.0 as f64, point.1 as f64));
+		    let result = incircle(&to_vector(a), &to_vector(b), &to_vector(c), &to_vector(d));
+		    assert_eq!(expected, sign(result), "{:?} {:?} {:?} {:?}", a, b, c, d);
+		}
+	    }
+	}
+    }
+
+    #[test]
+    fn test_expansion_arithmetic() {
+	let e = difference(1.0, 1e-30);
+	assert_eq!(2, e.len());
+	// (1 - 1e-30)^2 = 1 - 2e-30 + 1e-60, the tiny terms survive in the lower components
+	let square = product(&e, &e);
+	assert_eq!(1.0, estimate(&square));
+	let rest = expansion_sum(&square, &[-1.0]);
+	assert!(estimate(&rest) < 0.0);
+	assert!(estimate(&expansion_sum(&rest, &[2e-30])) > 0.0);
+    }
+
+}
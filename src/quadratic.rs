@@ -19,12 +19,14 @@
  * Utility functions and structs for quadratic aritmetic
  */
 
+use crate::float::Float;
+
 /**
  * The possible solutions of a quadratic equation
  */
 
 #[derive(Debug, PartialEq)]
-pub enum Solution {
+pub enum Solution<F = f64> {
 
     /**
      * There are no roots
@@ -34,23 +36,25 @@ pub enum Solution {
     /**
      * There is one (double) root
      */
-    One(f64),
+    One(F),
 
     /**
      * There are two distinct roots
      */
     
-    Two(f64, f64),
+    Two(F, F),
 }
 
 /**
  * Solves the quadratic equation
  */
-pub fn solve(quadratic: f64, linear: f64, constant: f64) -> Solution {
-    let discr = (linear * linear) - 4.0 * quadratic * constant;
-    if discr > 0.0 {
+pub fn solve<F>(quadratic: F, linear: F, constant: F) -> Solution<F> where F: Float {
+    let zero = F::from_f64(0.0);
+    let two = F::from_f64(2.0);
+    let discr = (linear * linear) - F::from_f64(4.0) * quadratic * constant;
+    if discr > zero {
 	let discr_sqrt = discr.sqrt();
-	let div = 2.0 * quadratic;
+	let div = two * quadratic;
 	let x1 =  (- linear - discr_sqrt) / div;
 	let x2 =  (- linear + discr_sqrt) / div;
 	if x1 < x2 {
@@ -58,10 +62,10 @@ pub fn solve(quadratic: f64, linear: f64, constant: f64) -> Solution {
 	} else {
 	    Solution::Two(x2, x1)
 	}
-    } else if discr < 0.0 {
+    } else if discr < zero {
 	Solution::None
     } else {
-	Solution::One(- linear / (2.0 * quadratic))
+	Solution::One(- linear / (two * quadratic))
     }
 }
 
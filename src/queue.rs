@@ -0,0 +1,273 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * A priority queue whose entries can be removed before they are popped
+ */
+
+use std::cmp::Ordering;
+
+struct Entry<P, V> {
+    handle: usize,
+    sequence: usize,
+    priority: P,
+    value: V,
+}
+
+/**
+ * A binary min heap that keeps track of the position of every entry, so entries can be removed by their handle
+ * Entries with the same priority are popped in the order in which they were pushed.
+ * The handles of popped and removed entries are reused, so the bookkeeping only grows with the number of entries in the queue.
+ */
+pub struct IndexedQueue<P, V> {
+    heap: Vec<Entry<P, V>>,
+
+    /**
+     * The position in the heap of the entry of each handle, or none if the handle is free
+     */
+    positions: Vec<Option<usize>>,
+
+    free_handles: Vec<usize>,
+    next_sequence: usize,
+}
+
+impl<P, V> IndexedQueue<P, V> where P: PartialOrd {
+
+    pub fn new() -> IndexedQueue<P, V> {
+	IndexedQueue {
+	    heap: vec![],
+	    positions: vec![],
+	    free_handles: vec![],
+	    next_sequence: 0,
+	}
+    }
+
+    pub fn len(&self) -> usize {
+	self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+	self.heap.is_empty()
+    }
+
+    /**
+     * Whether the entry of a handle is still in the queue
+     */
+    pub fn contains(&self, handle: usize) -> bool {
+	matches!(self.positions.get(handle), Some(Some(_)))
+    }
+
+    /**
+     * Adds an entry to the queue, returns the handle with which it can be removed
+     */
+    pub fn push(&mut self, priority: P, value: V) -> usize {
+	let handle = match self.free_handles.pop() {
+	    Some(handle) => handle,
+	    None => {
+		self.positions.push(None);
+		self.positions.len() - 1
+	    },
+	};
+	let sequence = self.next_sequence;
+	self.next_sequence += 1;
+	let position = self.heap.len();
+	self.heap.push(Entry {
+	    handle,
+	    sequence,
+	    priority,
+	    value,
+	});
+	self.positions[handle] = Some(position);
+	self.sift_up(position);
+	handle
+    }
+
+    /**
+     * Removes the entry with the lowest priority, returns its handle, priority and value
+     */
+    pub fn pop(&mut self) -> Option<(usize, P, V)> {
+	if self.heap.is_empty() {
+	    None
+	} else {
+	    Some(self.remove_at(0))
+	}
+    }
+
+    /**
+     * Removes the entry of a handle, returns its value or none if it has already left the queue
+     */
+    pub fn remove(&mut self, handle: usize) -> Option<V> {
+	let position = (*self.positions.get(handle)?)?;
+	Some(self.remove_at(position).2)
+    }
+
+    pub fn clear(&mut self) {
+	self.heap.clear();
+	self.positions.clear();
+	self.free_handles.clear();
+	self.next_sequence = 0;
+    }
+
+    fn remove_at(&mut self, position: usize) -> (usize, P, V) {
+	let last = self.heap.len() - 1;
+	self.swap(position, last);
+	let entry = self.heap.pop().expect("heap should not be empty");
+	self.positions[entry.handle] = None;
+	self.free_handles.push(entry.handle);
+	if position < self.heap.len() {
+	    self.sift_down(position);
+	    self.sift_up(position);
+	}
+	(entry.handle, entry.priority, entry.value)
+    }
+
+    fn is_less(&self, first: usize, second: usize) -> bool {
+	let first = &self.heap[first];
+	let second = &self.heap[second];
+	match first.priority.partial_cmp(&second.priority) {
+	    Some(Ordering::Less) => true,
+	    Some(Ordering::Greater) => false,
+	    Some(Ordering::Equal) | None => first.sequence < second.sequence,
+	}
+    }
+
+    fn swap(&mut self, first: usize, second: usize) {
+	self.heap.swap(first, second);
+	self.positions[self.heap[first].handle] = Some(first);
+	self.positions[self.heap[second].handle] = Some(second);
+    }
+
+    fn sift_up(&mut self, mut position: usize) {
+	while position > 0 {
+	    let parent = (position - 1) / 2;
+	    if !self.is_less(position, parent) {
+		break;
+	    }
+	    self.swap(position, parent);
+	    position = parent;
+	}
+    }
+
+    fn sift_down(&mut self, mut position: usize) {
+	loop {
+	    let left = 2 * position + 1;
+	    let right = left + 1;
+	    let mut smallest = position;
+	    if left < self.heap.len() && self.is_less(left, smallest) {
+		smallest = left;
+	    }
+	    if right < self.heap.len() && self.is_less(right, smallest) {
+		smallest = right;
+	    }
+	    if smallest == position {
+		break;
+	    }
+	    self.swap(position, smallest);
+	    position = smallest;
+	}
+    }
+
+}
+
+impl<P, V> Default for IndexedQueue<P, V> where P: PartialOrd {
+
+    fn default() -> Self {
+	IndexedQueue::new()
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::random::Random;
+
+    #[test]
+    fn test_pop_in_order() {
+	let mut queue = IndexedQueue::new();
+	for (i, priority) in [5.0, 1.0, 4.0, 2.0, 3.0].iter().enumerate() {
+	    queue.push(*priority, i);
+	}
+	let values: Vec<usize> = std::iter::from_fn(|| queue.pop()).map(|(_, _, value)| value).collect();
+	assert_eq!(vec![1, 3, 4, 2, 0], values);
+	assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_equal_priorities_in_push_order() {
+	let mut queue = IndexedQueue::new();
+	for i in 0..20 {
+	    queue.push((i % 3) as f64, i);
+	}
+	let values: Vec<usize> = std::iter::from_fn(|| queue.pop()).map(|(_, _, value)| value).collect();
+	let mut expected: Vec<usize> = (0..20).collect();
+	expected.sort_by_key(|i| i % 3);
+	assert_eq!(expected, values);
+    }
+
+    #[test]
+    fn test_remove() {
+	let mut queue = IndexedQueue::new();
+	let handles: Vec<usize> = (0..10).map(|i| queue.push(i as f64, i)).collect();
+	assert_eq!(Some(4), queue.remove(handles[4]));
+	assert_eq!(None, queue.remove(handles[4]));
+	assert!(!queue.contains(handles[4]));
+	assert_eq!(Some(0), queue.remove(handles[0]));
+	assert_eq!(8, queue.len());
+	let values: Vec<usize> = std::iter::from_fn(|| queue.pop()).map(|(_, _, value)| value).collect();
+	assert_eq!(vec![1, 2, 3, 5, 6, 7, 8, 9], values);
+    }
+
+    #[test]
+    fn test_reuse_handles() {
+	let mut queue = IndexedQueue::new();
+	for i in 0..100 {
+	    let handle = queue.push(i as f64, i);
+	    queue.remove(handle);
+	}
+	assert!(queue.is_empty());
+	assert_eq!(1, queue.positions.len());
+    }
+
+    #[test]
+    fn test_random_operations() {
+	// the queue must pop the same values as a sorted list of the entries that were not removed
+	let mut random = Random::new(7);
+	let mut queue = IndexedQueue::new();
+	let mut expected: Vec<(f64, usize, usize)> = vec![];
+	let mut next_value = 0;
+	for _ in 0..2000 {
+	    match random.index(3) {
+		0 | 1 => {
+		    let priority = random.index(50) as f64;
+		    let handle = queue.push(priority, next_value);
+		    expected.push((priority, next_value, handle));
+		    next_value += 1;
+		},
+		_ if !expected.is_empty() => {
+		    let (_, value, handle) = expected.remove(random.index(expected.len()));
+		    assert_eq!(Some(value), queue.remove(handle));
+		},
+		_ => {},
+	    }
+	}
+	expected.sort_by(|first, second| first.0.partial_cmp(&second.0).unwrap().then(first.1.cmp(&second.1)));
+	let values: Vec<usize> = std::iter::from_fn(|| queue.pop()).map(|(_, _, value)| value).collect();
+	assert_eq!(expected.iter().map(|entry| entry.1).collect::<Vec<_>>(), values);
+    }
+
+}
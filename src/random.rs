@@ -0,0 +1,121 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * A seeded pseudo random number generator, so generated maps can be reproduced from their seed
+ */
+
+/**
+ * The xoshiro256** generator, seeded through splitmix64
+ * The sequence only depends on the seed, not on the platform
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Random {
+    state: [u64; 4],
+}
+
+impl Random {
+
+    pub fn new(seed: u64) -> Random {
+	let mut seed = seed;
+	let mut state = [0; 4];
+	for value in state.iter_mut() {
+	    seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+	    let mut z = seed;
+	    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+	    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+	    *value = z ^ (z >> 31);
+	}
+	Random {
+	    state,
+	}
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+	let result = self.state[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+	let t = self.state[1] << 17;
+	self.state[2] ^= self.state[0];
+	self.state[3] ^= self.state[1];
+	self.state[1] ^= self.state[2];
+	self.state[0] ^= self.state[3];
+	self.state[2] ^= t;
+	self.state[3] = self.state[3].rotate_left(45);
+	result
+    }
+
+    /**
+     * Returns a number in [0, 1)
+     */
+    pub fn next_f64(&mut self) -> f64 {
+	(self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /**
+     * Returns a number in [min, max)
+     */
+    pub fn range(&mut self, min: f64, max: f64) -> f64 {
+	min + self.next_f64() * (max - min)
+    }
+
+    /**
+     * Returns an index in [0, count), count must be positive
+     */
+    pub fn index(&mut self, count: usize) -> usize {
+	// the multiplication avoids the bias of the modulo operator
+	((self.next_u64() as u128 * count as u128) >> 64) as usize
+    }
+
+}
+
+/**
+ * Creates sites spread evenly over the bounds, so tests of different modules share the same generator
+ */
+#[cfg(test)]
+pub(crate) fn random_sites(count: usize, seed: u64, bounds: &crate::geom::BoundingBox) -> Vec<(f64, f64)> {
+    let mut random = Random::new(seed);
+    (0..count).map(|_| (random.range(bounds.left(), bounds.right()), random.range(bounds.top(), bounds.bottom()))).collect()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_random_sequence() {
+	let mut first = Random::new(42);
+	let mut second = Random::new(42);
+	let mut other = Random::new(43);
+	let values: Vec<u64> = (0..10).map(|_| first.next_u64()).collect();
+	assert_eq!(values, (0..10).map(|_| second.next_u64()).collect::<Vec<u64>>());
+	assert_ne!(values, (0..10).map(|_| other.next_u64()).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn test_random_ranges() {
+	let mut random = Random::new(7);
+	let mut sum = 0.0;
+	for _ in 0..10000 {
+	    let value = random.range(-2.0, 3.0);
+	    assert!((-2.0..3.0).contains(&value));
+	    sum += value;
+	    assert!(random.index(5) < 5);
+	}
+	assert!((sum / 10000.0 - 0.5).abs() < 0.05);
+    }
+
+}
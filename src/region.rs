@@ -0,0 +1,424 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * Groups of faces merged into regions, such as the provinces or countries of a map
+ *
+ * Every face is assigned to a region. The half edges between faces of the same region are dropped, and the remaining
+ * half edges are joined into loops: the outer boundary of every connected part of a region, and the boundaries of the
+ * holes in it. Where a region only touches itself at a vertex, the loops turn as tightly as possible, so they never
+ * cross. The loops of periodic diagrams are unwrapped, so they stay continuous across the seams.
+ */
+
+use crate::attributes::Element;
+use crate::geom::polygon_signed_area;
+use crate::vector::{Vector2, Vector2F64};
+use crate::voronoi::Diagram;
+
+/**
+ * A connected part of a region
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegionPolygon {
+    outer: Vec<Vector2F64>,
+    holes: Vec<Vec<Vector2F64>>,
+    face_ids: Vec<usize>,
+}
+
+impl RegionPolygon {
+
+    /**
+     * The outer boundary, ordered like the boundaries of faces
+     */
+    pub fn outer(&self) -> &[Vector2F64] {
+	&self.outer
+    }
+
+    /**
+     * The boundaries of the holes, ordered against the outer boundary
+     */
+    pub fn holes(&self) -> &[Vec<Vector2F64>] {
+	&self.holes
+    }
+
+    /**
+     * The faces of the part, in ascending order
+     */
+    pub fn face_ids(&self) -> &[usize] {
+	&self.face_ids
+    }
+
+    /**
+     * The area of the part without its holes
+     */
+    pub fn area(&self) -> f64 {
+	polygon_signed_area(&self.outer).abs() - self.holes.iter().map(|hole| polygon_signed_area(hole).abs()).sum::<f64>()
+    }
+
+}
+
+/**
+ * A group of faces
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    id: usize,
+    polygons: Vec<RegionPolygon>,
+    neighbour_ids: Vec<usize>,
+}
+
+impl Region {
+
+    pub fn id(&self) -> usize {
+	self.id
+    }
+
+    /**
+     * The connected parts of the region, ordered by their first face
+     */
+    pub fn polygons(&self) -> &[RegionPolygon] {
+	&self.polygons
+    }
+
+    /**
+     * The regions sharing an edge with this region, in ascending order
+     */
+    pub fn neighbour_ids(&self) -> &[usize] {
+	&self.neighbour_ids
+    }
+
+    /**
+     * True if no face belongs to the region
+     */
+    pub fn is_empty(&self) -> bool {
+	self.polygons.is_empty()
+    }
+
+}
+
+/**
+ * The regions of the faces of a diagram
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Regions {
+    face_regions: Vec<usize>,
+    regions: Vec<Region>,
+
+    /**
+     * The half edges between different regions, or on the border of the diagram, each border only once
+     */
+    border_ids: Vec<usize>,
+}
+
+impl Regions {
+
+    /**
+     * Merges the faces of a diagram into regions, where the region ids are indexed by face id
+     * There is a region for every id up to the largest one, so ids that are not used give empty regions.
+     * Panics if there is not a region id for every face.
+     */
+    pub fn new<T>(diagram: &Diagram<T>, face_regions: &[usize]) -> Regions {
+	assert_eq!(diagram.faces.len(), face_regions.len(), "there should be a region for every face");
+	let region_count = face_regions.iter().max().map_or(0, |max_id| max_id + 1);
+	let region = |half_edge_id: usize| face_regions[diagram.half_edges[half_edge_id].face_id];
+	let is_boundary = |half_edge_id: usize| match diagram.half_edges[half_edge_id].twin_id {
+	    Some(twin_id) => region(twin_id) != region(half_edge_id),
+	    None => true,
+	};
+
+	let components = diagram.connected_components(|first_id, second_id| face_regions[first_id] == face_regions[second_id]);
+	let mut polygons: Vec<RegionPolygon> = (0..components.count())
+	    .map(|_| RegionPolygon { outer: vec![], holes: vec![], face_ids: vec![] })
+	    .collect();
+	for (face_id, component) in components.face_components().iter().copied().enumerate() {
+	    polygons[component].face_ids.push(face_id);
+	}
+
+	let mut visited = vec![false; diagram.half_edges.len()];
+	for start_id in 0..diagram.half_edges.len() {
+	    if visited[start_id] || !is_boundary(start_id) {
+		continue;
+	    }
+	    let points = boundary_loop(diagram, start_id, &is_boundary, &mut visited);
+	    let face_id = diagram.half_edges[start_id].face_id;
+	    let polygon = &mut polygons[components.component(face_id)];
+	    // outer boundaries turn the same way as the faces, holes turn the other way
+	    let face_area = polygon_signed_area(&diagram.face_polygon(face_id));
+	    if polygon_signed_area(&points) * face_area >= 0.0 && polygon.outer.is_empty() {
+		polygon.outer = points;
+	    } else {
+		polygon.holes.push(points);
+	    }
+	}
+
+	let mut regions: Vec<Region> = (0..region_count)
+	    .map(|id| Region { id, polygons: vec![], neighbour_ids: vec![] })
+	    .collect();
+	for polygon in polygons {
+	    regions[face_regions[polygon.face_ids[0]]].polygons.push(polygon);
+	}
+	let mut border_ids = vec![];
+	for half_edge in diagram.half_edges.iter() {
+	    match half_edge.twin_id {
+		Some(twin_id) if region(twin_id) != region(half_edge.id) => {
+		    regions[region(half_edge.id)].neighbour_ids.push(region(twin_id));
+		    if region(half_edge.id) < region(twin_id) {
+			border_ids.push(half_edge.id);
+		    }
+		},
+		None => border_ids.push(half_edge.id),
+		Some(_) => {},
+	    }
+	}
+	for region in regions.iter_mut() {
+	    region.neighbour_ids.sort_unstable();
+	    region.neighbour_ids.dedup();
+	}
+	Regions {
+	    face_regions: face_regions.to_vec(),
+	    regions,
+	    border_ids,
+	}
+    }
+
+    /**
+     * Merges the faces of a diagram into the regions of a face attribute, or returns None if there is no face
+     * attribute of region ids with that name
+     */
+    pub fn from_attribute<T>(diagram: &Diagram<T>, name: &str) -> Option<Regions> {
+	if diagram.attributes().element(name)? != Element::Face {
+	    return None;
+	}
+	let column = diagram.attributes().get::<usize>(name)?;
+	Some(Regions::new(diagram, column.values()))
+    }
+
+    pub fn regions(&self) -> &[Region] {
+	&self.regions
+    }
+
+    pub fn region(&self, region_id: usize) -> &Region {
+	&self.regions[region_id]
+    }
+
+    /**
+     * The region of a face
+     */
+    pub fn region_of(&self, face_id: usize) -> usize {
+	self.face_regions[face_id]
+    }
+
+    /**
+     * Whether two regions share an edge
+     */
+    pub fn are_neighbours(&self, first_id: usize, second_id: usize) -> bool {
+	self.regions[first_id].neighbour_ids.binary_search(&second_id).is_ok()
+    }
+
+    /**
+     * The half edges that separate regions or lie on the border of the diagram
+     * Of two twins on a border between regions, only the one in the region with the smaller id is included.
+     */
+    pub fn border_half_edge_ids(&self) -> &[usize] {
+	&self.border_ids
+    }
+
+    /**
+     * Creates lines along all borders, as pairs of indices into vertices given as x, y, z
+     * The coordinates are transformed like those of the diagram's triangles.
+     */
+    pub fn create_lines<T>(&self, diagram: &Diagram<T>) -> (Vec<f32>, Vec<u32>) {
+	let scale = diagram.bounds.width() / 2.0;
+	let left = diagram.bounds.left();
+	let top = diagram.bounds.top();
+	let mut vertices = Vec::with_capacity(self.border_ids.len() * 6);
+	let mut indices = Vec::with_capacity(self.border_ids.len() * 2);
+	for half_edge_id in self.border_ids.iter() {
+	    let start = diagram.half_edge_start(*half_edge_id);
+	    let end = diagram.half_edge_start(diagram.half_edges[*half_edge_id].next_id);
+	    for point in [start, end].iter() {
+		indices.push((vertices.len() / 3) as u32);
+		vertices.push(((point.get_x() - left) / scale - 1.0) as f32);
+		vertices.push(((point.get_y() - top) / scale - 1.0) as f32);
+		vertices.push(0.0f32);
+	    }
+	}
+	(vertices, indices)
+    }
+
+}
+
+/**
+ * Follows the boundary half edges of a region from a start half edge until it returns to it
+ * At the end of every boundary half edge, the walk turns around the vertex through the faces of the region until it
+ * finds the next boundary half edge. The periods by which the faces are moved against each other are added up, so the
+ * points of periodic diagrams stay continuous.
+ */
+fn boundary_loop<T, B>(diagram: &Diagram<T>, start_id: usize, is_boundary: &B, visited: &mut [bool]) -> Vec<Vector2F64> where B: Fn(usize) -> bool {
+    let half_edges = &diagram.half_edges;
+    let (width, height) = (diagram.bounds.width(), diagram.bounds.height());
+    let mut points = vec![];
+    let mut offset = (0, 0);
+    let mut half_edge_id = start_id;
+    loop {
+	visited[half_edge_id] = true;
+	let half_edge = &half_edges[half_edge_id];
+	let vertex = &diagram.vertices[half_edge.start_id];
+	let periods = (half_edge.wrap.0 + offset.0, half_edge.wrap.1 + offset.1);
+	points.push(Vector2F64::from_values(vertex.x + periods.0 as f64 * width, vertex.y + periods.1 as f64 * height));
+	let mut next_id = half_edge.next_id;
+	while !is_boundary(next_id) {
+	    let twin_id = half_edges[next_id].twin_id.expect("inner half edges should have a twin");
+	    let turned_id = half_edges[twin_id].next_id;
+	    // the shared vertex is moved by the periods of the half edge leaving it in each face
+	    let (before, after) = (half_edges[next_id].wrap, half_edges[turned_id].wrap);
+	    offset = (offset.0 + before.0 - after.0, offset.1 + before.1 - after.1);
+	    next_id = turned_id;
+	}
+	half_edge_id = next_id;
+	if half_edge_id == start_id {
+	    break points;
+	}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::geom::BoundingBox;
+    use crate::periodic::Wrap;
+    use crate::sites;
+    use crate::voronoi::DiagramBuilder;
+
+    use float_eq::assert_float_eq;
+
+    fn create_diagram() -> Diagram {
+	let bounds = BoundingBox::new(0.0, 100.0, 0.0, 100.0);
+	let mut builder = DiagramBuilder::new(bounds.clone());
+	builder.add_sites(sites::jittered_grid(&bounds, 10.0, 0.5, 2));
+	builder.build().unwrap()
+    }
+
+    fn assign<P>(diagram: &Diagram, region: P) -> Vec<usize> where P: Fn(f64, f64) -> usize {
+	diagram.faces().iter().map(|face| region(face.x(), face.y())).collect()
+    }
+
+    fn total_area(regions: &Regions) -> f64 {
+	regions.regions().iter().flat_map(|region| region.polygons().iter()).map(RegionPolygon::area).sum()
+    }
+
+    #[test]
+    fn test_two_regions() {
+	let diagram = create_diagram();
+	let regions = Regions::new(&diagram, &assign(&diagram, |x, _| if x < 50.0 { 0 } else { 1 }));
+	assert_eq!(2, regions.regions().len());
+	for region in regions.regions() {
+	    assert_eq!(1, region.polygons().len());
+	    assert!(region.polygons()[0].holes().is_empty());
+	}
+	assert_eq!(&[1], regions.region(0).neighbour_ids());
+	assert_eq!(&[0], regions.region(1).neighbour_ids());
+	assert!(regions.are_neighbours(0, 1));
+	assert_float_eq!(10000.0, total_area(&regions), abs <= 1e-6);
+	let faces: usize = regions.regions().iter().map(|region| region.polygons()[0].face_ids().len()).sum();
+	assert_eq!(diagram.faces().len(), faces);
+    }
+
+    #[test]
+    fn test_outer_boundary_follows_faces() {
+	let diagram = create_diagram();
+	let regions = Regions::new(&diagram, &vec![0; diagram.faces().len()]);
+	let polygon = &regions.region(0).polygons()[0];
+	// the only region is bounded by the bounds, with a corner at every vertex on them
+	let border_count = diagram.half_edges().iter().filter(|half_edge| half_edge.twin_id().is_none()).count();
+	assert_eq!(border_count, polygon.outer().len());
+	assert_float_eq!(10000.0, polygon_signed_area(polygon.outer()).abs(), abs <= 1e-6);
+	let face_area = polygon_signed_area(&diagram.face_polygon(0));
+	assert!(polygon_signed_area(polygon.outer()) * face_area > 0.0);
+	assert!(regions.region(0).neighbour_ids().is_empty());
+    }
+
+    #[test]
+    fn test_hole() {
+	let diagram = create_diagram();
+	let regions = Regions::new(&diagram, &assign(&diagram, |x, y| if (x - 50.0).hypot(y - 50.0) < 25.0 { 1 } else { 0 }));
+	let outside = &regions.region(0).polygons();
+	assert_eq!(1, outside.len());
+	assert_eq!(1, outside[0].holes().len());
+	let inside = &regions.region(1).polygons();
+	assert_eq!(1, inside.len());
+	assert!(inside[0].holes().is_empty());
+	// the hole has the same corners as the outer boundary of the inner region, in the other direction
+	let hole = &outside[0].holes()[0];
+	assert_float_eq!(-polygon_signed_area(inside[0].outer()), polygon_signed_area(hole), abs <= 1e-9);
+	assert_eq!(inside[0].outer().len(), hole.len());
+	assert_float_eq!(10000.0, total_area(&regions), abs <= 1e-6);
+    }
+
+    #[test]
+    fn test_disconnected_region() {
+	let diagram = create_diagram();
+	let regions = Regions::new(&diagram, &assign(&diagram, |x, y| if (x < 30.0 && y < 30.0) || (x > 70.0 && y > 70.0) { 2 } else { 0 }));
+	assert_eq!(3, regions.regions().len());
+	assert!(regions.region(1).is_empty());
+	assert_eq!(2, regions.region(2).polygons().len());
+	assert_eq!(&[0], regions.region(2).neighbour_ids());
+	assert!(regions.region(2).polygons().iter().all(|polygon| polygon.holes().is_empty()));
+	assert_float_eq!(10000.0, total_area(&regions), abs <= 1e-6);
+    }
+
+    #[test]
+    fn test_from_attribute() {
+	let mut diagram = create_diagram();
+	let face_regions = assign(&diagram, |_, y| if y < 40.0 { 0 } else { 1 });
+	diagram.add_attribute("province", Element::Face, 0usize).values_mut().copy_from_slice(&face_regions);
+	assert_eq!(Regions::new(&diagram, &face_regions), Regions::from_attribute(&diagram, "province").unwrap());
+	assert!(Regions::from_attribute(&diagram, "country").is_none());
+    }
+
+    #[test]
+    fn test_periodic() {
+	let bounds = BoundingBox::new(0.0, 100.0, 0.0, 100.0);
+	let mut builder = DiagramBuilder::new(bounds.clone());
+	builder.set_wrap(Wrap::Horizontal);
+	builder.add_sites(sites::jittered_grid(&bounds, 10.0, 0.5, 4));
+	let diagram = builder.build().unwrap();
+	// the region in the middle is a band with a loop around it, the other one wraps around the seam
+	let regions = Regions::new(&diagram, &assign(&diagram, |x, _| if x > 30.0 && x < 70.0 { 1 } else { 0 }));
+	for region in regions.regions() {
+	    assert_eq!(1, region.polygons().len());
+	    assert!(region.polygons()[0].holes().is_empty());
+	}
+	assert_float_eq!(10000.0, total_area(&regions), abs <= 1e-6);
+    }
+
+    #[test]
+    fn test_create_lines() {
+	let diagram = create_diagram();
+	let regions = Regions::new(&diagram, &assign(&diagram, |x, _| if x < 50.0 { 0 } else { 1 }));
+	let inner = diagram.half_edges().iter()
+	    .filter(|half_edge| half_edge.twin_id().is_some_and(|twin_id| regions.region_of(half_edge.face_id()) < regions.region_of(diagram.half_edges()[twin_id].face_id())))
+	    .count();
+	let outer = diagram.half_edges().iter().filter(|half_edge| half_edge.twin_id().is_none()).count();
+	assert_eq!(inner + outer, regions.border_half_edge_ids().len());
+	let (vertices, indices) = regions.create_lines(&diagram);
+	assert_eq!(regions.border_half_edge_ids().len() * 2, indices.len());
+	assert_eq!(indices.len() * 3, vertices.len());
+	assert!(vertices.iter().all(|value| *value >= -1.0 && *value <= 1.0));
+    }
+
+}
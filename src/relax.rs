@@ -0,0 +1,157 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * Lloyd relaxation of voronoi diagrams towards centroidal voronoi diagrams
+ */
+
+use crate::geom::polygon_centroid;
+use crate::vector::{Vector2, Vector2F64};
+use crate::voronoi::{Diagram, DiagramBuilder, VoronoiError};
+
+/**
+ * Moves every site to the centroid of its face and rebuilds the diagram, repeating this the specified number of times
+ * If a tolerance is specified, the iterations stop early once no site moves further than the tolerance
+ * Face attributes keep their values, vertex and half edge attributes are reset to their defaults.
+ * Clipped diagrams are not supported, because the rebuilt diagram would not be clipped and the pieces of split
+ * cells would become separate sites. Neither are power diagrams, whose rebuilt diagram would lose the weights.
+ */
+pub fn relax<T>(diagram: Diagram<T>, iterations: usize, tolerance: Option<f64>) -> Result<Diagram<T>, VoronoiError> {
+    if diagram.is_clipped() || diagram.is_weighted() {
+	return Err(VoronoiError::Unsupported(String::from("only unclipped and unweighted diagrams can be relaxed")));
+    }
+    let mut diagram = diagram;
+    for _ in 0..iterations {
+	let centroids: Vec<Vector2F64> = (0..diagram.faces().len()).map(|face_id| face_centroid(&diagram, face_id)).collect();
+	let bounds = diagram.bounds().clone();
+	let mut max_distance: f64 = 0.0;
+	let wrap = diagram.wrap();
+	let attributes = std::mem::take(&mut diagram.attributes);
+	let mut builder = DiagramBuilder::with_data(bounds.clone());
+	builder.set_wrap(wrap);
+	for ((x, y, data), centroid) in diagram.into_sites().into_iter().zip(centroids) {
+	    max_distance = max_distance.max((centroid.get_x() - x).hypot(centroid.get_y() - y));
+	    // centroids of cells on a seam of a periodic diagram can lie outside the bounds
+	    let (x, y) = wrap.wrap_point(&bounds, centroid.get_x(), centroid.get_y());
+	    builder.add_site_with_data(x, y, data);
+	}
+	diagram = builder.build()?;
+	// the faces keep their ids, but all vertices and half edges are new
+	diagram.attributes = attributes.rebuilt(diagram.faces.len(), diagram.vertices.len(), diagram.half_edges.len());
+	if let Some(tolerance) = tolerance {
+	    if max_distance <= tolerance {
+		break;
+	    }
+	}
+    }
+    Ok(diagram)
+}
+
+/**
+ * Calculates the centroid of a face from its half edge loop, or returns the site if the face has no area
+ */
+fn face_centroid<T>(diagram: &Diagram<T>, face_id: usize) -> Vector2F64 {
+    polygon_centroid(&diagram.face_polygon(face_id)).unwrap_or_else(|| diagram.site(face_id))
+}
+
+#[cfg(test)]
+mod test {
+
+    use float_eq::assert_float_eq;
+
+    use super::*;
+    use crate::clip::ClipPolygon;
+    use crate::geom::BoundingBox;
+    use crate::periodic::Wrap;
+    use crate::power::PowerDiagramBuilder;
+    use crate::random::random_sites;
+
+    fn create_diagram() -> Diagram<usize> {
+	let bounds = BoundingBox::new(0.0, 100.0, 0.0, 100.0);
+	let mut builder = DiagramBuilder::with_data(bounds.clone());
+	builder.add_sites_with_data(random_sites(50, 7, &bounds).into_iter().enumerate().map(|(id, (x, y))| (x, y, id)));
+	builder.build().unwrap()
+    }
+
+    /**
+     * The largest distance between a site and the centroid of its face
+     */
+    fn max_centroid_distance<T>(diagram: &Diagram<T>) -> f64 {
+	diagram.faces().iter().map(|face| {
+	    let centroid = face_centroid(diagram, face.id());
+	    (centroid.get_x() - face.x()).hypot(centroid.get_y() - face.y())
+	}).fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn test_relax_single_site() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(10.0, 30.0, 20.0, 60.0));
+	builder.add_site(12.0, 55.0);
+	let diagram = relax(builder.build().unwrap(), 1, None).unwrap();
+	assert_float_eq!(20.0, diagram.faces()[0].x(), abs <= 1e-9);
+	assert_float_eq!(40.0, diagram.faces()[0].y(), abs <= 1e-9);
+    }
+
+    #[test]
+    fn test_relax_no_iterations() {
+	assert_eq!(create_diagram(), relax(create_diagram(), 0, None).unwrap());
+    }
+
+    #[test]
+    fn test_relax_converges() {
+	let diagram = create_diagram();
+	let before = max_centroid_distance(&diagram);
+	let diagram = relax(diagram, 50, None).unwrap();
+	assert_eq!(50, diagram.faces().len());
+	assert!(max_centroid_distance(&diagram) < before / 10.0);
+	for (id, face) in diagram.faces().iter().enumerate() {
+	    assert_eq!(id, *face.data());
+	}
+    }
+
+    #[test]
+    fn test_relax_periodic() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 100.0, 0.0, 100.0));
+	builder.add_sites(vec![(1.0, 1.0), (99.0, 2.0), (3.0, 98.0), (50.0, 50.0), (60.0, 40.0)]);
+	builder.set_wrap(Wrap::Both);
+	let diagram = relax(builder.build().unwrap(), 30, None).unwrap();
+	assert_eq!(Wrap::Both, diagram.wrap());
+	assert!(diagram.faces().iter().all(|face| face.x() >= 0.0 && face.x() < 100.0 && face.y() >= 0.0 && face.y() < 100.0));
+	assert!(max_centroid_distance(&diagram) < 1.0);
+    }
+
+    #[test]
+    fn test_relax_tolerance() {
+	let once = relax(create_diagram(), 1, None).unwrap();
+	assert_eq!(once, relax(create_diagram(), 10, Some(100.0)).unwrap());
+	assert_ne!(once, relax(create_diagram(), 10, Some(1e-12)).unwrap());
+    }
+
+    #[test]
+    fn test_relax_clipped() {
+	let clipped = create_diagram().clip(&ClipPolygon::new(vec![(0.0, 0.0), (100.0, 0.0), (0.0, 100.0)]).unwrap()).unwrap();
+	assert!(matches!(relax(clipped, 1, None), Err(VoronoiError::Unsupported(_))));
+    }
+
+    #[test]
+    fn test_relax_weighted() {
+	let mut builder = PowerDiagramBuilder::new(BoundingBox::new(0.0, 100.0, 0.0, 100.0));
+	builder.add_sites(vec![(20.0, 20.0, 0.0), (80.0, 30.0, 50.0), (50.0, 80.0, 0.0)]);
+	assert!(matches!(relax(builder.build().unwrap(), 1, None), Err(VoronoiError::Unsupported(_))));
+    }
+
+}
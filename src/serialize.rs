@@ -0,0 +1,1742 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * Saves and loads voronoi diagrams in a versioned JSON or binary format
+ *
+ * The JSON format is meant for debugging and looks like this:
+ *
+ * {
+ *   "version": 1,
+ *   "bounds": [left, right, top, bottom],
+ *   "vertices": [[x, y], ...],
+ *   "half_edges": [[face_id, start_id, twin_id or null, prev_id, next_id], ...],
+ *   "faces": [[x, y, start_id, [data...]], ...],
+ *   "clip": {
+ *     "boundary": [half_edge_id, ...],
+ *     "faces": [[site_id, [hole_start_id, ...]], ...]
+ *   },
+ *   "weights": [weight, ...],
+ *   "wrap": {
+ *     "axes": [horizontal, vertical],
+ *     "offsets": [[half_edge_id, periods_x, periods_y], ...]
+ *   },
+ *   "attributes": [
+ *     {"name": name, "element": "face", "vertex" or "half_edge", "default": [value...], "values": [[value...], ...]},
+ *     ...
+ *   ]
+ * }
+ *
+ * The "clip" field is only written for clipped diagrams and power diagrams with empty cells, and lists
+ * the half edges on the clip boundary and the site and holes of every face. The "weights" field is only
+ * written for power diagrams, and has the weight of every face. The "wrap" field is only written for periodic
+ * diagrams, and lists the axes that wrap and the half edges whose start vertex is moved by whole periods.
+ * The "attributes" field is only written for diagrams with attribute columns, and has the default value and the
+ * value of every element of each column.
+ *
+ * The binary format stores the same values in the same order after a magic number and the version,
+ * with little endian numbers, lengths before every list and u64::MAX for a missing twin.
+ * The clip values, weights, wrap values and attributes follow the faces when they are written, each after a tag byte.
+ * As attribute columns are loaded before their type is known, every primitive value of an attribute is preceded by
+ * a byte with its kind.
+ * Ids are implied by the position in each list. Both formats reproduce floating point values exactly.
+ */
+
+use crate::attributes::{AnyColumn, AttributeValue, Column, Element};
+use crate::geom::BoundingBox;
+use crate::periodic::Wrap;
+use crate::voronoi::{Diagram, FaceParts, HalfEdge};
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{Display, Error as FormatError, Formatter};
+use std::io::{Read, Write};
+
+/**
+ * The version written to new files
+ */
+pub const FORMAT_VERSION: u32 = 1;
+
+/**
+ * The first bytes of a file in the binary format
+ */
+const MAGIC: &[u8; 4] = b"POPD";
+
+/**
+ * The tag before the clip values in the binary format
+ */
+const CLIP_TAG: u8 = 1;
+
+/**
+ * The tag before the weights in the binary format
+ */
+const WEIGHTS_TAG: u8 = 2;
+
+/**
+ * The tag before the wrap values in the binary format
+ */
+const WRAP_TAG: u8 = 3;
+
+/**
+ * The tag before the attributes in the binary format
+ */
+const ATTRIBUTES_TAG: u8 = 4;
+
+/**
+ * The kinds of the primitive values of attributes in the binary format
+ */
+const VALUE_F64: u8 = 0;
+const VALUE_U64: u8 = 1;
+const VALUE_I64: u8 = 2;
+const VALUE_BOOL: u8 = 3;
+const VALUE_STRING: u8 = 4;
+
+/**
+ * The encoding of a saved diagram
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+
+    /**
+     * Human readable JSON
+     */
+    Json,
+
+    /**
+     * Compact binary data
+     */
+    Binary,
+}
+
+/**
+ * Errors that can occur while saving or loading a diagram
+ */
+#[derive(Debug, PartialEq)]
+pub enum SerializeError {
+
+    /**
+     * Reading or writing failed
+     */
+    Io(String),
+
+    /**
+     * The data was written by an unknown version of the format
+     */
+    UnsupportedVersion(u32),
+
+    /**
+     * The data is not well formed at the specified byte offset
+     */
+    Syntax {
+	offset: usize,
+	message: String,
+    },
+
+    /**
+     * The data is well formed, but does not describe a valid diagram
+     */
+    Invalid(String),
+}
+
+impl SerializeError {
+
+    fn invalid(message: String) -> SerializeError {
+	SerializeError::Invalid(message)
+    }
+
+    fn syntax(offset: usize, message: &str) -> SerializeError {
+	SerializeError::Syntax {
+	    offset,
+	    message: String::from(message),
+	}
+    }
+
+}
+
+impl Display for SerializeError {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FormatError> {
+	match self {
+	    SerializeError::Io(message) => write!(f, "i/o error: {}", message),
+	    SerializeError::UnsupportedVersion(version) => write!(f, "unsupported format version {}, expected {}", version, FORMAT_VERSION),
+	    SerializeError::Syntax { offset, message } => write!(f, "syntax error at byte {}: {}", offset, message),
+	    SerializeError::Invalid(message) => write!(f, "invalid diagram: {}", message),
+	}
+    }
+
+}
+
+impl Error for SerializeError {}
+
+impl From<std::io::Error> for SerializeError {
+
+    fn from(error: std::io::Error) -> SerializeError {
+	SerializeError::Io(error.to_string())
+    }
+
+}
+
+/**
+ * The user data attached to faces, written as a sequence of primitive values
+ */
+pub trait Payload: Sized {
+
+    fn encode(&self, encoder: &mut Encoder);
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, SerializeError>;
+
+}
+
+/**
+ * Writes primitive values in either format
+ */
+pub struct Encoder {
+    format: Format,
+    bytes: Vec<u8>,
+    values: Vec<JsonValue>,
+}
+
+impl Encoder {
+
+    fn new(format: Format) -> Encoder {
+	Encoder {
+	    format,
+	    bytes: Vec::new(),
+	    values: Vec::new(),
+	}
+    }
+
+    pub fn write_f64(&mut self, value: f64) {
+	match self.format {
+	    Format::Json => self.values.push(JsonValue::from_f64(value)),
+	    Format::Binary => self.bytes.extend_from_slice(&value.to_le_bytes()),
+	}
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+	match self.format {
+	    Format::Json => self.values.push(JsonValue::Number(value.to_string())),
+	    Format::Binary => self.bytes.extend_from_slice(&value.to_le_bytes()),
+	}
+    }
+
+    pub fn write_i64(&mut self, value: i64) {
+	match self.format {
+	    Format::Json => self.values.push(JsonValue::Number(value.to_string())),
+	    Format::Binary => self.bytes.extend_from_slice(&value.to_le_bytes()),
+	}
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+	match self.format {
+	    Format::Json => self.values.push(JsonValue::Bool(value)),
+	    Format::Binary => self.bytes.push(value as u8),
+	}
+    }
+
+    pub fn write_str(&mut self, value: &str) {
+	match self.format {
+	    Format::Json => self.values.push(JsonValue::String(String::from(value))),
+	    Format::Binary => {
+		self.write_u64(value.len() as u64);
+		self.bytes.extend_from_slice(value.as_bytes());
+	    },
+	}
+    }
+
+    fn write_usize(&mut self, value: usize) {
+	self.write_u64(value as u64);
+    }
+
+    /**
+     * Takes the values written since the last call, as a JSON array
+     */
+    fn take_values(&mut self) -> JsonValue {
+	JsonValue::Array(std::mem::take(&mut self.values))
+    }
+
+}
+
+/**
+ * Reads primitive values in either format
+ */
+pub struct Decoder<'a> {
+    source: DecoderSource<'a>,
+}
+
+enum DecoderSource<'a> {
+    Json {
+	values: &'a [JsonValue],
+	position: usize,
+    },
+    Binary {
+	bytes: &'a [u8],
+	position: usize,
+    },
+}
+
+impl<'a> Decoder<'a> {
+
+    fn from_json(values: &'a [JsonValue]) -> Decoder<'a> {
+	Decoder {
+	    source: DecoderSource::Json {
+		values,
+		position: 0,
+	    },
+	}
+    }
+
+    fn from_binary(bytes: &'a [u8]) -> Decoder<'a> {
+	Decoder {
+	    source: DecoderSource::Binary {
+		bytes,
+		position: 0,
+	    },
+	}
+    }
+
+    fn next_value(&mut self) -> Result<&'a JsonValue, SerializeError> {
+	match &mut self.source {
+	    DecoderSource::Json { values, position } => {
+		let value = values.get(*position).ok_or_else(|| SerializeError::invalid(String::from("missing data value")))?;
+		*position += 1;
+		Ok(value)
+	    },
+	    DecoderSource::Binary { .. } => Err(SerializeError::invalid(String::from("expected binary data"))),
+	}
+    }
+
+    fn next_bytes(&mut self, count: usize) -> Result<&'a [u8], SerializeError> {
+	match &mut self.source {
+	    DecoderSource::Binary { bytes, position } => {
+		if bytes.len() - *position < count {
+		    return Err(SerializeError::syntax(bytes.len(), "unexpected end of data"));
+		}
+		let result = &bytes[*position..*position + count];
+		*position += count;
+		Ok(result)
+	    },
+	    DecoderSource::Json { .. } => Err(SerializeError::invalid(String::from("expected JSON data"))),
+	}
+    }
+
+    fn next_array<const N: usize>(&mut self) -> Result<[u8; N], SerializeError> {
+	let mut result = [0; N];
+	result.copy_from_slice(self.next_bytes(N)?);
+	Ok(result)
+    }
+
+    fn is_json(&self) -> bool {
+	matches!(self.source, DecoderSource::Json { .. })
+    }
+
+    pub fn read_f64(&mut self) -> Result<f64, SerializeError> {
+	if self.is_json() {
+	    self.next_value()?.as_f64()
+	} else {
+	    Ok(f64::from_le_bytes(self.next_array()?))
+	}
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SerializeError> {
+	if self.is_json() {
+	    self.next_value()?.parse_number()
+	} else {
+	    Ok(u64::from_le_bytes(self.next_array()?))
+	}
+    }
+
+    pub fn read_i64(&mut self) -> Result<i64, SerializeError> {
+	if self.is_json() {
+	    self.next_value()?.parse_number()
+	} else {
+	    Ok(i64::from_le_bytes(self.next_array()?))
+	}
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SerializeError> {
+	if self.is_json() {
+	    match self.next_value()? {
+		JsonValue::Bool(value) => Ok(*value),
+		_ => Err(SerializeError::invalid(String::from("expected a boolean"))),
+	    }
+	} else {
+	    match self.next_bytes(1)?[0] {
+		0 => Ok(false),
+		1 => Ok(true),
+		_ => Err(SerializeError::invalid(String::from("expected a boolean"))),
+	    }
+	}
+    }
+
+    pub fn read_string(&mut self) -> Result<String, SerializeError> {
+	if self.is_json() {
+	    match self.next_value()? {
+		JsonValue::String(value) => Ok(value.clone()),
+		_ => Err(SerializeError::invalid(String::from("expected a string"))),
+	    }
+	} else {
+	    let length = self.read_usize()?;
+	    String::from_utf8(self.next_bytes(length)?.to_vec())
+		.map_err(|_| SerializeError::invalid(String::from("string is not valid UTF-8")))
+	}
+    }
+
+    fn read_usize(&mut self) -> Result<usize, SerializeError> {
+	let value = self.read_u64()?;
+	if value > usize::MAX as u64 {
+	    Err(SerializeError::invalid(format!("{} is too large", value)))
+	} else {
+	    Ok(value as usize)
+	}
+    }
+
+    /**
+     * Reads a length and checks that the remaining data can hold that many items of the specified size
+     */
+    fn read_length(&mut self, item_size: usize) -> Result<usize, SerializeError> {
+	let length = self.read_usize()?;
+	if let DecoderSource::Binary { bytes, position } = &self.source {
+	    if length > (bytes.len() - *position) / item_size.max(1) {
+		return Err(SerializeError::syntax(*position, "list is longer than the data"));
+	    }
+	}
+	Ok(length)
+    }
+
+    fn position(&self) -> usize {
+	match &self.source {
+	    DecoderSource::Json { position, .. } | DecoderSource::Binary { position, .. } => *position,
+	}
+    }
+
+    fn is_finished(&self) -> bool {
+	match &self.source {
+	    DecoderSource::Json { values, position } => *position == values.len(),
+	    DecoderSource::Binary { bytes, position } => *position == bytes.len(),
+	}
+    }
+
+    /**
+     * Fails if there are values left, which means the data does not match the expected type
+     */
+    fn finish(&self) -> Result<(), SerializeError> {
+	match &self.source {
+	    DecoderSource::Json { values, position } if *position < values.len() => Err(SerializeError::invalid(String::from("unexpected data value"))),
+	    DecoderSource::Binary { bytes, position } if *position < bytes.len() => Err(SerializeError::syntax(*position, "unexpected data after the diagram")),
+	    _ => Ok(()),
+	}
+    }
+
+}
+
+impl Payload for () {
+
+    fn encode(&self, _encoder: &mut Encoder) {}
+
+    fn decode(_decoder: &mut Decoder<'_>) -> Result<(), SerializeError> {
+	Ok(())
+    }
+
+}
+
+impl Payload for f64 {
+
+    fn encode(&self, encoder: &mut Encoder) {
+	encoder.write_f64(*self);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<f64, SerializeError> {
+	decoder.read_f64()
+    }
+
+}
+
+impl Payload for u64 {
+
+    fn encode(&self, encoder: &mut Encoder) {
+	encoder.write_u64(*self);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<u64, SerializeError> {
+	decoder.read_u64()
+    }
+
+}
+
+impl Payload for usize {
+
+    fn encode(&self, encoder: &mut Encoder) {
+	encoder.write_usize(*self);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<usize, SerializeError> {
+	decoder.read_usize()
+    }
+
+}
+
+impl Payload for i64 {
+
+    fn encode(&self, encoder: &mut Encoder) {
+	encoder.write_i64(*self);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<i64, SerializeError> {
+	decoder.read_i64()
+    }
+
+}
+
+impl Payload for bool {
+
+    fn encode(&self, encoder: &mut Encoder) {
+	encoder.write_bool(*self);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<bool, SerializeError> {
+	decoder.read_bool()
+    }
+
+}
+
+impl Payload for String {
+
+    fn encode(&self, encoder: &mut Encoder) {
+	encoder.write_str(self);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<String, SerializeError> {
+	decoder.read_string()
+    }
+
+}
+
+impl<T> Payload for Option<T> where T: Payload {
+
+    fn encode(&self, encoder: &mut Encoder) {
+	encoder.write_bool(self.is_some());
+	if let Some(value) = self {
+	    value.encode(encoder);
+	}
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Option<T>, SerializeError> {
+	if decoder.read_bool()? {
+	    Ok(Some(T::decode(decoder)?))
+	} else {
+	    Ok(None)
+	}
+    }
+
+}
+
+impl<T> Payload for Vec<T> where T: Payload {
+
+    fn encode(&self, encoder: &mut Encoder) {
+	encoder.write_usize(self.len());
+	for value in self.iter() {
+	    value.encode(encoder);
+	}
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Vec<T>, SerializeError> {
+	let length = decoder.read_length(1)?;
+	let mut result = Vec::with_capacity(length);
+	for _ in 0..length {
+	    result.push(T::decode(decoder)?);
+	}
+	Ok(result)
+    }
+
+}
+
+impl<A, B> Payload for (A, B) where A: Payload, B: Payload {
+
+    fn encode(&self, encoder: &mut Encoder) {
+	self.0.encode(encoder);
+	self.1.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<(A, B), SerializeError> {
+	Ok((A::decode(decoder)?, B::decode(decoder)?))
+    }
+
+}
+
+/**
+ * A parsed JSON document
+ */
+#[derive(Clone, Debug, PartialEq)]
+enum JsonValue {
+    Null,
+    Bool(bool),
+
+    /**
+     * A number in its textual form, so integers and floats can both be read back exactly
+     */
+    Number(String),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+
+    /**
+     * Creates a number, using a string for values JSON numbers can not represent
+     */
+    fn from_f64(value: f64) -> JsonValue {
+	if value.is_finite() {
+	    // the debug representation is the shortest one that reads back to the same value
+	    JsonValue::Number(format!("{:?}", value))
+	} else {
+	    JsonValue::String(format!("{}", value))
+	}
+    }
+
+    fn as_f64(&self) -> Result<f64, SerializeError> {
+	match self {
+	    JsonValue::Number(text) => text.parse().map_err(|_| SerializeError::invalid(format!("{} is not a number", text))),
+	    JsonValue::String(text) if text == "inf" || text == "-inf" || text == "NaN" => Ok(text.parse().unwrap_or(f64::NAN)),
+	    _ => Err(SerializeError::invalid(String::from("expected a number"))),
+	}
+    }
+
+    fn as_bool(&self) -> Result<bool, SerializeError> {
+	match self {
+	    JsonValue::Bool(value) => Ok(*value),
+	    _ => Err(SerializeError::invalid(String::from("expected a boolean"))),
+	}
+    }
+
+    fn parse_number<N>(&self) -> Result<N, SerializeError> where N: std::str::FromStr {
+	match self {
+	    JsonValue::Number(text) => text.parse().map_err(|_| SerializeError::invalid(format!("{} is not a valid integer", text))),
+	    _ => Err(SerializeError::invalid(String::from("expected an integer"))),
+	}
+    }
+
+    fn as_str(&self, name: &str) -> Result<&str, SerializeError> {
+	match self {
+	    JsonValue::String(text) => Ok(text),
+	    _ => Err(SerializeError::invalid(format!("{} should be a string", name))),
+	}
+    }
+
+    fn as_array(&self, name: &str) -> Result<&[JsonValue], SerializeError> {
+	match self {
+	    JsonValue::Array(values) => Ok(values),
+	    _ => Err(SerializeError::invalid(format!("{} should be an array", name))),
+	}
+    }
+
+    fn get(&self, key: &str) -> Result<&JsonValue, SerializeError> {
+	self.get_optional(key)?.ok_or_else(|| SerializeError::invalid(format!("missing field \"{}\"", key)))
+    }
+
+    fn get_optional(&self, key: &str) -> Result<Option<&JsonValue>, SerializeError> {
+	match self {
+	    JsonValue::Object(entries) => Ok(entries.iter()
+					     .find(|(name, _)| name == key)
+					     .map(|(_, value)| value)),
+	    _ => Err(SerializeError::invalid(String::from("expected an object"))),
+	}
+    }
+
+    fn is_scalar(&self) -> bool {
+	!matches!(self, JsonValue::Array(_) | JsonValue::Object(_))
+    }
+
+    /**
+     * Writes the value, putting nested lists and objects on separate lines
+     */
+    fn write(&self, indent: usize, out: &mut String) {
+	match self {
+	    JsonValue::Null => out.push_str("null"),
+	    JsonValue::Bool(value) => out.push_str(if *value { "true" } else { "false" }),
+	    JsonValue::Number(text) => out.push_str(text),
+	    JsonValue::String(text) => JsonValue::write_string(text, out),
+	    JsonValue::Array(values) if values.iter().all(JsonValue::is_scalar) => {
+		out.push('[');
+		for (id, value) in values.iter().enumerate() {
+		    if id > 0 {
+			out.push_str(", ");
+		    }
+		    value.write(indent, out);
+		}
+		out.push(']');
+	    },
+	    JsonValue::Array(values) => {
+		out.push('[');
+		for (id, value) in values.iter().enumerate() {
+		    out.push_str(if id > 0 { ",\n" } else { "\n" });
+		    JsonValue::write_indent(indent + 1, out);
+		    value.write(indent + 1, out);
+		}
+		out.push('\n');
+		JsonValue::write_indent(indent, out);
+		out.push(']');
+	    },
+	    JsonValue::Object(entries) => {
+		out.push('{');
+		for (id, (key, value)) in entries.iter().enumerate() {
+		    out.push_str(if id > 0 { ",\n" } else { "\n" });
+		    JsonValue::write_indent(indent + 1, out);
+		    JsonValue::write_string(key, out);
+		    out.push_str(": ");
+		    value.write(indent + 1, out);
+		}
+		out.push('\n');
+		JsonValue::write_indent(indent, out);
+		out.push('}');
+	    },
+	}
+    }
+
+    fn write_indent(indent: usize, out: &mut String) {
+	for _ in 0..indent {
+	    out.push_str("  ");
+	}
+    }
+
+    fn write_string(text: &str, out: &mut String) {
+	out.push('"');
+	for c in text.chars() {
+	    match c {
+		'"' => out.push_str("\\\""),
+		'\\' => out.push_str("\\\\"),
+		'\n' => out.push_str("\\n"),
+		'\r' => out.push_str("\\r"),
+		'\t' => out.push_str("\\t"),
+		c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+		c => out.push(c),
+	    }
+	}
+	out.push('"');
+    }
+
+}
+
+/**
+ * Parses JSON text into values
+ */
+struct JsonParser<'a> {
+    text: &'a [u8],
+    position: usize,
+}
+
+impl<'a> JsonParser<'a> {
+
+    fn parse(text: &'a [u8]) -> Result<JsonValue, SerializeError> {
+	let mut parser = JsonParser {
+	    text,
+	    position: 0,
+	};
+	let value = parser.parse_value()?;
+	parser.skip_whitespace();
+	if parser.position < text.len() {
+	    Err(SerializeError::syntax(parser.position, "unexpected text after the document"))
+	} else {
+	    Ok(value)
+	}
+    }
+
+    fn peek(&self) -> Option<u8> {
+	self.text.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+	while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+	    self.position += 1;
+	}
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<(), SerializeError> {
+	self.skip_whitespace();
+	if self.peek() == Some(expected) {
+	    self.position += 1;
+	    Ok(())
+	} else {
+	    Err(SerializeError::syntax(self.position, &format!("expected '{}'", expected as char)))
+	}
+    }
+
+    fn parse_value(&mut self) -> Result<JsonValue, SerializeError> {
+	self.skip_whitespace();
+	match self.peek() {
+	    Some(b'{') => self.parse_object(),
+	    Some(b'[') => self.parse_array(),
+	    Some(b'"') => Ok(JsonValue::String(self.parse_string()?)),
+	    Some(b't') => self.parse_literal("true", JsonValue::Bool(true)),
+	    Some(b'f') => self.parse_literal("false", JsonValue::Bool(false)),
+	    Some(b'n') => self.parse_literal("null", JsonValue::Null),
+	    Some(b'-') | Some(b'0'..=b'9') => self.parse_number(),
+	    Some(_) => Err(SerializeError::syntax(self.position, "unexpected character")),
+	    None => Err(SerializeError::syntax(self.position, "unexpected end of text")),
+	}
+    }
+
+    fn parse_literal(&mut self, literal: &str, value: JsonValue) -> Result<JsonValue, SerializeError> {
+	if self.text[self.position..].starts_with(literal.as_bytes()) {
+	    self.position += literal.len();
+	    Ok(value)
+	} else {
+	    Err(SerializeError::syntax(self.position, "unknown literal"))
+	}
+    }
+
+    fn parse_number(&mut self) -> Result<JsonValue, SerializeError> {
+	let start = self.position;
+	let digits = |parser: &mut JsonParser<'a>| {
+	    let first = parser.position;
+	    while let Some(b'0'..=b'9') = parser.peek() {
+		parser.position += 1;
+	    }
+	    parser.position > first
+	};
+	if self.peek() == Some(b'-') {
+	    self.position += 1;
+	}
+	let mut valid = digits(self);
+	if self.peek() == Some(b'.') {
+	    self.position += 1;
+	    valid &= digits(self);
+	}
+	if let Some(b'e') | Some(b'E') = self.peek() {
+	    self.position += 1;
+	    if let Some(b'+') | Some(b'-') = self.peek() {
+		self.position += 1;
+	    }
+	    valid &= digits(self);
+	}
+	if valid {
+	    Ok(JsonValue::Number(String::from_utf8_lossy(&self.text[start..self.position]).into_owned()))
+	} else {
+	    Err(SerializeError::syntax(start, "malformed number"))
+	}
+    }
+
+    fn parse_string(&mut self) -> Result<String, SerializeError> {
+	self.expect(b'"')?;
+	let mut bytes = Vec::new();
+	loop {
+	    let position = self.position;
+	    let c = self.peek().ok_or_else(|| SerializeError::syntax(position, "unterminated string"))?;
+	    self.position += 1;
+	    match c {
+		b'"' => break,
+		b'\\' => {
+		    let escape = self.peek().ok_or_else(|| SerializeError::syntax(position, "unterminated string"))?;
+		    self.position += 1;
+		    match escape {
+			b'"' | b'\\' | b'/' => bytes.push(escape),
+			b'b' => bytes.push(0x08),
+			b'f' => bytes.push(0x0c),
+			b'n' => bytes.push(b'\n'),
+			b'r' => bytes.push(b'\r'),
+			b't' => bytes.push(b'\t'),
+			b'u' => {
+			    let c = self.parse_unicode_escape()?;
+			    let mut buffer = [0; 4];
+			    bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+			},
+			_ => return Err(SerializeError::syntax(position, "unknown escape sequence")),
+		    }
+		},
+		c => bytes.push(c),
+	    }
+	}
+	String::from_utf8(bytes).map_err(|_| SerializeError::syntax(self.position, "string is not valid UTF-8"))
+    }
+
+    fn parse_hex(&mut self) -> Result<u32, SerializeError> {
+	let position = self.position;
+	let digits = self.text.get(position..position + 4).ok_or_else(|| SerializeError::syntax(position, "unterminated escape sequence"))?;
+	let value = std::str::from_utf8(digits).ok()
+	    .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+	    .ok_or_else(|| SerializeError::syntax(position, "malformed escape sequence"))?;
+	self.position += 4;
+	Ok(value)
+    }
+
+    fn parse_unicode_escape(&mut self) -> Result<char, SerializeError> {
+	let position = self.position;
+	let high = self.parse_hex()?;
+	let code = if (0xd800..0xdc00).contains(&high) {
+	    // a surrogate pair encodes a character outside the basic multilingual plane
+	    if !self.text[self.position..].starts_with(b"\\u") {
+		return Err(SerializeError::syntax(position, "unpaired surrogate"));
+	    }
+	    self.position += 2;
+	    let low = self.parse_hex()?;
+	    if !(0xdc00..0xe000).contains(&low) {
+		return Err(SerializeError::syntax(position, "unpaired surrogate"));
+	    }
+	    0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+	} else {
+	    high
+	};
+	std::char::from_u32(code).ok_or_else(|| SerializeError::syntax(position, "invalid character"))
+    }
+
+    fn parse_array(&mut self) -> Result<JsonValue, SerializeError> {
+	self.expect(b'[')?;
+	let mut values = Vec::new();
+	self.skip_whitespace();
+	if self.peek() == Some(b']') {
+	    self.position += 1;
+	    return Ok(JsonValue::Array(values));
+	}
+	loop {
+	    values.push(self.parse_value()?);
+	    self.skip_whitespace();
+	    match self.peek() {
+		Some(b',') => self.position += 1,
+		Some(b']') => {
+		    self.position += 1;
+		    break Ok(JsonValue::Array(values));
+		},
+		_ => break Err(SerializeError::syntax(self.position, "expected ',' or ']'")),
+	    }
+	}
+    }
+
+    fn parse_object(&mut self) -> Result<JsonValue, SerializeError> {
+	self.expect(b'{')?;
+	let mut entries = Vec::new();
+	self.skip_whitespace();
+	if self.peek() == Some(b'}') {
+	    self.position += 1;
+	    return Ok(JsonValue::Object(entries));
+	}
+	loop {
+	    self.skip_whitespace();
+	    let key = self.parse_string()?;
+	    self.expect(b':')?;
+	    entries.push((key, self.parse_value()?));
+	    self.skip_whitespace();
+	    match self.peek() {
+		Some(b',') => self.position += 1,
+		Some(b'}') => {
+		    self.position += 1;
+		    break Ok(JsonValue::Object(entries));
+		},
+		_ => break Err(SerializeError::syntax(self.position, "expected ',' or '}'")),
+	    }
+	}
+    }
+
+}
+
+/**
+ * The parts of a diagram before they are checked and assembled
+ */
+struct DiagramParts<T> {
+    bounds: BoundingBox,
+    vertices: Vec<(f64, f64)>,
+    half_edges: Vec<(usize, usize, Option<usize>, usize, usize)>,
+    faces: Vec<(f64, f64, usize, T)>,
+    clip: Option<ClipParts>,
+    weights: Option<Vec<f64>>,
+    wrap: Option<WrapParts>,
+    attributes: Vec<(String, Element, EncodedColumn)>,
+}
+
+/**
+ * The parts of a clipped diagram that are not in the half edges and faces
+ */
+struct ClipParts {
+    boundary: Vec<usize>,
+    faces: Vec<(usize, Vec<usize>)>,
+}
+
+/**
+ * The parts of a periodic diagram that are not in the half edges and faces
+ */
+struct WrapParts {
+    wrap: Wrap,
+    offsets: Vec<(usize, i32, i32)>,
+}
+
+impl<T> DiagramParts<T> {
+
+    /**
+     * Assembles the diagram and checks the half edge invariants, so a loaded diagram can be traversed safely
+     */
+    fn into_diagram(self) -> Result<Diagram<T>, SerializeError> {
+	let face_count = self.faces.len();
+	let (boundary, clip_faces) = match self.clip {
+	    None => (Vec::new(), (0..face_count).map(|site_id| (site_id, Vec::new())).collect()),
+	    Some(clip) if clip.faces.len() != face_count => {
+		return Err(SerializeError::invalid(String::from("the clip values should have an entry for every face")));
+	    },
+	    Some(clip) => (clip.boundary, clip.faces),
+	};
+	let weights = match self.weights {
+	    None => vec![0.0; face_count],
+	    Some(weights) if weights.len() != face_count => {
+		return Err(SerializeError::invalid(String::from("there should be a weight for every face")));
+	    },
+	    Some(weights) => weights,
+	};
+	let mut boundaries = vec![false; self.half_edges.len()];
+	for half_edge_id in boundary {
+	    *boundaries.get_mut(half_edge_id)
+		.ok_or_else(|| SerializeError::invalid(format!("clip boundary half edge {} does not exist", half_edge_id)))? = true;
+	}
+	let half_edges = self.half_edges.into_iter().zip(boundaries)
+	    .map(|((face_id, start_id, twin_id, prev_id, next_id), boundary)| (face_id, start_id, twin_id, prev_id, next_id, boundary))
+	    .collect();
+	let faces = self.faces.into_iter().zip(clip_faces).zip(weights)
+	    .map(|(((x, y, start_id, data), (site_id, hole_start_ids)), weight)| FaceParts { x, y, start_id, site_id, hole_start_ids, weight, data })
+	    .collect();
+	let mut diagram = Diagram::from_face_parts(self.bounds, self.vertices, half_edges, faces);
+	if let Some(wrap) = self.wrap {
+	    diagram.wrap = wrap.wrap;
+	    for (half_edge_id, periods_x, periods_y) in wrap.offsets {
+		diagram.half_edges.get_mut(half_edge_id)
+		    .ok_or_else(|| SerializeError::invalid(format!("wrapped half edge {} does not exist", half_edge_id)))?
+		    .wrap = (periods_x, periods_y);
+	    }
+	}
+	for (name, element, column) in self.attributes {
+	    if column.len() != diagram.element_count(element) {
+		return Err(SerializeError::invalid(format!("attribute \"{}\" should have a value for every {}", name, element.name())));
+	    }
+	    diagram.attributes.insert(&name, element, Box::new(column));
+	}
+	diagram.validate_topology().map_err(|violations| {
+	    let messages: Vec<String> = violations.iter().map(|violation| violation.to_string()).collect();
+	    SerializeError::invalid(messages.join("; "))
+	})?;
+	Ok(diagram)
+    }
+
+}
+
+impl<T> Diagram<T> where T: Payload {
+
+    /**
+     * Writes the diagram in the specified format
+     */
+    pub fn write<W>(&self, writer: &mut W, format: Format) -> Result<(), SerializeError> where W: Write {
+	match format {
+	    Format::Json => writer.write_all(self.to_json().as_bytes())?,
+	    Format::Binary => writer.write_all(&self.to_binary())?,
+	}
+	Ok(())
+    }
+
+    /**
+     * Reads a diagram in the specified format, checking that the half edges are consistent
+     */
+    pub fn read<R>(reader: &mut R, format: Format) -> Result<Diagram<T>, SerializeError> where R: Read {
+	let mut bytes = Vec::new();
+	reader.read_to_end(&mut bytes)?;
+	match format {
+	    Format::Json => Diagram::from_json(&String::from_utf8(bytes).map_err(|error| SerializeError::syntax(error.utf8_error().valid_up_to(), "text is not valid UTF-8"))?),
+	    Format::Binary => Diagram::from_binary(&bytes),
+	}
+    }
+
+    pub fn to_json(&self) -> String {
+	let mut encoder = Encoder::new(Format::Json);
+	let bounds = self.bounds();
+	let vertices = self.vertices().iter()
+	    .map(|vertex| JsonValue::Array(vec![JsonValue::from_f64(vertex.x()), JsonValue::from_f64(vertex.y())]))
+	    .collect();
+	let half_edges = self.half_edges().iter()
+	    .map(|half_edge| JsonValue::Array(vec![
+		JsonValue::Number(half_edge.face_id().to_string()),
+		JsonValue::Number(half_edge.start_id().to_string()),
+		half_edge.twin_id().map(|twin_id| JsonValue::Number(twin_id.to_string())).unwrap_or(JsonValue::Null),
+		JsonValue::Number(half_edge.prev_id().to_string()),
+		JsonValue::Number(half_edge.next_id().to_string()),
+	    ]))
+	    .collect();
+	let faces = self.faces().iter()
+	    .map(|face| {
+		face.data().encode(&mut encoder);
+		JsonValue::Array(vec![
+		    JsonValue::from_f64(face.x()),
+		    JsonValue::from_f64(face.y()),
+		    JsonValue::Number(face.start_id().to_string()),
+		    encoder.take_values(),
+		])
+	    })
+	    .collect();
+	let document = JsonValue::Object(vec![
+	    (String::from("version"), JsonValue::Number(FORMAT_VERSION.to_string())),
+	    (String::from("bounds"), JsonValue::Array(vec![
+		JsonValue::from_f64(bounds.left()),
+		JsonValue::from_f64(bounds.right()),
+		JsonValue::from_f64(bounds.top()),
+		JsonValue::from_f64(bounds.bottom()),
+	    ])),
+	    (String::from("vertices"), JsonValue::Array(vertices)),
+	    (String::from("half_edges"), JsonValue::Array(half_edges)),
+	    (String::from("faces"), JsonValue::Array(faces)),
+	]);
+	let document = match document {
+	    JsonValue::Object(mut entries) if has_clip_values(self) => {
+		let id = |id: usize| JsonValue::Number(id.to_string());
+		let boundary = self.half_edges().iter()
+		    .filter(|half_edge| half_edge.is_clip_boundary())
+		    .map(|half_edge| id(half_edge.id()))
+		    .collect();
+		let faces = self.faces().iter()
+		    .map(|face| JsonValue::Array(vec![
+			id(face.site_id()),
+			JsonValue::Array(face.hole_start_ids().iter().map(|start_id| id(*start_id)).collect()),
+		    ]))
+		    .collect();
+		entries.push((String::from("clip"), JsonValue::Object(vec![
+		    (String::from("boundary"), JsonValue::Array(boundary)),
+		    (String::from("faces"), JsonValue::Array(faces)),
+		])));
+		JsonValue::Object(entries)
+	    },
+	    document => document,
+	};
+	let document = match document {
+	    JsonValue::Object(mut entries) if self.is_weighted() => {
+		let weights = self.faces().iter().map(|face| JsonValue::from_f64(face.weight())).collect();
+		entries.push((String::from("weights"), JsonValue::Array(weights)));
+		JsonValue::Object(entries)
+	    },
+	    document => document,
+	};
+	let document = match document {
+	    JsonValue::Object(mut entries) if self.wrap() != Wrap::None => {
+		let number = |value: i64| JsonValue::Number(value.to_string());
+		let offsets = self.half_edges().iter()
+		    .filter(|half_edge| half_edge.wrap() != (0, 0))
+		    .map(|half_edge| JsonValue::Array(vec![number(half_edge.id() as i64), number(half_edge.wrap().0 as i64), number(half_edge.wrap().1 as i64)]))
+		    .collect();
+		entries.push((String::from("wrap"), JsonValue::Object(vec![
+		    (String::from("axes"), JsonValue::Array(vec![JsonValue::Bool(self.wrap().is_horizontal()), JsonValue::Bool(self.wrap().is_vertical())])),
+		    (String::from("offsets"), JsonValue::Array(offsets)),
+		])));
+		JsonValue::Object(entries)
+	    },
+	    document => document,
+	};
+	let document = match document {
+	    JsonValue::Object(mut entries) if !self.attributes().is_empty() => {
+		let columns = self.attributes().columns()
+		    .map(|(name, element, column)| {
+			let (default, values) = encode_column(column);
+			JsonValue::Object(vec![
+			    (String::from("name"), JsonValue::String(String::from(name))),
+			    (String::from("element"), JsonValue::String(String::from(element.name()))),
+			    (String::from("default"), default),
+			    (String::from("values"), JsonValue::Array(values)),
+			])
+		    })
+		    .collect();
+		entries.push((String::from("attributes"), JsonValue::Array(columns)));
+		JsonValue::Object(entries)
+	    },
+	    document => document,
+	};
+	let mut result = String::new();
+	document.write(0, &mut result);
+	result.push('\n');
+	result
+    }
+
+    pub fn from_json(text: &str) -> Result<Diagram<T>, SerializeError> {
+	let document = JsonParser::parse(text.as_bytes())?;
+	let version = document.get("version")?.parse_number()?;
+	if version != FORMAT_VERSION {
+	    return Err(SerializeError::UnsupportedVersion(version));
+	}
+	let read_tuple = |value: &JsonValue, name: &str, length: usize| -> Result<Vec<JsonValue>, SerializeError> {
+	    let values = value.as_array(name)?;
+	    if values.len() == length {
+		Ok(values.to_vec())
+	    } else {
+		Err(SerializeError::invalid(format!("{} should have {} values", name, length)))
+	    }
+	};
+	let bounds = read_tuple(document.get("bounds")?, "bounds", 4)?;
+	let bounds = BoundingBox::new(bounds[0].as_f64()?, bounds[1].as_f64()?, bounds[2].as_f64()?, bounds[3].as_f64()?);
+	let vertices = document.get("vertices")?.as_array("vertices")?.iter()
+	    .map(|value| {
+		let values = read_tuple(value, "vertex", 2)?;
+		Ok((values[0].as_f64()?, values[1].as_f64()?))
+	    })
+	    .collect::<Result<Vec<(f64, f64)>, SerializeError>>()?;
+	let half_edges = document.get("half_edges")?.as_array("half_edges")?.iter()
+	    .map(|value| {
+		let values = read_tuple(value, "half edge", 5)?;
+		let twin_id = match &values[2] {
+		    JsonValue::Null => None,
+		    value => Some(value.parse_number()?),
+		};
+		Ok((values[0].parse_number()?, values[1].parse_number()?, twin_id, values[3].parse_number()?, values[4].parse_number()?))
+	    })
+	    .collect::<Result<Vec<(usize, usize, Option<usize>, usize, usize)>, SerializeError>>()?;
+	let faces = document.get("faces")?.as_array("faces")?.iter()
+	    .map(|value| {
+		let values = read_tuple(value, "face", 4)?;
+		let mut decoder = Decoder::from_json(values[3].as_array("face data")?);
+		let data = T::decode(&mut decoder)?;
+		decoder.finish()?;
+		Ok((values[0].as_f64()?, values[1].as_f64()?, values[2].parse_number()?, data))
+	    })
+	    .collect::<Result<Vec<(f64, f64, usize, T)>, SerializeError>>()?;
+	let clip = match document.get_optional("clip")? {
+	    None => None,
+	    Some(clip) => {
+		let boundary = clip.get("boundary")?.as_array("clip boundary")?.iter()
+		    .map(JsonValue::parse_number)
+		    .collect::<Result<Vec<usize>, SerializeError>>()?;
+		let faces = clip.get("faces")?.as_array("clip faces")?.iter()
+		    .map(|value| {
+			let values = read_tuple(value, "clip face", 2)?;
+			let hole_start_ids = values[1].as_array("holes")?.iter()
+			    .map(JsonValue::parse_number)
+			    .collect::<Result<Vec<usize>, SerializeError>>()?;
+			Ok((values[0].parse_number()?, hole_start_ids))
+		    })
+		    .collect::<Result<Vec<(usize, Vec<usize>)>, SerializeError>>()?;
+		Some(ClipParts { boundary, faces })
+	    },
+	};
+	let weights = match document.get_optional("weights")? {
+	    None => None,
+	    Some(weights) => Some(weights.as_array("weights")?.iter()
+				  .map(JsonValue::as_f64)
+				  .collect::<Result<Vec<f64>, SerializeError>>()?),
+	};
+	let wrap = match document.get_optional("wrap")? {
+	    None => None,
+	    Some(wrap) => {
+		let axes = read_tuple(wrap.get("axes")?, "wrap axes", 2)?;
+		let offsets = wrap.get("offsets")?.as_array("wrap offsets")?.iter()
+		    .map(|value| {
+			let values = read_tuple(value, "wrap offset", 3)?;
+			Ok((values[0].parse_number()?, values[1].parse_number()?, values[2].parse_number()?))
+		    })
+		    .collect::<Result<Vec<(usize, i32, i32)>, SerializeError>>()?;
+		Some(WrapParts { wrap: Wrap::from_axes(axes[0].as_bool()?, axes[1].as_bool()?), offsets })
+	    },
+	};
+	let attributes = match document.get_optional("attributes")? {
+	    None => Vec::new(),
+	    Some(attributes) => attributes.as_array("attributes")?.iter()
+		.map(|value| {
+		    let name = value.get("name")?.as_str("attribute name")?;
+		    let element = read_element(value.get("element")?.as_str("attribute element")?)?;
+		    let default = check_attribute_value(value.get("default")?)?;
+		    let values = value.get("values")?.as_array("attribute values")?.iter()
+			.map(check_attribute_value)
+			.collect::<Result<Vec<JsonValue>, SerializeError>>()?;
+		    Ok((String::from(name), element, EncodedColumn { default, values }))
+		})
+		.collect::<Result<Vec<(String, Element, EncodedColumn)>, SerializeError>>()?,
+	};
+	DiagramParts {
+	    bounds,
+	    vertices,
+	    half_edges,
+	    faces,
+	    clip,
+	    weights,
+	    wrap,
+	    attributes,
+	}.into_diagram()
+    }
+
+    pub fn to_binary(&self) -> Vec<u8> {
+	let mut encoder = Encoder::new(Format::Binary);
+	encoder.bytes.extend_from_slice(MAGIC);
+	encoder.bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+	let bounds = self.bounds();
+	encoder.write_f64(bounds.left());
+	encoder.write_f64(bounds.right());
+	encoder.write_f64(bounds.top());
+	encoder.write_f64(bounds.bottom());
+	encoder.write_usize(self.vertices().len());
+	for vertex in self.vertices().iter() {
+	    encoder.write_f64(vertex.x());
+	    encoder.write_f64(vertex.y());
+	}
+	encoder.write_usize(self.half_edges().len());
+	for half_edge in self.half_edges().iter() {
+	    encoder.write_usize(half_edge.face_id());
+	    encoder.write_usize(half_edge.start_id());
+	    encoder.write_u64(half_edge.twin_id().map(|twin_id| twin_id as u64).unwrap_or(u64::MAX));
+	    encoder.write_usize(half_edge.prev_id());
+	    encoder.write_usize(half_edge.next_id());
+	}
+	encoder.write_usize(self.faces().len());
+	for face in self.faces().iter() {
+	    encoder.write_f64(face.x());
+	    encoder.write_f64(face.y());
+	    encoder.write_usize(face.start_id());
+	    face.data().encode(&mut encoder);
+	}
+	if has_clip_values(self) {
+	    encoder.bytes.push(CLIP_TAG);
+	    let boundary: Vec<&HalfEdge> = self.half_edges().iter().filter(|half_edge| half_edge.is_clip_boundary()).collect();
+	    encoder.write_usize(boundary.len());
+	    for half_edge in boundary {
+		encoder.write_usize(half_edge.id());
+	    }
+	    for face in self.faces().iter() {
+		encoder.write_usize(face.site_id());
+		encoder.write_usize(face.hole_start_ids().len());
+		for start_id in face.hole_start_ids().iter() {
+		    encoder.write_usize(*start_id);
+		}
+	    }
+	}
+	if self.is_weighted() {
+	    encoder.bytes.push(WEIGHTS_TAG);
+	    for face in self.faces().iter() {
+		encoder.write_f64(face.weight());
+	    }
+	}
+	if self.wrap() != Wrap::None {
+	    encoder.bytes.push(WRAP_TAG);
+	    encoder.write_bool(self.wrap().is_horizontal());
+	    encoder.write_bool(self.wrap().is_vertical());
+	    let offsets: Vec<&HalfEdge> = self.half_edges().iter().filter(|half_edge| half_edge.wrap() != (0, 0)).collect();
+	    encoder.write_usize(offsets.len());
+	    for half_edge in offsets {
+		encoder.write_usize(half_edge.id());
+		encoder.write_i64(half_edge.wrap().0 as i64);
+		encoder.write_i64(half_edge.wrap().1 as i64);
+	    }
+	}
+	if !self.attributes().is_empty() {
+	    encoder.bytes.push(ATTRIBUTES_TAG);
+	    encoder.write_usize(self.attributes().len());
+	    for (name, element, column) in self.attributes().columns() {
+		let (default, values) = encode_column(column);
+		encoder.write_str(name);
+		encoder.write_str(element.name());
+		write_tagged_value(&mut encoder, &default);
+		encoder.write_usize(values.len());
+		for value in values.iter() {
+		    write_tagged_value(&mut encoder, value);
+		}
+	    }
+	}
+	encoder.bytes
+    }
+
+    pub fn from_binary(bytes: &[u8]) -> Result<Diagram<T>, SerializeError> {
+	let mut decoder = Decoder::from_binary(bytes);
+	if decoder.next_bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+	    return Err(SerializeError::syntax(0, "not a diagram file"));
+	}
+	let version = u32::from_le_bytes(decoder.next_array()?);
+	if version != FORMAT_VERSION {
+	    return Err(SerializeError::UnsupportedVersion(version));
+	}
+	let bounds = BoundingBox::new(decoder.read_f64()?, decoder.read_f64()?, decoder.read_f64()?, decoder.read_f64()?);
+	let vertex_count = decoder.read_length(16)?;
+	let mut vertices = Vec::with_capacity(vertex_count);
+	for _ in 0..vertex_count {
+	    vertices.push((decoder.read_f64()?, decoder.read_f64()?));
+	}
+	let half_edge_count = decoder.read_length(40)?;
+	let mut half_edges = Vec::with_capacity(half_edge_count);
+	for _ in 0..half_edge_count {
+	    let face_id = decoder.read_usize()?;
+	    let start_id = decoder.read_usize()?;
+	    let twin_id = match decoder.read_u64()? {
+		u64::MAX => None,
+		twin_id => Some(twin_id as usize),
+	    };
+	    half_edges.push((face_id, start_id, twin_id, decoder.read_usize()?, decoder.read_usize()?));
+	}
+	let face_count = decoder.read_length(24)?;
+	let mut faces = Vec::with_capacity(face_count);
+	for _ in 0..face_count {
+	    let x = decoder.read_f64()?;
+	    let y = decoder.read_f64()?;
+	    let start_id = decoder.read_usize()?;
+	    faces.push((x, y, start_id, T::decode(&mut decoder)?));
+	}
+	let mut clip = None;
+	let mut weights = None;
+	let mut wrap = None;
+	let mut attributes = None;
+	while !decoder.is_finished() {
+	    let offset = decoder.position();
+	    match decoder.next_array::<1>()?[0] {
+		CLIP_TAG if clip.is_none() => {
+		    let boundary_count = decoder.read_length(8)?;
+		    let mut boundary = Vec::with_capacity(boundary_count);
+		    for _ in 0..boundary_count {
+			boundary.push(decoder.read_usize()?);
+		    }
+		    let mut clip_faces = Vec::with_capacity(face_count);
+		    for _ in 0..face_count {
+			let site_id = decoder.read_usize()?;
+			let hole_count = decoder.read_length(8)?;
+			let mut hole_start_ids = Vec::with_capacity(hole_count);
+			for _ in 0..hole_count {
+			    hole_start_ids.push(decoder.read_usize()?);
+			}
+			clip_faces.push((site_id, hole_start_ids));
+		    }
+		    clip = Some(ClipParts { boundary, faces: clip_faces });
+		},
+		WEIGHTS_TAG if weights.is_none() => {
+		    let mut face_weights = Vec::with_capacity(face_count);
+		    for _ in 0..face_count {
+			face_weights.push(decoder.read_f64()?);
+		    }
+		    weights = Some(face_weights);
+		},
+		WRAP_TAG if wrap.is_none() => {
+		    let axes = Wrap::from_axes(decoder.read_bool()?, decoder.read_bool()?);
+		    let offset_count = decoder.read_length(24)?;
+		    let mut offsets = Vec::with_capacity(offset_count);
+		    for _ in 0..offset_count {
+			let half_edge_id = decoder.read_usize()?;
+			offsets.push((half_edge_id, read_period(&mut decoder)?, read_period(&mut decoder)?));
+		    }
+		    wrap = Some(WrapParts { wrap: axes, offsets });
+		},
+		ATTRIBUTES_TAG if attributes.is_none() => {
+		    let column_count = decoder.read_length(1)?;
+		    let mut columns = Vec::with_capacity(column_count);
+		    for _ in 0..column_count {
+			let name = decoder.read_string()?;
+			let element = read_element(&decoder.read_string()?)?;
+			let default = read_tagged_value(&mut decoder)?;
+			let value_count = decoder.read_length(8)?;
+			let mut values = Vec::with_capacity(value_count);
+			for _ in 0..value_count {
+			    values.push(read_tagged_value(&mut decoder)?);
+			}
+			columns.push((name, element, EncodedColumn { default, values }));
+		    }
+		    attributes = Some(columns);
+		},
+		_ => return Err(SerializeError::syntax(offset, "unknown or repeated section")),
+	    }
+	}
+	decoder.finish()?;
+	DiagramParts {
+	    bounds,
+	    vertices,
+	    half_edges,
+	    faces,
+	    clip,
+	    weights,
+	    wrap,
+	    attributes: attributes.unwrap_or_default(),
+	}.into_diagram()
+    }
+
+}
+
+/**
+ * Reads by how many periods a half edge is moved, which has to fit the diagram
+ */
+fn read_period(decoder: &mut Decoder<'_>) -> Result<i32, SerializeError> {
+    let value = decoder.read_i64()?;
+    i32::try_from(value).map_err(|_| SerializeError::invalid(format!("{} periods is too large", value)))
+}
+
+/**
+ * Encodes the default value and the values of an attribute column as JSON arrays
+ */
+fn encode_column(column: &dyn AnyColumn) -> (JsonValue, Vec<JsonValue>) {
+    let mut encoder = Encoder::new(Format::Json);
+    column.encode_default(&mut encoder);
+    let default = encoder.take_values();
+    let values = (0..column.len())
+	.map(|id| {
+	    column.encode(id, &mut encoder);
+	    encoder.take_values()
+	})
+	.collect();
+    (default, values)
+}
+
+fn read_element(name: &str) -> Result<Element, SerializeError> {
+    Element::from_name(name).ok_or_else(|| SerializeError::invalid(format!("unknown attribute element \"{}\"", name)))
+}
+
+/**
+ * Checks that the value of an attribute is a list of primitive values, which is how values are encoded
+ */
+fn check_attribute_value(value: &JsonValue) -> Result<JsonValue, SerializeError> {
+    let primitives = value.as_array("attribute value")?;
+    if primitives.iter().all(|primitive| primitive.is_scalar() && *primitive != JsonValue::Null) {
+	Ok(value.clone())
+    } else {
+	Err(SerializeError::invalid(String::from("attribute values should only contain numbers, booleans and strings")))
+    }
+}
+
+/**
+ * Writes the primitive values of an attribute value in the binary format, each after its kind
+ * Numbers are written as floats if they have a fraction or an exponent, which is how floats are written in JSON.
+ */
+fn write_tagged_value(encoder: &mut Encoder, value: &JsonValue) {
+    let primitives = match value {
+	JsonValue::Array(primitives) => primitives.as_slice(),
+	_ => &[],
+    };
+    encoder.write_usize(primitives.len());
+    for primitive in primitives {
+	match primitive {
+	    JsonValue::Bool(value) => {
+		encoder.bytes.push(VALUE_BOOL);
+		encoder.write_bool(*value);
+	    },
+	    JsonValue::Number(text) if !text.contains(['.', 'e', 'E']) && text.parse::<u64>().is_ok() => {
+		encoder.bytes.push(VALUE_U64);
+		encoder.write_u64(text.parse().unwrap_or(0));
+	    },
+	    JsonValue::Number(text) if !text.contains(['.', 'e', 'E']) && text.parse::<i64>().is_ok() => {
+		encoder.bytes.push(VALUE_I64);
+		encoder.write_i64(text.parse().unwrap_or(0));
+	    },
+	    JsonValue::Number(text) => {
+		encoder.bytes.push(VALUE_F64);
+		encoder.write_f64(text.parse().unwrap_or(f64::NAN));
+	    },
+	    JsonValue::String(text) => {
+		encoder.bytes.push(VALUE_STRING);
+		encoder.write_str(text);
+	    },
+	    // the values of columns have been checked or encoded from payloads, which only write primitive values
+	    JsonValue::Null | JsonValue::Array(_) | JsonValue::Object(_) => {},
+	}
+    }
+}
+
+/**
+ * Reads the primitive values of an attribute value in the binary format as JSON values
+ */
+fn read_tagged_value(decoder: &mut Decoder<'_>) -> Result<JsonValue, SerializeError> {
+    let count = decoder.read_length(2)?;
+    let mut primitives = Vec::with_capacity(count);
+    for _ in 0..count {
+	let offset = decoder.position();
+	primitives.push(match decoder.next_array::<1>()?[0] {
+	    VALUE_F64 => JsonValue::from_f64(decoder.read_f64()?),
+	    VALUE_U64 => JsonValue::Number(decoder.read_u64()?.to_string()),
+	    VALUE_I64 => JsonValue::Number(decoder.read_i64()?.to_string()),
+	    VALUE_BOOL => JsonValue::Bool(decoder.read_bool()?),
+	    VALUE_STRING => JsonValue::String(decoder.read_string()?),
+	    _ => return Err(SerializeError::syntax(offset, "unknown kind of attribute value")),
+	});
+    }
+    Ok(JsonValue::Array(primitives))
+}
+
+/**
+ * A loaded attribute column whose type is not known yet, with every value as a JSON array of primitive values
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct EncodedColumn {
+    default: JsonValue,
+    values: Vec<JsonValue>,
+}
+
+impl EncodedColumn {
+
+    pub(crate) fn decode<V>(&self) -> Result<Column<V>, SerializeError> where V: AttributeValue {
+	let values = self.values.iter()
+	    .map(decode_attribute_value)
+	    .collect::<Result<Vec<V>, SerializeError>>()?;
+	Ok(Column::new(decode_attribute_value(&self.default)?, values))
+    }
+
+}
+
+fn decode_attribute_value<V>(value: &JsonValue) -> Result<V, SerializeError> where V: Payload {
+    let mut decoder = Decoder::from_json(value.as_array("attribute value")?);
+    let value = V::decode(&mut decoder)?;
+    decoder.finish()?;
+    Ok(value)
+}
+
+impl AnyColumn for EncodedColumn {
+
+    fn as_any(&self) -> &dyn std::any::Any {
+	self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+	self
+    }
+
+    fn len(&self) -> usize {
+	self.values.len()
+    }
+
+    fn resize(&mut self, len: usize) {
+	self.values.resize(len, self.default.clone());
+    }
+
+    fn reset(&mut self, id: usize) {
+	self.values[id] = self.default.clone();
+    }
+
+    fn swap_remove(&mut self, id: usize) {
+	self.values.swap_remove(id);
+    }
+
+    fn encode_default(&self, encoder: &mut Encoder) {
+	if let JsonValue::Array(primitives) = &self.default {
+	    encoder.values.extend(primitives.iter().cloned());
+	}
+    }
+
+    fn encode(&self, id: usize, encoder: &mut Encoder) {
+	if let JsonValue::Array(primitives) = &self.values[id] {
+	    encoder.values.extend(primitives.iter().cloned());
+	}
+    }
+
+    fn equals(&self, other: &dyn AnyColumn) -> bool {
+	match other.as_any().downcast_ref::<EncodedColumn>() {
+	    Some(other) => self == other,
+	    None => other.len() == self.len() && encode_column(other) == (self.default.clone(), self.values.clone()),
+	}
+    }
+
+}
+
+/**
+ * True if the faces of a diagram have values that are only written for clipped diagrams,
+ * which includes power diagrams where some sites have no face
+ */
+fn has_clip_values<T>(diagram: &Diagram<T>) -> bool {
+    diagram.is_clipped() || diagram.faces().iter().any(|face| face.site_id() != face.id())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::clip::ClipPolygon;
+    use crate::voronoi::DiagramBuilder;
+
+    fn create_diagram() -> Diagram<(String, Option<f64>)> {
+	let mut builder = DiagramBuilder::with_data(BoundingBox::new(-10.0, 10.0, 0.1, 7.3));
+	builder.add_sites_with_data(vec![
+	    (-3.3, 1.7, (String::from("first \"quoted\"\n"), Some(0.1))),
+	    (4.1, 2.9, (String::from("zweite \u{1f30d}"), None)),
+	    (0.7, 6.6, (String::new(), Some(-1e-300))),
+	    (-9.0, 5.0, (String::from("\\"), Some(f64::INFINITY))),
+	]);
+	builder.build().unwrap()
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+	let diagram = create_diagram();
+	let text = diagram.to_json();
+	assert!(text.starts_with("{\n  \"version\": 1,\n"));
+	assert_eq!(diagram, Diagram::from_json(&text).unwrap());
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+	let diagram = create_diagram();
+	let mut bytes = Vec::new();
+	diagram.write(&mut bytes, Format::Binary).unwrap();
+	assert_eq!(&MAGIC[..], &bytes[0..4]);
+	assert_eq!(diagram, Diagram::read(&mut &bytes[..], Format::Binary).unwrap());
+    }
+
+    #[test]
+    fn test_empty_round_trip() {
+	let diagram = DiagramBuilder::new(BoundingBox::new(0.0, 1.0, 0.0, 1.0)).build().unwrap();
+	assert_eq!(diagram, Diagram::from_json(&diagram.to_json()).unwrap());
+	assert_eq!(diagram, Diagram::from_binary(&diagram.to_binary()).unwrap());
+    }
+
+    #[test]
+    fn test_clipped_round_trip() {
+	let mut builder = DiagramBuilder::with_data(BoundingBox::new(0.0, 100.0, 0.0, 100.0));
+	builder.add_sites_with_data(vec![(25.0, 50.0, 1.5), (75.0, 50.0, -2.0), (50.0, 90.0, 0.0)]);
+	let mut polygon = ClipPolygon::new(vec![(5.0, 5.0), (95.0, 5.0), (95.0, 95.0), (70.0, 95.0), (50.0, 60.0), (30.0, 95.0), (5.0, 95.0)]).unwrap();
+	polygon.add_hole(vec![(10.0, 40.0), (20.0, 40.0), (20.0, 60.0), (10.0, 60.0)]).unwrap();
+	let diagram = builder.build().unwrap().clip(&polygon).unwrap();
+	assert!(diagram.faces().iter().any(|face| !face.hole_start_ids().is_empty()));
+	let text = diagram.to_json();
+	assert!(text.contains("\"clip\": {"));
+	assert_eq!(diagram, Diagram::from_json(&text).unwrap());
+	assert_eq!(diagram, Diagram::from_binary(&diagram.to_binary()).unwrap());
+	let bytes = diagram.to_binary();
+	assert!(matches!(Diagram::<f64>::from_binary(&bytes[0..bytes.len() - 1]), Err(SerializeError::Syntax { .. })));
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+	let diagram = create_diagram();
+	let text = diagram.to_json().replacen("\"version\": 1", "\"version\": 7", 1);
+	assert_eq!(Err(SerializeError::UnsupportedVersion(7)), Diagram::<(String, Option<f64>)>::from_json(&text));
+	let mut bytes = diagram.to_binary();
+	bytes[4] = 2;
+	assert_eq!(Err(SerializeError::UnsupportedVersion(2)), Diagram::<(String, Option<f64>)>::from_binary(&bytes));
+    }
+
+    #[test]
+    fn test_malformed_data() {
+	let text = create_diagram().to_json();
+	assert!(matches!(Diagram::<(String, Option<f64>)>::from_json(&text[0..text.len() / 2]), Err(SerializeError::Syntax { .. })));
+	assert!(matches!(Diagram::<()>::from_json("{\"version\": 1}"), Err(SerializeError::Invalid(_))));
+	// the payload does not match the type
+	assert!(matches!(Diagram::<()>::from_json(&text), Err(SerializeError::Invalid(_))));
+	let bytes = create_diagram().to_binary();
+	assert!(matches!(Diagram::<(String, Option<f64>)>::from_binary(&bytes[0..bytes.len() - 1]), Err(SerializeError::Syntax { .. })));
+	assert!(matches!(Diagram::<()>::from_binary(b"PNG!"), Err(SerializeError::Syntax { .. })));
+    }
+
+    #[test]
+    fn test_corrupt_half_edges() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 1000.0, 0.0, 1000.0));
+	builder.add_site(100.0, 100.0);
+	builder.add_site(900.0, 900.0);
+	let text = builder.build().unwrap().to_json();
+	// half edge 0 is [0, 1, 1, 3, 2]
+	let corrupt = |from: &str, to: &str| {
+	    assert!(text.contains(from));
+	    Diagram::<()>::from_json(&text.replacen(from, to, 1)).unwrap_err()
+	};
+	assert_eq!(SerializeError::Invalid(String::from("half edge 0 has next half edge 3, whose previous half edge is 2; \
+							 half edge 0 does not end where its twin 1 starts; \
+							 half edge 2 has previous half edge 0, whose next half edge is 3; \
+							 half edge 2 is not part of the loop around its face")),
+		   corrupt("[0, 1, 1, 3, 2]", "[0, 1, 1, 3, 3]"));
+	assert_eq!(SerializeError::Invalid(String::from("half edge 0 has twin 2, whose twin is None; \
+							 half edge 0 and its twin 2 belong to the same face; \
+							 half edge 1 has twin 0, whose twin is Some(2)")),
+		   corrupt("[0, 1, 1, 3, 2]", "[0, 1, 2, 3, 2]"));
+	assert_eq!(SerializeError::Invalid(String::from("half edge 0 refers to half edge 9, which does not exist")),
+		   corrupt("[0, 1, 1, 3, 2]", "[0, 1, 9, 3, 2]"));
+	assert_eq!(SerializeError::Invalid(String::from("half edge 0 refers to vertex 4, which does not exist")),
+		   corrupt("[0, 1, 1, 3, 2]", "[0, 4, 1, 3, 2]"));
+    }
+
+}
@@ -0,0 +1,255 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * Generates sites for voronoi diagrams, reproducibly from a seed
+ * All generators only return sites inside the bounds, in a form that can be passed to DiagramBuilder::add_sites
+ */
+
+use crate::geom::BoundingBox;
+use crate::random::Random;
+use crate::vector::{Vector2, Vector2F64};
+
+/**
+ * The number of candidates tried around each active sample of the poisson disc generator
+ */
+const POISSON_DISC_CANDIDATES: usize = 30;
+
+/**
+ * Generates sites that are uniformly distributed over the bounds
+ */
+pub fn uniform(bounds: &BoundingBox, count: usize, seed: u64) -> Vec<(f64, f64)> {
+    let mut random = Random::new(seed);
+    (0..count)
+	.map(|_| (random.range(bounds.left(), bounds.right()), random.range(bounds.top(), bounds.bottom())))
+	.collect()
+}
+
+/**
+ * Generates sites that are at least the minimum distance apart, filling the bounds until no more sites fit
+ * (Bridson's algorithm). Returns no sites if the minimum distance is not positive.
+ */
+pub fn poisson_disc(bounds: &BoundingBox, min_distance: f64, seed: u64) -> Vec<(f64, f64)> {
+    if min_distance.is_nan() || min_distance <= 0.0 || bounds.width() <= 0.0 || bounds.height() <= 0.0 {
+	return Vec::new();
+    }
+    let mut random = Random::new(seed);
+
+    // every grid cell is small enough to hold at most one site
+    let cell_size = min_distance / 2f64.sqrt();
+    let columns = (bounds.width() / cell_size).ceil() as usize;
+    let rows = (bounds.height() / cell_size).ceil() as usize;
+    let mut grid: Vec<Option<usize>> = vec![None; columns * rows];
+    let cell_of = |x: f64, y: f64| {
+	let column = (((x - bounds.left()) / cell_size) as usize).min(columns - 1);
+	let row = (((y - bounds.top()) / cell_size) as usize).min(rows - 1);
+	(column, row)
+    };
+
+    let mut sites = Vec::new();
+    let mut active = Vec::new();
+    let first = (random.range(bounds.left(), bounds.right()), random.range(bounds.top(), bounds.bottom()));
+    let (column, row) = cell_of(first.0, first.1);
+    grid[row * columns + column] = Some(0);
+    sites.push(first);
+    active.push(0);
+
+    while !active.is_empty() {
+	let active_index = random.index(active.len());
+	let (x, y) = sites[active[active_index]];
+	let mut found = false;
+	for _ in 0..POISSON_DISC_CANDIDATES {
+	    // candidates are taken uniformly from the ring between one and two times the minimum distance
+	    let angle = random.range(0.0, 2.0 * std::f64::consts::PI);
+	    let radius = min_distance * random.range(1.0, 4.0).sqrt();
+	    let candidate = (x + radius * angle.cos(), y + radius * angle.sin());
+	    if !bounds.contains(&Vector2F64::from_values(candidate.0, candidate.1)) {
+		continue;
+	    }
+	    let (column, row) = cell_of(candidate.0, candidate.1);
+	    let mut free = true;
+	    'neighbours: for neighbour_row in row.saturating_sub(2)..(row + 3).min(rows) {
+		for neighbour_column in column.saturating_sub(2)..(column + 3).min(columns) {
+		    if let Some(site_id) = grid[neighbour_row * columns + neighbour_column] {
+			let (site_x, site_y) = sites[site_id];
+			if (site_x - candidate.0).hypot(site_y - candidate.1) < min_distance {
+			    free = false;
+			    break 'neighbours;
+			}
+		    }
+		}
+	    }
+	    if free {
+		grid[row * columns + column] = Some(sites.len());
+		active.push(sites.len());
+		sites.push(candidate);
+		found = true;
+		break;
+	    }
+	}
+	if !found {
+	    active.swap_remove(active_index);
+	}
+    }
+    sites
+}
+
+/**
+ * Generates one site in every cell of a square grid, moved randomly within its cell
+ * A jitter of 0 gives a regular grid, a jitter of 1 lets the site move anywhere in its cell.
+ * Returns no sites if the spacing is not positive.
+ */
+pub fn jittered_grid(bounds: &BoundingBox, spacing: f64, jitter: f64, seed: u64) -> Vec<(f64, f64)> {
+    if spacing.is_nan() || spacing <= 0.0 {
+	return Vec::new();
+    }
+    let mut random = Random::new(seed);
+    let jitter = jitter.clamp(0.0, 1.0);
+    let columns = (bounds.width() / spacing).ceil() as usize;
+    let rows = (bounds.height() / spacing).ceil() as usize;
+    let mut sites = Vec::with_capacity(columns * rows);
+    for row in 0..rows {
+	for column in 0..columns {
+	    let x = bounds.left() + spacing * (column as f64 + 0.5 + jitter * random.range(-0.5, 0.5));
+	    let y = bounds.top() + spacing * (row as f64 + 0.5 + jitter * random.range(-0.5, 0.5));
+	    if bounds.contains(&Vector2F64::from_values(x, y)) {
+		sites.push((x, y));
+	    }
+	}
+    }
+    sites
+}
+
+/**
+ * Generates sites on a hexagonal grid, where every site is the specified distance from its six neighbours,
+ * moved randomly by up to half the jitter times the spacing along each axis
+ * Returns no sites if the spacing is not positive.
+ */
+pub fn hex_grid(bounds: &BoundingBox, spacing: f64, jitter: f64, seed: u64) -> Vec<(f64, f64)> {
+    if spacing.is_nan() || spacing <= 0.0 {
+	return Vec::new();
+    }
+    let mut random = Random::new(seed);
+    let jitter = jitter.clamp(0.0, 1.0);
+    let row_spacing = spacing * 3f64.sqrt() / 2.0;
+    let columns = (bounds.width() / spacing).ceil() as usize + 1;
+    let rows = (bounds.height() / row_spacing).ceil() as usize + 1;
+    let mut sites = Vec::with_capacity(columns * rows);
+    for row in 0..rows {
+	// odd rows are shifted by half the spacing, so each site sits between two sites of the neighbouring rows
+	let shift = if row % 2 == 0 { 0.25 } else { 0.75 };
+	for column in 0..columns {
+	    let x = bounds.left() + spacing * (column as f64 + shift + jitter * random.range(-0.5, 0.5));
+	    let y = bounds.top() + row_spacing * (row as f64 + 0.5) + spacing * jitter * random.range(-0.5, 0.5);
+	    if bounds.contains(&Vector2F64::from_values(x, y)) {
+		sites.push((x, y));
+	    }
+	}
+    }
+    sites
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::voronoi::DiagramBuilder;
+
+    fn assert_inside(bounds: &BoundingBox, sites: &[(f64, f64)]) {
+	for (x, y) in sites.iter() {
+	    assert!(bounds.contains(&Vector2F64::from_values(*x, *y)));
+	}
+    }
+
+    fn min_distance(sites: &[(f64, f64)]) -> f64 {
+	let mut result = f64::INFINITY;
+	for (i, (x1, y1)) in sites.iter().enumerate() {
+	    for (x2, y2) in sites[i + 1..].iter() {
+		result = result.min((x2 - x1).hypot(y2 - y1));
+	    }
+	}
+	result
+    }
+
+    #[test]
+    fn test_uniform() {
+	let bounds = BoundingBox::new(-10.0, 30.0, 5.0, 25.0);
+	let sites = uniform(&bounds, 500, 1);
+	assert_eq!(500, sites.len());
+	assert_inside(&bounds, &sites);
+	assert_eq!(sites, uniform(&bounds, 500, 1));
+	assert_ne!(sites, uniform(&bounds, 500, 2));
+	let mut builder = DiagramBuilder::new(bounds);
+	builder.add_sites(sites);
+	assert!(builder.build().unwrap().validate().is_ok());
+    }
+
+    #[test]
+    fn test_poisson_disc() {
+	let bounds = BoundingBox::new(0.0, 100.0, 0.0, 60.0);
+	let sites = poisson_disc(&bounds, 5.0, 3);
+	assert_inside(&bounds, &sites);
+	assert!(min_distance(&sites) >= 5.0);
+	assert_eq!(sites, poisson_disc(&bounds, 5.0, 3));
+	assert_ne!(sites, poisson_disc(&bounds, 5.0, 4));
+	// the bounds are filled, so no point is further than twice the minimum distance from a site
+	for i in 0..=50 {
+	    for j in 0..=30 {
+		let (x, y) = (i as f64 * 2.0, j as f64 * 2.0);
+		assert!(sites.iter().any(|(site_x, site_y)| (site_x - x).hypot(site_y - y) < 10.0));
+	    }
+	}
+	assert!(poisson_disc(&bounds, 0.0, 3).is_empty());
+    }
+
+    #[test]
+    fn test_jittered_grid() {
+	let bounds = BoundingBox::new(0.0, 100.0, 0.0, 50.0);
+	let regular = jittered_grid(&bounds, 10.0, 0.0, 5);
+	assert_eq!(50, regular.len());
+	assert_eq!((5.0, 5.0), regular[0]);
+	assert_eq!((95.0, 45.0), regular[49]);
+	let sites = jittered_grid(&bounds, 10.0, 1.0, 5);
+	assert_eq!(50, sites.len());
+	assert_inside(&bounds, &sites);
+	assert_eq!(sites, jittered_grid(&bounds, 10.0, 1.0, 5));
+	for (site, cell) in sites.iter().zip(regular.iter()) {
+	    assert!((site.0 - cell.0).abs() <= 5.0 && (site.1 - cell.1).abs() <= 5.0);
+	}
+	assert!(jittered_grid(&bounds, -1.0, 1.0, 5).is_empty());
+    }
+
+    #[test]
+    fn test_hex_grid() {
+	let bounds = BoundingBox::new(0.0, 100.0, 0.0, 100.0);
+	let sites = hex_grid(&bounds, 10.0, 0.0, 5);
+	assert_inside(&bounds, &sites);
+	assert!((min_distance(&sites) - 10.0).abs() < 1e-9);
+	// sites away from the bounds have six neighbours at the spacing
+	for (x, y) in sites.iter().filter(|(x, y)| *x > 20.0 && *x < 80.0 && *y > 20.0 && *y < 80.0) {
+	    let count = sites.iter().filter(|(other_x, other_y)| ((other_x - x).hypot(other_y - y) - 10.0).abs() < 1e-9).count();
+	    assert_eq!(6, count);
+	}
+	let jittered = hex_grid(&bounds, 10.0, 0.5, 5);
+	assert_inside(&bounds, &jittered);
+	assert_eq!(jittered, hex_grid(&bounds, 10.0, 0.5, 5));
+	let mut builder = DiagramBuilder::new(bounds);
+	builder.add_sites(jittered);
+	assert!(builder.build().unwrap().validate().is_ok());
+    }
+
+}
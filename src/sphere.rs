@@ -0,0 +1,892 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * Voronoi diagrams of sites on the unit sphere
+ *
+ * The diagram is the dual of the convex hull of the sites: every triangle of the hull becomes a vertex at the
+ * center of its circumcircle on the sphere, and every site becomes a face around the triangles that meet at it.
+ * Faces use the same half edges as flat diagrams, and their corners are in counter clockwise order when the
+ * sphere is seen from outside. Points are unit vectors, with the z axis through the north pole and the x axis
+ * through latitude and longitude zero.
+ */
+
+use crate::random::Random;
+use crate::vector::{Vector, Vector3, Vector3F64};
+use crate::voronoi::{HalfEdge, VoronoiError};
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/**
+ * The distance below which the centers of neighbouring hull triangles become one vertex,
+ * which happens when four or more sites lie on a circle
+ */
+const VERTEX_TOLERANCE: f64 = 1e-10;
+
+/**
+ * The seed of the random order in which sites are added to the hull
+ */
+const INSERTION_SEED: u64 = 0x5eed;
+
+/**
+ * Converts a latitude and longitude in degrees to a point on the unit sphere
+ */
+pub fn from_lat_long(latitude: f64, longitude: f64) -> Vector3F64 {
+    let (latitude, longitude) = (latitude.to_radians(), longitude.to_radians());
+    Vector3F64::from_values(latitude.cos() * longitude.cos(), latitude.cos() * longitude.sin(), latitude.sin())
+}
+
+/**
+ * Converts a point on the unit sphere to a latitude and longitude in degrees
+ */
+pub fn to_lat_long(point: &Vector3F64) -> (f64, f64) {
+    let latitude = point.get_z().atan2(point.get_x().hypot(point.get_y()));
+    let longitude = point.get_y().atan2(point.get_x());
+    (latitude.to_degrees(), longitude.to_degrees())
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SphereVertex {
+    id: usize,
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+impl SphereVertex {
+
+    /**
+     * The index of this vertex in the diagram
+     */
+    pub fn id(&self) -> usize {
+	self.id
+    }
+
+    pub fn x(&self) -> f64 {
+	self.x
+    }
+
+    pub fn y(&self) -> f64 {
+	self.y
+    }
+
+    pub fn z(&self) -> f64 {
+	self.z
+    }
+
+    pub fn position(&self) -> Vector3F64 {
+	Vector3F64::from_values(self.x, self.y, self.z)
+    }
+
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SphereFace<T = ()> {
+    id: usize,
+    x: f64,
+    y: f64,
+    z: f64,
+    start_id: usize,
+    data: T,
+}
+
+impl<T> SphereFace<T> {
+
+    /**
+     * The index of this face in the diagram, which is also the index of its site
+     */
+    pub fn id(&self) -> usize {
+	self.id
+    }
+
+    /**
+     * The x coordinate of the site, which is a unit vector
+     */
+    pub fn x(&self) -> f64 {
+	self.x
+    }
+
+    /**
+     * The y coordinate of the site, which is a unit vector
+     */
+    pub fn y(&self) -> f64 {
+	self.y
+    }
+
+    /**
+     * The z coordinate of the site, which is a unit vector
+     */
+    pub fn z(&self) -> f64 {
+	self.z
+    }
+
+    /**
+     * The first half edge of the loop around this face
+     */
+    pub fn start_id(&self) -> usize {
+	self.start_id
+    }
+
+    /**
+     * The user data of the site
+     */
+    pub fn data(&self) -> &T {
+	&self.data
+    }
+
+}
+
+/**
+ * A voronoi diagram on the unit sphere, which covers the whole sphere
+ * Every half edge has a twin, and no half edge is a clip boundary.
+ */
+#[derive(Debug, PartialEq)]
+pub struct SphereDiagram<T = ()> {
+    vertices: Vec<SphereVertex>,
+    half_edges: Vec<HalfEdge>,
+    faces: Vec<SphereFace<T>>,
+}
+
+impl<T> SphereDiagram<T> {
+
+    pub fn vertices(&self) -> &[SphereVertex] {
+	&self.vertices
+    }
+
+    pub fn half_edges(&self) -> &[HalfEdge] {
+	&self.half_edges
+    }
+
+    pub fn faces(&self) -> &[SphereFace<T>] {
+	&self.faces
+    }
+
+    /**
+     * The site of a face as a unit vector
+     */
+    pub fn site(&self, face_id: usize) -> Vector3F64 {
+	let face = &self.faces[face_id];
+	Vector3F64::from_values(face.x, face.y, face.z)
+    }
+
+    /**
+     * Walks the half edges around a face, in counter clockwise order seen from outside the sphere
+     */
+    pub fn face_half_edges(&self, face_id: usize) -> SphereFaceHalfEdges<'_, T> {
+	SphereFaceHalfEdges {
+	    diagram: self,
+	    start_id: self.faces[face_id].start_id,
+	    next_id: Some(self.faces[face_id].start_id),
+	}
+    }
+
+    /**
+     * The corners of a face, in counter clockwise order seen from outside the sphere
+     */
+    pub fn face_vertices(&self, face_id: usize) -> impl Iterator<Item = &SphereVertex> {
+	self.face_half_edges(face_id).map(move |half_edge| &self.vertices[half_edge.start_id()])
+    }
+
+    /**
+     * The corners of a face as unit vectors, in counter clockwise order seen from outside the sphere
+     */
+    pub fn face_polygon(&self, face_id: usize) -> Vec<Vector3F64> {
+	self.face_vertices(face_id).map(SphereVertex::position).collect()
+    }
+
+    /**
+     * The area of a face in steradians, so the areas of all faces add up to 4 pi
+     * The face is split into triangles around its site, whose areas follow from the formula of Van Oosterom and Strackee.
+     */
+    pub fn face_area(&self, face_id: usize) -> f64 {
+	let site = self.site(face_id);
+	let corners = self.face_polygon(face_id);
+	(0..corners.len()).map(|i| {
+	    let (first, second) = (&corners[i], &corners[(i + 1) % corners.len()]);
+	    let volume = site.scalar_product(&first.cross_product(second));
+	    let denominator = 1.0 + site.scalar_product(first) + first.scalar_product(second) + second.scalar_product(&site);
+	    2.0 * volume.atan2(denominator)
+	}).sum()
+    }
+
+    /**
+     * The faces sharing an edge with a face, in the order of its half edges
+     */
+    pub fn neighbours(&self, face_id: usize) -> impl Iterator<Item = usize> + '_ {
+	self.face_half_edges(face_id)
+	    .filter_map(move |half_edge| half_edge.twin_id().map(|twin_id| self.half_edges[twin_id].face_id()))
+    }
+
+    /**
+     * Finds the face containing the point in the specified direction, or None if the diagram is empty or the direction is zero
+     * Every step moves to the neighbour whose site is closest to the point, which ends at the face with the closest site.
+     */
+    pub fn locate(&self, x: f64, y: f64, z: f64) -> Option<usize> {
+	let length = (x * x + y * y + z * z).sqrt();
+	if self.faces.is_empty() || !(length.is_finite() && length > 0.0) {
+	    return None;
+	}
+	let point = Vector3F64::from_values(x, y, z);
+	let closeness = |face_id: usize| self.site(face_id).scalar_product(&point);
+	let mut face_id = 0;
+	let mut face_closeness = closeness(face_id);
+	loop {
+	    let closest = self.neighbours(face_id)
+		.map(|neighbour_id| (neighbour_id, closeness(neighbour_id)))
+		.filter(|(_, neighbour_closeness)| *neighbour_closeness > face_closeness)
+		.max_by(|first, second| first.1.partial_cmp(&second.1).unwrap_or(Ordering::Equal));
+	    match closest {
+		Some((neighbour_id, neighbour_closeness)) => {
+		    face_id = neighbour_id;
+		    face_closeness = neighbour_closeness;
+		},
+		None => return Some(face_id),
+	    }
+	}
+    }
+
+    /**
+     * Creates triangles for a globe, with three coordinates for every vertex and a fan of triangles
+     * around the site of every face
+     */
+    pub fn create_triangles(&self) -> (Vec<f32>, Vec<u32>) {
+	let mut vertices = Vec::new();
+	let mut indices = Vec::new();
+	for face in self.faces.iter() {
+	    let site = (vertices.len() / 3) as u32;
+	    vertices.extend_from_slice(&[face.x as f32, face.y as f32, face.z as f32]);
+	    let corners = self.face_polygon(face.id);
+	    for corner in corners.iter() {
+		vertices.extend_from_slice(&[corner.get_x() as f32, corner.get_y() as f32, corner.get_z() as f32]);
+	    }
+	    let count = corners.len() as u32;
+	    for i in 0..count {
+		indices.push(site);
+		indices.push(site + 1 + i);
+		indices.push(site + 1 + (i + 1) % count);
+	    }
+	}
+	(vertices, indices)
+    }
+
+}
+
+/**
+ * Iterator over the half edges of a face of a spherical diagram
+ */
+pub struct SphereFaceHalfEdges<'a, T> {
+    diagram: &'a SphereDiagram<T>,
+    start_id: usize,
+    next_id: Option<usize>,
+}
+
+impl<'a, T> Iterator for SphereFaceHalfEdges<'a, T> {
+    type Item = &'a HalfEdge;
+
+    fn next(&mut self) -> Option<&'a HalfEdge> {
+	let half_edge = &self.diagram.half_edges[self.next_id?];
+	self.next_id = if half_edge.next_id() == self.start_id {
+	    None
+	} else {
+	    Some(half_edge.next_id())
+	};
+	Some(half_edge)
+    }
+
+}
+
+/**
+ * Builds a voronoi diagram of sites on the unit sphere
+ * Sites are directions, which are scaled to unit length. Faces are numbered in the order in which their sites were added.
+ */
+#[derive(Default)]
+pub struct SphereDiagramBuilder<T = ()> {
+    sites: Vec<(f64, f64, f64, T)>,
+}
+
+impl SphereDiagramBuilder {
+
+    /**
+     * Creates a new builder without user data for the sites
+     */
+    pub fn new() -> SphereDiagramBuilder {
+	SphereDiagramBuilder::with_data()
+    }
+
+    pub fn add_site(&mut self, x: f64, y: f64, z: f64) {
+	self.add_site_with_data(x, y, z, ());
+    }
+
+    pub fn add_sites<I>(&mut self, sites: I) where I: IntoIterator<Item = (f64, f64, f64)> {
+	for (x, y, z) in sites {
+	    self.add_site(x, y, z);
+	}
+    }
+
+}
+
+impl<T> SphereDiagramBuilder<T> {
+
+    /**
+     * Creates a new builder with user data for each site
+     */
+    pub fn with_data() -> SphereDiagramBuilder<T> {
+	SphereDiagramBuilder {
+	    sites: Vec::new(),
+	}
+    }
+
+    /**
+     * Adds a site in the specified direction with the specified user data
+     */
+    pub fn add_site_with_data(&mut self, x: f64, y: f64, z: f64, data: T) {
+	self.sites.push((x, y, z, data));
+    }
+
+    /**
+     * Adds all sites and their user data
+     */
+    pub fn add_sites_with_data<I>(&mut self, sites: I) where I: IntoIterator<Item = (f64, f64, f64, T)> {
+	for (x, y, z, data) in sites {
+	    self.add_site_with_data(x, y, z, data);
+	}
+    }
+
+    /**
+     * Builds the diagram from the sites that were added
+     * At least four sites are needed, and they must not all lie on one circle. Sites closer than 1e-10 on the unit
+     * sphere are duplicates. The builder is cleared afterwards.
+     */
+    pub fn build(&mut self) -> Result<SphereDiagram<T>, VoronoiError> {
+	let mut points = Vec::with_capacity(self.sites.len());
+	let mut data = Vec::with_capacity(self.sites.len());
+	for (id, (x, y, z, site_data)) in self.sites.drain(..).enumerate() {
+	    let length = (x * x + y * y + z * z).sqrt();
+	    if !(length.is_finite() && length > 0.0) {
+		return Err(VoronoiError::DegenerateInput(format!("site {} is not a direction", id)));
+	    }
+	    points.push(Vector3F64::from_values(x / length, y / length, z / length));
+	    data.push(site_data);
+	}
+	check_duplicates(&points)?;
+	if points.len() < 4 {
+	    return Err(VoronoiError::DegenerateInput(String::from("a spherical diagram needs at least four sites")));
+	}
+	let triangles = Hull::new(&points).build()?;
+	create_diagram(&points, data, &triangles)
+    }
+
+}
+
+/**
+ * Returns the duplicate with the lowest ids among the sites that are closer than the tolerance for vertices, such as
+ * the poles at different longitudes
+ */
+fn check_duplicates(points: &[Vector3F64]) -> Result<(), VoronoiError> {
+    let mut ids: Vec<usize> = (0..points.len()).collect();
+    ids.sort_by(|first, second| points[*first].get_x().partial_cmp(&points[*second].get_x()).unwrap_or(Ordering::Equal));
+    let mut duplicate: Option<(usize, usize)> = None;
+    for (i, first_id) in ids.iter().enumerate() {
+	let close_ids = ids[i + 1..].iter().take_while(|second_id| points[**second_id].get_x() - points[*first_id].get_x() < VERTEX_TOLERANCE);
+	for second_id in close_ids {
+	    if (points[*first_id] - points[*second_id]).len() < VERTEX_TOLERANCE {
+		let pair = (*first_id.min(second_id), *first_id.max(second_id));
+		duplicate = Some(duplicate.map_or(pair, |other| other.min(pair)));
+	    }
+	}
+    }
+    match duplicate {
+	Some((first_id, second_id)) => Err(VoronoiError::DuplicateSite { first_id, second_id }),
+	None => Ok(()),
+    }
+}
+
+/**
+ * A triangle of the convex hull, with its corners in counter clockwise order seen from outside
+ */
+struct Triangle {
+    corners: [usize; 3],
+    normal: Vector3F64,
+    alive: bool,
+
+    /**
+     * The sites that are not yet in the hull and lie above this triangle
+     */
+    conflicts: Vec<usize>,
+}
+
+/**
+ * Computes the convex hull of points on the sphere by adding them in random order,
+ * keeping for every point that is not yet added one triangle of the hull it lies above
+ */
+struct Hull<'a> {
+    points: &'a [Vector3F64],
+    triangles: Vec<Triangle>,
+
+    /**
+     * The triangle of every directed edge between two corners
+     */
+    edges: HashMap<(usize, usize), usize>,
+    conflicts: Vec<Option<usize>>,
+    visited: Vec<usize>,
+}
+
+impl<'a> Hull<'a> {
+
+    fn new(points: &'a [Vector3F64]) -> Hull<'a> {
+	Hull {
+	    points,
+	    triangles: Vec::new(),
+	    edges: HashMap::new(),
+	    conflicts: vec![None; points.len()],
+	    visited: Vec::new(),
+	}
+    }
+
+    /**
+     * The height of a point above the plane of a triangle, scaled by twice the area of the triangle
+     */
+    fn height(&self, triangle_id: usize, point_id: usize) -> f64 {
+	let triangle = &self.triangles[triangle_id];
+	triangle.normal.scalar_product(&(self.points[point_id] - self.points[triangle.corners[0]]))
+    }
+
+    fn add_triangle(&mut self, corners: [usize; 3]) -> Result<usize, VoronoiError> {
+	let (first, second, third) = (self.points[corners[0]], self.points[corners[1]], self.points[corners[2]]);
+	let triangle_id = self.triangles.len();
+	self.triangles.push(Triangle {
+	    corners,
+	    normal: (second - first).cross_product(&(third - first)),
+	    alive: true,
+	    conflicts: Vec::new(),
+	});
+	self.visited.push(0);
+	for k in 0..3 {
+	    if self.edges.insert((corners[k], corners[(k + 1) % 3]), triangle_id).is_some() {
+		return Err(VoronoiError::topology("an edge of the hull belongs to two triangles"));
+	    }
+	}
+	Ok(triangle_id)
+    }
+
+    fn neighbour(&self, start_id: usize, end_id: usize) -> Result<usize, VoronoiError> {
+	self.edges.get(&(end_id, start_id)).copied().ok_or_else(|| VoronoiError::topology("an edge of the hull has only one triangle"))
+    }
+
+    /**
+     * Assigns a point to the triangle it lies highest above among the specified triangles
+     * Points on the sphere always lie outside the hull of other points on the sphere, so when rounding makes
+     * the point lie below all of them, it is still assigned to the closest one.
+     */
+    fn assign(&mut self, point_id: usize, triangle_ids: &[usize]) {
+	let heights = triangle_ids.iter().map(|triangle_id| (*triangle_id, self.height(*triangle_id, point_id)));
+	if let Some((triangle_id, _)) = heights.max_by(|first, second| first.1.partial_cmp(&second.1).unwrap_or(Ordering::Equal)) {
+	    self.conflicts[point_id] = Some(triangle_id);
+	    self.triangles[triangle_id].conflicts.push(point_id);
+	}
+    }
+
+    /**
+     * Returns the corners of all triangles of the hull
+     */
+    fn build(mut self) -> Result<Vec<[usize; 3]>, VoronoiError> {
+	let mut order: Vec<usize> = (0..self.points.len()).collect();
+	let mut random = Random::new(INSERTION_SEED);
+	for i in (1..order.len()).rev() {
+	    order.swap(i, random.index(i + 1));
+	}
+	let tetrahedron = self.find_tetrahedron(&order)?;
+	for (i, corners) in [[0, 1, 2], [0, 1, 3], [0, 2, 3], [1, 2, 3]].iter().enumerate() {
+	    let (first, second, third) = (tetrahedron[corners[0]], tetrahedron[corners[1]], tetrahedron[corners[2]]);
+	    let other = self.points[tetrahedron[3 - i]];
+	    let normal = (self.points[second] - self.points[first]).cross_product(&(self.points[third] - self.points[first]));
+	    if normal.scalar_product(&(other - self.points[first])) > 0.0 {
+		self.add_triangle([first, third, second])?;
+	    } else {
+		self.add_triangle([first, second, third])?;
+	    }
+	}
+	for point_id in order.iter().filter(|point_id| !tetrahedron.contains(point_id)) {
+	    self.assign(*point_id, &[0, 1, 2, 3]);
+	}
+	for (step, point_id) in order.iter().filter(|point_id| !tetrahedron.contains(point_id)).enumerate() {
+	    self.insert(*point_id, step + 1)?;
+	}
+	Ok(self.triangles.iter().filter(|triangle| triangle.alive).map(|triangle| triangle.corners).collect())
+    }
+
+    /**
+     * Finds four points that span a tetrahedron, preferring large ones
+     */
+    fn find_tetrahedron(&self, order: &[usize]) -> Result<[usize; 4], VoronoiError> {
+	let points = self.points;
+	let farthest = |score: &dyn Fn(usize) -> f64| order.iter().copied()
+	    .map(|point_id| (point_id, score(point_id)))
+	    .max_by(|first, second| first.1.partial_cmp(&second.1).unwrap_or(Ordering::Equal))
+	    .unwrap_or((0, 0.0));
+	let first = order[0];
+	let (second, _) = farthest(&|point_id| (points[point_id] - points[first]).len_squared());
+	let (third, area) = farthest(&|point_id| (points[second] - points[first]).cross_product(&(points[point_id] - points[first])).len_squared());
+	let normal = (points[second] - points[first]).cross_product(&(points[third] - points[first]));
+	let (fourth, volume) = farthest(&|point_id| normal.scalar_product(&(points[point_id] - points[first])).abs());
+	if !(area > 0.0 && volume > VERTEX_TOLERANCE) {
+	    return Err(VoronoiError::DegenerateInput(String::from("all sites lie on one circle")));
+	}
+	Ok([first, second, third, fourth])
+    }
+
+    /**
+     * Adds a point to the hull, replacing the triangles it lies above with a fan of triangles around the point
+     */
+    fn insert(&mut self, point_id: usize, step: usize) -> Result<(), VoronoiError> {
+	let start_id = self.conflicts[point_id].ok_or_else(|| VoronoiError::topology("a site lies inside the hull"))?;
+	let mut visible = vec![start_id];
+	let mut horizon = Vec::new();
+	self.visited[start_id] = step;
+	let mut index = 0;
+	while index < visible.len() {
+	    let corners = self.triangles[visible[index]].corners;
+	    for k in 0..3 {
+		let (start, end) = (corners[k], corners[(k + 1) % 3]);
+		let neighbour_id = self.neighbour(start, end)?;
+		if self.visited[neighbour_id] == step {
+		    continue;
+		}
+		if self.height(neighbour_id, point_id) > 0.0 {
+		    self.visited[neighbour_id] = step;
+		    visible.push(neighbour_id);
+		} else {
+		    horizon.push((start, end));
+		}
+	    }
+	    index += 1;
+	}
+	let mut conflicts = Vec::new();
+	for triangle_id in visible {
+	    let triangle = &mut self.triangles[triangle_id];
+	    triangle.alive = false;
+	    conflicts.append(&mut triangle.conflicts);
+	    let corners = triangle.corners;
+	    for k in 0..3 {
+		self.edges.remove(&(corners[k], corners[(k + 1) % 3]));
+	    }
+	}
+	let new_ids = horizon.iter()
+	    .map(|(start, end)| self.add_triangle([*start, *end, point_id]))
+	    .collect::<Result<Vec<usize>, VoronoiError>>()?;
+	// the new triangles have to form a single fan around the point
+	let mut triangle_id = new_ids[0];
+	for _ in 0..new_ids.len() {
+	    triangle_id = self.neighbour(point_id, self.triangles[triangle_id].corners[0])?;
+	}
+	if triangle_id != new_ids[0] {
+	    return Err(VoronoiError::topology("the triangles below a site do not form a disc"));
+	}
+	for conflict_id in conflicts {
+	    if conflict_id != point_id {
+		self.assign(conflict_id, &new_ids);
+	    }
+	}
+	self.conflicts[point_id] = None;
+	Ok(())
+    }
+
+}
+
+/**
+ * Creates the diagram from the triangles of the hull, merging the centers of triangles that lie on one circle
+ */
+fn create_diagram<T>(points: &[Vector3F64], data: Vec<T>, triangles: &[[usize; 3]]) -> Result<SphereDiagram<T>, VoronoiError> {
+    let mut edges = HashMap::with_capacity(triangles.len() * 3);
+    let mut site_triangles = vec![None; points.len()];
+    for (triangle_id, corners) in triangles.iter().enumerate() {
+	for k in 0..3 {
+	    edges.insert((corners[k], corners[(k + 1) % 3]), triangle_id);
+	    site_triangles[corners[k]] = Some(triangle_id);
+	}
+    }
+    let centers: Vec<Vector3F64> = triangles.iter().map(|corners| {
+	let first = points[corners[0]];
+	let normal = (points[corners[1]] - first).cross_product(&(points[corners[2]] - first));
+	normal / normal.len()
+    }).collect();
+    let mut parents: Vec<usize> = (0..triangles.len()).collect();
+    for ((start, end), triangle_id) in edges.iter() {
+	let neighbour_id = *edges.get(&(*end, *start)).ok_or_else(|| VoronoiError::topology("an edge of the hull has only one triangle"))?;
+	if (centers[*triangle_id] - centers[neighbour_id]).len() <= VERTEX_TOLERANCE {
+	    let (root, neighbour_root) = (find_root(&mut parents, *triangle_id), find_root(&mut parents, neighbour_id));
+	    parents[root.max(neighbour_root)] = root.min(neighbour_root);
+	}
+    }
+    let mut vertex_ids = vec![None; triangles.len()];
+    let mut vertices = Vec::new();
+    let triangle_vertices: Vec<usize> = (0..triangles.len()).map(|triangle_id| {
+	let root = find_root(&mut parents, triangle_id);
+	*vertex_ids[root].get_or_insert_with(|| {
+	    let center = centers[root];
+	    vertices.push(SphereVertex { id: vertices.len(), x: center.get_x(), y: center.get_y(), z: center.get_z() });
+	    vertices.len() - 1
+	})
+    }).collect();
+
+    let mut half_edges = Vec::new();
+    let mut half_edge_ids = HashMap::with_capacity(triangles.len() * 3);
+    let mut faces = Vec::with_capacity(points.len());
+    for (site_id, site_data) in data.into_iter().enumerate() {
+	let first_id = site_triangles[site_id].ok_or_else(|| VoronoiError::topology("a site is not a corner of the hull"))?;
+	// walks the triangles around the site, each edge between two of them separates the site from a neighbour
+	let mut corners = Vec::new();
+	let mut triangle_id = first_id;
+	loop {
+	    let triangle = &triangles[triangle_id];
+	    let k = triangle.iter().position(|corner| *corner == site_id).unwrap_or(0);
+	    let neighbour = triangle[(k + 2) % 3];
+	    let next_id = edges[&(site_id, neighbour)];
+	    if triangle_vertices[triangle_id] != triangle_vertices[next_id] {
+		corners.push((triangle_vertices[triangle_id], neighbour));
+	    }
+	    triangle_id = next_id;
+	    if triangle_id == first_id {
+		break;
+	    }
+	}
+	if corners.len() < 3 {
+	    return Err(VoronoiError::topology("a spherical face has less than three corners"));
+	}
+	let start_id = half_edges.len();
+	let count = corners.len();
+	for (i, (vertex_id, neighbour)) in corners.into_iter().enumerate() {
+	    half_edge_ids.insert((site_id, neighbour), half_edges.len());
+	    half_edges.push(HalfEdge {
+		id: half_edges.len(),
+		face_id: site_id,
+		start_id: vertex_id,
+		twin_id: None,
+		prev_id: start_id + (i + count - 1) % count,
+		next_id: start_id + (i + 1) % count,
+		clip_boundary: false,
+		wrap: (0, 0),
+	    });
+	}
+	let site = points[site_id];
+	faces.push(SphereFace {
+	    id: site_id,
+	    x: site.get_x(),
+	    y: site.get_y(),
+	    z: site.get_z(),
+	    start_id,
+	    data: site_data,
+	});
+    }
+    for (&(site_id, neighbour), half_edge_id) in half_edge_ids.iter() {
+	half_edges[*half_edge_id].twin_id = Some(*half_edge_ids.get(&(neighbour, site_id))
+						  .ok_or_else(|| VoronoiError::topology("a spherical edge has no twin"))?);
+    }
+    Ok(SphereDiagram {
+	vertices,
+	half_edges,
+	faces,
+    })
+}
+
+fn find_root(parents: &mut [usize], id: usize) -> usize {
+    let mut root = id;
+    while parents[root] != root {
+	root = parents[root];
+    }
+    let mut id = id;
+    while parents[id] != root {
+	let parent = parents[id];
+	parents[id] = root;
+	id = parent;
+    }
+    root
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use std::f64::consts::PI;
+
+    fn random_sites(count: usize, seed: u64) -> Vec<(f64, f64, f64)> {
+	let mut random = Random::new(seed);
+	(0..count).map(|_| {
+	    let z = random.range(-1.0, 1.0);
+	    let angle = random.range(0.0, 2.0 * PI);
+	    let radius = (1.0 - z * z).sqrt();
+	    (radius * angle.cos(), radius * angle.sin(), z)
+	}).collect()
+    }
+
+    /**
+     * Checks the half edge structure, the orientation of the faces and that the faces cover the sphere once
+     */
+    fn assert_valid<T>(diagram: &SphereDiagram<T>) {
+	for half_edge in diagram.half_edges().iter() {
+	    let twin = &diagram.half_edges()[half_edge.twin_id().unwrap()];
+	    assert_eq!(Some(half_edge.id()), twin.twin_id());
+	    assert_ne!(half_edge.face_id(), twin.face_id());
+	    assert_eq!(twin.start_id(), diagram.half_edges()[half_edge.next_id()].start_id());
+	    assert_eq!(half_edge.id(), diagram.half_edges()[half_edge.next_id()].prev_id());
+	}
+	let edge_count = diagram.half_edges().len() / 2;
+	assert_eq!(2, diagram.vertices().len() + diagram.faces().len() - edge_count);
+	let mut total = 0.0;
+	for face in diagram.faces().iter() {
+	    let area = diagram.face_area(face.id());
+	    assert!(area > 0.0);
+	    total += area;
+	    // the site lies on the inner side of every edge
+	    let site = diagram.site(face.id());
+	    let corners = diagram.face_polygon(face.id());
+	    for i in 0..corners.len() {
+		assert!(corners[i].cross_product(&corners[(i + 1) % corners.len()]).scalar_product(&site) > 0.0);
+	    }
+	}
+	assert!((total - 4.0 * PI).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_lat_long() {
+	let point = from_lat_long(52.5, 13.4);
+	assert!((point.len() - 1.0).abs() < 1e-15);
+	let (latitude, longitude) = to_lat_long(&point);
+	assert!((latitude - 52.5).abs() < 1e-12);
+	assert!((longitude - 13.4).abs() < 1e-12);
+	assert!((from_lat_long(90.0, 0.0).get_z() - 1.0).abs() < 1e-15);
+	assert!((from_lat_long(0.0, 90.0).get_y() - 1.0).abs() < 1e-15);
+    }
+
+    #[test]
+    fn test_octahedron() {
+	let mut builder = SphereDiagramBuilder::new();
+	builder.add_sites(vec![(1.0, 0.0, 0.0), (-2.0, 0.0, 0.0), (0.0, 1.0, 0.0), (0.0, -1.0, 0.0), (0.0, 0.0, 3.0), (0.0, 0.0, -1.0)]);
+	let diagram = builder.build().unwrap();
+	assert_valid(&diagram);
+	// the cells are the faces of a cube
+	assert_eq!(8, diagram.vertices().len());
+	assert_eq!(24, diagram.half_edges().len());
+	assert_eq!(-1.0, diagram.faces()[1].x());
+	for face in diagram.faces().iter() {
+	    assert_eq!(4, diagram.face_vertices(face.id()).count());
+	    assert!((diagram.face_area(face.id()) - 4.0 * PI / 6.0).abs() < 1e-12);
+	    assert_eq!(4, diagram.neighbours(face.id()).count());
+	}
+	for vertex in diagram.vertices().iter() {
+	    let expected = 1.0 / 3.0f64.sqrt();
+	    assert!((vertex.x().abs() - expected).abs() < 1e-12 && (vertex.y().abs() - expected).abs() < 1e-12 && (vertex.z().abs() - expected).abs() < 1e-12);
+	}
+    }
+
+    #[test]
+    fn test_random_sites() {
+	let mut builder = SphereDiagramBuilder::with_data();
+	builder.add_sites_with_data(random_sites(2000, 1).into_iter().enumerate().map(|(id, (x, y, z))| (x, y, z, id)));
+	let diagram = builder.build().unwrap();
+	assert_eq!(2000, diagram.faces().len());
+	assert!(diagram.faces().iter().all(|face| *face.data() == face.id()));
+	// every triangle of the hull becomes a vertex
+	assert_eq!(2 * 2000 - 4, diagram.vertices().len());
+	assert_valid(&diagram);
+    }
+
+    #[test]
+    fn test_lat_long_grid() {
+	// the sites on each circle of latitude and each pair of neighbouring meridians lie on common circles
+	let mut builder = SphereDiagramBuilder::new();
+	builder.add_site(0.0, 0.0, 1.0);
+	builder.add_site(0.0, 0.0, -1.0);
+	for latitude in (-80..=80).step_by(10) {
+	    for longitude in (-180..180).step_by(15) {
+		let point = from_lat_long(latitude as f64, longitude as f64);
+		builder.add_site(point.get_x(), point.get_y(), point.get_z());
+	    }
+	}
+	let diagram = builder.build().unwrap();
+	assert_valid(&diagram);
+	assert_eq!(24, diagram.face_vertices(0).count());
+	for face in diagram.faces().iter().skip(2) {
+	    assert_eq!(4, diagram.face_vertices(face.id()).count());
+	}
+    }
+
+    #[test]
+    fn test_lat_long_poles() {
+	// the poles at different longitudes differ by rounding
+	let mut builder = SphereDiagramBuilder::new();
+	for latitude in (-90..=90).step_by(10) {
+	    for longitude in (-180..180).step_by(15) {
+		let point = from_lat_long(latitude as f64, longitude as f64);
+		builder.add_site(point.get_x(), point.get_y(), point.get_z());
+	    }
+	}
+	assert_eq!(Err(VoronoiError::DuplicateSite { first_id: 0, second_id: 1 }), builder.build());
+	for latitude in (-90i32..=90).step_by(10) {
+	    let longitudes = if latitude.abs() == 90 { 0..1 } else { 0..24 };
+	    for longitude in longitudes {
+		let point = from_lat_long(latitude as f64, (15 * longitude - 180) as f64);
+		builder.add_site(point.get_x(), point.get_y(), point.get_z());
+	    }
+	}
+	let diagram = builder.build().unwrap();
+	assert_valid(&diagram);
+	assert_eq!(24, diagram.face_vertices(0).count());
+	assert_eq!(24, diagram.face_vertices(diagram.faces().len() - 1).count());
+    }
+
+    #[test]
+    fn test_locate() {
+	let mut builder = SphereDiagramBuilder::new();
+	builder.add_sites(random_sites(500, 2));
+	let diagram = builder.build().unwrap();
+	for (x, y, z) in random_sites(1000, 3) {
+	    let point = Vector3F64::from_values(x, y, z);
+	    let closest_id = (0..diagram.faces().len())
+		.max_by(|first, second| diagram.site(*first).scalar_product(&point).partial_cmp(&diagram.site(*second).scalar_product(&point)).unwrap())
+		.unwrap();
+	    assert_eq!(Some(closest_id), diagram.locate(2.0 * x, 2.0 * y, 2.0 * z));
+	}
+	assert_eq!(None, diagram.locate(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_create_triangles() {
+	let mut builder = SphereDiagramBuilder::new();
+	builder.add_sites(random_sites(100, 4));
+	let diagram = builder.build().unwrap();
+	let (vertices, indices) = diagram.create_triangles();
+	assert_eq!(3 * (100 + diagram.half_edges().len()), vertices.len());
+	assert_eq!(3 * diagram.half_edges().len(), indices.len());
+	assert!(indices.iter().all(|index| (*index as usize) < vertices.len() / 3));
+    }
+
+    #[test]
+    fn test_invalid_sites() {
+	let mut builder = SphereDiagramBuilder::new();
+	builder.add_sites(vec![(1.0, 0.0, 0.0), (0.0, 1.0, 0.0), (0.0, 0.0, 1.0)]);
+	assert!(matches!(builder.build(), Err(VoronoiError::DegenerateInput(_))));
+	builder.add_sites((0..10).map(|i| (f64::cos(i as f64), f64::sin(i as f64), 0.0)));
+	assert!(matches!(builder.build(), Err(VoronoiError::DegenerateInput(_))));
+	builder.add_sites(vec![(1.0, 0.0, 0.0), (0.0, 1.0, 0.0), (0.0, 0.0, 1.0), (0.0, 0.0, 0.0)]);
+	assert!(matches!(builder.build(), Err(VoronoiError::DegenerateInput(_))));
+	builder.add_sites(vec![(1.0, 0.0, 0.0), (0.0, 1.0, 0.0), (0.0, 0.0, 1.0), (0.0, 0.0, -1.0), (0.0, 2.0, 0.0)]);
+	assert_eq!(Err(VoronoiError::DuplicateSite { first_id: 1, second_id: 4 }), builder.build());
+    }
+
+}
@@ -0,0 +1,322 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * Draws voronoi diagrams as SVG images, for debugging without a graphics context
+ */
+
+use crate::graphics::Color;
+use crate::periodic::SeamMode;
+use crate::vector::Vector2;
+use crate::voronoi::Diagram;
+
+use std::fmt::Write as FormatWrite;
+use std::io::Write;
+
+/**
+ * Selects what is drawn in an SVG image of a diagram
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct SvgOptions {
+
+    /**
+     * The width of the image in pixels, the height follows from the bounds of the diagram
+     */
+    pub width: f64,
+
+    /**
+     * Draw the outlines of the faces
+     */
+    pub cells: bool,
+
+    /**
+     * Draw a dot at every site
+     */
+    pub sites: bool,
+
+    /**
+     * Label every site with the id of its face
+     */
+    pub face_ids: bool,
+
+    /**
+     * Label every vertex with its id
+     */
+    pub vertex_ids: bool,
+
+    /**
+     * Draw every half edge as an arrow on the side of its face
+     */
+    pub half_edges: bool,
+
+    /**
+     * Connect the middle of every half edge with the middle of its twin
+     */
+    pub twins: bool,
+
+    /**
+     * The fill color of each face, indexed by face id; faces without a color are not filled
+     */
+    pub fills: Vec<Option<Color>>,
+
+    /**
+     * How the cells on the seams of periodic diagrams are drawn
+     */
+    pub seams: SeamMode,
+}
+
+impl Default for SvgOptions {
+
+    fn default() -> SvgOptions {
+	SvgOptions {
+	    width: 800.0,
+	    cells: true,
+	    sites: true,
+	    face_ids: false,
+	    vertex_ids: false,
+	    half_edges: false,
+	    twins: false,
+	    fills: Vec::new(),
+	    seams: SeamMode::Split,
+	}
+    }
+
+}
+
+/**
+ * Converts a color to an SVG paint and opacity
+ */
+fn format_color(color: &Color) -> (String, f32) {
+    let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    (format!("#{:02x}{:02x}{:02x}", channel(color.red), channel(color.green), channel(color.blue)), color.alpha)
+}
+
+impl<T> Diagram<T> {
+
+    /**
+     * Writes an SVG image of the diagram
+     */
+    pub fn write_svg<W>(&self, writer: &mut W, options: &SvgOptions) -> std::io::Result<()> where W: Write {
+	writer.write_all(self.to_svg(options).as_bytes())
+    }
+
+    /**
+     * Creates an SVG image of the diagram
+     */
+    pub fn to_svg(&self, options: &SvgOptions) -> String {
+	let bounds = self.bounds();
+	let scale = if bounds.width() > 0.0 { options.width / bounds.width() } else { 1.0 };
+	let transform = |x: f64, y: f64| ((x - bounds.left()) * scale, (y - bounds.top()) * scale);
+	let height = bounds.height() * scale;
+	let mut svg = String::new();
+
+	// writing to a string can not fail
+	let _ = writeln!(svg, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.2}\" height=\"{:.2}\" viewBox=\"0 0 {:.2} {:.2}\">",
+			 options.width, height, options.width, height);
+	let _ = writeln!(svg, "<defs><marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" markerWidth=\"6\" markerHeight=\"6\" orient=\"auto\">\
+			       <path d=\"M 0 0 L 10 5 L 0 10 z\" fill=\"#1f5fbf\"/></marker></defs>");
+	let _ = writeln!(svg, "<rect x=\"0\" y=\"0\" width=\"{:.2}\" height=\"{:.2}\" fill=\"white\"/>", options.width, height);
+
+	if options.cells || options.fills.iter().any(Option::is_some) {
+	    let _ = writeln!(svg, "<g id=\"cells\" stroke=\"black\" stroke-width=\"1\" stroke-linejoin=\"round\">");
+	    for face_id in 0..self.faces().len() {
+		let paint = match options.fills.get(face_id).and_then(Option::as_ref) {
+		    Some(color) => {
+			let (paint, opacity) = format_color(color);
+			format!("fill=\"{}\" fill-opacity=\"{}\"", paint, opacity)
+		    },
+		    None => String::from("fill=\"none\""),
+		};
+		let stroke = if options.cells { "" } else { " stroke=\"none\"" };
+		if self.faces()[face_id].hole_start_ids().is_empty() {
+		    for polygon in self.seam_polygons(face_id, options.seams) {
+			let points: Vec<String> = polygon.iter().map(|point| {
+			    let (x, y) = transform(point.get_x(), point.get_y());
+			    format!("{:.2},{:.2}", x, y)
+			}).collect();
+			let _ = writeln!(svg, "<polygon points=\"{}\" {}{}/>", points.join(" "), paint, stroke);
+		    }
+		} else {
+		    // faces with holes are drawn as a path with one closed part for the outside and one for every hole
+		    let points: Vec<String> = self.face_vertices(face_id).map(|vertex| {
+			let (x, y) = transform(vertex.x(), vertex.y());
+			format!("{:.2},{:.2}", x, y)
+		    }).collect();
+		    let mut path = format!("M {} Z", points.join(" L "));
+		    for hole in self.hole_polygons(face_id) {
+			let points: Vec<String> = hole.iter().map(|point| {
+			    let (x, y) = transform(point.get_x(), point.get_y());
+			    format!("{:.2},{:.2}", x, y)
+			}).collect();
+			let _ = write!(path, " M {} Z", points.join(" L "));
+		    }
+		    let _ = writeln!(svg, "<path d=\"{}\" fill-rule=\"evenodd\" {}{}/>", path, paint, stroke);
+		}
+	    }
+	    let _ = writeln!(svg, "</g>");
+	}
+
+	if options.half_edges || options.twins {
+	    // half edges are drawn shortened and moved towards their face, so both halves of an edge are visible
+	    let offset: f64 = 4.0;
+	    let half_edge_lines: Vec<((f64, f64), (f64, f64))> = self.half_edges().iter().map(|half_edge| {
+		let start = self.half_edge_start(half_edge.id());
+		let end = self.half_edge_start(half_edge.next_id());
+		let (x1, y1) = transform(start.get_x(), start.get_y());
+		let (x2, y2) = transform(end.get_x(), end.get_y());
+		let length = (x2 - x1).hypot(y2 - y1).max(f64::MIN_POSITIVE);
+		let (dx, dy) = ((x2 - x1) / length, (y2 - y1) / length);
+		let shift = offset.min(length / 4.0);
+		let (nx, ny) = (- dy * shift, dx * shift);
+		let trim = length * 0.15;
+		((x1 + dx * trim + nx, y1 + dy * trim + ny), (x2 - dx * trim + nx, y2 - dy * trim + ny))
+	    }).collect();
+	    if options.half_edges {
+		let _ = writeln!(svg, "<g id=\"half-edges\" stroke=\"#1f5fbf\" stroke-width=\"1\" marker-end=\"url(#arrow)\">");
+		for (id, ((x1, y1), (x2, y2))) in half_edge_lines.iter().enumerate() {
+		    let _ = writeln!(svg, "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\"><title>half edge {}</title></line>", x1, y1, x2, y2, id);
+		}
+		let _ = writeln!(svg, "</g>");
+	    }
+	    if options.twins {
+		let _ = writeln!(svg, "<g id=\"twins\" stroke=\"#bf1f5f\" stroke-width=\"1\" stroke-dasharray=\"2,2\">");
+		for half_edge in self.half_edges().iter() {
+		    if let Some(twin_id) = half_edge.twin_id().filter(|twin_id| half_edge.id() < *twin_id) {
+			let middle = |((x1, y1), (x2, y2)): ((f64, f64), (f64, f64))| ((x1 + x2) / 2.0, (y1 + y2) / 2.0);
+			let (x1, y1) = middle(half_edge_lines[half_edge.id()]);
+			let (x2, y2) = middle(half_edge_lines[twin_id]);
+			let _ = writeln!(svg, "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\"/>", x1, y1, x2, y2);
+		    }
+		}
+		let _ = writeln!(svg, "</g>");
+	    }
+	}
+
+	if options.sites {
+	    let _ = writeln!(svg, "<g id=\"sites\" fill=\"#bf1f1f\">");
+	    for face in self.faces().iter() {
+		let (x, y) = transform(face.x(), face.y());
+		let _ = writeln!(svg, "<circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"3\"/>", x, y);
+	    }
+	    let _ = writeln!(svg, "</g>");
+	}
+
+	if options.face_ids {
+	    let _ = writeln!(svg, "<g id=\"face-ids\" font-family=\"sans-serif\" font-size=\"12\" fill=\"#bf1f1f\">");
+	    for face in self.faces().iter() {
+		let (x, y) = transform(face.x(), face.y());
+		let _ = writeln!(svg, "<text x=\"{:.2}\" y=\"{:.2}\">{}</text>", x + 4.0, y - 4.0, face.id());
+	    }
+	    let _ = writeln!(svg, "</g>");
+	}
+
+	if options.vertex_ids {
+	    let _ = writeln!(svg, "<g id=\"vertex-ids\" font-family=\"sans-serif\" font-size=\"10\" fill=\"#3f3f3f\">");
+	    for vertex in self.vertices().iter() {
+		let (x, y) = transform(vertex.x(), vertex.y());
+		let _ = writeln!(svg, "<text x=\"{:.2}\" y=\"{:.2}\">{}</text>", x + 2.0, y - 2.0, vertex.id());
+	    }
+	    let _ = writeln!(svg, "</g>");
+	}
+
+	let _ = writeln!(svg, "</svg>");
+	svg
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::clip::ClipPolygon;
+    use crate::geom::BoundingBox;
+    use crate::voronoi::DiagramBuilder;
+
+    fn create_diagram() -> Diagram {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(100.0, 300.0, 0.0, 100.0));
+	builder.add_sites(vec![(150.0, 50.0), (250.0, 25.0), (250.0, 75.0)]);
+	builder.build().unwrap()
+    }
+
+    #[test]
+    fn test_svg_default_layers() {
+	let diagram = create_diagram();
+	let svg = diagram.to_svg(&SvgOptions::default());
+	assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"800.00\" height=\"400.00\""));
+	assert!(svg.ends_with("</svg>\n"));
+	assert_eq!(3, svg.matches("<polygon").count());
+	assert_eq!(3, svg.matches("<circle").count());
+	assert!(svg.contains("<circle cx=\"200.00\" cy=\"200.00\" r=\"3\"/>"));
+	assert!(!svg.contains("id=\"half-edges\""));
+	assert!(!svg.contains("id=\"twins\""));
+	assert!(!svg.contains("<text"));
+    }
+
+    #[test]
+    fn test_svg_all_layers() {
+	let diagram = create_diagram();
+	let options = SvgOptions {
+	    face_ids: true,
+	    vertex_ids: true,
+	    half_edges: true,
+	    twins: true,
+	    ..SvgOptions::default()
+	};
+	let svg = diagram.to_svg(&options);
+	assert_eq!(diagram.half_edges().len(), svg.matches("<title>half edge").count());
+	let twin_count = diagram.half_edges().iter().filter(|half_edge| half_edge.twin_id().is_some()).count();
+	assert_eq!(twin_count / 2 + diagram.half_edges().len(), svg.matches("<line").count());
+	assert_eq!(diagram.faces().len() + diagram.vertices().len(), svg.matches("<text").count());
+    }
+
+    #[test]
+    fn test_svg_fills() {
+	let diagram = create_diagram();
+	let options = SvgOptions {
+	    cells: false,
+	    sites: false,
+	    fills: vec![None, Some(Color { red: 1.0, green: 0.5, blue: 0.0, alpha: 0.5 })],
+	    ..SvgOptions::default()
+	};
+	let svg = diagram.to_svg(&options);
+	assert_eq!(3, svg.matches("<polygon").count());
+	assert_eq!(1, svg.matches("fill=\"#ff8000\" fill-opacity=\"0.5\" stroke=\"none\"").count());
+	assert_eq!(2, svg.matches("fill=\"none\" stroke=\"none\"").count());
+	assert!(!svg.contains("<circle"));
+    }
+
+    #[test]
+    fn test_svg_holes() {
+	let mut polygon = ClipPolygon::new(vec![(100.0, 0.0), (300.0, 0.0), (300.0, 100.0), (100.0, 100.0)]).unwrap();
+	polygon.add_hole(vec![(120.0, 40.0), (140.0, 40.0), (140.0, 60.0), (120.0, 60.0)]).unwrap();
+	let diagram = create_diagram().clip(&polygon).unwrap();
+	let svg = diagram.to_svg(&SvgOptions::default());
+	assert_eq!(2, svg.matches("<polygon").count());
+	assert!(svg.contains("<path d=\"M 362.50,0.00 L 412.50,200.00 L 362.50,400.00 L 0.00,400.00 L 0.00,0.00 Z \
+			      M 80.00,240.00 L 160.00,240.00 L 160.00,160.00 L 80.00,160.00 Z\" fill-rule=\"evenodd\" fill=\"none\"/>"));
+    }
+
+    #[test]
+    fn test_write_svg() {
+	let diagram = create_diagram();
+	let mut bytes = Vec::new();
+	diagram.write_svg(&mut bytes, &SvgOptions::default()).unwrap();
+	assert_eq!(diagram.to_svg(&SvgOptions::default()).into_bytes(), bytes);
+    }
+
+}
@@ -0,0 +1,551 @@
+/*
+ * This file is part of pop.
+ *
+ * Pop is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pop is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with pop.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/**
+ * Checks the structural invariants of voronoi diagrams
+ */
+
+use crate::float::{relative_tolerance, to_vector2_f64, Float};
+use crate::geom::is_clockwise;
+use crate::periodic::Wrap;
+use crate::vector::{Vector2, Vector2F64};
+use crate::voronoi::{Diagram, HalfEdge};
+
+use std::fmt::{Display, Error as FormatError, Formatter};
+
+/**
+ * A broken invariant of a diagram
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum Violation {
+
+    /**
+     * A half edge refers to a face that does not exist
+     */
+    MissingFace {
+	half_edge_id: usize,
+	face_id: usize,
+    },
+
+    /**
+     * A half edge refers to a vertex that does not exist
+     */
+    MissingVertex {
+	half_edge_id: usize,
+	vertex_id: usize,
+    },
+
+    /**
+     * A half edge or face refers to a half edge that does not exist
+     */
+    MissingHalfEdge {
+	half_edge_id: usize,
+	missing_id: usize,
+    },
+
+    /**
+     * The previous half edge of the next half edge is not the half edge itself
+     */
+    NextNotInverse {
+	half_edge_id: usize,
+	next_id: usize,
+	next_prev_id: usize,
+    },
+
+    /**
+     * The next half edge of the previous half edge is not the half edge itself
+     */
+    PrevNotInverse {
+	half_edge_id: usize,
+	prev_id: usize,
+	prev_next_id: usize,
+    },
+
+    /**
+     * A half edge and its next half edge belong to different faces
+     */
+    NextInOtherFace {
+	half_edge_id: usize,
+	next_id: usize,
+    },
+
+    /**
+     * The twin of the twin of a half edge is not the half edge itself
+     */
+    TwinNotSymmetric {
+	half_edge_id: usize,
+	twin_id: usize,
+	twin_twin_id: Option<usize>,
+    },
+
+    /**
+     * A half edge and its twin belong to the same face, which is allowed in periodic diagrams
+     */
+    TwinInSameFace {
+	half_edge_id: usize,
+	twin_id: usize,
+    },
+
+    /**
+     * A half edge does not end where its twin starts
+     */
+    TwinNotAligned {
+	half_edge_id: usize,
+	twin_id: usize,
+    },
+
+    /**
+     * The half edge a face starts at belongs to another face
+     */
+    ForeignStart {
+	face_id: usize,
+	half_edge_id: usize,
+    },
+
+    /**
+     * Following the next half edges from the start of a face does not lead back to the start
+     */
+    OpenLoop {
+	face_id: usize,
+    },
+
+    /**
+     * A half edge can not be reached from the start of its face
+     */
+    Unreachable {
+	half_edge_id: usize,
+    },
+
+    /**
+     * The loop around a face turns clockwise between a half edge and its next half edge
+     */
+    ClockwiseTurn {
+	face_id: usize,
+	half_edge_id: usize,
+    },
+
+    /**
+     * The site of a face lies outside its loop of half edges
+     */
+    SiteOutsideCell {
+	face_id: usize,
+    },
+
+    /**
+     * The areas of two faces overlap
+     */
+    Overlap {
+	first_id: usize,
+	second_id: usize,
+    },
+}
+
+impl Display for Violation {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FormatError> {
+	match self {
+	    Violation::MissingFace { half_edge_id, face_id } => write!(f, "half edge {} refers to face {}, which does not exist", half_edge_id, face_id),
+	    Violation::MissingVertex { half_edge_id, vertex_id } => write!(f, "half edge {} refers to vertex {}, which does not exist", half_edge_id, vertex_id),
+	    Violation::MissingHalfEdge { half_edge_id, missing_id } => write!(f, "half edge {} refers to half edge {}, which does not exist", half_edge_id, missing_id),
+	    Violation::NextNotInverse { half_edge_id, next_id, next_prev_id } => write!(f, "half edge {} has next half edge {}, whose previous half edge is {}", half_edge_id, next_id, next_prev_id),
+	    Violation::PrevNotInverse { half_edge_id, prev_id, prev_next_id } => write!(f, "half edge {} has previous half edge {}, whose next half edge is {}", half_edge_id, prev_id, prev_next_id),
+	    Violation::NextInOtherFace { half_edge_id, next_id } => write!(f, "half edge {} and its next half edge {} belong to different faces", half_edge_id, next_id),
+	    Violation::TwinNotSymmetric { half_edge_id, twin_id, twin_twin_id } => write!(f, "half edge {} has twin {}, whose twin is {:?}", half_edge_id, twin_id, twin_twin_id),
+	    Violation::TwinInSameFace { half_edge_id, twin_id } => write!(f, "half edge {} and its twin {} belong to the same face", half_edge_id, twin_id),
+	    Violation::TwinNotAligned { half_edge_id, twin_id } => write!(f, "half edge {} does not end where its twin {} starts", half_edge_id, twin_id),
+	    Violation::ForeignStart { face_id, half_edge_id } => write!(f, "face {} starts at half edge {}, which does not belong to it", face_id, half_edge_id),
+	    Violation::OpenLoop { face_id } => write!(f, "the loop around face {} does not close", face_id),
+	    Violation::Unreachable { half_edge_id } => write!(f, "half edge {} is not part of the loop around its face", half_edge_id),
+	    Violation::ClockwiseTurn { face_id, half_edge_id } => write!(f, "the loop around face {} turns clockwise after half edge {}", face_id, half_edge_id),
+	    Violation::SiteOutsideCell { face_id } => write!(f, "the site of face {} lies outside its cell", face_id),
+	    Violation::Overlap { first_id, second_id } => write!(f, "faces {} and {} overlap", first_id, second_id),
+	}
+    }
+
+}
+
+impl<T, F> Diagram<T, F> where F: Float {
+
+    /**
+     * Checks the structure and the geometry of the diagram, reporting every broken invariant
+     */
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+	let mut violations = Vec::new();
+	if self.check_references(&mut violations) {
+	    self.check_half_edges(&mut violations);
+	    let closed = self.check_loops(&mut violations);
+	    self.check_geometry(&closed, &mut violations);
+	}
+	if violations.is_empty() {
+	    Ok(())
+	} else {
+	    Err(violations)
+	}
+    }
+
+    /**
+     * Checks only the half edge structure, which has to be consistent before the diagram can be traversed
+     */
+    pub fn validate_topology(&self) -> Result<(), Vec<Violation>> {
+	let mut violations = Vec::new();
+	if self.check_references(&mut violations) {
+	    self.check_half_edges(&mut violations);
+	    self.check_loops(&mut violations);
+	}
+	if violations.is_empty() {
+	    Ok(())
+	} else {
+	    Err(violations)
+	}
+    }
+
+    /**
+     * Checks that all ids refer to existing elements, returns false if any do not
+     */
+    fn check_references(&self, violations: &mut Vec<Violation>) -> bool {
+	let count = violations.len();
+	let half_edge_count = self.half_edges().len();
+	for half_edge in self.half_edges().iter() {
+	    let half_edge_id = half_edge.id();
+	    if half_edge.face_id() >= self.faces().len() {
+		violations.push(Violation::MissingFace { half_edge_id, face_id: half_edge.face_id() });
+	    }
+	    if half_edge.start_id() >= self.vertices().len() {
+		violations.push(Violation::MissingVertex { half_edge_id, vertex_id: half_edge.start_id() });
+	    }
+	    for missing_id in [half_edge.prev_id(), half_edge.next_id()].iter().chain(half_edge.twin_id().iter()) {
+		if *missing_id >= half_edge_count {
+		    violations.push(Violation::MissingHalfEdge { half_edge_id, missing_id: *missing_id });
+		}
+	    }
+	}
+	for face in self.faces().iter() {
+	    for start_id in std::iter::once(&face.start_id()).chain(face.hole_start_ids().iter()) {
+		if *start_id >= half_edge_count {
+		    violations.push(Violation::ForeignStart { face_id: face.id(), half_edge_id: *start_id });
+		}
+	    }
+	}
+	violations.len() == count
+    }
+
+    fn check_half_edges(&self, violations: &mut Vec<Violation>) {
+	let half_edges = self.half_edges();
+	for half_edge in half_edges.iter() {
+	    let half_edge_id = half_edge.id();
+	    let next = &half_edges[half_edge.next_id()];
+	    let prev = &half_edges[half_edge.prev_id()];
+	    if next.prev_id() != half_edge_id {
+		violations.push(Violation::NextNotInverse { half_edge_id, next_id: next.id(), next_prev_id: next.prev_id() });
+	    }
+	    if prev.next_id() != half_edge_id {
+		violations.push(Violation::PrevNotInverse { half_edge_id, prev_id: prev.id(), prev_next_id: prev.next_id() });
+	    }
+	    if next.face_id() != half_edge.face_id() {
+		violations.push(Violation::NextInOtherFace { half_edge_id, next_id: next.id() });
+	    }
+	    if let Some(twin_id) = half_edge.twin_id() {
+		let twin = &half_edges[twin_id];
+		if twin.twin_id() != Some(half_edge_id) {
+		    violations.push(Violation::TwinNotSymmetric { half_edge_id, twin_id, twin_twin_id: twin.twin_id() });
+		}
+		// a cell of a periodic diagram can border its own copy across a seam
+		if twin.face_id() == half_edge.face_id() && self.wrap() == Wrap::None {
+		    violations.push(Violation::TwinInSameFace { half_edge_id, twin_id });
+		}
+		if twin.start_id() != next.start_id() {
+		    violations.push(Violation::TwinNotAligned { half_edge_id, twin_id });
+		}
+	    }
+	}
+    }
+
+    /**
+     * Walks the loop around every face and around its holes, returns which faces have only closed loops
+     */
+    fn check_loops(&self, violations: &mut Vec<Violation>) -> Vec<bool> {
+	let half_edges = self.half_edges();
+	let mut visited = vec![false; half_edges.len()];
+	let mut closed = vec![true; self.faces().len()];
+	for face in self.faces().iter() {
+	    for start_id in std::iter::once(&face.start_id()).chain(face.hole_start_ids().iter()) {
+		if half_edges[*start_id].face_id() != face.id() {
+		    violations.push(Violation::ForeignStart { face_id: face.id(), half_edge_id: *start_id });
+		    closed[face.id()] = false;
+		    continue;
+		}
+		let mut half_edge_id = *start_id;
+		let mut loop_closed = false;
+		for _ in 0..half_edges.len() {
+		    let half_edge = &half_edges[half_edge_id];
+		    if half_edge.face_id() != face.id() || visited[half_edge_id] {
+			break;
+		    }
+		    visited[half_edge_id] = true;
+		    half_edge_id = half_edge.next_id();
+		    if half_edge_id == *start_id {
+			loop_closed = true;
+			break;
+		    }
+		}
+		if !loop_closed && closed[face.id()] {
+		    violations.push(Violation::OpenLoop { face_id: face.id() });
+		}
+		closed[face.id()] &= loop_closed;
+	    }
+	}
+	for (half_edge_id, _) in visited.iter().enumerate().filter(|(_, visited)| !**visited) {
+	    violations.push(Violation::Unreachable { half_edge_id });
+	}
+	closed
+    }
+
+    /**
+     * Checks that the cells are convex and contain their sites, and that they do not overlap
+     * Clipped cells are only a part of a convex cell, so turns next to the clip boundary may be clockwise,
+     * the site only has to lie on the inside of the other half edges, and clipped cells are not checked for overlaps.
+     * Turns are only clockwise if the next corner lies further than the tolerance from the line of the half edge,
+     * because clipping can split an edge at a point that is not exactly on it.
+     * The cells of power diagrams do not always contain their sites, so that check is skipped for them.
+     */
+    fn check_geometry(&self, closed: &[bool], violations: &mut Vec<Violation>) {
+	let tolerance = self.geometry_tolerance();
+	let weighted = self.is_weighted();
+	let mut cells = Vec::new();
+	for face in self.faces().iter().filter(|face| closed[face.id()]) {
+	    let face_id = face.id();
+	    let site = to_vector2_f64(&self.site(face_id));
+	    let points: Vec<Vector2F64> = self.face_polygon(face_id).iter().map(to_vector2_f64).collect();
+	    let half_edges: Vec<&HalfEdge> = self.face_half_edges(face_id).collect();
+	    let mut site_inside = true;
+	    for i in 0..points.len() {
+		let start = &points[i];
+		let end = &points[(i + 1) % points.len()];
+		let after = &points[(i + 2) % points.len()];
+		let dir = Vector2F64::from_values(end.get_x() - start.get_x(), end.get_y() - start.get_y());
+		let next_dir = Vector2F64::from_values(after.get_x() - end.get_x(), after.get_y() - end.get_y());
+		let clipped = half_edges[i].is_clip_boundary() || half_edges[(i + 1) % points.len()].is_clip_boundary();
+		let length = dir.get_x().hypot(dir.get_y());
+		let turn = dir.get_x() * next_dir.get_y() - dir.get_y() * next_dir.get_x();
+		if !clipped && turn < - tolerance * length {
+		    violations.push(Violation::ClockwiseTurn { face_id, half_edge_id: half_edges[i].id() });
+		}
+		if !half_edges[i].is_clip_boundary() {
+		    let to_site = Vector2F64::from_values(site.get_x() - start.get_x(), site.get_y() - start.get_y());
+		    site_inside &= !is_clockwise(&dir, &to_site);
+		}
+	    }
+	    if !site_inside && !weighted {
+		violations.push(Violation::SiteOutsideCell { face_id });
+	    }
+	    if face.hole_start_ids().is_empty() && half_edges.iter().all(|half_edge| !half_edge.is_clip_boundary()) {
+		cells.push((face_id, points));
+	    }
+	}
+	self.check_overlaps(cells, violations);
+    }
+
+    /**
+     * Finds overlapping cells, comparing only cells whose extents overlap along the x axis
+     */
+    fn check_overlaps(&self, cells: Vec<(usize, Vec<Vector2F64>)>, violations: &mut Vec<Violation>) {
+	let tolerance = self.geometry_tolerance();
+	let extent = |points: &[Vector2F64]| points.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), point| {
+	    (min.min(point.get_x()), max.max(point.get_x()))
+	});
+	let mut cells: Vec<(usize, Vec<Vector2F64>, (f64, f64))> = cells.into_iter()
+	    .map(|(face_id, points)| {
+		let x_extent = extent(&points);
+		(face_id, points, x_extent)
+	    })
+	    .collect();
+	cells.sort_by(|first, second| (first.2).0.partial_cmp(&(second.2).0).unwrap_or(std::cmp::Ordering::Equal));
+	let mut overlaps = Vec::new();
+	for (i, (first_id, first, (_, first_right))) in cells.iter().enumerate() {
+	    for (second_id, second, (second_left, _)) in cells[i + 1..].iter() {
+		if *second_left >= first_right - tolerance {
+		    break;
+		}
+		if convex_polygons_overlap(first, second, tolerance) {
+		    overlaps.push(((*first_id).min(*second_id), (*first_id).max(*second_id)));
+		}
+	    }
+	}
+	overlaps.sort_unstable();
+	violations.extend(overlaps.into_iter().map(|(first_id, second_id)| Violation::Overlap { first_id, second_id }));
+    }
+
+    /**
+     * The tolerance of the geometric checks, relative to the size of the bounds and the precision of the coordinates
+     */
+    fn geometry_tolerance(&self) -> f64 {
+	relative_tolerance::<F>(1e-9) * (self.bounds().width() + self.bounds().height()).to_f64()
+    }
+
+}
+
+/**
+ * Tests whether the interiors of two convex polygons overlap by more than the tolerance,
+ * by looking for an edge of either polygon that separates them
+ */
+fn convex_polygons_overlap(first: &[Vector2F64], second: &[Vector2F64], tolerance: f64) -> bool {
+    let separates = |polygon: &[Vector2F64], other: &[Vector2F64]| {
+	(0..polygon.len()).any(|i| {
+	    let start = &polygon[i];
+	    let end = &polygon[(i + 1) % polygon.len()];
+	    let (dx, dy) = (end.get_x() - start.get_x(), end.get_y() - start.get_y());
+	    let length = dx.hypot(dy);
+	    if length == 0.0 {
+		return false;
+	    }
+	    // the polygon lies on the left side of its edges, so the other polygon has to lie on the right side
+	    other.iter().all(|point| {
+		(dx * (point.get_y() - start.get_y()) - dy * (point.get_x() - start.get_x())) / length <= tolerance
+	    })
+	})
+    };
+    !separates(first, second) && !separates(second, first)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::geom::BoundingBox;
+    use crate::voronoi::DiagramBuilder;
+
+    fn create_diagram() -> Diagram {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 1000.0, 0.0, 1000.0));
+	builder.add_site(100.0, 100.0);
+	builder.add_site(900.0, 900.0);
+	builder.build().unwrap()
+    }
+
+    /**
+     * Rebuilds the binary diagram with changed half edges or faces
+     */
+    fn create_corrupt_diagram<F>(change: F) -> Diagram where F: Fn(&mut Vec<(usize, usize, Option<usize>, usize, usize)>, &mut Vec<(f64, f64, usize, ())>) {
+	let diagram = create_diagram();
+	let vertices = diagram.vertices().iter().map(|vertex| (vertex.x(), vertex.y())).collect();
+	let mut half_edges = diagram.half_edges().iter()
+	    .map(|half_edge| (half_edge.face_id(), half_edge.start_id(), half_edge.twin_id(), half_edge.prev_id(), half_edge.next_id()))
+	    .collect();
+	let mut faces = diagram.faces().iter().map(|face| (face.x(), face.y(), face.start_id(), ())).collect();
+	change(&mut half_edges, &mut faces);
+	Diagram::from_parts(diagram.bounds().clone(), vertices, half_edges, faces)
+    }
+
+    #[test]
+    fn test_validate_diagram() {
+	assert_eq!(Ok(()), create_diagram().validate());
+	assert_eq!(Ok(()), DiagramBuilder::new(BoundingBox::new(0.0, 1.0, 0.0, 1.0)).build().unwrap().validate());
+    }
+
+    #[test]
+    fn test_validate_missing_references() {
+	let diagram = create_corrupt_diagram(|half_edges, _| {
+	    half_edges[0].1 = 17;
+	    half_edges[1].2 = Some(6);
+	});
+	assert_eq!(Err(vec![
+	    Violation::MissingVertex { half_edge_id: 0, vertex_id: 17 },
+	    Violation::MissingHalfEdge { half_edge_id: 1, missing_id: 6 },
+	]), diagram.validate());
+    }
+
+    #[test]
+    fn test_validate_twins() {
+	let diagram = create_corrupt_diagram(|half_edges, _| {
+	    half_edges[0].2 = Some(2);
+	});
+	assert_eq!(Err(vec![
+	    Violation::TwinNotSymmetric { half_edge_id: 0, twin_id: 2, twin_twin_id: None },
+	    Violation::TwinInSameFace { half_edge_id: 0, twin_id: 2 },
+	    Violation::TwinNotSymmetric { half_edge_id: 1, twin_id: 0, twin_twin_id: Some(2) },
+	]), diagram.validate_topology());
+	let diagram = create_corrupt_diagram(|half_edges, _| {
+	    half_edges[0].2 = Some(4);
+	    half_edges[4].2 = Some(0);
+	    half_edges[1].2 = None;
+	});
+	assert_eq!(Err(vec![
+	    Violation::TwinNotAligned { half_edge_id: 0, twin_id: 4 },
+	    Violation::TwinNotAligned { half_edge_id: 4, twin_id: 0 },
+	]), diagram.validate_topology());
+    }
+
+    #[test]
+    fn test_validate_loops() {
+	// half edges 0, 2 and 3 form the loop of face 0, skip half edge 2
+	let diagram = create_corrupt_diagram(|half_edges, _| {
+	    half_edges[0].4 = 3;
+	});
+	assert_eq!(Err(vec![
+	    Violation::NextNotInverse { half_edge_id: 0, next_id: 3, next_prev_id: 2 },
+	    Violation::TwinNotAligned { half_edge_id: 0, twin_id: 1 },
+	    Violation::PrevNotInverse { half_edge_id: 2, prev_id: 0, prev_next_id: 3 },
+	    Violation::Unreachable { half_edge_id: 2 },
+	]), diagram.validate_topology());
+	let diagram = create_corrupt_diagram(|half_edges, faces| {
+	    half_edges[2].0 = 1;
+	    faces[1].2 = 2;
+	});
+	let violations = diagram.validate().unwrap_err();
+	assert!(violations.contains(&Violation::OpenLoop { face_id: 0 }));
+	assert!(violations.contains(&Violation::OpenLoop { face_id: 1 }));
+	assert!(violations.contains(&Violation::NextInOtherFace { half_edge_id: 0, next_id: 2 }));
+    }
+
+    #[test]
+    fn test_validate_geometry() {
+	// moving the sites swaps which side of the diagonal they are on
+	let diagram = create_corrupt_diagram(|_, faces| {
+	    faces[0].0 = 900.0;
+	    faces[0].1 = 900.0;
+	    faces[1].0 = 100.0;
+	    faces[1].1 = 100.0;
+	});
+	assert_eq!(Err(vec![
+	    Violation::SiteOutsideCell { face_id: 0 },
+	    Violation::SiteOutsideCell { face_id: 1 },
+	]), diagram.validate());
+	assert_eq!(Ok(()), diagram.validate_topology());
+    }
+
+    #[test]
+    fn test_validate_overlap() {
+	// both faces use the loop of the first face
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 1000.0, 0.0, 1000.0));
+	builder.add_site(500.0, 500.0);
+	let single = builder.build().unwrap();
+	let vertices = single.vertices().iter().map(|vertex| (vertex.x(), vertex.y())).collect();
+	let mut half_edges: Vec<(usize, usize, Option<usize>, usize, usize)> = single.half_edges().iter()
+	    .map(|half_edge| (half_edge.face_id(), half_edge.start_id(), None, half_edge.prev_id(), half_edge.next_id()))
+	    .collect();
+	half_edges.extend(single.half_edges().iter().map(|half_edge| (1, half_edge.start_id(), None, half_edge.prev_id() + 4, half_edge.next_id() + 4)));
+	let diagram = Diagram::from_parts(single.bounds().clone(), vertices, half_edges, vec![(500.0, 500.0, 0, ()), (600.0, 600.0, 4, ())]);
+	assert_eq!(Err(vec![Violation::Overlap { first_id: 0, second_id: 1 }]), diagram.validate());
+    }
+
+    #[test]
+    fn test_display_violation() {
+	assert_eq!("half edge 3 has twin 5, whose twin is Some(4)",
+		   format!("{}", Violation::TwinNotSymmetric { half_edge_id: 3, twin_id: 5, twin_twin_id: Some(4) }));
+	assert_eq!("faces 1 and 2 overlap", format!("{}", Violation::Overlap { first_id: 1, second_id: 2 }));
+    }
+
+}
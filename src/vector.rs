@@ -308,6 +308,15 @@ pub trait Vector3 :  FixedVector {
 	self.set(2, value);
     }    
 
+    /**
+     * Calculates the cross product
+     */
+    fn cross_product(&self, other: &Self) -> Self where Self::Value: Mul<Output = Self::Value> + Sub<Output = Self::Value> {
+	Self::from_values(self.get_y() * other.get_z() - self.get_z() * other.get_y(),
+			  self.get_z() * other.get_x() - self.get_x() * other.get_z(),
+			  self.get_x() * other.get_y() - self.get_y() * other.get_x())
+    }
+
 }
 
 macro_rules! define_variable_vector_type {
@@ -622,4 +631,14 @@ mod test {
 	
 	assert_eq!(23.0, first.scalar_product(&second));
     }
+
+    #[test]
+    fn cross_product() {
+	let first = Vector3F64::from_values(1.0, 0.0, 0.0);
+	let second = Vector3F64::from_values(0.0, 1.0, 0.0);
+	assert_eq!(Vector3F64::from_values(0.0, 0.0, 1.0), first.cross_product(&second));
+	assert_eq!(Vector3F64::from_values(0.0, 0.0, -1.0), second.cross_product(&first));
+	let third = Vector3F64::from_values(2.0, 3.0, 4.0);
+	assert_eq!(0.0, third.cross_product(&first).scalar_product(&third));
+    }
 }
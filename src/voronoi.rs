@@ -19,155 +19,520 @@
  * Calculates the voronoi diagram
  */
 
-use crate::geom::{intersect_parabolas_from_foci, is_clockwise, BoundingBox, IntersectionCalculator, ParabolaIntersection};
-use crate::vector::{Vector2, Vector2F64};
+use crate::attributes::Attributes;
+use crate::delaunay::Triangulation;
+use crate::float::{relative_tolerance, to_vector2_f64, Float};
+use crate::geom::{BoundingBox, IntersectionCalculator, Polygon};
+use crate::locate::PointLocator;
+#[cfg(feature = "parallel")]
+use crate::parallel::{build_parallel, SweepTrace};
+use crate::periodic::{build_periodic, SeamMode, Wrap};
+use crate::predicates::{incircle, orient2d};
+use crate::queue::IndexedQueue;
+use crate::validate::Violation;
+use crate::vector::{Vector, Vector2, Vector2F64};
 
 use std::cmp::Ordering;
-use std::collections::BinaryHeap;
+use std::error::Error;
 use std::fmt::{Display, Error as FormatError, Formatter};
 
+/**
+ * Errors that can occur while building a voronoi diagram
+ */
 #[derive(Debug, PartialEq)]
-pub struct Vertex {
-    id: usize,
-    x: f64,
-    y: f64,
+pub enum VoronoiError {
+
+    /**
+     * The input can not be turned into a diagram, e.g. because the bounds have no area
+     */
+    DegenerateInput(String),
+
+    /**
+     * Two sites have the same position
+     */
+    DuplicateSite {
+	first_id: usize,
+	second_id: usize,
+    },
+
+    /**
+     * A site lies outside the bounds of the diagram
+     */
+    SiteOutOfBounds {
+	id: usize,
+	x: f64,
+	y: f64,
+    },
+
+    /**
+     * The sweep ended up with an inconsistent half edge structure
+     */
+    Topology(String),
+
+    /**
+     * Validation after the build found broken invariants
+     */
+    Invalid(Vec<Violation>),
+
+    /**
+     * The operation does not support this kind of diagram, e.g. editing the sites of a clipped diagram
+     */
+    Unsupported(String),
+
+}
+
+impl VoronoiError {
+
+    pub(crate) fn topology(message: &str) -> VoronoiError {
+	VoronoiError::Topology(String::from(message))
+    }
+
+}
+
+impl Display for VoronoiError {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FormatError> {
+	match self {
+	    VoronoiError::DegenerateInput(message) => write!(f, "degenerate input: {}", message),
+	    VoronoiError::DuplicateSite { first_id, second_id } => write!(f, "sites {} and {} have the same position", first_id, second_id),
+	    VoronoiError::SiteOutOfBounds { id, x, y } => write!(f, "site {} at ({}, {}) lies outside the bounds", id, x, y),
+	    VoronoiError::Topology(message) => write!(f, "inconsistent topology: {}", message),
+	    VoronoiError::Invalid(violations) => {
+		write!(f, "invalid diagram:")?;
+		for violation in violations.iter() {
+		    write!(f, " {};", violation)?;
+		}
+		Ok(())
+	    },
+	    VoronoiError::Unsupported(message) => write!(f, "unsupported: {}", message),
+	}
+    }
+
+}
+
+impl Error for VoronoiError {}
+
+#[derive(Debug, PartialEq)]
+pub struct Vertex<F = f64> {
+    pub(crate) id: usize,
+    pub(crate) x: F,
+    pub(crate) y: F,
+}
+
+impl<F> Vertex<F> where F: Float {
+
+    /**
+     * The index of this vertex in the diagram
+     */
+    pub fn id(&self) -> usize {
+	self.id
+    }
+
+    pub fn x(&self) -> F {
+	self.x
+    }
+
+    pub fn y(&self) -> F {
+	self.y
+    }
+
 }
 
 #[derive(Debug, PartialEq)]
 pub struct HalfEdge {
-    id: usize,
-    face_id: usize,
-    start_id: usize,
-    twin_id: Option<usize>,
-    prev_id: usize,
-    next_id: usize,
+    pub(crate) id: usize,
+    pub(crate) face_id: usize,
+    pub(crate) start_id: usize,
+    pub(crate) twin_id: Option<usize>,
+    pub(crate) prev_id: usize,
+    pub(crate) next_id: usize,
+    pub(crate) clip_boundary: bool,
+    pub(crate) wrap: (i32, i32),
+}
+
+impl HalfEdge {
+
+    /**
+     * The index of this half edge in the diagram
+     */
+    pub fn id(&self) -> usize {
+	self.id
+    }
+
+    /**
+     * The face on the left side of this half edge
+     */
+    pub fn face_id(&self) -> usize {
+	self.face_id
+    }
+
+    /**
+     * The vertex this half edge starts at; it ends at the start of the next half edge
+     */
+    pub fn start_id(&self) -> usize {
+	self.start_id
+    }
+
+    /**
+     * The half edge running in the opposite direction along the neighbouring face, if it is not on the bounds
+     */
+    pub fn twin_id(&self) -> Option<usize> {
+	self.twin_id
+    }
+
+    pub fn prev_id(&self) -> usize {
+	self.prev_id
+    }
+
+    pub fn next_id(&self) -> usize {
+	self.next_id
+    }
+
+    /**
+     * True if this half edge lies on the boundary of the polygon the diagram was clipped to
+     */
+    pub fn is_clip_boundary(&self) -> bool {
+	self.clip_boundary
+    }
+
+    /**
+     * The number of periods along the x and y axis by which the start vertex is moved in the face of this
+     * half edge, so the faces of periodic diagrams are continuous across the seams; zero for other diagrams
+     */
+    pub fn wrap(&self) -> (i32, i32) {
+	self.wrap
+    }
+
 }
 
-struct HalfEdgeBuilder {
+struct HalfEdgeBuilder<F: Float> {
     id: usize,
     face_id: usize,
     start_id: Option<usize>,
     twin_id: Option<usize>,
     prev_id: Option<usize>,
     next_id: Option<usize>,
+
+    /**
+     * The direction in which an unbounded half edge starts
+     */
+    start_dir: Option<F::Vector2>,
+
+    /**
+     * False if the half edge lies outside the bounds of the diagram
+     */
+    visible: bool,
 }
 
-impl HalfEdgeBuilder {
+impl<F> HalfEdgeBuilder<F> where F: Float {
 
-    fn into_half_edge(self) -> HalfEdge {
-	HalfEdge {
+    fn into_half_edge(self) -> Result<HalfEdge, VoronoiError> {
+	Ok(HalfEdge {
 	    id: self.id,
 	    face_id: self.face_id,
-	    start_id: self.start_id.expect("all half edges should have a start vertex"),
+	    start_id: self.start_id.ok_or_else(|| VoronoiError::topology("all half edges should have a start vertex"))?,
 	    twin_id: self.twin_id,
-	    prev_id: self.prev_id.expect("all half edges should have a previous half edge"),
-	    next_id: self.next_id.expect("all half edges should have a next half edge"),
-	}
+	    prev_id: self.prev_id.ok_or_else(|| VoronoiError::topology("all half edges should have a previous half edge"))?,
+	    next_id: self.next_id.ok_or_else(|| VoronoiError::topology("all half edges should have a next half edge"))?,
+	    clip_boundary: false,
+	    wrap: (0, 0),
+	})
     }
-    
+
 }
 
 #[derive(Debug, PartialEq)]
-pub struct Face {
-    id: usize,
-    x: f64,
-    y: f64,
-    start_id: usize,
+pub struct Face<T = (), F = f64> {
+    pub(crate) id: usize,
+    pub(crate) x: F,
+    pub(crate) y: F,
+    pub(crate) start_id: usize,
+    pub(crate) site_id: usize,
+    pub(crate) hole_start_ids: Vec<usize>,
+    pub(crate) weight: F,
+    pub(crate) data: T,
 }
 
-struct FaceBuilder {
+impl<T, F> Face<T, F> where F: Float {
+
+    /**
+     * The index of this face in the diagram, which is also the index of its site unless the diagram was clipped
+     * or sites of a power diagram have no cell
+     */
+    pub fn id(&self) -> usize {
+	self.id
+    }
+
+    /**
+     * The index of the site of this face in the order the sites were added
+     * The pieces of a cell that was cut apart by clipping are separate faces with the same site id.
+     */
+    pub fn site_id(&self) -> usize {
+	self.site_id
+    }
+
+    /**
+     * The weight of the site in a power diagram, zero for ordinary voronoi diagrams
+     */
+    pub fn weight(&self) -> F {
+	self.weight
+    }
+
+    /**
+     * The x coordinate of the site
+     */
+    pub fn x(&self) -> F {
+	self.x
+    }
+
+    /**
+     * The y coordinate of the site
+     */
+    pub fn y(&self) -> F {
+	self.y
+    }
+
+    /**
+     * One of the half edges bounding this face
+     */
+    pub fn start_id(&self) -> usize {
+	self.start_id
+    }
+
+    /**
+     * One half edge of every hole in this face, only clipped faces have holes
+     */
+    pub fn hole_start_ids(&self) -> &[usize] {
+	&self.hole_start_ids
+    }
+
+    /**
+     * The user data attached to the site of this face
+     */
+    pub fn data(&self) -> &T {
+	&self.data
+    }
+
+}
+
+/**
+ * The parts of a face before they are assembled into a diagram
+ */
+pub(crate) struct FaceParts<T> {
+    pub(crate) x: f64,
+    pub(crate) y: f64,
+    pub(crate) start_id: usize,
+    pub(crate) site_id: usize,
+    pub(crate) hole_start_ids: Vec<usize>,
+    pub(crate) weight: f64,
+    pub(crate) data: T,
+}
+
+struct FaceBuilder<T, F> {
     id: usize,
-    x: f64,
-    y: f64,
+    x: F,
+    y: F,
+    data: T,
     half_edge_id: Option<usize>,
-    start_id: Option<usize>,
-    end_id: Option<usize>,
 }
 
-impl FaceBuilder {
+impl<T, F> FaceBuilder<T, F> where F: Float {
 
-    fn into_face(self) -> Face {
-	Face {
+    fn into_face(self) -> Result<Face<T, F>, VoronoiError> {
+	Ok(Face {
 	    id: self.id,
 	    x: self.x,
 	    y: self.y,
-	    start_id: self.half_edge_id.expect("face should have at least one half edge"),
-	}
+	    start_id: self.half_edge_id.ok_or_else(|| VoronoiError::topology("face should have at least one half edge"))?,
+	    site_id: self.id,
+	    hole_start_ids: Vec::new(),
+	    weight: F::from_f64(0.0),
+	    data: self.data,
+	})
     }
 
-    fn has_complete_bounds(&self) -> bool {
-	self.start_id.is_none() && self.end_id.is_none()
-    } 
-    
 }
 
 #[derive(Debug, PartialEq)]
-pub struct Diagram {
-    width: f64,
-    height: f64,
-    vertices: Vec<Vertex>,
-    half_edges: Vec<HalfEdge>,
-    faces: Vec<Face>,
+pub struct Diagram<T = (), F: Float = f64> {
+    pub(crate) bounds: BoundingBox<F>,
+    pub(crate) vertices: Vec<Vertex<F>>,
+    pub(crate) half_edges: Vec<HalfEdge>,
+    pub(crate) faces: Vec<Face<T, F>>,
+    pub(crate) wrap: Wrap,
+    pub(crate) attributes: Attributes,
 }
 
-impl Diagram {
+impl<T> Diagram<T> {
+
+    /**
+     * Assembles a diagram from its parts, using the position in each list as the id
+     * The caller is responsible for the consistency of the half edge structure
+     */
+    #[cfg(test)]
+    pub(crate) fn from_parts(bounds: BoundingBox,
+			     vertices: Vec<(f64, f64)>,
+			     half_edges: Vec<(usize, usize, Option<usize>, usize, usize)>,
+			     faces: Vec<(f64, f64, usize, T)>) -> Diagram<T> {
+	let half_edges = half_edges.into_iter()
+	    .map(|(face_id, start_id, twin_id, prev_id, next_id)| (face_id, start_id, twin_id, prev_id, next_id, false))
+	    .collect();
+	let faces = faces.into_iter().enumerate()
+	    .map(|(site_id, (x, y, start_id, data))| FaceParts { x, y, start_id, site_id, hole_start_ids: Vec::new(), weight: 0.0, data })
+	    .collect();
+	Diagram::from_face_parts(bounds, vertices, half_edges, faces)
+    }
 
-    fn fmt_face(&self, face_id: usize, f: &mut Formatter<'_>) -> Result<(), FormatError> {
-	let face = &self.faces[face_id];
-	write!(f, "Face {}\nsite: ({}, {})\nbounds:\n", face_id, face.x, face.y)?;
-	let start_id = face.start_id;
-	self.fmt_half_edge(start_id, f)?;
-	let mut cur_id = start_id;
+    /**
+     * Assembles a diagram from its parts, where half edges also have a clip boundary flag
+     */
+    pub(crate) fn from_face_parts(bounds: BoundingBox,
+				  vertices: Vec<(f64, f64)>,
+				  half_edges: Vec<(usize, usize, Option<usize>, usize, usize, bool)>,
+				  faces: Vec<FaceParts<T>>) -> Diagram<T> {
+	Diagram {
+	    bounds,
+	    vertices: vertices.into_iter().enumerate()
+		.map(|(id, (x, y))| Vertex { id, x, y })
+		.collect(),
+	    half_edges: half_edges.into_iter().enumerate()
+		.map(|(id, (face_id, start_id, twin_id, prev_id, next_id, clip_boundary))| {
+		    HalfEdge { id, face_id, start_id, twin_id, prev_id, next_id, clip_boundary, wrap: (0, 0) }
+		})
+		.collect(),
+	    faces: faces.into_iter().enumerate()
+		.map(|(id, face)| Face {
+		    id,
+		    x: face.x,
+		    y: face.y,
+		    start_id: face.start_id,
+		    site_id: face.site_id,
+		    hole_start_ids: face.hole_start_ids,
+		    weight: face.weight,
+		    data: face.data,
+		})
+		.collect(),
+	    wrap: Wrap::None,
+	    attributes: Attributes::new(),
+	}
+    }
+
+    /**
+     * Finds the face containing a point, or None if the point lies outside the bounds or the clip polygon
+     * This walks the faces starting at the first face; use a locator for repeated queries
+     */
+    pub fn locate(&self, x: f64, y: f64) -> Option<usize> {
+	self.locate_from(0, x, y)
+    }
+
+    /**
+     * Finds the face containing a point by walking from face to face, starting at the specified face
+     * Every step moves to the neighbour whose site is closest to the point, which ends at the face with the closest site.
+     * Distances are power distances, the squared distance minus the weight, so this also works for power diagrams,
+     * and are measured to the closest copy of each site in periodic diagrams.
+     * Faces of clipped diagrams may be cut off from their neighbours, so if the walk ends at a face along the clip
+     * polygon, the point is tested against the face and its holes, and otherwise against all faces. This returns
+     * None for points outside the clip polygon.
+     */
+    pub fn locate_from(&self, start_id: usize, x: f64, y: f64) -> Option<usize> {
+	if self.faces.is_empty() || !self.bounds.contains(&Vector2F64::from_values(x, y)) {
+	    return None;
+	}
+	let face_id = self.walk_to_closest(start_id, x, y);
+	let clipped = !self.faces[face_id].hole_start_ids.is_empty()
+	    || self.face_half_edges(face_id).any(|half_edge| half_edge.clip_boundary);
+	if !clipped || self.face_contains(face_id, x, y) {
+	    Some(face_id)
+	} else {
+	    (0..self.faces.len()).find(|face_id| self.face_contains(*face_id, x, y))
+	}
+    }
+
+    /**
+     * Walks from the specified face towards the face whose site is closest to a point, which is the face containing
+     * the point unless the diagram is clipped
+     */
+    pub(crate) fn walk_to_closest(&self, start_id: usize, x: f64, y: f64) -> usize {
+	let distance = |face_id: usize| {
+	    let face = &self.faces[face_id];
+	    let (dx, dy) = self.wrap.shortest_offset(&self.bounds, face.x - x, face.y - y);
+	    dx * dx + dy * dy - face.weight
+	};
+	let mut face_id = start_id.min(self.faces.len() - 1);
+	let mut face_distance = distance(face_id);
 	loop {
-	    let next_id = self.half_edges[cur_id].next_id;
-	    if next_id == start_id {
-		break;
-	    } else {
-		cur_id = next_id;
-		self.fmt_half_edge(cur_id, f)?;
+	    let closest = self.neighbours(face_id)
+		.map(|neighbour_id| (neighbour_id, distance(neighbour_id)))
+		.fold(None, |closest: Option<(usize, f64)>, candidate| match closest {
+		    Some(closest) if closest.1 <= candidate.1 => Some(closest),
+		    _ => Some(candidate),
+		});
+	    match closest {
+		Some((neighbour_id, neighbour_distance)) if neighbour_distance < face_distance => {
+		    face_id = neighbour_id;
+		    face_distance = neighbour_distance;
+		},
+		_ => break face_id,
 	    }
 	}
-	write!(f, "\n")?;
-	Ok(())
     }
 
-    fn fmt_half_edge(&self, half_edge_id: usize, f: &mut Formatter<'_>) -> Result<(), FormatError> {
-	let half_edge = &self.half_edges[half_edge_id];
-	write!(f, "Half edge {}\n", half_edge_id)?;
-	self.fmt_vertex(half_edge.start_id, "start", f)?;
-	self.fmt_vertex(self.half_edges[half_edge.next_id].start_id, "end", f)?;
-	match &half_edge.twin_id {
-	    Some(twin_id) => write!(f, "twin: {}", *twin_id)?,
-	    None => write!(f, "twin: none")?
-	}
-	Ok(())
+    /**
+     * Tests whether a point lies inside the outer boundary of a face and outside its holes
+     */
+    fn face_contains(&self, face_id: usize, x: f64, y: f64) -> bool {
+	let point = Vector2F64::from_values(x, y);
+	self.face_to_polygon(face_id).contains(&point)
+	    && !self.hole_polygons(face_id).into_iter().any(|hole| Polygon::new(hole).contains(&point))
     }
 
-    fn fmt_vertex(&self, vertex_id: usize, label: &str, f: &mut Formatter<'_>) -> Result<(), FormatError> {
-	let vertex = &self.vertices[vertex_id];
-	write!(f, "{}: ({}, {})\n", label, vertex.x, vertex.y)?;
-	Ok(())
+    /**
+     * Creates a spatial index for fast point location
+     */
+    pub fn locator(&self) -> PointLocator<'_, T> {
+	PointLocator::new(self)
+    }
+
+    /**
+     * Creates the delaunay triangulation of the sites, which is the dual of this diagram
+     * Returns an error for clipped and periodic diagrams.
+     */
+    pub fn delaunay(&self) -> Result<Triangulation, VoronoiError> {
+	Triangulation::new(self)
+    }
+
+    /**
+     * The outer boundary of a face as a polygon, in counter clockwise order
+     * Holes left by clipping are not included; see hole_polygons.
+     */
+    pub fn face_to_polygon(&self, face_id: usize) -> Polygon {
+	Polygon::new(self.face_polygon(face_id))
     }
 
     pub fn create_triangles(&self) -> (Vec<f32>, Vec<u32>) {
-	let scale = self.width / 2.0;
+	if self.wrap != Wrap::None {
+	    return self.create_seam_triangles(SeamMode::Split);
+	}
+	let scale = self.bounds.width() / 2.0;
+	let left = self.bounds.left();
+	let top = self.bounds.top();
 	let mut vertices = Vec::with_capacity((self.vertices.len() + self.faces.len()) * 3);
-	
+
 	for f in self.faces.iter() {
-	    vertices.push((f.x / scale - 1.0) as f32);
-	    vertices.push((f.y / scale - 1.0) as f32);
+	    vertices.push(((f.x - left) / scale - 1.0) as f32);
+	    vertices.push(((f.y - top) / scale - 1.0) as f32);
 	    vertices.push(0.0f32);
 	}
-	
+
 	for v in self.vertices.iter() {
-	    vertices.push((v.x / scale - 1.0) as f32);
-	    vertices.push((v.y / scale - 1.0) as f32);
+	    vertices.push(((v.x - left) / scale - 1.0) as f32);
+	    vertices.push(((v.y - top) / scale - 1.0) as f32);
 	    vertices.push(0.0f32);
 	}
 
 	let offset = self.faces.len();
 	let mut indices = Vec::new();
-	
+
 	for id in 0..self.faces.len() {
 	    let f = &self.faces[id];
 	    let mut cur_id = f.start_id;
@@ -188,139 +553,629 @@ impl Diagram {
 	}
 	(vertices, indices)
     }
-    
+
 }
 
-impl Display for Diagram {
+impl<T, F> Diagram<T, F> where F: Float {
 
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FormatError> {
-	for id in 0..self.faces.len() {
-	    self.fmt_face(id, f)?;
+    pub fn bounds(&self) -> &BoundingBox<F> {
+	&self.bounds
+    }
+
+    pub fn vertices(&self) -> &[Vertex<F>] {
+	&self.vertices
+    }
+
+    pub fn half_edges(&self) -> &[HalfEdge] {
+	&self.half_edges
+    }
+
+    /**
+     * The faces of the diagram, one for every site that was added to the builder
+     */
+    pub fn faces(&self) -> &[Face<T, F>] {
+	&self.faces
+    }
+
+    /**
+     * The position of the site of a face
+     */
+    pub fn site(&self, face_id: usize) -> F::Vector2 {
+	let face = &self.faces[face_id];
+	F::Vector2::from_values(face.x, face.y)
+    }
+
+    /**
+     * Walks the half edges around a face, in counter clockwise order
+     */
+    pub fn face_half_edges(&self, face_id: usize) -> FaceHalfEdges<'_, T, F> {
+	FaceHalfEdges {
+	    diagram: self,
+	    start_id: self.faces[face_id].start_id,
+	    next_id: Some(self.faces[face_id].start_id),
 	}
-	Ok(())
     }
-    
-}
 
-enum EventKind {
-    AddArc {
-	face_id: usize,
-    },
-    RemoveArc {
-	arc_id: usize,
-    },
-}
+    /**
+     * The corners of a face, in counter clockwise order
+     */
+    pub fn face_vertices(&self, face_id: usize) -> impl Iterator<Item = &Vertex<F>> {
+	self.face_half_edges(face_id).map(move |half_edge| &self.vertices[half_edge.start_id])
+    }
 
-struct Event {
-    id: usize,
-    priority: f64,
-    kind: EventKind,
-}
+    /**
+     * The corners of a face as points, in counter clockwise order
+     */
+    pub fn face_polygon(&self, face_id: usize) -> Vec<F::Vector2> {
+	self.face_half_edges(face_id).map(|half_edge| self.half_edge_start(half_edge.id)).collect()
+    }
 
-impl PartialEq for Event {
+    /**
+     * The position of the start vertex of a half edge in its face, which differs from the position of the vertex
+     * by whole periods if the half edge crosses a seam of a periodic diagram
+     */
+    pub fn half_edge_start(&self, half_edge_id: usize) -> F::Vector2 {
+	let half_edge = &self.half_edges[half_edge_id];
+	let vertex = &self.vertices[half_edge.start_id];
+	if half_edge.wrap == (0, 0) {
+	    return F::Vector2::from_values(vertex.x, vertex.y);
+	}
+	F::Vector2::from_values(vertex.x + F::from_f64(half_edge.wrap.0 as f64) * self.bounds.width(),
+				vertex.y + F::from_f64(half_edge.wrap.1 as f64) * self.bounds.height())
+    }
 
-    fn eq(&self, other: &Self) -> bool {
-	self.id.eq(&other.id)
+    /**
+     * How the diagram wraps around its bounds
+     */
+    pub fn wrap(&self) -> Wrap {
+	self.wrap
     }
-    
-}
 
-impl Eq for Event {}
+    /**
+     * Walks the half edges around a hole of a face, in clockwise order
+     */
+    pub fn hole_half_edges(&self, face_id: usize, hole: usize) -> FaceHalfEdges<'_, T, F> {
+	let start_id = self.faces[face_id].hole_start_ids[hole];
+	FaceHalfEdges {
+	    diagram: self,
+	    start_id,
+	    next_id: Some(start_id),
+	}
+    }
+
+    /**
+     * The corners of every hole of a face, in clockwise order
+     */
+    pub fn hole_polygons(&self, face_id: usize) -> Vec<Vec<F::Vector2>> {
+	(0..self.faces[face_id].hole_start_ids.len())
+	    .map(|hole| {
+		self.hole_half_edges(face_id, hole)
+		    .map(|half_edge| {
+			let vertex = &self.vertices[half_edge.start_id];
+			F::Vector2::from_values(vertex.x, vertex.y)
+		    })
+		    .collect()
+	    })
+	    .collect()
+    }
+
+    /**
+     * True if the diagram was clipped to a polygon
+     */
+    pub fn is_clipped(&self) -> bool {
+	self.half_edges.iter().any(|half_edge| half_edge.clip_boundary)
+	    || self.faces.iter().any(|face| !face.hole_start_ids.is_empty())
+    }
 
-impl Ord for Event {
+    /**
+     * True if any site has a weight, which means this is a power diagram
+     */
+    pub fn is_weighted(&self) -> bool {
+	self.faces.iter().any(|face| face.weight != F::from_f64(0.0))
+    }
+
+    /**
+     * The faces sharing an edge with a face, in the order of its half edges
+     */
+    pub fn neighbours(&self, face_id: usize) -> impl Iterator<Item = usize> + '_ {
+	self.face_half_edges(face_id)
+	    .filter_map(move |half_edge| half_edge.twin_id.map(|twin_id| self.half_edges[twin_id].face_id))
+    }
+
+    /**
+     * Consumes the diagram and returns the position and data of every site, ordered by face id
+     * The weights of the sites of power diagrams are not included.
+     */
+    pub fn into_sites(self) -> Vec<(F, F, T)> {
+	self.faces.into_iter().map(|face| (face.x, face.y, face.data)).collect()
+    }
+
+    /**
+     * Converts the coordinates of the diagram to another scalar type, keeping its topology
+     */
+    pub fn cast<G>(self) -> Diagram<T, G> where G: Float {
+	Diagram {
+	    bounds: self.bounds.cast(),
+	    vertices: self.vertices.into_iter()
+		.map(|vertex| Vertex { id: vertex.id, x: G::from_f64(vertex.x.to_f64()), y: G::from_f64(vertex.y.to_f64()) })
+		.collect(),
+	    half_edges: self.half_edges,
+	    faces: self.faces.into_iter()
+		.map(|face| Face {
+		    id: face.id,
+		    x: G::from_f64(face.x.to_f64()),
+		    y: G::from_f64(face.y.to_f64()),
+		    start_id: face.start_id,
+		    site_id: face.site_id,
+		    hole_start_ids: face.hole_start_ids,
+		    weight: G::from_f64(face.weight.to_f64()),
+		    data: face.data,
+		})
+		.collect(),
+	    wrap: self.wrap,
+	    attributes: self.attributes,
+	}
+    }
+
+    fn fmt_face(&self, face_id: usize, f: &mut Formatter<'_>) -> Result<(), FormatError> {
+	let face = &self.faces[face_id];
+	write!(f, "Face {}\nsite: ({}, {})\nbounds:\n", face_id, face.x, face.y)?;
+	let start_id = face.start_id;
+	self.fmt_half_edge(start_id, f)?;
+	let mut cur_id = start_id;
+	loop {
+	    let next_id = self.half_edges[cur_id].next_id;
+	    if next_id == start_id {
+		break;
+	    } else {
+		cur_id = next_id;
+		self.fmt_half_edge(cur_id, f)?;
+	    }
+	}
+	write!(f, "\n")?;
+	Ok(())
+    }
 
-    fn cmp(&self, other: &Self) -> Ordering {
-	match self.priority.partial_cmp(&other.priority) {
-	    Some(Ordering::Equal) | None => self.id.cmp(&other.id),
-	    Some(Ordering::Less) => Ordering::Less,
-	    Some(Ordering::Greater) => Ordering::Greater,
+    fn fmt_half_edge(&self, half_edge_id: usize, f: &mut Formatter<'_>) -> Result<(), FormatError> {
+	let half_edge = &self.half_edges[half_edge_id];
+	write!(f, "Half edge {}\n", half_edge_id)?;
+	self.fmt_vertex(half_edge.start_id, "start", f)?;
+	self.fmt_vertex(self.half_edges[half_edge.next_id].start_id, "end", f)?;
+	match &half_edge.twin_id {
+	    Some(twin_id) => write!(f, "twin: {}", *twin_id)?,
+	    None => write!(f, "twin: none")?
 	}
+	Ok(())
     }
-    
+
+    fn fmt_vertex(&self, vertex_id: usize, label: &str, f: &mut Formatter<'_>) -> Result<(), FormatError> {
+	let vertex = &self.vertices[vertex_id];
+	write!(f, "{}: ({}, {})\n", label, vertex.x, vertex.y)?;
+	Ok(())
+    }
+
+}
+
+/**
+ * Iterator over the half edges of a face
+ */
+pub struct FaceHalfEdges<'a, T, F: Float = f64> {
+    diagram: &'a Diagram<T, F>,
+    start_id: usize,
+    next_id: Option<usize>,
 }
 
-impl PartialOrd for Event {
+impl<'a, T, F> Iterator for FaceHalfEdges<'a, T, F> where F: Float {
+    type Item = &'a HalfEdge;
 
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-	Some(self.cmp(other))
+    fn next(&mut self) -> Option<&'a HalfEdge> {
+	let half_edge = &self.diagram.half_edges[self.next_id?];
+	self.next_id = if half_edge.next_id == self.start_id {
+	    None
+	} else {
+	    Some(half_edge.next_id)
+	};
+	Some(half_edge)
     }
-    
+
 }
 
+impl<T, F> Display for Diagram<T, F> where F: Float {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FormatError> {
+	for id in 0..self.faces.len() {
+	    self.fmt_face(id, f)?;
+	}
+	Ok(())
+    }
+
+}
 
-#[derive(PartialEq)]
+enum EventKind<F: Float> {
+    AddArc {
+	face_id: usize,
+    },
+    RemoveArc {
+	arc_id: usize,
+	center: F::Vector2,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq)]
 enum NodeId {
     Edge(usize),
     Arc(usize),
 }
 
 
+/**
+ * A breakpoint between two arcs of the beach line
+ * The breakpoints are the inner nodes of an AVL tree, whose leaves are the arcs in order from left to right.
+ */
 struct Edge {
     id: usize,
+
+    /**
+     * The half edge that starts at the vertex where this breakpoint disappears
+     */
     half_edge_id: usize,
 
+    left_arc_id: usize,
+    right_arc_id: usize,
+
     parent_id: Option<usize>,
     left_child: NodeId,
     right_child: NodeId,
+
+    /**
+     * The number of breakpoints on the longest path down to an arc, including this one
+     */
+    height: usize,
 }
 
+/**
+ * An arc of the beach line, which is linked to its neighbours by the breakpoints on either side
+ */
 struct Arc {
     id: usize,
     face_id: usize,
     remove_event_id: Option<usize>,
 
+    left_edge_id: Option<usize>,
+    right_edge_id: Option<usize>,
+
     parent_id: Option<usize>,
 }
 
-struct Builder {
-    width: f64,
-    height: f64,
-    vertices: Vec<Vertex>,
-    half_edges: Vec<HalfEdgeBuilder>,
-    faces: Vec<FaceBuilder>,
-    events: BinaryHeap<Event>,
+/**
+ * Describes how the builder handles sites that have the same position
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DuplicatePolicy {
+
+    /**
+     * Building fails with a duplicate site error
+     */
+    Reject,
+
+    /**
+     * Only the first of the sites with the same position is kept, the user data of the others is dropped
+     */
+    Merge,
+
+}
+
+/**
+ * Counts of the events that were handled by the last sweep of a builder
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SweepMetrics {
+
+    /**
+     * The number of sites that were added to the beach line
+     */
+    pub site_events: usize,
+
+    /**
+     * The number of arcs that were removed from the beach line, each of which created a vertex
+     */
+    pub circle_events: usize,
+
+    /**
+     * The number of circle events that were removed from the queue, because a neighbour of their arc changed first
+     */
+    pub cancelled_events: usize,
+
+    /**
+     * The largest number of events that were queued at the same time
+     */
+    pub max_queued_events: usize,
+}
+
+/**
+ * The maximum distance between two vertices that are considered equal, relative to the size of the bounds
+ * The tolerance is widened for scalar types that can not resolve it
+ */
+const VERTEX_TOLERANCE: f64 = 1e-9;
+
+/**
+ * An end point of a voronoi edge, which is either a vertex or a direction towards infinity
+ */
+enum EdgeEnd<F: Float> {
+    Vertex(F::Vector2),
+    Infinite(F::Vector2),
+}
+
+/**
+ * Builds a voronoi diagram from a list of sites using Fortune's algorithm
+ * The user can attach data to each site, which ends up in the face of that site
+ * Faces are numbered in the order in which their sites were added, skipping merged duplicates
+ * The sweep runs in the scalar type of the bounds, e.g. f32 to save memory for large maps
+ */
+pub struct DiagramBuilder<T = (), F: Float = f64> {
+    bounds: BoundingBox<F>,
+    duplicate_policy: DuplicatePolicy,
+    validate: bool,
+    wrap: Wrap,
+    #[cfg(feature = "parallel")]
+    threads: usize,
+    vertices: Vec<Vertex<F>>,
+
+    /**
+     * The sites on the circle around each vertex that was created by removing an arc
+     */
+    vertex_sites: Vec<Option<[usize; 3]>>,
+
+    half_edges: Vec<HalfEdgeBuilder<F>>,
+    faces: Vec<FaceBuilder<T, F>>,
+    events: IndexedQueue<F, EventKind<F>>,
+    metrics: SweepMetrics,
     edges: Vec<Edge>,
     arcs: Vec<Arc>,
     root: Option<NodeId>,
-    calculator: IntersectionCalculator,
+    corner_ids: [Option<usize>; 4],
+    calculator: IntersectionCalculator<F>,
+    #[cfg(feature = "parallel")]
+    trace: Option<SweepTrace<F>>,
 }
 
-impl Builder {
+impl<F> DiagramBuilder<(), F> where F: Float {
 
-    fn new(width: f64, height: f64) -> Builder {
-	Builder{
-	    width,
-	    height,
+    /**
+     * Creates a new builder for a diagram clipped to the specified bounds, without user data for the sites
+     */
+    pub fn new(bounds: BoundingBox<F>) -> DiagramBuilder<(), F> {
+	DiagramBuilder::with_data(bounds)
+    }
+
+}
+
+impl<T, F> DiagramBuilder<T, F> where F: Float {
+
+    /**
+     * Creates a new builder for a diagram clipped to the specified bounds, with user data for each site
+     */
+    pub fn with_data(bounds: BoundingBox<F>) -> DiagramBuilder<T, F> {
+	DiagramBuilder {
+	    bounds,
+	    duplicate_policy: DuplicatePolicy::Reject,
+	    validate: false,
+	    wrap: Wrap::None,
+	    #[cfg(feature = "parallel")]
+	    threads: 1,
 	    vertices: vec![],
+	    vertex_sites: vec![],
 	    half_edges: vec![],
 	    faces: vec![],
-	    events: BinaryHeap::new(),
+	    events: IndexedQueue::new(),
+	    metrics: SweepMetrics::default(),
 	    edges: vec![],
 	    arcs: vec![],
 	    root: None,
+	    corner_ids: [None; 4],
 	    calculator: IntersectionCalculator::new(),
+	    #[cfg(feature = "parallel")]
+	    trace: None,
 	}
     }
 
-    fn add_site(&mut self, x: f64, y: f64) {
+    /**
+     * Sets how sites with the same position are handled, duplicates are rejected by default
+     */
+    pub fn set_duplicate_policy(&mut self, duplicate_policy: DuplicatePolicy) {
+	self.duplicate_policy = duplicate_policy;
+    }
+
+    /**
+     * Sets whether every built diagram is validated, which is off by default
+     */
+    pub fn set_validate(&mut self, validate: bool) {
+	self.validate = validate;
+    }
+
+    /**
+     * Sets whether the diagram wraps around its bounds, which it does not by default
+     * Periodic diagrams are computed on a cylinder or torus, so cells on the seams are not cut.
+     */
+    pub fn set_wrap(&mut self, wrap: Wrap) {
+	self.wrap = wrap;
+    }
+
+    /**
+     * Sets the number of threads that build the diagram, which is one by default
+     * More threads build strips of the sites at the same time and join them into the same diagram a single thread
+     * builds. Periodic diagrams are always built on a single thread.
+     * Only available with the parallel feature, which is off by default.
+     */
+    #[cfg(feature = "parallel")]
+    pub fn set_threads(&mut self, threads: usize) {
+	self.threads = threads.max(1);
+    }
+
+    /**
+     * Adds a site with the specified user data
+     * Returns the id of the face that will be created for the site; merging duplicates lowers the ids of
+     * the later sites by the number of duplicates that were dropped before them
+     */
+    pub fn add_site_with_data(&mut self, x: F, y: F, data: T) -> usize {
 	let id = self.faces.len();
 	self.faces.push(FaceBuilder {
 	    id,
 	    x,
 	    y,
+	    data,
 	    half_edge_id: None,
-	    start_id: None,
-	    end_id: None,
 	});
+	id
+    }
+
+    /**
+     * Adds all sites and their user data
+     */
+    pub fn add_sites_with_data<I>(&mut self, sites: I) where I: IntoIterator<Item = (F, F, T)> {
+	for (x, y, data) in sites {
+	    self.add_site_with_data(x, y, data);
+	}
+    }
+
+    /**
+     * Builds the diagram from the sites that were added
+     * The builder is cleared afterwards, so it can be reused
+     */
+    pub fn build(&mut self) -> Result<Diagram<T, F>, VoronoiError> {
+	let result = self.sweep();
+	self.clear();
+	let diagram = result?;
+	if self.validate {
+	    diagram.validate().map_err(VoronoiError::Invalid)?;
+	}
+	Ok(diagram)
+    }
+
+    /**
+     * Builds the diagram like build, and records which event of the sweep created each vertex and half edge
+     */
+    #[cfg(feature = "parallel")]
+    pub(crate) fn build_traced(&mut self) -> Result<(Diagram<T, F>, SweepTrace<F>), VoronoiError> {
+	self.trace = Some(SweepTrace::new());
+	let result = self.build();
+	let trace = self.trace.take();
+	let diagram = result?;
+	trace.map(|trace| (diagram, trace)).ok_or_else(|| VoronoiError::topology("sweep should be traced"))
+    }
+
+    /**
+     * Returns the counts of the events of the last build, which are zero for periodic diagrams
+     * Builds on several threads add up the events of the sweeps of all strips, including the sites in their margins.
+     */
+    pub fn metrics(&self) -> &SweepMetrics {
+	&self.metrics
+    }
+
+    fn sweep(&mut self) -> Result<Diagram<T, F>, VoronoiError> {
+	self.metrics = SweepMetrics::default();
+	self.check_bounds()?;
+	// sites outside the bounds or with NaN coordinates can not be sorted for merging
+	self.check_site_bounds()?;
+	if self.duplicate_policy == DuplicatePolicy::Merge {
+	    self.merge_duplicate_sites();
+	}
+	self.check_duplicate_sites()?;
+	if self.wrap != Wrap::None {
+	    // the periodic cells are cut from copies of the sites in f64
+	    let sites = self.faces.drain(..).map(|face| (face.x.to_f64(), face.y.to_f64(), face.data)).collect();
+	    return build_periodic(&self.bounds.cast(), self.wrap, sites).map(Diagram::cast);
+	}
+	#[cfg(feature = "parallel")]
+	if self.threads > 1 {
+	    let sites = self.faces.drain(..).map(|face| (face.x, face.y, face.data)).collect();
+	    let (diagram, metrics) = build_parallel(&self.bounds, self.threads, sites)?;
+	    self.metrics = metrics;
+	    return Ok(diagram);
+	}
+	self.create_events();
+	self.handle_events()?;
+	self.complete_edges()?;
+	self.merge_vertices();
+	self.clip_edges()?;
+	self.bound()?;
+	self.create_diagram()
+    }
+
+    fn check_bounds(&self) -> Result<(), VoronoiError> {
+	let width = self.bounds.width();
+	let height = self.bounds.height();
+	if !width.is_finite() || !height.is_finite() {
+	    Err(VoronoiError::DegenerateInput(String::from("bounds should be finite")))
+	} else if width <= F::from_f64(0.0) || height <= F::from_f64(0.0) {
+	    Err(VoronoiError::DegenerateInput(String::from("bounds should have a positive width and height")))
+	} else {
+	    Ok(())
+	}
     }
 
-    fn create_vertex(&mut self, x: f64, y: f64) -> usize {
+    fn check_site_bounds(&self) -> Result<(), VoronoiError> {
+	match self.faces.iter().find(|face| !self.bounds.contains(&F::Vector2::from_values(face.x, face.y))) {
+	    Some(face) => Err(VoronoiError::SiteOutOfBounds {
+		id: face.id,
+		x: face.x.to_f64(),
+		y: face.y.to_f64(),
+	    }),
+	    None => Ok(()),
+	}
+    }
+
+    fn check_duplicate_sites(&self) -> Result<(), VoronoiError> {
+	let mut face_ids: Vec<usize> = (0..self.faces.len()).collect();
+	face_ids.sort_by(|first, second| self.compare_sites(*first, *second));
+	for pair in face_ids.windows(2) {
+	    if self.compare_sites(pair[0], pair[1]) == Ordering::Equal {
+		return Err(VoronoiError::DuplicateSite {
+		    first_id: pair[0].min(pair[1]),
+		    second_id: pair[0].max(pair[1]),
+		});
+	    }
+	}
+	Ok(())
+    }
+
+    /**
+     * Removes all sites that have the same position as a site that was added before
+     */
+    fn merge_duplicate_sites(&mut self) {
+	let mut face_ids: Vec<usize> = (0..self.faces.len()).collect();
+	face_ids.sort_by(|first, second| self.compare_sites(*first, *second));
+	let mut duplicate = vec![false; self.faces.len()];
+	for pair in face_ids.windows(2) {
+	    if self.compare_sites(pair[0], pair[1]) == Ordering::Equal {
+		// the sort is stable, so the first site with a position comes first
+		duplicate[pair[1]] = true;
+	    }
+	}
+	self.faces = self.faces.drain(..)
+	    .filter(|face| !duplicate[face.id])
+	    .enumerate()
+	    .map(|(id, mut face)| {
+		face.id = id;
+		face
+	    })
+	    .collect();
+    }
+
+    fn compare_sites(&self, first_id: usize, second_id: usize) -> Ordering {
+	let first = &self.faces[first_id];
+	let second = &self.faces[second_id];
+	first.y.compare(second.y).then(first.x.compare(second.x))
+    }
+
+    fn create_vertex(&mut self, x: F, y: F) -> usize {
 	let id = self.vertices.len();
 	self.vertices.push(Vertex{
 	    id,
 	    x,
 	    y,
 	});
+	self.vertex_sites.push(None);
 	id
     }
 
@@ -333,6 +1188,8 @@ impl Builder {
 	    twin_id: None,
 	    prev_id: None,
 	    next_id: None,
+	    start_dir: None,
+	    visible: true,
 	});
 	id
     }
@@ -342,292 +1199,444 @@ impl Builder {
 	let twin_id = self.create_half_edge(twin_face_id, None);
 	self.half_edges[id].twin_id = Some(twin_id);
 	self.half_edges[twin_id].twin_id = Some(id);
+	#[cfg(feature = "parallel")]
+	if let Some(trace) = &mut self.trace {
+	    trace.create_pair();
+	}
 	(id, twin_id)
     }
 
-    fn connect_half_edges(&mut self, first_id: usize, second_id: usize) {
+    fn connect_half_edges(&mut self, first_id: usize, second_id: usize) -> Result<(), VoronoiError> {
 	let first = &self.half_edges[first_id];
 	let second = &self.half_edges[second_id];
 	if first.face_id != second.face_id {
-	    panic!("half edges have different face");
-	}
-	if first.next_id.is_some() {
-	    panic!("first half edge is already connected");
-	}
-	if second.prev_id.is_some() {
-	    panic!("second half edge is already connected");
+	    Err(VoronoiError::topology("half edges have different face"))
+	} else if first.next_id.is_some() {
+	    Err(VoronoiError::topology("first half edge is already connected"))
+	} else if second.prev_id.is_some() {
+	    Err(VoronoiError::topology("second half edge is already connected"))
+	} else {
+	    self.half_edges[first_id].next_id = Some(second_id);
+	    self.half_edges[second_id].prev_id = Some(first_id);
+	    Ok(())
 	}
-	self.half_edges[first_id].next_id = Some(second_id);
-	self.half_edges[second_id].prev_id = Some(first_id);
     }
-    
+
+    fn get_start_id(&self, half_edge_id: usize) -> Result<usize, VoronoiError> {
+	self.half_edges[half_edge_id].start_id.ok_or_else(|| VoronoiError::topology("half edge should have a start vertex"))
+    }
+
+    fn get_twin_id(&self, half_edge_id: usize) -> Result<usize, VoronoiError> {
+	self.half_edges[half_edge_id].twin_id.ok_or_else(|| VoronoiError::topology("half edge should have a twin"))
+    }
+
     fn create_arc(&mut self, face_id: usize) -> usize {
 	let id = self.arcs.len();
 	self.arcs.push(Arc {
 	    id,
 	    face_id,
 	    remove_event_id: None,
+	    left_edge_id: None,
+	    right_edge_id: None,
 	    parent_id: None,
 	});
 	id
     }
 
-    fn create_edge(&mut self, half_edge_id: usize, left_child: NodeId, right_child: NodeId) -> usize {
+    /**
+     * Creates a breakpoint between two neighbouring arcs, which become linked through it
+     */
+    fn create_edge(&mut self, half_edge_id: usize, left_arc_id: usize, right_arc_id: usize, left_child: NodeId, right_child: NodeId) -> usize {
 	let id = self.edges.len();
+	let height = 1 + self.get_height(&left_child).max(self.get_height(&right_child));
 	self.edges.push(Edge{
 	    id,
 	    half_edge_id,
+	    left_arc_id,
+	    right_arc_id,
 	    parent_id: None,
 	    left_child,
 	    right_child,
+	    height,
 	});
+	self.arcs[left_arc_id].right_edge_id = Some(id);
+	self.arcs[right_arc_id].left_edge_id = Some(id);
+	self.set_parent(&left_child, Some(id));
+	self.set_parent(&right_child, Some(id));
 	id
     }
 
-    fn find_prev_arc_id(&self, edge_id: usize) -> usize {
-	let mut node_id = &self.edges[edge_id].left_child;
-	loop {
-	    match node_id {
-		NodeId::Arc(arc_id) => break *arc_id,
-		NodeId::Edge(child_edge_id) => {
-		    node_id = &self.edges[*child_edge_id].right_child;
-		}
-	    }
+    fn push_event(&mut self, priority: F, kind: EventKind<F>) -> usize {
+	let id = self.events.push(priority, kind);
+	self.metrics.max_queued_events = self.metrics.max_queued_events.max(self.events.len());
+	#[cfg(feature = "parallel")]
+	if let Some(trace) = &mut self.trace {
+	    trace.push_event(id);
 	}
+	id
+    }
+
+    fn find_prev_arc_id(&self, edge_id: usize) -> usize {
+	self.edges[edge_id].left_arc_id
     }
 
     fn find_next_arc_id(&self, edge_id: usize) -> usize {
-	let mut node_id = &self.edges[edge_id].right_child;
-	loop {
-	    match node_id {
-		NodeId::Arc(arc_id) => break *arc_id,
-		NodeId::Edge(child_edge_id) => {
-		    node_id = &self.edges[*child_edge_id].left_child;
-		}
-	    }
-	}
+	self.edges[edge_id].right_arc_id
     }
 
     fn find_first_edge_id(&self) -> Option<usize> {
-	match &self.root {
-	    Some(root) => {
-		let mut result = None;
-		let mut node = root;
-		while let NodeId::Edge(edge_id) = node {
-		    result = Some(*edge_id);
-		    node = &self.edges[*edge_id].left_child;
-		}
-		result
-	    },
-	    None => None,
+	let mut node_id = self.root?;
+	while let NodeId::Edge(edge_id) = node_id {
+	    node_id = self.edges[edge_id].left_child;
+	}
+	match node_id {
+	    NodeId::Arc(arc_id) => self.arcs[arc_id].right_edge_id,
+	    NodeId::Edge(_) => None,
 	}
     }
 
     fn find_prev_edge_id(&self, arc_id: usize) -> Option<usize> {
-	match &self.arcs[arc_id].parent_id {
-	    Some(parent_id) => {
-		if self.edges[*parent_id].right_child == NodeId::Arc(arc_id) {
-		    Some(*parent_id)
-		} else {
-		    let mut edge_id = parent_id;
-		    loop {
-			match &self.edges[*edge_id].parent_id {
-			    Some(parent_id) => {
-				if self.edges[*parent_id].right_child == NodeId::Edge(*edge_id) {
-				    break Some(*parent_id);
-				}
-				edge_id = parent_id;
-			    },
-			    None => {
-				break None;
-			    }
-			}
-		    }
-		}
-	    },
-	    None => {
-		None
-	    }
-	}
+	self.arcs[arc_id].left_edge_id
     }
-    
+
     fn find_next_edge_id(&self, arc_id: usize) -> Option<usize> {
-	match &self.arcs[arc_id].parent_id {
-	    Some(parent_id) => {
-		if self.edges[*parent_id].left_child == NodeId::Arc(arc_id) {
-		    Some(*parent_id)
-		} else {
-		    let mut edge_id = parent_id;
-		    loop {
-			match &self.edges[*edge_id].parent_id {
-			    Some(parent_id) => {
-				if self.edges[*parent_id].left_child == NodeId::Edge(*edge_id) {
-				    break Some(*parent_id);
-				}
-				edge_id = parent_id;
-			    },
-			    None => {
-				break None;
-			    }
-			}
-		    }
-		}
-	    },
-	    None => {
-		None
-	    }
-	}
+	self.arcs[arc_id].right_edge_id
     }
-     
-    fn find_split_arc(&self, start_node_id: &NodeId, new_face_id: usize) -> usize {
+
+    fn find_split_arc(&self, start_node_id: &NodeId, new_face_id: usize) -> Result<usize, VoronoiError> {
 	let mut node_id = start_node_id;
 	let face = &self.faces[new_face_id];
 	loop {
 	    match node_id {
-		NodeId::Arc(arc_id) => break *arc_id,
+		NodeId::Arc(arc_id) => break Ok(*arc_id),
 		NodeId::Edge(edge_id) => {
 		    let left_arc_id = self.find_prev_arc_id(*edge_id);
 		    let right_arc_id = self.find_next_arc_id(*edge_id);
-		    let left_face = &self.faces[self.arcs[left_arc_id].face_id];
-		    let right_face = &self.faces[self.arcs[right_arc_id].face_id];
-		    match intersect_parabolas_from_foci(
-			&Vector2F64::from_values(left_face.x, left_face.y),
-			&Vector2F64::from_values(right_face.x, right_face.y),
-			face.y
-		    ){
-			ParabolaIntersection::Two(i1, i2) => {
-			    //note i1.x < i2.x
-			    let ix = if left_face.y < right_face.y {
-				i1.get_x()
-			    } else {
-				i2.get_x()
-			    };
-			    node_id = if face.x < ix {
-				&self.edges[*edge_id].left_child
-			    } else {
-				&self.edges[*edge_id].right_child
-			    };
-			},
-			_ => panic!("expected two intersections")
-		    }
+		    let ix = self.get_breakpoint_x(self.arcs[left_arc_id].face_id, self.arcs[right_arc_id].face_id, face.y)?;
+		    node_id = if face.x < ix {
+			&self.edges[*edge_id].left_child
+		    } else {
+			&self.edges[*edge_id].right_child
+		    };
 		}
 	    }
 	}
     }
-    
-    fn add_arc(&mut self, face_id: usize) {
+
+    /**
+     * Calculates the x coordinate of the breakpoint between the arcs of two sites
+     * Arcs of sites on the directrix are vertical lines, arcs of sites with the same y coordinate meet halfway.
+     * The breakpoint is the root of d2 (x - x1)² - d1 (x - x2)² + d1 d2 (d1 - d2) = 0, where d1 and d2 are the
+     * distances of the sites to the directrix. Its discriminant is d1 d2 times the squared distance between the
+     * sites, so it never rounds below zero, and the root is taken in the form that does not cancel, which stays
+     * accurate when the y coordinates of the sites are nearly equal.
+     */
+    fn get_breakpoint_x(&self, left_face_id: usize, right_face_id: usize, directrix: F) -> Result<F, VoronoiError> {
+	let left_face = &self.faces[left_face_id];
+	let right_face = &self.faces[right_face_id];
+	let zero = F::from_f64(0.0);
+	let half = (left_face.x + right_face.x) / F::from_f64(2.0);
+	if left_face.y == right_face.y {
+	    return Ok(half);
+	} else if left_face.y == directrix {
+	    return Ok(left_face.x);
+	} else if right_face.y == directrix {
+	    return Ok(right_face.x);
+	}
+	// relative to the left site, so the coefficients do not lose precision far from the origin
+	let dx = right_face.x - left_face.x;
+	let d1 = directrix - left_face.y;
+	let d2 = directrix - right_face.y;
+	let a = d2 - d1;
+	let h = - d1 * dx;
+	let c = d1 * (d2 * (d1 - d2) - dx * dx);
+	let r = (d1 * d2).sqrt() * (dx * dx + a * a).sqrt();
+	// of the roots (h ± r) / a, the breakpoint with the left arc on the left is (h + r) / a = c / (h - r)
+	let offset = if h < zero {
+	    c / (h - r)
+	} else if a != zero {
+	    (h + r) / a
+	} else {
+	    return Ok(half);
+	};
+	if offset.is_finite() {
+	    Ok(left_face.x + offset)
+	} else {
+	    Err(VoronoiError::DegenerateInput(
+		format!("no breakpoint between the arcs of sites {} and {}", left_face_id, right_face_id)
+	    ))
+	}
+    }
+
+    fn add_arc(&mut self, face_id: usize) -> Result<(), VoronoiError> {
 	match &self.root {
 	    None => {
 		self.root = Some(NodeId::Arc(self.create_arc(face_id)));
 	    },
+	    Some(_) if self.faces[self.arcs[0].face_id].y == self.faces[face_id].y => {
+		self.append_arc(face_id)?;
+	    },
 	    Some(node_id) => {
-		let split_arc_id = self.find_split_arc(node_id, face_id);
+		let split_arc_id = self.find_split_arc(node_id, face_id)?;
 		let split_face_id = self.arcs[split_arc_id].face_id;
 
-		let (half_edge_id, twin_id) = self.create_half_edge_pair(face_id, split_face_id);
-		self.faces[face_id].half_edge_id.get_or_insert(half_edge_id);
-		self.faces[split_face_id].half_edge_id.get_or_insert(twin_id);
-		
+		// the left breakpoint fixes the start of the half edge of the split face, the right breakpoint that of the new face
+		let (twin_id, half_edge_id) = self.create_half_edge_pair(split_face_id, face_id);
+
 		let parent_id = self.arcs[split_arc_id].parent_id;
+		let next_edge_id = self.arcs[split_arc_id].right_edge_id;
 		let new_arc_id = self.create_arc(face_id);
-		let cloned_arc_id = self.create_arc(self.arcs[split_arc_id].face_id);
+		let cloned_arc_id = self.create_arc(split_face_id);
+		if let Some(next_edge_id) = next_edge_id {
+		    self.edges[next_edge_id].left_arc_id = cloned_arc_id;
+		    self.arcs[cloned_arc_id].right_edge_id = Some(next_edge_id);
+		}
 		let right_edge_id = self.create_edge(half_edge_id,
+						     new_arc_id,
+						     cloned_arc_id,
 						     NodeId::Arc(new_arc_id),
 						     NodeId::Arc(cloned_arc_id)
 		);
-		self.arcs[new_arc_id].parent_id = Some(right_edge_id);
-		self.arcs[cloned_arc_id].parent_id = Some(right_edge_id);
 		let left_edge_id = self.create_edge(twin_id,
+						    split_arc_id,
+						    new_arc_id,
 						    NodeId::Arc(split_arc_id),
 						    NodeId::Edge(right_edge_id)
 		);
-		self.arcs[split_arc_id].parent_id = Some(left_edge_id);
-		self.edges[right_edge_id].parent_id = Some(left_edge_id);
-		self.edges[left_edge_id].parent_id = parent_id;
-		if let None = parent_id {
-		    self.root = Some(NodeId::Edge(left_edge_id));
-		}
-		let scan_line_y = self.faces[face_id].y;
-		self.update_remove_event(split_arc_id, scan_line_y);
-		self.update_remove_event(cloned_arc_id, scan_line_y);
+		self.replace_node(parent_id, &NodeId::Arc(split_arc_id), NodeId::Edge(left_edge_id))?;
+		self.rebalance(parent_id)?;
+		self.update_remove_event(split_arc_id);
+		self.update_remove_event(cloned_arc_id);
 	    }
 	}
+	Ok(())
     }
 
-    fn remove_arc(&mut self, arc_id: usize) {
-	let left_edge_id = self.find_prev_edge_id(arc_id).unwrap();
-	let right_edge_id = self.find_next_edge_id(arc_id).unwrap();
+    /**
+     * Adds an arc to the right end of the beach line, for sites with the same y coordinate as the first site
+     * The arcs of these sites are vertical lines, so the new arc does not split an existing arc
+     */
+    fn append_arc(&mut self, face_id: usize) -> Result<(), VoronoiError> {
+	let mut node_id = self.root.ok_or_else(|| VoronoiError::topology("beach line should not be empty"))?;
+	while let NodeId::Edge(edge_id) = node_id {
+	    node_id = self.edges[edge_id].right_child;
+	}
+	let last_arc_id = match node_id {
+	    NodeId::Arc(arc_id) => arc_id,
+	    NodeId::Edge(_) => return Err(VoronoiError::topology("beach line should end with an arc")),
+	};
+	let last_face_id = self.arcs[last_arc_id].face_id;
+
+	// the edge between both sites is a vertical line that starts infinitely high
+	let (half_edge_id, twin_id) = self.create_half_edge_pair(last_face_id, face_id);
+	let last_site = self.get_site(last_face_id);
+	let site = self.get_site(face_id);
+	self.half_edges[half_edge_id].start_dir = Some(F::Vector2::from_values(F::from_f64(0.0), last_site.get_x() - site.get_x()));
+
+	let parent_id = self.arcs[last_arc_id].parent_id;
+	let new_arc_id = self.create_arc(face_id);
+	let edge_id = self.create_edge(twin_id, last_arc_id, new_arc_id, NodeId::Arc(last_arc_id), NodeId::Arc(new_arc_id));
+	self.replace_node(parent_id, &NodeId::Arc(last_arc_id), NodeId::Edge(edge_id))?;
+	self.rebalance(parent_id)
+    }
+
+    fn remove_arc(&mut self, arc_id: usize, center: &F::Vector2) -> Result<(), VoronoiError> {
+	self.arcs[arc_id].remove_event_id = None;
+	let left_edge_id = self.find_prev_edge_id(arc_id)
+	    .ok_or_else(|| VoronoiError::topology("removed arc should have a left breakpoint"))?;
+	let right_edge_id = self.find_next_edge_id(arc_id)
+	    .ok_or_else(|| VoronoiError::topology("removed arc should have a right breakpoint"))?;
 	let left_arc_id = self.find_prev_arc_id(left_edge_id);
 	let left_face_id = self.arcs[left_arc_id].face_id;
 	let right_arc_id = self.find_next_arc_id(right_edge_id);
 	let right_face_id = self.arcs[right_arc_id].face_id;
-	let focus = self.get_arc_focus(arc_id);
-	let left_focus = self.get_arc_focus(left_arc_id);
-	let right_focus = self.get_arc_focus(right_arc_id);
-	let (center, _) = self.calculator.circle_through_points(
-	    &left_focus, &focus, &right_focus).unwrap();
-
-	let intersection_id = self.create_vertex(center.get_x(), center.get_y());
-	let left_in_id = self.edges[left_edge_id].half_edge_id;
-	let left_out_id = self.half_edges[left_in_id].twin_id.unwrap();
-	let right_out_id = self.edges[right_edge_id].half_edge_id;
-	let right_in_id = self.half_edges[right_out_id].twin_id.unwrap();
-	let (down_out_id, down_in_id) = self.create_half_edge_pair(left_face_id, right_face_id);
-
-	self.half_edges[down_out_id].start_id = Some(intersection_id);
-	self.connect_half_edges(left_in_id, down_out_id);
-
-	self.half_edges[left_out_id].start_id = Some(intersection_id);
-	self.connect_half_edges(right_in_id, left_out_id);
-
-	self.half_edges[right_out_id].start_id = Some(intersection_id);
-	self.connect_half_edges(down_in_id, right_out_id);
-
-	
-	if self.edges[left_edge_id].right_child == NodeId::Arc(arc_id) {
-	    // arc is the right child of its parent
-	    let grand_parent_id = self.edges[left_edge_id].parent_id.unwrap();
-	    self.replace_child(grand_parent_id, &NodeId::Edge(left_edge_id), NodeId::Arc(left_arc_id));
-	    self.edges[right_edge_id].half_edge_id = down_out_id;
+
+	let vertex_id = self.create_vertex(center.get_x(), center.get_y());
+	self.vertex_sites[vertex_id] = Some([left_face_id, self.arcs[arc_id].face_id, right_face_id]);
+	let left_half_edge_id = self.edges[left_edge_id].half_edge_id;
+	self.half_edges[left_half_edge_id].start_id = Some(vertex_id);
+	let right_half_edge_id = self.edges[right_edge_id].half_edge_id;
+	self.half_edges[right_half_edge_id].start_id = Some(vertex_id);
+
+	// the new breakpoint traces an edge that starts at the vertex
+	let (half_edge_id, twin_id) = self.create_half_edge_pair(left_face_id, right_face_id);
+	self.half_edges[half_edge_id].start_id = Some(vertex_id);
+
+	// the parent of the arc is replaced by the arc's sibling, the other breakpoint becomes the new breakpoint
+	let (parent_id, sibling, merged_edge_id) = if self.edges[left_edge_id].right_child == NodeId::Arc(arc_id) {
+	    (left_edge_id, self.edges[left_edge_id].left_child, right_edge_id)
+	} else {
+	    (right_edge_id, self.edges[right_edge_id].right_child, left_edge_id)
+	};
+	let grand_parent_id = self.edges[parent_id].parent_id
+	    .ok_or_else(|| VoronoiError::topology("parent of removed arc should not be the root"))?;
+	self.replace_child(grand_parent_id, &NodeId::Edge(parent_id), sibling)?;
+	self.rebalance(Some(grand_parent_id))?;
+	self.edges[merged_edge_id].half_edge_id = twin_id;
+	self.edges[merged_edge_id].left_arc_id = left_arc_id;
+	self.edges[merged_edge_id].right_arc_id = right_arc_id;
+	self.arcs[left_arc_id].right_edge_id = Some(merged_edge_id);
+	self.arcs[right_arc_id].left_edge_id = Some(merged_edge_id);
+
+	self.update_remove_event(left_arc_id);
+	self.update_remove_event(right_arc_id);
+	Ok(())
+    }
+
+    fn set_parent(&mut self, node_id: &NodeId, parent_id: Option<usize>) {
+	match node_id {
+	    NodeId::Arc(arc_id) => {
+		self.arcs[*arc_id].parent_id = parent_id;
+	    },
+	    NodeId::Edge(edge_id) => {
+		self.edges[*edge_id].parent_id = parent_id;
+	    }
+	}
+    }
+
+    /**
+     * Replaces a node of the beach line by another one, which becomes the root if the old node has no parent
+     */
+    fn replace_node(&mut self, parent_id: Option<usize>, old_node: &NodeId, new_node: NodeId) -> Result<(), VoronoiError> {
+	match parent_id {
+	    Some(parent_id) => self.replace_child(parent_id, old_node, new_node),
+	    None => {
+		self.set_parent(&new_node, None);
+		self.root = Some(new_node);
+		Ok(())
+	    },
+	}
+    }
+
+    fn replace_child(&mut self, parent_edge_id: usize, old_child: &NodeId, new_child: NodeId) -> Result<(), VoronoiError> {
+	self.set_parent(&new_child, Some(parent_edge_id));
+	if &self.edges[parent_edge_id].left_child == old_child {
+	    self.edges[parent_edge_id].left_child = new_child;
+	    Ok(())
+	} else if &self.edges[parent_edge_id].right_child == old_child {
+	    self.edges[parent_edge_id].right_child = new_child;
+	    Ok(())
 	} else {
-	    // arc is the left child of its parent
-	    let grand_parent_id = self.edges[right_edge_id].parent_id.unwrap();
-	    self.replace_child(grand_parent_id, &NodeId::Edge(right_edge_id), NodeId::Arc(right_arc_id));
-	    self.edges[left_edge_id].half_edge_id = down_out_id;
+	    Err(VoronoiError::topology("node to be replaced is not a child of the parent"))
 	}
     }
 
-    fn replace_child(&mut self, parent_edge_id: usize, old_child: &NodeId, new_child: NodeId) {
-	match &new_child {
-	    NodeId::Arc(arc_id) => {
-		self.arcs[*arc_id].parent_id = Some(parent_edge_id);
-	    },
-	    NodeId::Edge(edge_id) => {
-		self.edges[*edge_id].parent_id = Some(parent_edge_id);
-	    }
-	}
-	if &self.edges[parent_edge_id].left_child == old_child {
-	    self.edges[parent_edge_id].left_child = new_child;
-	} else if &self.edges[parent_edge_id].right_child == old_child {
-	    self.edges[parent_edge_id].right_child = new_child;
-	} else {
-	    panic!("node to be replaces is not a child of the parent");
-	}
+    fn get_height(&self, node_id: &NodeId) -> usize {
+	match node_id {
+	    NodeId::Arc(_) => 0,
+	    NodeId::Edge(edge_id) => self.edges[*edge_id].height,
+	}
+    }
+
+    fn update_height(&mut self, edge_id: usize) {
+	let edge = &self.edges[edge_id];
+	let height = 1 + self.get_height(&edge.left_child).max(self.get_height(&edge.right_child));
+	self.edges[edge_id].height = height;
+    }
+
+    /**
+     * The difference between the heights of the left and right subtree of a breakpoint
+     */
+    fn get_balance(&self, edge_id: usize) -> isize {
+	let edge = &self.edges[edge_id];
+	self.get_height(&edge.left_child) as isize - self.get_height(&edge.right_child) as isize
+    }
+
+    /**
+     * Restores the heights and the balance of the breakpoints from the specified one up to the root
+     * Rotations keep the order of the nodes, so every breakpoint stays between the same two arcs.
+     */
+    fn rebalance(&mut self, start_edge_id: Option<usize>) -> Result<(), VoronoiError> {
+	let mut next = start_edge_id;
+	while let Some(edge_id) = next {
+	    let balance = self.get_balance(edge_id);
+	    let top_id = if balance > 1 {
+		if let NodeId::Edge(left_id) = self.edges[edge_id].left_child {
+		    if self.get_balance(left_id) < 0 {
+			self.rotate_left(left_id)?;
+		    }
+		}
+		self.rotate_right(edge_id)?
+	    } else if balance < -1 {
+		if let NodeId::Edge(right_id) = self.edges[edge_id].right_child {
+		    if self.get_balance(right_id) > 0 {
+			self.rotate_right(right_id)?;
+		    }
+		}
+		self.rotate_left(edge_id)?
+	    } else {
+		self.update_height(edge_id);
+		edge_id
+	    };
+	    next = self.edges[top_id].parent_id;
+	}
+	Ok(())
+    }
+
+    /**
+     * Moves the left child of a breakpoint up into its place, returns the id of the moved child
+     */
+    fn rotate_right(&mut self, edge_id: usize) -> Result<usize, VoronoiError> {
+	let child_id = match self.edges[edge_id].left_child {
+	    NodeId::Edge(child_id) => child_id,
+	    NodeId::Arc(_) => return Err(VoronoiError::topology("rotated breakpoint should have a breakpoint as left child")),
+	};
+	let parent_id = self.edges[edge_id].parent_id;
+	let inner = self.edges[child_id].right_child;
+	self.edges[edge_id].left_child = inner;
+	self.set_parent(&inner, Some(edge_id));
+	self.edges[child_id].right_child = NodeId::Edge(edge_id);
+	self.edges[edge_id].parent_id = Some(child_id);
+	self.replace_node(parent_id, &NodeId::Edge(edge_id), NodeId::Edge(child_id))?;
+	self.update_height(edge_id);
+	self.update_height(child_id);
+	Ok(child_id)
+    }
+
+    /**
+     * Moves the right child of a breakpoint up into its place, returns the id of the moved child
+     */
+    fn rotate_left(&mut self, edge_id: usize) -> Result<usize, VoronoiError> {
+	let child_id = match self.edges[edge_id].right_child {
+	    NodeId::Edge(child_id) => child_id,
+	    NodeId::Arc(_) => return Err(VoronoiError::topology("rotated breakpoint should have a breakpoint as right child")),
+	};
+	let parent_id = self.edges[edge_id].parent_id;
+	let inner = self.edges[child_id].left_child;
+	self.edges[edge_id].right_child = inner;
+	self.set_parent(&inner, Some(edge_id));
+	self.edges[child_id].left_child = NodeId::Edge(edge_id);
+	self.edges[edge_id].parent_id = Some(child_id);
+	self.replace_node(parent_id, &NodeId::Edge(edge_id), NodeId::Edge(child_id))?;
+	self.update_height(edge_id);
+	self.update_height(child_id);
+	Ok(child_id)
+    }
+
+    fn get_arc_focus(&self, arc_id: usize) -> F::Vector2 {
+	self.get_site(self.arcs[arc_id].face_id)
     }
 
-    fn check_remove_arc(&mut self, arc_id: usize, event_id: usize) {
-	if self.arcs[arc_id].remove_event_id == Some(event_id) {
-	    self.remove_arc(arc_id);
-	}
+    fn get_site(&self, face_id: usize) -> F::Vector2 {
+	let face = &self.faces[face_id];
+	F::Vector2::from_values(face.x, face.y)
     }
-    
-    fn get_arc_focus(&self, arc_id: usize) -> Vector2F64 {
-	let face = &self.faces[self.arcs[arc_id].face_id];
-	Vector2F64::from_values(face.x, face.y)
+
+    fn get_vertex(&self, vertex_id: usize) -> F::Vector2 {
+	let vertex = &self.vertices[vertex_id];
+	F::Vector2::from_values(vertex.x, vertex.y)
     }
-    
-    fn update_remove_event(&mut self, arc_id: usize, scan_line_y: f64) {
-	self.arcs[arc_id].remove_event_id = None;
+
+    /**
+     * Replaces the circle event of an arc after one of its neighbours changed
+     * The previous event is taken out of the queue, so the queue only holds events that will be handled.
+     */
+    fn update_remove_event(&mut self, arc_id: usize) {
+	if let Some(event_id) = self.arcs[arc_id].remove_event_id.take() {
+	    if self.events.remove(event_id).is_some() {
+		self.metrics.cancelled_events += 1;
+	    }
+	}
 	if let Some(left_edge_id) = self.find_prev_edge_id(arc_id) {
 	    if let Some(right_edge_id) = self.find_next_edge_id(arc_id) {
 		let left_arc_id = self.find_prev_arc_id(left_edge_id);
@@ -635,223 +1644,531 @@ impl Builder {
 		let focus = self.get_arc_focus(arc_id);
 		let left_focus = self.get_arc_focus(left_arc_id);
 		let right_focus = self.get_arc_focus(right_arc_id);
+		// the breakpoints around the arc only converge if the foci make a counter clockwise turn
+		if orient2d(&to_vector2_f64(&left_focus), &to_vector2_f64(&focus), &to_vector2_f64(&right_focus)) <= 0.0 {
+		    return;
+		}
 		if let Some((center, radius)) = self.calculator.circle_through_points(
 		    &left_focus,
 		    &focus,
 		    &right_focus
 		){
-		    let priority = center.get_y() + radius;
-		    if priority >= scan_line_y && center.get_y() <= self.height && center.get_y() >= focus.get_y(){
-			let id = self.events.len();
-			self.events.push(Event{
-			    id,
-			    priority,
-			    kind: EventKind::RemoveArc {
-				arc_id
-			    }
-			});
-			self.arcs[arc_id].remove_event_id = Some(arc_id);
-		    }
+		    let id = self.push_event(center.get_y() + radius, EventKind::RemoveArc {
+			arc_id,
+			center,
+		    });
+		    self.arcs[arc_id].remove_event_id = Some(id);
 		}
 	    }
 	}
     }
-    
+
     fn create_events(&mut self) {
-	for face in self.faces.iter() {
-	    let id = self.events.len();
-	    self.events.push(Event{
-		id,
-		priority: face.x,
-		kind: EventKind::AddArc{
-		    face_id: face.id,
-		},
+	// events with the same priority are handled in order of creation, so sites on the same line are added from left to right
+	let mut face_ids: Vec<usize> = (0..self.faces.len()).collect();
+	face_ids.sort_by(|first, second| self.compare_sites(*first, *second));
+	for face_id in face_ids {
+	    let priority = self.faces[face_id].y;
+	    self.push_event(priority, EventKind::AddArc{
+		face_id,
 	    });
 	}
     }
 
-    fn handle_events(&mut self) {
-	while let Some(event) = self.events.pop() {
-	    match event.kind {
+    #[cfg_attr(not(feature = "parallel"), allow(unused_variables))]
+    fn handle_events(&mut self) -> Result<(), VoronoiError> {
+	while let Some((handle, priority, kind)) = self.events.pop() {
+	    match kind {
 		EventKind::AddArc{face_id} => {
-		    self.add_arc(face_id);
+		    self.metrics.site_events += 1;
+		    #[cfg(feature = "parallel")]
+		    if let Some(trace) = &mut self.trace {
+			trace.handle_site(face_id);
+		    }
+		    self.add_arc(face_id)?;
 		},
-		EventKind::RemoveArc{arc_id} => {
-		    self.check_remove_arc(arc_id, event.id);
+		EventKind::RemoveArc{arc_id, center} => {
+		    self.metrics.circle_events += 1;
+		    #[cfg(feature = "parallel")]
+		    if let Some(trace) = &mut self.trace {
+			trace.handle_circle(handle, priority, &center);
+		    }
+		    self.remove_arc(arc_id, &center)?;
 		}
 	    }
 	}
+	Ok(())
     }
 
-    fn complete_edges(&mut self) {
-	let bounds = BoundingBox::new(0.0, self.width, 0.0, self.height);
-	
+    /**
+     * Assigns a direction to all half edges that start at infinity, i.e. those of the breakpoints left on the beach line
+     */
+    fn complete_edges(&mut self) -> Result<(), VoronoiError> {
 	let mut next = self.find_first_edge_id();
 	while let Some(edge_id) = next {
-	    self.complete_edge(&bounds, edge_id);
-	    next = self.find_next_edge_id(self.find_next_arc_id(edge_id));
+	    let left_focus = self.get_arc_focus(self.find_prev_arc_id(edge_id));
+	    let right_arc_id = self.find_next_arc_id(edge_id);
+	    let right_focus = self.get_arc_focus(right_arc_id);
+	    let half_edge_id = self.edges[edge_id].half_edge_id;
+	    self.half_edges[half_edge_id].start_dir = Some(F::Vector2::from_values(
+		left_focus.get_y() - right_focus.get_y(),
+		right_focus.get_x() - left_focus.get_x()
+	    ));
+	    next = self.find_next_edge_id(right_arc_id);
 	}
+	Ok(())
     }
 
-    fn complete_edge(&mut self, bounds: &BoundingBox, edge_id: usize) {
-	let half_edge_id = self.edges[edge_id].half_edge_id;
-	let face_id = self.half_edges[half_edge_id].face_id;
-	let twin_id = self.half_edges[half_edge_id].twin_id.expect("half edge should have a twin");
-	let twin_face_id = self.half_edges[twin_id].face_id;
-	if let None = self.half_edges[half_edge_id].start_id {
-	    self.half_edges[half_edge_id].start_id = Some(self.calc_half_edge_start(bounds, face_id, twin_face_id));
-	}
-	self.faces[face_id].end_id = Some(half_edge_id);
-	self.faces[twin_face_id].start_id = Some(twin_id);
-    }
-    
-    fn calc_half_edge_start(&mut self, bounds: &BoundingBox, face_id: usize, twin_face_id: usize) -> usize {
-	let face = &self.faces[face_id];
-	let twin_face = &self.faces[twin_face_id];
-	let pos = Vector2F64::from_values((face.x + twin_face.x) / 2.0, (face.y + twin_face.y) / 2.0);
-	let sub = Vector2F64::from_values(twin_face.x - face.x, twin_face.y - face.y);
-	let mut dir = Vector2F64::from_values(face.y - twin_face.y, twin_face.x - face.x);
-	if !is_clockwise(&sub, &dir) {
-	    dir = Vector2F64::from_values(-dir.get_x(), -dir.get_y());
-	}
-	match self.calculator.ray_with_bounding_box(&pos, &dir, bounds) {
-	    Some(v) => {
-		self.create_vertex(v.get_x(), v.get_y())
-	    },
-	    None => panic!("ray should intersect with bounds")
-	}
-    }
-
-    fn bound(&mut self) {
-	match self.root {
-	    None => {},
-	    Some(NodeId::Arc(arc_id)) => {
-		let face_id = self.arcs[arc_id].face_id;
-		let top_left_id = self.create_vertex(0.0, 0.0);
-		let top_right_id = self.create_vertex(self.width, 0.0);
-		let bottom_right_id = self.create_vertex(self.width, self.height);
-		let bottom_left_id = self.create_vertex(0.0, self.height);
-		let top_id = self.create_half_edge(face_id, Some(top_left_id));
-		let right_id = self.create_half_edge(face_id, Some(top_right_id));
-		let bottom_id = self.create_half_edge(face_id, Some(bottom_right_id));
-		let left_id = self.create_half_edge(face_id, Some(bottom_left_id));
-		self.connect_half_edges(top_id, right_id);
-		self.connect_half_edges(right_id, bottom_id);
-		self.connect_half_edges(bottom_id, left_id);
-		self.connect_half_edges(left_id, top_id);
-		self.faces[0].half_edge_id = Some(top_id);
-	    },
-	    Some(NodeId::Edge(_)) => {
-		for id in 0..self.faces.len() {
-		    if !self.faces[id].has_complete_bounds() {
-			self.complete_face_bounds(id);
+    /**
+     * Merges the end points of edges with (nearly) zero length
+     * The sweep creates these edges when four or more sites lie on the same circle
+     */
+    fn merge_vertices(&mut self) {
+	let tolerance = self.vertex_tolerance();
+	let mut merged_ids: Vec<usize> = (0..self.vertices.len()).collect();
+	for id in 0..self.half_edges.len() {
+	    let twin_id = match self.half_edges[id].twin_id {
+		Some(twin_id) if id < twin_id => twin_id,
+		_ => continue,
+	    };
+	    if let (Some(start_id), Some(end_id)) = (self.half_edges[id].start_id, self.half_edges[twin_id].start_id) {
+		let start_id = find_merged_vertex(&merged_ids, start_id);
+		let end_id = find_merged_vertex(&merged_ids, end_id);
+		let start = self.get_vertex(start_id);
+		let end = self.get_vertex(end_id);
+		let (dx, dy) = (end.get_x() - start.get_x(), end.get_y() - start.get_y());
+		if self.are_cocircular(start_id, end_id) || (dx * dx + dy * dy).sqrt() <= tolerance {
+		    if start_id != end_id {
+			merged_ids[end_id] = start_id;
 		    }
+		    self.half_edges[id].visible = false;
+		    self.half_edges[twin_id].visible = false;
 		}
+	    }
+	}
+	for half_edge in self.half_edges.iter_mut() {
+	    half_edge.start_id = half_edge.start_id.map(|vertex_id| find_merged_vertex(&merged_ids, vertex_id));
+	}
+    }
+
+    /**
+     * Checks exactly whether the sites around two vertices of the sweep lie on the same circle,
+     * in which case the vertices are the same point and the edge between them has zero length
+     */
+    fn are_cocircular(&self, first_id: usize, second_id: usize) -> bool {
+	match (self.vertex_sites[first_id], self.vertex_sites[second_id]) {
+	    (Some(first_sites), Some(second_sites)) => {
+		let circle: Vec<Vector2F64> = first_sites.iter().map(|face_id| to_vector2_f64(&self.get_site(*face_id))).collect();
+		let mut others = second_sites.iter().filter(|face_id| !first_sites.contains(face_id)).peekable();
+		others.peek().is_some() && others.all(|face_id| {
+		    incircle(&circle[0], &circle[1], &circle[2], &to_vector2_f64(&self.get_site(*face_id))) == 0.0
+		})
 	    },
+	    _ => false,
 	}
     }
 
-    fn complete_face_bounds(&mut self, face_id: usize) {
-	let mut cur_half_edge_id = self.faces[face_id].start_id.expect("face bounds must have start half edge");
-	let mut cur_vertex_id = self.half_edges[self.half_edges[cur_half_edge_id].twin_id.unwrap()]
-	    .start_id.unwrap();
-	let mut cur_x = self.vertices[cur_vertex_id].x;
-	let mut cur_y = self.vertices[cur_vertex_id].y;
-	let end_half_edge_id = self.faces[face_id].end_id.expect("face bounds must have end half_edge");
-	let end_vertex_id = self.half_edges[cur_half_edge_id].start_id.unwrap();
-	let end_x = self.vertices[end_vertex_id].x;
-	let end_y = self.vertices[end_vertex_id].y;
-	loop {
-	    let (next_x, next_y) = if cur_y == 0.0 && cur_x != self.width {
-		if end_y == 0.0 && end_x >= cur_x {
-		    break;
+    /**
+     * The tolerance for merging vertices in the units of the bounds
+     */
+    fn vertex_tolerance(&self) -> F {
+	F::from_f64(relative_tolerance::<F>(VERTEX_TOLERANCE)) * (self.bounds.width() + self.bounds.height())
+    }
+
+    fn get_edge_end(&self, half_edge_id: usize) -> Result<EdgeEnd<F>, VoronoiError> {
+	let half_edge = &self.half_edges[half_edge_id];
+	match (half_edge.start_id, half_edge.start_dir) {
+	    (Some(vertex_id), _) => Ok(EdgeEnd::Vertex(self.get_vertex(vertex_id))),
+	    (None, Some(dir)) => Ok(EdgeEnd::Infinite(dir)),
+	    (None, None) => Err(VoronoiError::topology("unbounded half edge should have a direction")),
+	}
+    }
+
+    /**
+     * Clips all edges to the bounds of the diagram and makes sure each half edge has its face on the left
+     */
+    fn clip_edges(&mut self) -> Result<(), VoronoiError> {
+	for id in 0..self.half_edges.len() {
+	    if let (Some(twin_id), true) = (self.half_edges[id].twin_id, self.half_edges[id].visible) {
+		if id < twin_id {
+		    self.clip_edge(id, twin_id)?;
 		}
-		(self.width, 0.0)
-	    } else if cur_x == self.width && cur_y != self.height{
-		if end_x == self.width && end_y >= cur_y {
-		    break;
+	    }
+	}
+	Ok(())
+    }
+
+    fn clip_edge(&mut self, half_edge_id: usize, twin_id: usize) -> Result<(), VoronoiError> {
+	// the edge lies on the bisector of both sites, which is parametrized as point + t * dir starting halfway
+	// between the sites, so the clipped ends are accurate even if the vertices of nearly colinear sites are far away
+	let site = self.get_site(self.half_edges[half_edge_id].face_id);
+	let twin_site = self.get_site(self.half_edges[twin_id].face_id);
+	let point = (site + twin_site) / F::from_f64(2.0);
+	let start_end = self.get_edge_end(half_edge_id)?;
+	let end_end = self.get_edge_end(twin_id)?;
+	let edge_dir = match (&start_end, &end_end) {
+	    (EdgeEnd::Vertex(start), EdgeEnd::Vertex(end)) => *end - *start,
+	    (EdgeEnd::Infinite(start_dir), EdgeEnd::Vertex(_)) => - *start_dir,
+	    (_, EdgeEnd::Infinite(end_dir)) => *end_dir,
+	};
+	let mut dir = F::Vector2::from_values(site.get_y() - twin_site.get_y(), twin_site.get_x() - site.get_x());
+	if dir.scalar_product(&edge_dir) < F::from_f64(0.0) {
+	    dir = - dir;
+	}
+	let param = |vertex: &F::Vector2| (*vertex - point).scalar_product(&dir) / dir.scalar_product(&dir);
+	let t_min = match &start_end {
+	    EdgeEnd::Vertex(start) => param(start),
+	    EdgeEnd::Infinite(_) => - F::INFINITY,
+	};
+	let t_max = match &end_end {
+	    EdgeEnd::Vertex(end) => param(end),
+	    EdgeEnd::Infinite(_) => F::INFINITY,
+	};
+	match self.bounds.clip_line(&point, &dir, t_min, t_max) {
+	    Some((start, end)) if start < end => {
+		if start > t_min {
+		    let vertex_id = self.create_boundary_vertex(&(point + dir * start));
+		    self.half_edges[half_edge_id].start_id = Some(vertex_id);
+		    #[cfg(feature = "parallel")]
+		    if let Some(trace) = &mut self.trace {
+			trace.clip(half_edge_id, 0);
+		    }
 		}
-		(self.width, self.height) 
-	    } else if cur_y == self.height && cur_x != 0.0 {
-		if end_y == self.height && end_x <= cur_x {
-		    break;
+		if end < t_max {
+		    let vertex_id = self.create_boundary_vertex(&(point + dir * end));
+		    self.half_edges[twin_id].start_id = Some(vertex_id);
+		    #[cfg(feature = "parallel")]
+		    if let Some(trace) = &mut self.trace {
+			trace.clip(twin_id, 1);
+		    }
 		}
-		(0.0, self.height)
-	    } else if cur_x == 0.0 && cur_y != 0.0 {
-		if end_x == 0.0 && end_y <= cur_y {
-		    break;
+		let start = self.get_vertex(self.get_start_id(half_edge_id)?);
+		let end = self.get_vertex(self.get_start_id(twin_id)?);
+		if orient2d(&to_vector2_f64(&start), &to_vector2_f64(&end), &to_vector2_f64(&site)) < 0.0 {
+		    let start_id = self.half_edges[half_edge_id].start_id;
+		    self.half_edges[half_edge_id].start_id = self.half_edges[twin_id].start_id;
+		    self.half_edges[twin_id].start_id = start_id;
+		    #[cfg(feature = "parallel")]
+		    if let Some(trace) = &mut self.trace {
+			trace.swap_clip(half_edge_id, twin_id);
+		    }
 		}
-		(0.0, 0.0)
+	    },
+	    _ => {
+		self.half_edges[half_edge_id].visible = false;
+		self.half_edges[twin_id].visible = false;
+	    }
+	}
+	Ok(())
+    }
+
+    /**
+     * Creates a vertex on the bounding box, snapping it to the nearest side to avoid rounding errors
+     * Vertices very close to a corner are snapped to the corner, so the corner is not skipped when closing faces
+     */
+    fn create_boundary_vertex(&mut self, point: &F::Vector2) -> usize {
+	let mut x = point.get_x();
+	let mut y = point.get_y();
+	let distances = [
+	    (y - self.bounds.top()).abs(),
+	    (x - self.bounds.right()).abs(),
+	    (y - self.bounds.bottom()).abs(),
+	    (x - self.bounds.left()).abs(),
+	];
+	let mut side = 0;
+	for i in 1..4 {
+	    if distances[i] < distances[side] {
+		side = i;
+	    }
+	}
+	match side {
+	    0 => y = self.bounds.top(),
+	    1 => x = self.bounds.right(),
+	    2 => y = self.bounds.bottom(),
+	    _ => x = self.bounds.left(),
+	}
+	let tolerance = self.vertex_tolerance();
+	if side % 2 == 0 {
+	    if distances[1] <= tolerance {
+		x = self.bounds.right();
+	    } else if distances[3] <= tolerance {
+		x = self.bounds.left();
+	    }
+	} else if distances[0] <= tolerance {
+	    y = self.bounds.top();
+	} else if distances[2] <= tolerance {
+	    y = self.bounds.bottom();
+	}
+	match self.bounds.corners().iter().position(|corner| corner.get_x() == x && corner.get_y() == y) {
+	    Some(corner) => self.get_corner_vertex(corner),
+	    None => self.create_vertex(x, y),
+	}
+    }
+
+    fn get_corner_vertex(&mut self, corner: usize) -> usize {
+	match self.corner_ids[corner] {
+	    Some(vertex_id) => vertex_id,
+	    None => {
+		let point = self.bounds.corners()[corner];
+		let vertex_id = self.create_vertex(point.get_x(), point.get_y());
+		self.corner_ids[corner] = Some(vertex_id);
+		vertex_id
+	    }
+	}
+    }
+
+    /**
+     * Calculates the distance from the top left corner to the vertex, moving clockwise along the bounding box
+     */
+    fn get_bounds_offset(&self, vertex_id: usize) -> Result<F, VoronoiError> {
+	let vertex = &self.vertices[vertex_id];
+	let width = self.bounds.width();
+	let height = self.bounds.height();
+	if vertex.y == self.bounds.top() {
+	    Ok(vertex.x - self.bounds.left())
+	} else if vertex.x == self.bounds.right() {
+	    Ok(width + vertex.y - self.bounds.top())
+	} else if vertex.y == self.bounds.bottom() {
+	    Ok(width + height + self.bounds.right() - vertex.x)
+	} else if vertex.x == self.bounds.left() {
+	    Ok(width + width + height + self.bounds.bottom() - vertex.y)
+	} else {
+	    Err(VoronoiError::Topology(format!("end vertex is not on bounding box: ({:?}, {:?})", vertex.x, vertex.y)))
+	}
+    }
+
+    /**
+     * Returns the corners between two vertices on the bounding box, moving clockwise along the box
+     */
+    fn find_corners_between(&mut self, start_id: usize, end_id: usize) -> Result<Vec<usize>, VoronoiError> {
+	let width = self.bounds.width();
+	let height = self.bounds.height();
+	let perimeter = F::from_f64(2.0) * (width + height);
+	let corner_offsets = [F::from_f64(0.0), width, width + height, width + width + height];
+	let start = self.get_bounds_offset(start_id)?;
+	let mut end = self.get_bounds_offset(end_id)?;
+	if end <= start {
+	    end = end + perimeter;
+	}
+	let mut result = Vec::new();
+	for i in 0..8 {
+	    let offset = corner_offsets[i % 4] + perimeter * F::from_f64((i / 4) as f64);
+	    if offset > start && offset < end {
+		result.push(self.get_corner_vertex(i % 4));
+	    }
+	}
+	Ok(result)
+    }
+
+    /**
+     * Closes all faces by connecting their half edges, adding half edges along the bounding box where needed
+     */
+    fn bound(&mut self) -> Result<(), VoronoiError> {
+	let mut face_half_edge_ids = vec![Vec::new(); self.faces.len()];
+	for half_edge in self.half_edges.iter() {
+	    if half_edge.visible {
+		face_half_edge_ids[half_edge.face_id].push(half_edge.id);
+	    }
+	}
+	for (face_id, half_edge_ids) in face_half_edge_ids.into_iter().enumerate() {
+	    if half_edge_ids.is_empty() {
+		self.bound_face_by_box(face_id)?;
 	    } else {
-		panic!("end vertex is not on bounding box: ({:?}, {:?})", cur_x, cur_y);
-	    };
-	    let next_half_edge_id = self.create_half_edge(face_id, Some(cur_vertex_id));
-	    self.connect_half_edges(cur_half_edge_id, next_half_edge_id);
-	    
-	    cur_vertex_id = self.create_vertex(next_x, next_y);
-	    cur_half_edge_id = next_half_edge_id;
-	    cur_x = next_x;
-	    cur_y = next_y;
+		self.bound_face(face_id, half_edge_ids)?;
+	    }
 	}
-	let next_half_edge_id = self.create_half_edge(face_id, Some(cur_vertex_id));
-	self.connect_half_edges(cur_half_edge_id, next_half_edge_id);
-	self.connect_half_edges(next_half_edge_id, end_half_edge_id);
+	Ok(())
     }
 
-    fn build(&mut self) -> Diagram {
-	self.create_events();
-	self.handle_events();
-	self.complete_edges();
-	self.bound();
-	let diagram = Diagram {
-	    width: self.width,
-	    height: self.height,
-	    vertices: std::mem::take(&mut self.vertices),
-	    half_edges: self.half_edges.drain(..).map(|he| he.into_half_edge()).collect(),
-	    faces: self.faces.drain(..).map(|f| f.into_face()).collect(),
-	};
+    /**
+     * Closes a face that has no edges, i.e. a face that covers the entire bounding box
+     */
+    fn bound_face_by_box(&mut self, face_id: usize) -> Result<(), VoronoiError> {
+	let mut half_edge_ids = Vec::with_capacity(4);
+	for corner in 0..4 {
+	    let vertex_id = self.get_corner_vertex(corner);
+	    half_edge_ids.push(self.create_half_edge(face_id, Some(vertex_id)));
+	}
+	for i in 0..4 {
+	    self.connect_half_edges(half_edge_ids[i], half_edge_ids[(i + 1) % 4])?;
+	}
+	self.faces[face_id].half_edge_id = Some(half_edge_ids[0]);
+	Ok(())
+    }
+
+    fn bound_face(&mut self, face_id: usize, half_edge_ids: Vec<usize>) -> Result<(), VoronoiError> {
+	// a clipped cell is convex and contains its site, so sorting by angle yields the order of the half edges
+	let site = self.get_site(face_id);
+	let mut angles = Vec::with_capacity(half_edge_ids.len());
+	for half_edge_id in half_edge_ids {
+	    let start = self.get_vertex(self.get_start_id(half_edge_id)?);
+	    angles.push(((start.get_y() - site.get_y()).atan2(start.get_x() - site.get_x()), half_edge_id));
+	}
+	angles.sort_by(|first, second| first.0.partial_cmp(&second.0).unwrap_or(Ordering::Equal));
+	let half_edge_ids: Vec<usize> = angles.into_iter().map(|(_, half_edge_id)| half_edge_id).collect();
+	for i in 0..half_edge_ids.len() {
+	    let cur_id = half_edge_ids[i];
+	    let next_id = half_edge_ids[(i + 1) % half_edge_ids.len()];
+	    let end_id = self.get_start_id(self.get_twin_id(cur_id)?)?;
+	    let next_start_id = self.get_start_id(next_id)?;
+	    let mut prev_id = cur_id;
+	    if end_id != next_start_id {
+		let mut vertex_id = end_id;
+		for corner_id in self.find_corners_between(end_id, next_start_id)? {
+		    let bound_id = self.create_half_edge(face_id, Some(vertex_id));
+		    self.connect_half_edges(prev_id, bound_id)?;
+		    prev_id = bound_id;
+		    vertex_id = corner_id;
+		}
+		let bound_id = self.create_half_edge(face_id, Some(vertex_id));
+		self.connect_half_edges(prev_id, bound_id)?;
+		prev_id = bound_id;
+	    }
+	    self.connect_half_edges(prev_id, next_id)?;
+	}
+	self.faces[face_id].half_edge_id = Some(half_edge_ids[0]);
+	Ok(())
+    }
+
+    /**
+     * Creates the diagram from all visible vertices and half edges, renumbering them where needed
+     */
+    fn create_diagram(&mut self) -> Result<Diagram<T, F>, VoronoiError> {
+	let mut vertex_ids = vec![None; self.vertices.len()];
+	for half_edge in self.half_edges.iter().filter(|half_edge| half_edge.visible) {
+	    let start_id = half_edge.start_id.ok_or_else(|| VoronoiError::topology("half edge should have a start vertex"))?;
+	    vertex_ids[start_id] = Some(start_id);
+	}
+	let mut vertices = Vec::new();
+	for (id, vertex) in self.vertices.drain(..).enumerate() {
+	    if vertex_ids[id].is_some() {
+		vertex_ids[id] = Some(vertices.len());
+		vertices.push(Vertex {
+		    id: vertices.len(),
+		    x: vertex.x,
+		    y: vertex.y,
+		});
+	    }
+	}
+
+	let mut half_edge_ids = vec![None; self.half_edges.len()];
+	for (id, half_edge) in self.half_edges.iter().filter(|half_edge| half_edge.visible).enumerate() {
+	    half_edge_ids[half_edge.id] = Some(id);
+	}
+	#[cfg(feature = "parallel")]
+	if let Some(trace) = &mut self.trace {
+	    trace.renumber(&self.vertex_sites, &vertex_ids, &half_edge_ids);
+	}
+	self.vertex_sites.clear();
+	let half_edges = self.half_edges.drain(..)
+	    .filter(|half_edge| half_edge.visible)
+	    .map(|mut half_edge| {
+		half_edge.id = half_edge_ids[half_edge.id].unwrap_or(half_edge.id);
+		half_edge.start_id = half_edge.start_id.and_then(|id| vertex_ids[id]);
+		half_edge.twin_id = half_edge.twin_id.and_then(|id| half_edge_ids[id]);
+		half_edge.prev_id = half_edge.prev_id.and_then(|id| half_edge_ids[id]);
+		half_edge.next_id = half_edge.next_id.and_then(|id| half_edge_ids[id]);
+		half_edge.into_half_edge()
+	    }).collect::<Result<Vec<HalfEdge>, VoronoiError>>()?;
+
+	let faces = self.faces.drain(..)
+	    .map(|mut face| {
+		face.half_edge_id = face.half_edge_id.and_then(|id| half_edge_ids[id]);
+		face.into_face()
+	    }).collect::<Result<Vec<Face<T, F>>, VoronoiError>>()?;
+
+	Ok(Diagram {
+	    bounds: self.bounds.clone(),
+	    vertices,
+	    half_edges,
+	    faces,
+	    wrap: Wrap::None,
+	    attributes: Attributes::new(),
+	})
+    }
+
+    fn clear(&mut self) {
+	self.vertices.clear();
+	self.vertex_sites.clear();
+	self.half_edges.clear();
+	self.faces.clear();
 	self.events.clear();
 	self.edges.clear();
 	self.arcs.clear();
 	self.root = None;
-	diagram
+	self.corner_ids = [None; 4];
+    }
+
+}
+
+impl<T, F> DiagramBuilder<T, F> where T: Default, F: Float {
+
+    /**
+     * Adds a site without user data
+     * Returns the id of the face that will be created for the site; merging duplicates lowers the ids of
+     * the later sites by the number of duplicates that were dropped before them
+     */
+    pub fn add_site(&mut self, x: F, y: F) -> usize {
+	self.add_site_with_data(x, y, T::default())
+    }
+
+    /**
+     * Adds all sites without user data
+     */
+    pub fn add_sites<I>(&mut self, sites: I) where I: IntoIterator<Item = (F, F)> {
+	for (x, y) in sites {
+	    self.add_site(x, y);
+	}
     }
 
 }
 
-pub fn generate() -> Diagram {
-    let mut builder = Builder::new(1000.0, 1000.0);
+/**
+ * Follows the merged vertex ids until the vertex that was kept
+ */
+fn find_merged_vertex(merged_ids: &[usize], mut vertex_id: usize) -> usize {
+    while merged_ids[vertex_id] != vertex_id {
+	vertex_id = merged_ids[vertex_id];
+    }
+    vertex_id
+}
+
+pub fn generate() -> Result<Diagram, VoronoiError> {
+    let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 1000.0, 0.0, 1000.0));
     builder.add_site(100.0, 100.0);
-    builder.add_site(900.0, 900.0); 
+    builder.add_site(900.0, 900.0);
     builder.build()
 }
 
 #[cfg(test)]
 mod test {
 
+    use float_eq::assert_float_eq;
+
     use super::*;
+    use crate::random::{random_sites, Random};
 
     #[test]
     fn test_generate_empty() {
-	let mut builder = Builder::new(1000.0, 1000.0);
-	let diagram = builder.build();
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 1000.0, 0.0, 1000.0));
+	let diagram = builder.build().unwrap();
 	assert_eq!(Diagram {
-	    width: 1000.0,
-	    height: 1000.0,
+	    bounds: BoundingBox::new(0.0, 1000.0, 0.0, 1000.0),
 	    vertices: vec![],
 	    half_edges: vec![],
 	    faces: vec![],
+	    wrap: Wrap::None,
+	    attributes: Attributes::new(),
 	}, diagram);
     }
 
         #[test]
     fn test_generate_unary() {
-	let mut builder = Builder::new(1000.0, 1000.0);
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 1000.0, 0.0, 1000.0));
 	builder.add_site(500.0, 500.0);
-	let diagram = builder.build();
+	let diagram = builder.build().unwrap();
 	assert_eq!(Diagram {
-	    width: 1000.0,
-	    height: 1000.0,
+	    bounds: BoundingBox::new(0.0, 1000.0, 0.0, 1000.0),
 	    vertices: vec![
 		Vertex{id: 0, x: 0.0, y: 0.0},
 		Vertex{id: 1, x: 1000.0, y: 0.0},
@@ -860,16 +2177,16 @@ mod test {
 	    ],
 	    half_edges: vec![
 		HalfEdge{
-		    id: 0, face_id: 0, start_id: 0, prev_id: 3, next_id: 1, twin_id: None
+		    id: 0, face_id: 0, start_id: 0, prev_id: 3, next_id: 1, twin_id: None, clip_boundary: false, wrap: (0, 0)
 		},
 		HalfEdge{
-		    id: 1, face_id: 0, start_id: 1, prev_id: 0, next_id: 2, twin_id: None
+		    id: 1, face_id: 0, start_id: 1, prev_id: 0, next_id: 2, twin_id: None, clip_boundary: false, wrap: (0, 0)
 		},
 		HalfEdge{
-		    id: 2, face_id: 0, start_id: 2, prev_id: 1, next_id: 3, twin_id: None
+		    id: 2, face_id: 0, start_id: 2, prev_id: 1, next_id: 3, twin_id: None, clip_boundary: false, wrap: (0, 0)
 		},
 		HalfEdge{
-		    id: 3, face_id: 0, start_id: 3, prev_id: 2, next_id: 0, twin_id: None
+		    id: 3, face_id: 0, start_id: 3, prev_id: 2, next_id: 0, twin_id: None, clip_boundary: false, wrap: (0, 0)
 		},
 	    ],
 	    faces: vec![
@@ -878,20 +2195,25 @@ mod test {
 		    x: 500.0,
 		    y: 500.0,
 		    start_id: 0,
+		    site_id: 0,
+		    hole_start_ids: Vec::new(),
+		    weight: 0.0,
+		    data: (),
 		},
 	    ],
+	    wrap: Wrap::None,
+	    attributes: Attributes::new(),
 	}, diagram);
     }
 
     #[test]
     fn test_generate_binary() {
-	let mut builder = Builder::new(1000.0, 1000.0);
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 1000.0, 0.0, 1000.0));
 	builder.add_site(100.0, 100.0);
 	builder.add_site(900.0, 900.0);
-	let diagram = builder.build();
+	let diagram = builder.build().unwrap();
 	assert_eq!(Diagram {
-	    width: 1000.0,
-	    height: 1000.0,
+	    bounds: BoundingBox::new(0.0, 1000.0, 0.0, 1000.0),
 	    vertices: vec![
 		Vertex{id: 0, x: 0.0, y: 1000.0},
 		Vertex{id: 1, x: 1000.0, y: 0.0},
@@ -900,22 +2222,22 @@ mod test {
 	    ],
 	    half_edges: vec![
 		HalfEdge {
-		    id: 0, face_id: 0, start_id: 1, twin_id: Some(1), prev_id: 3, next_id: 2
+		    id: 0, face_id: 0, start_id: 1, twin_id: Some(1), prev_id: 3, next_id: 2, clip_boundary: false, wrap: (0, 0)
 		},
 		HalfEdge {
-		    id: 1, face_id: 1, start_id: 0, twin_id: Some(0), prev_id: 5, next_id: 4
+		    id: 1, face_id: 1, start_id: 0, twin_id: Some(0), prev_id: 5, next_id: 4, clip_boundary: false, wrap: (0, 0)
 		},
 		HalfEdge {
-		    id: 2, face_id: 0, start_id: 0, twin_id: None, prev_id: 0, next_id: 3
+		    id: 2, face_id: 0, start_id: 0, twin_id: None, prev_id: 0, next_id: 3, clip_boundary: false, wrap: (0, 0)
 		},
 		HalfEdge {
-		    id: 3, face_id: 0, start_id: 2, twin_id: None, prev_id: 2, next_id: 0
+		    id: 3, face_id: 0, start_id: 2, twin_id: None, prev_id: 2, next_id: 0, clip_boundary: false, wrap: (0, 0)
 		},
 		HalfEdge {
-		    id: 4, face_id: 1, start_id: 1, twin_id: None, prev_id: 1, next_id: 5
+		    id: 4, face_id: 1, start_id: 1, twin_id: None, prev_id: 1, next_id: 5, clip_boundary: false, wrap: (0, 0)
 		},
 		HalfEdge {
-		    id: 5, face_id: 1, start_id: 3, twin_id: None, prev_id: 4, next_id: 1
+		    id: 5, face_id: 1, start_id: 3, twin_id: None, prev_id: 4, next_id: 1, clip_boundary: false, wrap: (0, 0)
 		}
 	    ],
 	    faces: vec![
@@ -924,23 +2246,490 @@ mod test {
 		    x: 100.0,
 		    y: 100.0,
 		    start_id: 0,
+		    site_id: 0,
+		    hole_start_ids: Vec::new(),
+		    weight: 0.0,
+		    data: (),
 		},
 		Face {
 		    id: 1,
 		    x: 900.0,
 		    y: 900.0,
 		    start_id: 1,
+		    site_id: 1,
+		    hole_start_ids: Vec::new(),
+		    weight: 0.0,
+		    data: (),
 		},
 	    ],
+	    wrap: Wrap::None,
+	    attributes: Attributes::new(),
 	}, diagram);
     }
 
+    /**
+     * Returns the positions of the vertices of a face in order
+     */
+    fn get_face_points<T>(diagram: &Diagram<T>, face_id: usize) -> Vec<(f64, f64)> {
+	let start_id = diagram.faces[face_id].start_id;
+	let mut result = Vec::new();
+	let mut cur_id = start_id;
+	loop {
+	    let vertex = &diagram.vertices[diagram.half_edges[cur_id].start_id];
+	    result.push((vertex.x, vertex.y));
+	    cur_id = diagram.half_edges[cur_id].next_id;
+	    if cur_id == start_id {
+		break result;
+	    }
+	}
+    }
+
+    /**
+     * Checks that every vertex of a face is at least as close to the site of the face as to any other site
+     * and that the faces cover the bounding box
+     */
+    fn assert_voronoi_property<T>(diagram: &Diagram<T>) {
+	let mut area = 0.0;
+	for face in diagram.faces.iter() {
+	    let points = get_face_points(diagram, face.id);
+	    for (x, y) in points.iter() {
+		let distance = (x - face.x).hypot(y - face.y);
+		for other in diagram.faces.iter() {
+		    assert!(distance <= (x - other.x).hypot(y - other.y) + 0.000_1);
+		}
+	    }
+	    for i in 0..points.len() {
+		let (x1, y1) = points[i];
+		let (x2, y2) = points[(i + 1) % points.len()];
+		area += x1 * y2 - x2 * y1;
+	    }
+	}
+	assert_float_eq!(area / 2.0, diagram.bounds.width() * diagram.bounds.height(), r2nd <= 0.000_001);
+    }
+
+    /**
+     * Checks the structural invariants of the half edges of the diagram
+     */
+    fn assert_half_edge_invariants<T>(diagram: &Diagram<T>) {
+	for (id, half_edge) in diagram.half_edges.iter().enumerate() {
+	    assert_eq!(id, half_edge.id);
+	    assert_eq!(id, diagram.half_edges[half_edge.next_id].prev_id);
+	    assert_eq!(id, diagram.half_edges[half_edge.prev_id].next_id);
+	    assert_eq!(half_edge.face_id, diagram.half_edges[half_edge.next_id].face_id);
+	    if let Some(twin_id) = half_edge.twin_id {
+		let twin = &diagram.half_edges[twin_id];
+		assert_eq!(Some(id), twin.twin_id);
+		assert_ne!(half_edge.face_id, twin.face_id);
+		assert_eq!(twin.start_id, diagram.half_edges[half_edge.next_id].start_id);
+	    }
+	}
+	for face in diagram.faces.iter() {
+	    let mut cur_id = face.start_id;
+	    let mut count = 0;
+	    loop {
+		assert_eq!(face.id, diagram.half_edges[cur_id].face_id);
+		cur_id = diagram.half_edges[cur_id].next_id;
+		count += 1;
+		assert!(count <= diagram.half_edges.len());
+		if cur_id == face.start_id {
+		    break;
+		}
+	    }
+	    assert!(count >= 3);
+	}
+    }
+
+    fn assert_valid_diagram<T>(diagram: &Diagram<T>) {
+	assert_half_edge_invariants(diagram);
+	assert_voronoi_property(diagram);
+	assert_eq!(Ok(()), diagram.validate());
+    }
+
+    #[test]
+    fn test_generate_with_offset_bounds() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(-500.0, 500.0, 200.0, 1200.0));
+	builder.add_sites(vec![(-400.0, 300.0), (400.0, 1100.0)]);
+	let diagram = builder.build().unwrap();
+	assert_eq!(4, diagram.vertices.len());
+	assert_eq!(6, diagram.half_edges.len());
+	assert_voronoi_property(&diagram);
+    }
+
+    #[test]
+    fn test_face_queries() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 1000.0, 0.0, 1000.0));
+	builder.add_site(100.0, 100.0);
+	builder.add_site(900.0, 900.0);
+	let diagram: Diagram = builder.build().unwrap();
+	assert_eq!(vec![0, 2, 3], diagram.face_half_edges(0).map(|half_edge| half_edge.id()).collect::<Vec<usize>>());
+	assert_eq!(vec![(1000.0, 0.0), (0.0, 1000.0), (0.0, 0.0)], diagram.face_polygon(0).iter()
+		   .map(|point| (point.get_x(), point.get_y())).collect::<Vec<(f64, f64)>>());
+	assert_eq!(vec![0, 1, 3], diagram.face_vertices(1).map(|vertex| vertex.id()).collect::<Vec<usize>>());
+	assert_eq!(vec![1], diagram.neighbours(0).collect::<Vec<usize>>());
+	assert_eq!(vec![0], diagram.neighbours(1).collect::<Vec<usize>>());
+	let polygon = diagram.face_to_polygon(0);
+	assert_eq!(diagram.face_polygon(0), polygon.points());
+	assert_float_eq!(500000.0, polygon.area(), abs <= 1e-6);
+	assert!(polygon.contains(&Vector2F64::from_values(100.0, 100.0)));
+	assert!(polygon.is_convex());
+	assert_eq!(Some(0), diagram.locate(400.0, 400.0));
+	assert_eq!(Some(1), diagram.locate(600.0, 600.0));
+	assert_eq!(Some(1), diagram.locate_from(1, 600.0, 600.0));
+	assert_eq!(None, diagram.locate(1001.0, 600.0));
+    }
+
+    #[test]
+    fn test_generate_with_data() {
+	let mut builder = DiagramBuilder::with_data(BoundingBox::new(0.0, 100.0, 0.0, 100.0));
+	assert_eq!(0, builder.add_site_with_data(10.0, 20.0, "first"));
+	assert_eq!(1, builder.add_site_with_data(60.0, 70.0, "second"));
+	assert_eq!(2, builder.add_site_with_data(80.0, 10.0, "third"));
+	let diagram = builder.build().unwrap();
+	assert_eq!(&"first", diagram.faces[0].data());
+	assert_eq!(&"second", diagram.faces[1].data());
+	assert_eq!(&"third", diagram.faces[2].data());
+	assert_voronoi_property(&diagram);
+    }
+
+    #[test]
+    fn test_generate_site_out_of_bounds() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 100.0, 0.0, 100.0));
+	builder.add_sites(vec![(10.0, 20.0), (110.0, 70.0)]);
+	assert_eq!(Err(VoronoiError::SiteOutOfBounds { id: 1, x: 110.0, y: 70.0 }), builder.build());
+    }
+
+    #[test]
+    fn test_generate_duplicate_site() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 100.0, 0.0, 100.0));
+	builder.add_sites(vec![(10.0, 20.0), (50.0, 50.0), (10.0, 20.0)]);
+	assert_eq!(Err(VoronoiError::DuplicateSite { first_id: 0, second_id: 2 }), builder.build());
+    }
+
+    #[test]
+    fn test_generate_empty_bounds() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 100.0, 50.0, 50.0));
+	builder.add_site(10.0, 50.0);
+	match builder.build() {
+	    Err(VoronoiError::DegenerateInput(_)) => {},
+	    result => panic!("expected degenerate input error, got {:?}", result),
+	}
+    }
+
+    #[test]
+    fn test_generate_after_error() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 100.0, 0.0, 100.0));
+	builder.add_sites(vec![(10.0, 20.0), (10.0, 20.0)]);
+	assert!(builder.build().is_err());
+	builder.add_sites(vec![(10.0, 20.0), (60.0, 70.0)]);
+	let diagram = builder.build().unwrap();
+	assert_eq!(2, diagram.faces.len());
+	assert_voronoi_property(&diagram);
+    }
+
+    #[test]
+    fn test_generate_random() {
+	let bounds = BoundingBox::new(-250.0, 750.0, 100.0, 600.0);
+	for seed in 0..20 {
+	    let mut builder = DiagramBuilder::new(bounds.clone());
+	    builder.add_sites(random_sites(200, seed, &bounds));
+	    let diagram = builder.build().unwrap();
+	    assert_eq!(200, diagram.faces.len());
+	    assert_valid_diagram(&diagram);
+	}
+    }
+
+    #[test]
+    fn test_metrics() {
+	let bounds = BoundingBox::new(0.0, 100.0, 0.0, 100.0);
+	let mut builder = DiagramBuilder::new(bounds.clone());
+	builder.add_sites(random_sites(500, 5, &bounds));
+	let diagram = builder.build().unwrap();
+	let metrics = *builder.metrics();
+	assert_eq!(500, metrics.site_events);
+	assert!(metrics.circle_events > 0);
+	assert!(metrics.cancelled_events > 0);
+	assert!(metrics.max_queued_events >= 500);
+	assert_valid_diagram(&diagram);
+
+	// the queue is empty after each sweep and the metrics start over
+	assert!(builder.events.is_empty());
+	builder.add_sites(random_sites(10, 5, &bounds));
+	builder.build().unwrap();
+	assert_eq!(10, builder.metrics().site_events);
+    }
+
+    #[test]
+    fn test_generate_validated() {
+	let bounds = BoundingBox::new(0.0, 100.0, 0.0, 100.0);
+	let mut builder = DiagramBuilder::new(bounds.clone());
+	builder.set_validate(true);
+	builder.add_sites(random_sites(100, 3, &bounds));
+	assert!(builder.build().is_ok());
+    }
+
+    #[test]
+    fn test_generate_merged_duplicates() {
+	let mut builder = DiagramBuilder::with_data(BoundingBox::new(0.0, 100.0, 0.0, 100.0));
+	builder.set_duplicate_policy(DuplicatePolicy::Merge);
+	builder.add_sites_with_data(vec![(10.0, 20.0, 0), (50.0, 50.0, 1), (10.0, 20.0, 2), (80.0, 10.0, 3), (50.0, 50.0, 4)]);
+	let diagram = builder.build().unwrap();
+	let data: Vec<i32> = diagram.faces.iter().map(|face| *face.data()).collect();
+	assert_eq!(vec![0, 1, 3], data);
+	assert_valid_diagram(&diagram);
+    }
+
+    #[test]
+    fn test_generate_merged_nan_sites() {
+	for count in [30, 100].iter() {
+	    let mut random = Random::new(*count as u64);
+	    let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 100.0, 0.0, 100.0));
+	    builder.set_duplicate_policy(DuplicatePolicy::Merge);
+	    for id in 0..*count {
+		let x = if id % 7 == 3 { f64::NAN } else { random.range(0.0, 100.0) };
+		builder.add_site(x, random.range(0.0, 100.0));
+	    }
+	    match builder.build() {
+		Err(VoronoiError::SiteOutOfBounds { id, .. }) => assert_eq!(3, id),
+		result => panic!("expected site 3 to be out of bounds, got {:?}", result.map(|diagram| diagram.faces.len())),
+	    }
+	}
+    }
+
+    #[test]
+    fn test_generate_row() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 100.0, 0.0, 100.0));
+	builder.add_sites((0..10).map(|i| (95.0 - 10.0 * i as f64, 50.0)));
+	let diagram = builder.build().unwrap();
+	assert_eq!(10, diagram.faces.len());
+	assert_valid_diagram(&diagram);
+    }
+
+    #[test]
+    fn test_generate_first_row() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 100.0, 0.0, 100.0));
+	builder.add_sites(vec![(10.0, 10.0), (50.0, 10.0), (90.0, 10.0), (30.0, 60.0), (70.0, 40.0)]);
+	let diagram = builder.build().unwrap();
+	assert_valid_diagram(&diagram);
+    }
+
+    #[test]
+    fn test_generate_column() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 100.0, 0.0, 100.0));
+	builder.add_sites((0..10).map(|i| (50.0, 5.0 + 10.0 * i as f64)));
+	let diagram = builder.build().unwrap();
+	assert_valid_diagram(&diagram);
+    }
+
+    #[test]
+    fn test_generate_diagonal() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 100.0, 0.0, 100.0));
+	builder.add_sites((0..10).map(|i| (5.0 + 7.0 * i as f64, 2.0 + 10.0 * i as f64)));
+	let diagram = builder.build().unwrap();
+	assert_valid_diagram(&diagram);
+    }
+
+    #[test]
+    fn test_generate_grid() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 100.0, 0.0, 100.0));
+	builder.add_sites((0..100).map(|i| (5.0 + 10.0 * (i % 10) as f64, 5.0 + 10.0 * (i / 10) as f64)));
+	let diagram = builder.build().unwrap();
+	assert_valid_diagram(&diagram);
+	// all interior vertices are shared by four cells
+	assert_eq!(11 * 11, diagram.vertices.len());
+    }
+
+    #[test]
+    fn test_generate_cocircular() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(-100.0, 100.0, -100.0, 100.0));
+	builder.add_sites((0..12).map(|i| {
+	    let angle = i as f64 * std::f64::consts::PI / 6.0;
+	    (50.0 * angle.cos(), 50.0 * angle.sin())
+	}));
+	let diagram = builder.build().unwrap();
+	assert_valid_diagram(&diagram);
+	let centers = diagram.vertices.iter().filter(|v| v.x.abs() < 0.000_1 && v.y.abs() < 0.000_1).count();
+	assert_eq!(1, centers);
+    }
+
+    #[test]
+    fn test_generate_cocircular_offset() {
+	// rounding leaves the y coordinates of mirrored sites a few units in the last place apart
+	for count in [8, 10, 12, 16, 20, 24].iter() {
+	    let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 100.0, 0.0, 100.0));
+	    builder.add_sites((0..*count).map(|i| {
+		let angle = i as f64 * 2.0 * std::f64::consts::PI / *count as f64;
+		(50.0 + 30.7 * angle.cos(), 50.0 + 30.7 * angle.sin())
+	    }));
+	    let diagram = builder.build().unwrap();
+	    assert_eq!(*count, diagram.faces.len());
+	    assert_valid_diagram(&diagram);
+	}
+    }
+
+    #[test]
+    fn test_generate_near_colinear() {
+	// sites a few units in the last place off a diagonal line, so plain floating point turns are unreliable
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 100.0, 0.0, 100.0));
+	builder.add_sites((0..40).map(|i| {
+	    let t = 1.0 + 2.4 * i as f64;
+	    let offset = [0.0, 1e-14, -2e-14, 3e-14][i % 4];
+	    (t, t * 0.7 + 0.3 + offset)
+	}));
+	let diagram = builder.build().unwrap();
+	assert_eq!(40, diagram.faces.len());
+	assert_valid_diagram(&diagram);
+    }
+
+    #[test]
+    fn test_generate_near_cocircular_far_from_origin() {
+	let center = 1e6;
+	let mut builder = DiagramBuilder::new(BoundingBox::new(center - 2.0, center + 2.0, center - 2.0, center + 2.0));
+	builder.add_sites((0..16).map(|i| {
+	    let angle = i as f64 * std::f64::consts::PI / 8.0;
+	    (center + angle.cos(), center + angle.sin())
+	}));
+	builder.add_site(center + 0.1, center - 0.05);
+	let diagram = builder.build().unwrap();
+	assert_eq!(17, diagram.faces.len());
+	assert_valid_diagram(&diagram);
+    }
+
+    #[test]
+    fn test_generate_random_integer() {
+	let bounds = BoundingBox::new(0.0, 20.0, 0.0, 20.0);
+	for seed in 0..50 {
+	    let mut builder = DiagramBuilder::new(bounds.clone());
+	    builder.set_duplicate_policy(DuplicatePolicy::Merge);
+	    builder.add_sites(random_sites(60, seed, &bounds).into_iter().map(|(x, y)| (x.floor(), y.floor())));
+	    let diagram = builder.build().unwrap();
+	    assert_valid_diagram(&diagram);
+	}
+    }
+
+    #[test]
+    fn test_generate_f32() {
+	let bounds = BoundingBox::new(-250.0, 750.0, 100.0, 600.0);
+	let bounds_f32 = bounds.cast::<f32>();
+	for seed in 0..20 {
+	    let sites: Vec<(f32, f32)> = random_sites(200, seed, &bounds).into_iter()
+		.map(|(x, y)| (x as f32, y as f32))
+		.collect();
+	    let mut builder = DiagramBuilder::new(bounds_f32.clone());
+	    builder.add_sites(sites.iter().cloned());
+	    let diagram = builder.build().unwrap();
+	    assert!(diagram.validate().is_ok());
+	    let mut builder = DiagramBuilder::new(bounds.clone());
+	    builder.add_sites(sites.iter().map(|&(x, y)| (x as f64, y as f64)));
+	    let expected = builder.build().unwrap();
+	    // edges shorter than the tolerance of f32 may be merged into a vertex
+	    let converted = diagram.cast::<f64>();
+	    for face_id in 0..200 {
+		let neighbours: Vec<usize> = converted.neighbours(face_id).collect();
+		let expected_neighbours: Vec<usize> = expected.neighbours(face_id).collect();
+		for (first, second) in [(&converted, &expected_neighbours), (&expected, &neighbours)].iter() {
+		    for half_edge in first.face_half_edges(face_id) {
+			let start = first.half_edge_start(half_edge.id());
+			let end = first.half_edge_start(half_edge.next_id());
+			let length = (end.get_x() - start.get_x()).hypot(end.get_y() - start.get_y());
+			if let (Some(twin_id), true) = (half_edge.twin_id(), length > 0.1) {
+			    assert!(second.contains(&first.half_edges()[twin_id].face_id()));
+			}
+		    }
+		}
+	    }
+	}
+    }
+
+    #[test]
+    fn test_generate_f32_grid() {
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0f32, 100.0, 0.0, 100.0));
+	builder.add_sites((0..100).map(|i| (5.0 + 10.0 * (i % 10) as f32, 5.0 + 10.0 * (i / 10) as f32)));
+	let diagram = builder.build().unwrap();
+	assert!(diagram.validate().is_ok());
+	assert_eq!(11 * 11, diagram.vertices.len());
+	let converted = diagram.cast::<f64>();
+	assert_valid_diagram(&converted);
+    }
+
+    /**
+     * Checks the links and the balance of the beach line, returns the height of the tree below the node
+     */
+    fn assert_beach_line_node<T>(builder: &DiagramBuilder<T>, node_id: &NodeId, arc_ids: &mut Vec<usize>) -> usize {
+	match node_id {
+	    NodeId::Arc(arc_id) => {
+		arc_ids.push(*arc_id);
+		0
+	    },
+	    NodeId::Edge(edge_id) => {
+		let edge = &builder.edges[*edge_id];
+		for child in &[edge.left_child, edge.right_child] {
+		    let parent_id = match child {
+			NodeId::Arc(arc_id) => builder.arcs[*arc_id].parent_id,
+			NodeId::Edge(child_id) => builder.edges[*child_id].parent_id,
+		    };
+		    assert_eq!(Some(*edge_id), parent_id);
+		}
+		let left_height = assert_beach_line_node(builder, &edge.left_child, arc_ids);
+		assert_eq!(edge.left_arc_id, *arc_ids.last().unwrap());
+		assert_eq!(Some(*edge_id), builder.arcs[edge.left_arc_id].right_edge_id);
+		let right_height = assert_beach_line_node(builder, &edge.right_child, arc_ids);
+		assert_eq!(Some(*edge_id), builder.arcs[edge.right_arc_id].left_edge_id);
+		assert!((left_height as isize - right_height as isize).abs() <= 1);
+		assert_eq!(edge.height, 1 + left_height.max(right_height));
+		edge.height
+	    },
+	}
+    }
+
+    #[test]
+    fn test_beach_line_balanced() {
+	// the sites are sorted and lie on a convex curve, so every new arc splits the last one and no arc is removed
+	let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 1000.0, 0.0, 1000.0));
+	builder.add_sites((0..500).map(|i| (2.0 * i as f64, 0.001 * (i * i) as f64)));
+	builder.create_events();
+	while let Some((_, _, kind)) = builder.events.pop() {
+	    match kind {
+		EventKind::AddArc{face_id} => builder.add_arc(face_id).unwrap(),
+		EventKind::RemoveArc{arc_id, center} => builder.remove_arc(arc_id, &center).unwrap(),
+	    }
+	    let mut arc_ids = vec![];
+	    let height = assert_beach_line_node(&builder, &builder.root.unwrap(), &mut arc_ids);
+	    let limit = 1.45 * ((arc_ids.len() + 2) as f64).log2();
+	    assert!((height as f64) <= limit);
+	    assert_eq!(None, builder.arcs[arc_ids[0]].left_edge_id);
+	    assert_eq!(None, builder.arcs[*arc_ids.last().unwrap()].right_edge_id);
+	}
+	builder.complete_edges().unwrap();
+	builder.merge_vertices();
+	builder.clip_edges().unwrap();
+	builder.bound().unwrap();
+	assert_valid_diagram(&builder.create_diagram().unwrap());
+    }
+
+    #[test]
+    fn test_generate_edge_through_corner() {
+	// an edge of this diagram leaves the bounds a rounding error away from the bottom left corner
+	let bounds = BoundingBox::new(0.0, 20.0, 0.0, 20.0);
+	let mut builder = DiagramBuilder::new(bounds.clone());
+	builder.set_duplicate_policy(DuplicatePolicy::Merge);
+	builder.add_sites(vec![
+	    (7.0, 6.0), (1.0, 2.0), (9.0, 16.0), (8.0, 4.0), (19.0, 3.0), (6.0, 13.0), (19.0, 13.0), (12.0, 5.0), (2.0, 11.0), (7.0, 12.0),
+	    (2.0, 8.0), (13.0, 10.0), (16.0, 7.0), (12.0, 9.0), (15.0, 10.0), (5.0, 17.0), (15.0, 6.0), (5.0, 19.0), (2.0, 12.0), (0.0, 9.0),
+	    (19.0, 14.0), (0.0, 14.0), (17.0, 12.0), (7.0, 19.0), (17.0, 10.0), (15.0, 17.0), (18.0, 10.0), (14.0, 16.0), (13.0, 11.0), (10.0, 0.0),
+	    (0.0, 15.0), (17.0, 16.0), (11.0, 14.0), (8.0, 9.0), (9.0, 15.0), (13.0, 6.0), (5.0, 4.0), (5.0, 19.0), (4.0, 12.0), (6.0, 15.0),
+	    (3.0, 16.0), (12.0, 19.0), (1.0, 8.0), (12.0, 10.0), (16.0, 4.0), (3.0, 10.0), (13.0, 5.0), (18.0, 1.0), (15.0, 16.0), (7.0, 5.0),
+	]);
+	let diagram = builder.build().unwrap();
+	assert_valid_diagram(&diagram);
+	assert!(diagram.vertices.iter().any(|vertex| vertex.x == 0.0 && vertex.y == 20.0));
+    }
+
     #[test]
      fn create_triangles() {
-	 let mut builder = Builder::new(1000.0, 1000.0);
+	 let mut builder = DiagramBuilder::new(BoundingBox::new(0.0, 1000.0, 0.0, 1000.0));
 	 builder.add_site(100.0, 100.0);
 	 builder.add_site(900.0, 900.0);
-	 let (vertices, indices) = builder.build().create_triangles();
+	 let (vertices, indices) = builder.build().unwrap().create_triangles();
 	 let expected_vertices: Vec<f32> = vec![
 	     -0.8, -0.8, 0.0, 0.8, 0.8, 0.0, -1.0, 1.0, 0.0, 1.0, -1.0, 0.0, -1.0, -1.0, 0.0, 1.0, 1.0, 0.0
 	 ];